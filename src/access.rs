@@ -0,0 +1,299 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Peer allowlist and blacklist.
+//!
+//! An access list names peer ids and source IP ranges, each either allowed
+//! or denied. Denied entries always win. When the list has any allow entry
+//! the server becomes private: only traffic matching an allow entry gets
+//! through.
+//!
+//! Lists are read from a text file with one entry per line:
+//!
+//! ```text
+//! # comment
+//! allow peer 8F732D6748E20C80141405F5008B400B25D8166F
+//! allow ip 10.0.0.0/8
+//! deny ip 192.0.2.7
+//! deny peer mallory
+//! ```
+
+use std::{
+    collections::HashSet, fmt, fs, io, net::IpAddr, path::Path, str::FromStr,
+};
+
+/// A CIDR block, or a single address when written without a prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32);
+                let mask = mask.unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32);
+                let mask = mask.unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr =
+            addr.parse().map_err(|_| format!("invalid address '{addr}'"))?;
+
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => {
+                prefix.parse().ok().filter(|p| *p <= max).ok_or_else(|| {
+                    format!("invalid prefix length '{prefix}'")
+                })?
+            }
+            None => max,
+        };
+
+        Ok(IpRange { addr, prefix })
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Why traffic was refused by an [`AccessList`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Denial {
+    /// The source address is in a denied range.
+    DeniedIp(IpRange),
+    /// The peer id is denied.
+    DeniedPeer,
+    /// The list has allow entries and neither the source address nor the
+    /// peer id matches one.
+    NotAllowed,
+}
+
+impl fmt::Display for Denial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Denial::DeniedIp(range) => write!(f, "source in denied {range}"),
+            Denial::DeniedPeer => write!(f, "peer id denied"),
+            Denial::NotAllowed => write!(f, "not in the allowlist"),
+        }
+    }
+}
+
+/// Allowed and denied peer ids and source ranges.
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    allow_peers: HashSet<String>,
+    allow_ips: Vec<IpRange>,
+    deny_peers: HashSet<String>,
+    deny_ips: Vec<IpRange>,
+}
+
+impl AccessList {
+    /// Create a list letting everything through.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a list from the file at `path`, see the module docs.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        fs::read_to_string(path)?.parse().map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", path.display()),
+            )
+        })
+    }
+
+    pub fn allow_peer(&mut self, peer_id: impl Into<String>) {
+        self.allow_peers.insert(peer_id.into());
+    }
+
+    pub fn allow_ip(&mut self, range: IpRange) {
+        self.allow_ips.push(range);
+    }
+
+    pub fn deny_peer(&mut self, peer_id: impl Into<String>) {
+        self.deny_peers.insert(peer_id.into());
+    }
+
+    pub fn deny_ip(&mut self, range: IpRange) {
+        self.deny_ips.push(range);
+    }
+
+    /// Whether only allowed traffic gets through.
+    pub fn is_private(&self) -> bool {
+        !self.allow_peers.is_empty() || !self.allow_ips.is_empty()
+    }
+
+    /// Whether any entry names a peer id, so checks need to know who is
+    /// sending and not only from where.
+    pub fn names_peers(&self) -> bool {
+        !self.allow_peers.is_empty() || !self.deny_peers.is_empty()
+    }
+
+    /// Check traffic from `ip` on behalf of `peer_id`, if the peer is
+    /// known.
+    pub fn check(
+        &self,
+        ip: IpAddr,
+        peer_id: Option<&str>,
+    ) -> Result<(), Denial> {
+        if let Some(range) = self.deny_ips.iter().find(|r| r.contains(ip)) {
+            return Err(Denial::DeniedIp(*range));
+        }
+        if peer_id.is_some_and(|id| self.deny_peers.contains(id)) {
+            return Err(Denial::DeniedPeer);
+        }
+
+        if self.is_private()
+            && !self.allow_ips.iter().any(|r| r.contains(ip))
+            && !peer_id.is_some_and(|id| self.allow_peers.contains(id))
+        {
+            return Err(Denial::NotAllowed);
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for AccessList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut list = AccessList::new();
+
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let at = |e: String| format!("line {}: {e}", n + 1);
+            match fields.as_slice() {
+                ["allow", "peer", id] => list.allow_peer(*id),
+                ["deny", "peer", id] => list.deny_peer(*id),
+                ["allow", "ip", range] => {
+                    list.allow_ip(range.parse().map_err(at)?)
+                }
+                ["deny", "ip", range] => {
+                    list.deny_ip(range.parse().map_err(at)?)
+                }
+                _ => return Err(at(format!("malformed entry '{line}'"))),
+            }
+        }
+
+        Ok(list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ranges_match_their_prefix() {
+        let range: IpRange = "10.1.0.0/16".parse().unwrap();
+        assert!(range.contains(ip("10.1.200.3")));
+        assert!(!range.contains(ip("10.2.0.1")));
+        assert!(!range.contains(ip("::1")));
+
+        let single: IpRange = "192.0.2.7".parse().unwrap();
+        assert_eq!(single.to_string(), "192.0.2.7/32");
+        assert!(!single.contains(ip("192.0.2.8")));
+
+        let everything: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.contains(ip("203.0.113.9")));
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn denied_entries_win() {
+        let list: AccessList =
+            "allow ip 10.0.0.0/8\ndeny ip 10.0.0.66\ndeny peer mallory"
+                .parse()
+                .unwrap();
+        assert_eq!(list.check(ip("10.0.0.1"), Some("alice")), Ok(()));
+        assert!(matches!(
+            list.check(ip("10.0.0.66"), Some("alice")),
+            Err(Denial::DeniedIp(_))
+        ));
+        assert_eq!(
+            list.check(ip("10.0.0.1"), Some("mallory")),
+            Err(Denial::DeniedPeer)
+        );
+    }
+
+    #[test]
+    fn allow_entries_make_the_list_private() {
+        let mut list = AccessList::new();
+        assert_eq!(list.check(ip("192.0.2.1"), None), Ok(()));
+
+        list.allow_peer("alice");
+        assert!(list.is_private());
+        assert_eq!(list.check(ip("192.0.2.1"), Some("alice")), Ok(()));
+        assert_eq!(
+            list.check(ip("192.0.2.1"), Some("bob")),
+            Err(Denial::NotAllowed)
+        );
+        assert_eq!(list.check(ip("192.0.2.1"), None), Err(Denial::NotAllowed));
+    }
+
+    #[test]
+    fn only_peer_entries_need_the_sender() {
+        let mut list = AccessList::new();
+        list.deny_ip("10.0.0.0/8".parse().unwrap());
+        list.allow_ip("192.0.2.0/24".parse().unwrap());
+        assert!(!list.names_peers());
+
+        list.deny_peer("mallory");
+        assert!(list.names_peers());
+    }
+
+    #[test]
+    fn malformed_lines_are_reported() {
+        let error = "# ok\nallow peer a\npermit peer b"
+            .parse::<AccessList>()
+            .unwrap_err();
+        assert_eq!(error, "line 3: malformed entry 'permit peer b'");
+        assert!("deny ip 10.0.0.0/40".parse::<AccessList>().is_err());
+    }
+}
@@ -0,0 +1,313 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Batched datagram I/O.
+//!
+//! A server answering tens of thousands of registrations per second spends
+//! most of its time in system calls when every datagram takes one. On
+//! Linux, [`UdpTransport`] receives with `recvmmsg` and sends with
+//! `sendmmsg`, a whole batch per call. Other transports and systems fall
+//! back to one call per datagram, see [`Transport::recv_batch`] and
+//! [`Transport::send_batch`].
+//!
+//! [`UdpTransport`]: crate::transport::UdpTransport
+use std::{io, net::SocketAddr};
+
+use log::warn;
+
+use crate::{pool::BufferPool, protocol, transport::Transport};
+
+/// Default number of datagrams received or sent per call.
+pub const DEFAULT_BATCH_SIZE: usize = 32;
+
+/// Buffers receiving a batch of datagrams, see [`Transport::recv_batch`].
+#[derive(Debug)]
+pub struct RecvBatch {
+    bufs: Vec<Box<[u8]>>,
+    /// Buffer, length and sender of every datagram received.
+    received: Vec<(usize, usize, SocketAddr)>,
+}
+
+impl RecvBatch {
+    /// Room for `capacity` datagrams, at least one, each in a buffer of
+    /// [`protocol::RECV_BUFFER_SIZE`] bytes.
+    pub fn new(capacity: usize) -> Self {
+        RecvBatch {
+            bufs: (0..capacity.max(1))
+                .map(|_| vec![0; protocol::RECV_BUFFER_SIZE].into())
+                .collect(),
+            received: Vec::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.bufs.len()
+    }
+
+    /// Number of datagrams received.
+    pub fn len(&self) -> usize {
+        self.received.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.received.is_empty()
+    }
+
+    /// Forget the datagrams received, making room for a new batch.
+    pub fn clear(&mut self) {
+        self.received.clear();
+    }
+
+    /// The datagrams received with their sender, in order. A datagram
+    /// longer than [`protocol::MAX_MESSAGE_SIZE`] was truncated.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], SocketAddr)> {
+        self.received
+            .iter()
+            .map(|&(buf, len, from)| (&self.bufs[buf][..len], from))
+    }
+
+    /// Receive one more datagram with `recv`, given the next free buffer.
+    /// Does nothing when the batch is full.
+    pub fn receive_with<F>(&mut self, recv: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut [u8]) -> io::Result<(usize, SocketAddr)>,
+    {
+        let buf = self.received.len();
+        let Some(free) = self.bufs.get_mut(buf) else {
+            return Ok(());
+        };
+        let (len, from) = recv(free)?;
+        self.received.push((buf, len, from));
+        Ok(())
+    }
+
+    /// Replace the sender of every datagram with `f` of it.
+    pub fn map_senders(&mut self, f: impl Fn(SocketAddr) -> SocketAddr) {
+        for (_, _, from) in &mut self.received {
+            *from = f(*from);
+        }
+    }
+}
+
+/// Datagrams waiting to be sent together, see [`Transport::send_batch`].
+///
+/// Their buffers go back to the outbox's [`BufferPool`] once sent.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    datagrams: Vec<(Vec<u8>, SocketAddr)>,
+    pool: BufferPool,
+}
+
+impl Outbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An outbox recycling the buffers sent into `pool`.
+    pub fn with_pool(pool: BufferPool) -> Self {
+        Outbox { datagrams: Vec::new(), pool }
+    }
+
+    /// An empty buffer from the pool, to encode a datagram into.
+    pub fn buffer(&self) -> Vec<u8> {
+        self.pool.take()
+    }
+
+    /// Queue `datagram` for `to`.
+    pub fn push(&mut self, datagram: Vec<u8>, to: SocketAddr) {
+        self.datagrams.push((datagram, to));
+    }
+
+    /// Number of datagrams queued.
+    pub fn len(&self) -> usize {
+        self.datagrams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.datagrams.is_empty()
+    }
+
+    /// Send every queued datagram over `transport`, up to `batch_size` per
+    /// call, and return how many were sent. A datagram that can not be
+    /// sent is dropped, like UDP would.
+    pub fn flush<T: Transport>(
+        &mut self,
+        transport: &T,
+        batch_size: usize,
+    ) -> usize {
+        let mut sent = 0;
+        let mut next = 0;
+
+        while next < self.datagrams.len() {
+            let batch: Vec<(&[u8], SocketAddr)> = self.datagrams[next..]
+                .iter()
+                .take(batch_size.max(1))
+                .map(|(datagram, to)| (datagram.as_slice(), *to))
+                .collect();
+            match transport.send_batch(&batch) {
+                Ok(0) => next += 1,
+                Ok(n) => {
+                    sent += n;
+                    next += n;
+                }
+                Err(e) => {
+                    warn!("Failed to send to {}: {e}", batch[0].1);
+                    next += 1;
+                }
+            }
+        }
+
+        for (datagram, _) in self.datagrams.drain(..) {
+            self.pool.put(datagram);
+        }
+        sent
+    }
+}
+
+/// Send `datagrams` in order over `transport` one by one, see
+/// [`Transport::send_batch`].
+pub fn send_each<T: Transport + ?Sized>(
+    transport: &T,
+    datagrams: &[(&[u8], SocketAddr)],
+) -> io::Result<usize> {
+    for (sent, (datagram, to)) in datagrams.iter().enumerate() {
+        if let Err(e) = transport.send_to(datagram, *to) {
+            return if sent == 0 { Err(e) } else { Ok(sent) };
+        }
+    }
+    Ok(datagrams.len())
+}
+
+/// Receive into `batch` with a single `recvmmsg`, waiting for the first
+/// datagram only, and return how many were received.
+#[cfg(target_os = "linux")]
+pub(crate) fn recv_mmsg(
+    socket: &std::net::UdpSocket,
+    batch: &mut RecvBatch,
+) -> io::Result<usize> {
+    use std::{mem, os::fd::AsRawFd, ptr};
+
+    use socket2::{SockAddr, SockAddrStorage};
+
+    batch.clear();
+    let mut addrs: Vec<SockAddrStorage> =
+        batch.bufs.iter().map(|_| SockAddrStorage::zeroed()).collect();
+    let mut iovecs: Vec<libc::iovec> = batch
+        .bufs
+        .iter_mut()
+        .map(|buf| libc::iovec {
+            iov_base: buf.as_mut_ptr().cast(),
+            iov_len: buf.len(),
+        })
+        .collect();
+    let mut msgs: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .zip(&mut addrs)
+        .map(|(iovec, addr)| {
+            // SAFETY: all zeros is a valid, empty `msghdr`.
+            let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+            hdr.msg_name = (addr as *mut SockAddrStorage).cast();
+            hdr.msg_namelen = addr.size_of();
+            hdr.msg_iov = iovec;
+            hdr.msg_iovlen = 1;
+            libc::mmsghdr { msg_hdr: hdr, msg_len: 0 }
+        })
+        .collect();
+
+    // SAFETY: every header points to an address and a buffer of the
+    // length it gives, all alive until the call returns.
+    let received = unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            msgs.as_mut_ptr(),
+            msgs.len() as _,
+            libc::MSG_WAITFORONE as _,
+            ptr::null_mut(),
+        )
+    };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let lengths: Vec<_> = msgs
+        .iter()
+        .take(received as usize)
+        .map(|msg| (msg.msg_len as usize, msg.msg_hdr.msg_namelen))
+        .collect();
+    for (buf, ((len, namelen), addr)) in
+        lengths.into_iter().zip(addrs).enumerate()
+    {
+        // SAFETY: the kernel wrote an address of `namelen` bytes.
+        let addr = unsafe { SockAddr::new(addr, namelen) };
+        if let Some(from) = addr.as_socket() {
+            batch.received.push((buf, len, from));
+        }
+    }
+    Ok(batch.len())
+}
+
+/// Send `datagrams` with a single `sendmmsg` and return how many were
+/// sent, see [`Transport::send_batch`].
+#[cfg(target_os = "linux")]
+pub(crate) fn send_mmsg(
+    socket: &std::net::UdpSocket,
+    datagrams: &[(&[u8], SocketAddr)],
+) -> io::Result<usize> {
+    use std::{mem, os::fd::AsRawFd};
+
+    use socket2::SockAddr;
+
+    if datagrams.is_empty() {
+        return Ok(0);
+    }
+    let addrs: Vec<SockAddr> =
+        datagrams.iter().map(|(_, to)| SockAddr::from(*to)).collect();
+    let mut iovecs: Vec<libc::iovec> = datagrams
+        .iter()
+        .map(|(datagram, _)| libc::iovec {
+            iov_base: datagram.as_ptr().cast_mut().cast(),
+            iov_len: datagram.len(),
+        })
+        .collect();
+    let mut msgs: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .zip(&addrs)
+        .map(|(iovec, addr)| {
+            // SAFETY: all zeros is a valid, empty `msghdr`.
+            let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+            hdr.msg_name = addr.as_ptr().cast_mut().cast();
+            hdr.msg_namelen = addr.len();
+            hdr.msg_iov = iovec;
+            hdr.msg_iovlen = 1;
+            libc::mmsghdr { msg_hdr: hdr, msg_len: 0 }
+        })
+        .collect();
+
+    // SAFETY: every header points to an address and a datagram of the
+    // length it gives, all alive until the call returns. The kernel only
+    // reads them.
+    let sent = unsafe {
+        libc::sendmmsg(
+            socket.as_raw_fd(),
+            msgs.as_mut_ptr(),
+            msgs.len() as _,
+            0,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(sent as usize)
+}
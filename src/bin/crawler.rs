@@ -0,0 +1,267 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+use std::{
+    collections::HashMap,
+    fs,
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::info;
+use serde::Serialize;
+use tesseras::{
+    client::RendezvousClient,
+    dht,
+    fragment::{DEFAULT_MAX_PAYLOAD, FragmentingTransport},
+    node_id::NodeId,
+    protocol::{Contact, NetworkId, RendezvousMessage},
+    routing::random_id,
+    transport::UdpTransport,
+};
+
+/// Transport of the crawling client.
+type Crawler = FragmentingTransport<UdpTransport>;
+
+/// Default bound on the number of nodes crawled.
+const DEFAULT_MAX_NODES: usize = 10_000;
+
+/// Random targets asked of every node on top of its own id, to reach into
+/// its farther buckets.
+const RANDOM_TARGETS: usize = 8;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or("info"),
+    )
+    .format_timestamp(None)
+    .init();
+
+    let args = parse_args(std::env::args().skip(1))?;
+    if args.seeds.is_empty() {
+        return Err("usage: crawler [--network <name>] [--max-nodes <n>] \
+                    [--output <path>] <seed>..."
+            .into());
+    }
+
+    // Dual-stack where IPv6 is available, to reach nodes of both families.
+    let transport = UdpTransport::bind("[::]:0")
+        .or_else(|_| UdpTransport::bind("0.0.0.0:0"))?;
+    transport.socket().set_nonblocking(true)?;
+    // Nodes cut their larger replies into fragments.
+    let transport = FragmentingTransport::new(transport, DEFAULT_MAX_PAYLOAD);
+    // No rendezvous server is involved, nodes are queried directly.
+    let mut client = RendezvousClient::new(
+        transport,
+        SocketAddr::from(([0, 0, 0, 0], 0)),
+        String::new(),
+    );
+    client.set_network(args.network);
+
+    let nodes = crawl(&mut client, &args.seeds, args.max_nodes)?;
+    let snapshot = Snapshot {
+        network: args.network.to_string(),
+        crawled_at: unix_secs(SystemTime::now()),
+        answered: nodes.values().filter(|n| n.last_seen.is_some()).count(),
+        nodes: nodes.into_values().collect(),
+    };
+    info!(
+        "Found {} node(s), {} answered",
+        snapshot.nodes.len(),
+        snapshot.answered
+    );
+
+    let json = serde_json::to_string_pretty(&snapshot)?;
+    match args.output {
+        Some(path) => fs::write(path, json + "\n")?,
+        None => println!("{json}"),
+    }
+    Ok(())
+}
+
+/// Command line arguments.
+#[derive(Debug)]
+struct Args {
+    network: NetworkId,
+    max_nodes: usize,
+    output: Option<PathBuf>,
+    seeds: Vec<SocketAddr>,
+}
+
+/// Parse command line flags.
+///
+/// Every other argument is a seed address to start crawling from.
+///   --network <name>   network to crawl, the main one by default
+///   --max-nodes <n>    stop once this many nodes were found
+///   --output <path>    write the snapshot there instead of to stdout
+fn parse_args(
+    mut args: impl Iterator<Item = String>,
+) -> Result<Args, Box<dyn std::error::Error>> {
+    let mut parsed = Args {
+        network: NetworkId::MAIN,
+        max_nodes: DEFAULT_MAX_NODES,
+        output: None,
+        seeds: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value =
+            || args.next().ok_or_else(|| format!("missing value for {arg}"));
+
+        match arg.as_str() {
+            "--network" => parsed.network = value()?.parse()?,
+            "--max-nodes" => parsed.max_nodes = value()?.parse()?,
+            "--output" => parsed.output = Some(value()?.into()),
+            flag if flag.starts_with("--") => {
+                return Err(format!("unknown flag: {flag}").into());
+            }
+            seed => parsed.seeds.push(
+                seed.parse()
+                    .map_err(|e| format!("bad seed address {seed}: {e}"))?,
+            ),
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// What the crawl produced.
+#[derive(Serialize)]
+struct Snapshot {
+    network: String,
+    /// Seconds since the Unix epoch.
+    crawled_at: u64,
+    /// Nodes that answered a ping.
+    answered: usize,
+    nodes: Vec<Node>,
+}
+
+/// A node met during the crawl.
+#[derive(Serialize)]
+struct Node {
+    node_id: String,
+    addr: SocketAddr,
+    /// When it last answered, in seconds since the Unix epoch. `None` for
+    /// nodes only heard of from others.
+    last_seen: Option<u64>,
+    /// Number of nodes that returned it in their answers.
+    referrals: usize,
+}
+
+/// Walk the network from `seeds` in waves: ping the nodes found in the
+/// previous wave, then ask those that answered for the nodes they know
+/// closest to their own id and to a few random ids. Ends when a wave finds
+/// no new node or `max_nodes` were found.
+fn crawl(
+    client: &mut RendezvousClient<Crawler>,
+    seeds: &[SocketAddr],
+    max_nodes: usize,
+) -> Result<HashMap<[u8; 20], Node>, Box<dyn std::error::Error>> {
+    let mut nodes: HashMap<[u8; 20], Node> = HashMap::new();
+    let mut wave: Vec<SocketAddr> = seeds.to_vec();
+
+    while !wave.is_empty() {
+        let alive = dht::ping_all(client, &wave, &mut |_| None);
+        let now = unix_secs(SystemTime::now());
+        for contact in &alive {
+            let node = nodes.entry(contact.node_id).or_insert_with(|| Node {
+                node_id: NodeId::from(contact.node_id).to_string(),
+                addr: contact.addr,
+                last_seen: None,
+                referrals: 0,
+            });
+            node.last_seen = Some(now);
+        }
+        info!("Wave of {} node(s), {} answered", wave.len(), alive.len());
+
+        wave = Vec::new();
+        for contact in ask_around(client, &alive)? {
+            if let Some(node) = nodes.get_mut(&contact.node_id) {
+                node.referrals += 1;
+                continue;
+            }
+            if nodes.len() >= max_nodes {
+                continue;
+            }
+            nodes.insert(
+                contact.node_id,
+                Node {
+                    node_id: NodeId::from(contact.node_id).to_string(),
+                    addr: contact.addr,
+                    last_seen: None,
+                    referrals: 1,
+                },
+            );
+            wave.push(contact.addr);
+        }
+    }
+
+    Ok(nodes)
+}
+
+/// Send every node in `contacts` a `FindNode` for its own id and for
+/// [`RANDOM_TARGETS`] random ids at once, and return the contacts in the
+/// answers received in time, once per node returning them.
+fn ask_around(
+    client: &mut RendezvousClient<Crawler>,
+    contacts: &[Contact],
+) -> Result<Vec<Contact>, Box<dyn std::error::Error>> {
+    let mut waiting = Vec::new();
+    for contact in contacts {
+        let mut targets = vec![contact.node_id];
+        for _ in 0..RANDOM_TARGETS {
+            targets.push(random_id()?);
+        }
+        for target in targets {
+            let nonce = client.send_rpc(contact.addr, |nonce| {
+                RendezvousMessage::FindNode { nonce, target }
+            })?;
+            waiting.push((contact.addr, nonce));
+        }
+    }
+
+    let deadline = Instant::now() + dht::rpc_timeout(client, &waiting);
+    let mut found = Vec::new();
+    let mut referred = Vec::new();
+    while !waiting.is_empty() {
+        let Some(reply) =
+            client.recv_rpc(&waiting, deadline, &mut |_| None)?
+        else {
+            break;
+        };
+        let Some(nonce) = reply.rpc_nonce() else {
+            continue;
+        };
+        let Some(i) = waiting.iter().position(|&(_, n)| n == nonce) else {
+            continue;
+        };
+        let (from, _) = waiting.swap_remove(i);
+        if let RendezvousMessage::Nodes { contacts, .. } = reply {
+            for contact in contacts {
+                if !found.contains(&(from, contact.node_id)) {
+                    found.push((from, contact.node_id));
+                    referred.push(contact);
+                }
+            }
+        }
+    }
+    Ok(referred)
+}
+
+/// Seconds since the Unix epoch.
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
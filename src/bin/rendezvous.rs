@@ -14,155 +14,99 @@
 // OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
 //
 
-// https://en.wikipedia.org/wiki/Rendezvous_protocol
+use std::path::PathBuf;
 
-use std::{
-    collections::HashMap,
-    net::{SocketAddr, UdpSocket},
-    time::{Duration, SystemTime},
+use tesseras::{
+    config::{Settings, Source},
+    server::{RendezvousServer, ServerConfig},
 };
 
-use bincode::{Decode, Encode};
-use log::{debug, error, info};
-use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
-pub struct PeerInfo {
-    peer_id: String,
-    public_addr: SocketAddr,
-    private_addr: Option<SocketAddr>,
-    last_seen: SystemTime,
-}
-
-#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
-pub enum RendezvousMessage {
-    Register { peer_id: String, private_addr: SocketAddr },
-    Query { target_peer_id: String },
-    PeerInfo { peer: PeerInfo },
-    InitiateConnection { from_peer_id: String, to_peer_id: String },
-}
-
-/// RendezvousServer
-///
-/// A rendezvous protocol is a computer network protocol that enables resources
-/// or P2P network peers to find each other. A rendezvous protocol uses a
-/// handshaking model, unlike an eager protocol which directly copies the data
-pub struct RendezvousServer {
-    socket: UdpSocket,
-    peers: HashMap<String, PeerInfo>,
-}
-
-impl RendezvousServer {
-    pub fn new(bind_addr: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let socket = UdpSocket::bind(bind_addr)?;
-        socket.set_nonblocking(true)?;
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::builder().format_timestamp(None).init();
 
-        info!("Server Rendezvous Listening on {}", bind_addr);
+    let args = parse_args(std::env::args().skip(1))?;
 
-        Ok(RendezvousServer { socket, peers: HashMap::new() })
+    let mut settings = Settings::new();
+    let config_path = args
+        .config_path
+        .or_else(|| std::env::var_os("TESSERAS_CONFIG").map(PathBuf::from));
+    if let Some(path) = &config_path {
+        settings.load_file(path, ServerConfig::KEYS)?;
     }
-
-    pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let config = bincode::config::standard();
-        let mut buf = [0u8; 65536];
-
-        loop {
-            match self.socket.recv_from(&mut buf) {
-                Ok((len, peer_addr)) => {
-                    if let Ok((msg, _)) =
-                        bincode::decode_from_slice(&buf[..len], config)
-                    {
-                        self.handle_message(msg, peer_addr)?;
-                    }
-                }
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    std::thread::sleep(Duration::from_millis(10));
-                }
-                Err(e) => error!("Erro: {}", e),
-            }
-        }
+    settings.load_env(ServerConfig::KEYS, std::env::vars());
+    for (key, value) in args.settings {
+        settings.set(&key, value, Source::Cli);
     }
 
-    fn handle_message(
-        &mut self,
-        msg: RendezvousMessage,
-        from: SocketAddr,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let config = bincode::config::standard();
-        match msg {
-            RendezvousMessage::Register { peer_id, private_addr } => {
-                debug!(
-                    "Peer {} registrado: público={}, privado={}",
-                    peer_id, from, private_addr
-                );
-
-                self.peers.insert(
-                    peer_id.clone(),
-                    PeerInfo {
-                        peer_id,
-                        public_addr: from, // Address stun
-                        private_addr: Some(private_addr),
-                        last_seen: SystemTime::now(),
-                    },
-                );
-            }
-
-            RendezvousMessage::Query { target_peer_id } => {
-                if let Some(peer_info) = self.peers.get(&target_peer_id) {
-                    let response = RendezvousMessage::PeerInfo {
-                        peer: peer_info.clone(),
-                    };
+    let mut config = ServerConfig::default();
+    for (key, value, source) in settings.iter() {
+        config
+            .set(key, value)
+            .map_err(|e| format!("invalid {key} '{value}' ({source}): {e}"))?;
+    }
 
-                    self.socket.send_to(
-                        &bincode::encode_to_vec(&response, config)?,
-                        from,
-                    )?;
-                }
-            }
+    if args.print_config {
+        print_config(&config, &settings);
+        return Ok(());
+    }
 
-            RendezvousMessage::InitiateConnection {
-                from_peer_id,
-                to_peer_id,
-            } => {
-                // Notify peers
-                if let (Some(from_peer), Some(to_peer)) = (
-                    self.peers.get(&from_peer_id),
-                    self.peers.get(&to_peer_id),
-                ) {
-                    // Send info from B to A
-                    let msg_to_a =
-                        RendezvousMessage::PeerInfo { peer: to_peer.clone() };
-                    self.socket.send_to(
-                        &bincode::encode_to_vec(&msg_to_a, config)?,
-                        from_peer.public_addr,
-                    )?;
+    let mut server = RendezvousServer::with_config(config)?;
+    server.run()
+}
 
-                    // Send info from A to B
-                    let msg_to_b = RendezvousMessage::PeerInfo {
-                        peer: from_peer.clone(),
-                    };
-                    self.socket.send_to(
-                        &bincode::encode_to_vec(&msg_to_b, config)?,
-                        to_peer.public_addr,
-                    )?;
+/// Command line arguments.
+#[derive(Debug, Default)]
+struct Args {
+    config_path: Option<PathBuf>,
+    print_config: bool,
+    /// Settings given as flags, see [`ServerConfig::KEYS`].
+    settings: Vec<(String, String)>,
+}
 
-                    debug!(
-                        "Iniciando hole punching: {} <-> {}",
-                        from_peer_id, to_peer_id
-                    );
-                }
+/// Parse command line flags.
+///
+/// Every setting in [`ServerConfig::KEYS`] is accepted as a flag with
+/// dashes instead of underscores, e.g. `--peer-ttl 300`. Switches such as
+/// `--relay` take no value. Besides those:
+///   --config <path>    TOML file with settings, also `TESSERAS_CONFIG`
+///   --print-config     show the effective configuration and exit
+fn parse_args(
+    mut args: impl Iterator<Item = String>,
+) -> Result<Args, Box<dyn std::error::Error>> {
+    let mut parsed = Args::default();
+
+    while let Some(flag) = args.next() {
+        let mut value =
+            || args.next().ok_or_else(|| format!("missing value for {flag}"));
+
+        match flag.as_str() {
+            "--config" => parsed.config_path = Some(value()?.into()),
+            "--print-config" => parsed.print_config = true,
+            _ => {
+                let key = flag
+                    .strip_prefix("--")
+                    .map(|k| k.replace('-', "_"))
+                    .filter(|k| ServerConfig::KEYS.contains(&k.as_str()))
+                    .ok_or_else(|| format!("unknown flag: {flag}"))?;
+
+                let value = if ServerConfig::SWITCHES.contains(&key.as_str()) {
+                    "true".to_string()
+                } else {
+                    value()?
+                };
+                parsed.settings.push((key, value));
             }
-
-            _ => {}
         }
-
-        Ok(())
     }
-}
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::builder().format_timestamp(None).init();
+    Ok(parsed)
+}
 
-    let mut server = RendezvousServer::new("0.0.0.0:8000")?;
-    server.run()
+/// Print every setting with its effective value and where it came from.
+fn print_config(config: &ServerConfig, settings: &Settings) {
+    for key in ServerConfig::KEYS {
+        let source = settings.get(key).map_or(Source::Default, |(_, s)| s);
+        let value = config.get(key).unwrap_or_default();
+        println!("{key:<20} = {value:<24} ({source})");
+    }
 }
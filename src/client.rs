@@ -0,0 +1,2007 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Rendezvous client.
+//!
+//! Besides plain requests, the client can drive a hole punching attempt
+//! towards another peer, see [`RendezvousClient::connect`] and
+//! [`RendezvousClient::drive`]. Both peers are expected to connect to each
+//! other at about the same time so their probes open each side's NAT.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, info, warn};
+
+use crate::{
+    keepalive::{Keepalive, PeerKeepalives},
+    pins::{PinError, PinStore},
+    protocol::{self, NetworkId, PeerInfo, RendezvousMessage},
+    rpc::{Backoff, Failures, RttEstimate, RttEstimates, Transaction},
+    transport::Transport,
+};
+
+/// How long to wait for the server's introduction before asking again.
+const INITIATE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of introduction requests sent before giving up.
+const MAX_INITIATE_ATTEMPTS: u32 = 3;
+
+/// Time between rounds of hole punching probes.
+const PROBE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long to probe before giving up on a peer.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for a path set up by the transport before giving up
+/// on a peer, see [`ConnectionState::Negotiating`].
+const NEGOTIATE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Default number of requests in flight at once, Kademlia's alpha.
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 3;
+
+/// How long an unanswered request holds its in-flight slot. Some requests,
+/// like a query for an unknown peer, are never answered.
+const IN_FLIGHT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a DHT request sent for the last time is kept for its caller
+/// to give up on it, see [`RendezvousClient::give_up`]. Requests left
+/// longer count as failed.
+const TRANSACTION_LINGER: Duration = Duration::from_secs(10);
+
+/// Progress of a hole punching attempt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Registered with the server, introduction not requested yet.
+    Registering,
+    /// Waiting for the server to send the target's addresses.
+    Initiating,
+    /// Got the target's addresses, probes not sent yet.
+    Exchanging {
+        candidates: Vec<SocketAddr>,
+    },
+    /// Probing the candidate addresses until one answers.
+    Probing {
+        candidates: Vec<SocketAddr>,
+    },
+    /// No candidate answered and the transport is setting up another path
+    /// to the target, see [`Transport::negotiate`]. Probing `addr` until
+    /// the probes get through it.
+    Negotiating {
+        addr: SocketAddr,
+    },
+    /// The target answered from this address.
+    Connected(SocketAddr),
+    Failed(String),
+}
+
+impl ConnectionState {
+    /// Whether the attempt is over, successfully or not.
+    pub fn is_done(&self) -> bool {
+        matches!(
+            self,
+            ConnectionState::Connected(_) | ConnectionState::Failed(_)
+        )
+    }
+}
+
+/// A hole punching attempt towards `target`.
+#[derive(Debug)]
+struct Connection {
+    target: String,
+    state: ConnectionState,
+    /// When the current state was entered.
+    entered: Instant,
+    /// When the last introduction request or round of probes was sent.
+    last_sent: Instant,
+    attempts: u32,
+}
+
+/// Kind of request awaiting a reply from the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+    Query,
+    InitiateConnection,
+    ListPeers,
+}
+
+/// A request sent to the server that has not been answered yet.
+#[derive(Debug, Clone)]
+pub struct PendingRequest {
+    pub kind: RequestKind,
+    /// Target peer id, or the comma separated capabilities for
+    /// [`RequestKind::ListPeers`].
+    pub target: String,
+    /// When the request was first sent.
+    pub sent_at: Instant,
+    /// When the request was last sent, first time or retry.
+    pub last_sent: Instant,
+    /// How many times the request was sent again while still pending.
+    pub retries: u32,
+    /// Nonce reused by every retry, so the server can spot duplicates.
+    pub nonce: u64,
+}
+
+/// Outcome of the version handshake with the server, see
+/// [`RendezvousClient::register`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Negotiation {
+    /// No answer to the [`RendezvousMessage::Hello`] yet.
+    Pending,
+    /// Both ends speak this version.
+    Agreed(u8),
+    /// The server speaks none of our versions, only these.
+    Refused { min_version: u8, max_version: u8 },
+}
+
+/// Outcome of pinging a peer, see [`RendezvousClient::ping_peers`].
+#[derive(Debug, Clone)]
+pub struct PingResult {
+    pub peer: PeerInfo,
+    /// Round trip time, `None` if the peer did not answer in time.
+    pub rtt: Option<Duration>,
+}
+
+/// RendezvousClient
+///
+/// Talks to a rendezvous server on behalf of a single peer: registers the
+/// peer, looks up other peers and asks the server to introduce two peers
+/// for hole punching.
+pub struct RendezvousClient<T: Transport> {
+    transport: T,
+    server_addr: SocketAddr,
+    peer_id: String,
+    capabilities: Vec<String>,
+    candidates: Vec<SocketAddr>,
+    /// Address each peer last answered a probe at, probed first next time
+    /// so the address family that works is preferred.
+    reached: HashMap<String, SocketAddr>,
+    pending: HashMap<(RequestKind, String), PendingRequest>,
+    /// Requests waiting for an in-flight slot, oldest first.
+    queued: VecDeque<(RequestKind, String)>,
+    max_in_flight: usize,
+    next_nonce: u64,
+    connection: Option<Connection>,
+    /// Private address sent in the last registration, reused when
+    /// registering again after a server restart.
+    private_addr: Option<SocketAddr>,
+    /// Server epoch from the last heartbeat acknowledgement.
+    server_epoch: Option<u64>,
+    /// Identity keys pinned on first use.
+    pins: PinStore,
+    /// Heartbeat schedule, `None` when heartbeats are sent by hand.
+    keepalive: Option<Keepalive>,
+    /// Keep-alive schedule of the peers in use, `None` when they are not
+    /// kept alive.
+    peer_keepalives: Option<PeerKeepalives>,
+    /// Whether a [`RendezvousMessage::Redirect`] was already followed.
+    redirected: bool,
+    /// Network every message is sent from, messages of other networks are
+    /// dropped.
+    network: NetworkId,
+    /// DHT notices received and not taken yet, see [`Self::take_notices`].
+    notices: Vec<(RendezvousMessage, SocketAddr)>,
+    /// Outcome of the version handshake with the server.
+    negotiation: Negotiation,
+    /// Retransmission schedule of requests, see [`crate::rpc`], started
+    /// from the measured round trip time where known.
+    backoff: Backoff,
+    /// Round trip times measured per destination.
+    rtts: RttEstimates,
+    /// DHT requests awaiting their reply, by destination and nonce.
+    transactions: HashMap<(SocketAddr, u64), Transaction>,
+    /// Failed requests in a row per destination.
+    failures: Failures,
+    /// Destinations declared dead and not taken yet, see
+    /// [`Self::take_dead`].
+    dead: Vec<SocketAddr>,
+    /// When the last heartbeat was sent, until it is acknowledged.
+    heartbeat_sent: Option<Instant>,
+}
+
+impl<T: Transport> RendezvousClient<T> {
+    pub fn new(
+        transport: T,
+        server_addr: SocketAddr,
+        peer_id: String,
+    ) -> Self {
+        RendezvousClient {
+            transport,
+            server_addr,
+            peer_id,
+            capabilities: Vec::new(),
+            candidates: Vec::new(),
+            reached: HashMap::new(),
+            pending: HashMap::new(),
+            queued: VecDeque::new(),
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            // Seeded from the clock so a restarted client does not reuse
+            // the nonces of its previous run.
+            next_nonce: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or_default(),
+            connection: None,
+            private_addr: None,
+            server_epoch: None,
+            pins: PinStore::new(),
+            keepalive: None,
+            peer_keepalives: None,
+            redirected: false,
+            network: NetworkId::MAIN,
+            notices: Vec::new(),
+            negotiation: Negotiation::Pending,
+            backoff: Backoff::default(),
+            rtts: RttEstimates::default(),
+            transactions: HashMap::new(),
+            failures: Failures::default(),
+            dead: Vec::new(),
+            heartbeat_sent: None,
+        }
+    }
+
+    /// Join `network` instead of the main one. Set it before registering.
+    pub fn set_network(&mut self, network: NetworkId) {
+        self.network = network;
+    }
+
+    pub fn network(&self) -> NetworkId {
+        self.network
+    }
+
+    /// Use `pins`, e.g. loaded with [`PinStore::open`], as the identity
+    /// pin store. Pins are kept in memory only by default.
+    pub fn set_pins(&mut self, pins: PinStore) {
+        self.pins = pins;
+    }
+
+    pub fn pins(&self) -> &PinStore {
+        &self.pins
+    }
+
+    /// Take the identity pin store back, e.g. to hand it to the client
+    /// replacing this one, leaving an empty one in its place.
+    pub fn take_pins(&mut self) -> PinStore {
+        std::mem::take(&mut self.pins)
+    }
+
+    /// Check the identity key a signed message from `peer_id` was signed
+    /// with against the pinned one, pinning it on first use.
+    ///
+    /// Must be called for every signed message before acting on it, see
+    /// [`crate::dht::departed`]; a
+    /// [`PinError::IdentityMismatch`] means the message must be dropped.
+    /// Returns whether the key was newly pinned.
+    pub fn verify_identity(
+        &mut self,
+        peer_id: &str,
+        key: &[u8],
+    ) -> Result<bool, PinError> {
+        self.pins.verify(peer_id, key)
+    }
+
+    /// Forget the identity key pinned for `peer_id`, e.g. after the peer
+    /// legitimately rotated it. Returns whether a pin was removed.
+    pub fn reset_pin(&mut self, peer_id: &str) -> Result<bool, PinError> {
+        self.pins.reset(peer_id)
+    }
+
+    /// Send at most `max` queries, introductions and listings at once,
+    /// queueing the rest until replies free a slot. A request unanswered
+    /// for a while stops holding its slot.
+    pub fn set_max_in_flight(&mut self, max: usize) {
+        self.max_in_flight = max.max(1);
+    }
+
+    /// Capability tags advertised on the next [`Self::register`], along
+    /// with those of the transport, see [`Transport::capabilities`].
+    pub fn set_capabilities(&mut self, capabilities: Vec<String>) {
+        self.capabilities = capabilities;
+    }
+
+    /// Addresses in other address families advertised on the next
+    /// [`Self::register`], see [`PeerInfo::candidates`].
+    pub fn set_candidates(&mut self, candidates: Vec<SocketAddr>) {
+        self.candidates = candidates;
+    }
+
+    /// Private address sent in the last [`Self::register`].
+    pub fn private_addr(&self) -> Option<SocketAddr> {
+        self.private_addr
+    }
+
+    pub fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    pub fn server_addr(&self) -> SocketAddr {
+        self.server_addr
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Register this peer, advertising `private_addr` as its LAN address.
+    ///
+    /// The registration is preceded by a [`RendezvousMessage::Hello`], see
+    /// [`Self::negotiation`].
+    pub fn register(
+        &mut self,
+        private_addr: SocketAddr,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.private_addr = Some(private_addr);
+        let nonce = self.fresh_nonce();
+        self.send(&RendezvousMessage::Hello {
+            nonce,
+            min_version: protocol::MIN_PROTOCOL_VERSION,
+            max_version: protocol::PROTOCOL_VERSION,
+        })?;
+        let mut capabilities = self.capabilities.clone();
+        for tag in self.transport.capabilities() {
+            if !capabilities.contains(&tag) {
+                capabilities.push(tag);
+            }
+        }
+        let nonce = self.fresh_nonce();
+        self.send(&RendezvousMessage::Register {
+            nonce,
+            peer_id: self.peer_id.clone(),
+            private_addr,
+            capabilities,
+            candidates: self.candidates.clone(),
+        })
+    }
+
+    /// Retransmit requests following `backoff` instead of the default.
+    pub fn set_backoff(&mut self, backoff: Backoff) {
+        self.backoff = backoff;
+    }
+
+    /// How long to wait for the reply to a request to `to`, every
+    /// retransmission included, after the round trip times measured to it.
+    pub fn rpc_timeout(&self, to: SocketAddr) -> Duration {
+        self.rtts.backoff(self.backoff, to).timeout()
+    }
+
+    /// Round trip time measured to `addr`, if any.
+    pub fn rtt(&self, addr: SocketAddr) -> Option<RttEstimate> {
+        self.rtts.get(addr)
+    }
+
+    /// Declare a destination dead after `threshold` failed requests in a
+    /// row instead of [`crate::rpc::DEFAULT_DEAD_AFTER`].
+    pub fn set_dead_after(&mut self, threshold: u32) {
+        self.failures = Failures::new(threshold);
+    }
+
+    /// Tell the server this peer is alive.
+    ///
+    /// Call periodically, or let [`Self::set_keepalive`] do it. When the
+    /// acknowledgement carries a different server epoch than the previous
+    /// one, the server has restarted and lost its registrations, so the
+    /// client registers again and resends its pending requests. A
+    /// heartbeat still unacknowledged when the next one is sent counts as
+    /// a failed request to the server.
+    pub fn heartbeat(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.heartbeat_sent.is_some() {
+            self.transport.timed_out(self.server_addr);
+            self.fail(self.server_addr);
+        }
+        self.heartbeat_sent = Some(Instant::now());
+        self.send(&RendezvousMessage::Heartbeat {
+            peer_id: self.peer_id.clone(),
+        })
+    }
+
+    /// Send heartbeats on the `keepalive` schedule whenever the client
+    /// receives. `None` stops them.
+    pub fn set_keepalive(&mut self, keepalive: Option<Keepalive>) {
+        self.keepalive = keepalive;
+    }
+
+    /// Send the peers in use a [`RendezvousMessage::Heartbeat`] on the
+    /// `keepalives` schedule whenever the client receives, holding the NAT
+    /// mappings towards them open. `None` stops them.
+    pub fn set_peer_keepalive(&mut self, keepalives: Option<PeerKeepalives>) {
+        self.peer_keepalives = keepalives;
+    }
+
+    /// Number of peers currently kept alive.
+    pub fn kept_alive(&self) -> usize {
+        self.peer_keepalives.as_ref().map_or(0, PeerKeepalives::len)
+    }
+
+    /// When the next scheduled heartbeat is due, if any.
+    pub fn next_heartbeat(&self) -> Option<Instant> {
+        self.keepalive.as_ref().map(Keepalive::next_heartbeat)
+    }
+
+    /// Ask the server for its counters, answered with
+    /// [`RendezvousMessage::Stats`].
+    pub fn request_stats(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.send(&RendezvousMessage::GetStats)
+    }
+
+    /// Outcome of the version handshake with the server.
+    pub fn negotiation(&self) -> &Negotiation {
+        &self.negotiation
+    }
+
+    /// Server epoch seen in the last heartbeat acknowledgement.
+    pub fn server_epoch(&self) -> Option<u64> {
+        self.server_epoch
+    }
+
+    /// Ask the server for the addresses of `target_peer_id`.
+    pub fn query(
+        &mut self,
+        target_peer_id: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.submit(RequestKind::Query, target_peer_id.to_string())
+    }
+
+    /// Ask the server to introduce this peer and `to_peer_id` to each
+    /// other.
+    pub fn initiate_connection(
+        &mut self,
+        to_peer_id: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.submit(RequestKind::InitiateConnection, to_peer_id.to_string())
+    }
+
+    /// Ask the server for peers advertising all of `capabilities`.
+    pub fn list_peers(
+        &mut self,
+        capabilities: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.submit(RequestKind::ListPeers, capabilities.join(","))
+    }
+
+    /// Ask the server to forward `payload` to `to_peer_id`.
+    ///
+    /// Only works when the server has relaying enabled.
+    pub fn relay(
+        &self,
+        to_peer_id: &str,
+        payload: Vec<u8>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.send(&RendezvousMessage::Relay {
+            to_peer_id: to_peer_id.to_string(),
+            payload,
+            hops: protocol::DEFAULT_HOPS,
+        })
+    }
+
+    /// Start hole punching towards `to_peer_id`: register, advertising
+    /// `private_addr`, then call [`Self::drive`] until the state is done.
+    ///
+    /// Replaces any attempt already in progress.
+    pub fn connect(
+        &mut self,
+        to_peer_id: &str,
+        private_addr: SocketAddr,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.register(private_addr)?;
+
+        let now = Instant::now();
+        self.connection = Some(Connection {
+            target: to_peer_id.to_string(),
+            state: ConnectionState::Registering,
+            entered: now,
+            last_sent: now,
+            attempts: 0,
+        });
+        Ok(())
+    }
+
+    /// State of the current hole punching attempt, if any.
+    pub fn connection_state(&self) -> Option<&ConnectionState> {
+        self.connection.as_ref().map(|c| &c.state)
+    }
+
+    /// Advance the hole punching attempt by at most one step, then handle
+    /// every queued datagram. Probes from other peers are answered even
+    /// when no attempt is in progress.
+    ///
+    /// Meant to be called repeatedly from an event loop. Messages other
+    /// than the server's introduction and probes are discarded.
+    pub fn drive(
+        &mut self,
+    ) -> Result<Option<&ConnectionState>, Box<dyn std::error::Error>> {
+        self.advance(Instant::now())?;
+
+        while let Some((msg, from)) = self.recv_any()? {
+            self.on_connection_message(msg, from)?;
+        }
+
+        Ok(self.connection_state())
+    }
+
+    /// Probe every peer in `peers` and measure how long each takes to
+    /// answer, returning one result per peer in input order.
+    ///
+    /// At most `concurrency` peers are probed at a time and each gets
+    /// `timeout` to answer; peers still unanswered, or not probed yet, once
+    /// `deadline` has passed get `None`. Probes from other peers received
+    /// meanwhile are answered, everything else is discarded.
+    pub fn ping_peers(
+        &mut self,
+        peers: Vec<PeerInfo>,
+        concurrency: usize,
+        timeout: Duration,
+        deadline: Duration,
+    ) -> Result<Vec<PingResult>, Box<dyn std::error::Error>> {
+        let start = Instant::now();
+        let mut rtts: Vec<Option<Duration>> = vec![None; peers.len()];
+        // Index into `peers` and send time of every unanswered probe.
+        let mut in_flight: Vec<(usize, Instant)> = Vec::new();
+        let mut next = 0;
+
+        while start.elapsed() < deadline
+            && (next < peers.len() || !in_flight.is_empty())
+        {
+            while in_flight.len() < concurrency.max(1) && next < peers.len() {
+                let reached = self.reached.get(&peers[next].peer_id).copied();
+                self.probe(&candidates(&peers[next], reached), false)?;
+                in_flight.push((next, Instant::now()));
+                next += 1;
+            }
+
+            let mut received = false;
+            while let Some((msg, from)) = self.recv_any()? {
+                received = true;
+                let RendezvousMessage::Probe { from_peer_id, ack } = msg
+                else {
+                    continue;
+                };
+                if !ack {
+                    self.probe(&[from], true)?;
+                    continue;
+                }
+                if let Some(pos) = in_flight
+                    .iter()
+                    .position(|(i, _)| peers[*i].peer_id == from_peer_id)
+                {
+                    let (i, sent) = in_flight.swap_remove(pos);
+                    rtts[i] = Some(sent.elapsed());
+                    self.reached.insert(from_peer_id, from);
+                }
+            }
+
+            in_flight.retain(|(_, sent)| sent.elapsed() < timeout);
+            if !received {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        }
+
+        Ok(peers
+            .into_iter()
+            .zip(rtts)
+            .map(|(peer, rtt)| PingResult { peer, rtt })
+            .collect())
+    }
+
+    /// Send the DHT request built by `request` from a fresh nonce to `to`
+    /// and wait up to `timeout` for the reply echoing that nonce, sending
+    /// the request again meanwhile, see [`crate::rpc`].
+    ///
+    /// DHT requests from other nodes received meanwhile are handed to
+    /// `serve`, and its answer, if any, is sent back. Probes are answered
+    /// and everything else is discarded. Returns `None` on timeout, which
+    /// counts as a failure of `to`.
+    pub fn call<F>(
+        &mut self,
+        to: SocketAddr,
+        request: F,
+        timeout: Duration,
+        serve: &mut dyn FnMut(&RendezvousMessage) -> Option<RendezvousMessage>,
+    ) -> Result<Option<RendezvousMessage>, Box<dyn std::error::Error>>
+    where
+        F: FnOnce(u64) -> RendezvousMessage,
+    {
+        let nonce = self.send_rpc(to, request)?;
+        let reply =
+            self.recv_rpc(&[(to, nonce)], Instant::now() + timeout, serve)?;
+        if reply.is_none() {
+            self.give_up(to, nonce);
+        }
+        Ok(reply)
+    }
+
+    /// Send the DHT request built by `request` from a fresh nonce to `to`
+    /// without waiting for the reply, and return the nonce.
+    ///
+    /// The reply is collected with [`RendezvousClient::recv_rpc`], so
+    /// several requests can be in flight at once. Until it arrives the
+    /// request is sent again following the backoff, and a caller that
+    /// stops waiting should say so with [`RendezvousClient::give_up`].
+    pub fn send_rpc<F>(
+        &mut self,
+        to: SocketAddr,
+        request: F,
+    ) -> Result<u64, Box<dyn std::error::Error>>
+    where
+        F: FnOnce(u64) -> RendezvousMessage,
+    {
+        let nonce = self.fresh_nonce();
+        let payload = protocol::encode(self.network, &request(nonce))?;
+        self.transport.send_to(&payload, to)?;
+        if let Some(keepalives) = self.peer_keepalives.as_mut() {
+            let now = Instant::now();
+            keepalives.active(to, now);
+            keepalives.sent(to, now);
+        }
+        self.transactions.insert(
+            (to, nonce),
+            Transaction::new(to, nonce, payload, Instant::now()),
+        );
+        Ok(nonce)
+    }
+
+    /// Stop waiting for the reply to the DHT request sent to `to` with
+    /// `nonce`, counting it as a failure of `to`.
+    pub fn give_up(&mut self, to: SocketAddr, nonce: u64) {
+        self.transactions.remove(&(to, nonce));
+        self.transport.timed_out(to);
+        self.fail(to);
+    }
+
+    /// Take the destinations declared dead so far, after leaving
+    /// [`crate::rpc::DEFAULT_DEAD_AFTER`] requests in a row unanswered.
+    /// The server is one of them when its heartbeats go unacknowledged.
+    pub fn take_dead(&mut self) -> Vec<SocketAddr> {
+        std::mem::take(&mut self.dead)
+    }
+
+    /// Count a failed request to `addr`, declaring it dead once too many
+    /// failed in a row.
+    fn fail(&mut self, addr: SocketAddr) {
+        if self.failures.record_failure(addr) {
+            info!(
+                "{addr} left {} requests in a row unanswered, declaring it \
+                 dead",
+                self.failures.threshold()
+            );
+            self.dead.push(addr);
+            if let Some(keepalives) = self.peer_keepalives.as_mut() {
+                keepalives.remove(addr);
+            }
+        }
+    }
+
+    /// Send a [`RendezvousMessage::Heartbeat`] to the peers owed a
+    /// keep-alive at `now`.
+    fn keep_peers_alive(&mut self, now: Instant) {
+        let Some(keepalives) = self.peer_keepalives.as_mut() else {
+            return;
+        };
+        let due = keepalives.due(now);
+        if due.is_empty() {
+            return;
+        }
+        let heartbeat =
+            RendezvousMessage::Heartbeat { peer_id: self.peer_id.clone() };
+        for addr in due {
+            if let Err(e) = self.send_to(&heartbeat, addr) {
+                debug!("Could not keep {addr} alive: {e}");
+            }
+        }
+    }
+
+    /// Send again the requests whose reply is overdue: DHT requests and
+    /// requests to the server, see [`crate::rpc`].
+    fn retransmit(&mut self, now: Instant) {
+        for transaction in self.transactions.values_mut() {
+            let backoff = self.rtts.backoff(self.backoff, transaction.to);
+            if !transaction.is_due(&backoff, now) {
+                continue;
+            }
+            if let Err(e) =
+                self.transport.send_to(&transaction.payload, transaction.to)
+            {
+                debug!(
+                    "Could not resend a request to {}: {e}",
+                    transaction.to
+                );
+            }
+            transaction.resent(now);
+        }
+
+        let abandoned: Vec<(SocketAddr, u64)> = self
+            .transactions
+            .values()
+            .filter(|t| now >= t.last_sent + TRANSACTION_LINGER)
+            .map(|t| (t.to, t.nonce))
+            .collect();
+        for (to, nonce) in abandoned {
+            self.give_up(to, nonce);
+        }
+
+        let backoff = self.rtts.backoff(self.backoff, self.server_addr);
+        let mut due: Vec<&PendingRequest> = self
+            .pending
+            .values()
+            .filter(|p| {
+                backoff
+                    .delay(p.retries + 1)
+                    .is_some_and(|delay| now >= p.last_sent + delay)
+            })
+            .collect();
+        due.sort_by_key(|p| p.sent_at);
+        let due: Vec<(RequestKind, String)> =
+            due.into_iter().map(|p| (p.kind, p.target.clone())).collect();
+        for (kind, target) in due {
+            let nonce = self.track(kind, target.clone());
+            let msg = self.request_message(kind, target, nonce);
+            if let Err(e) = self.send(&msg) {
+                debug!("Could not resend a request to the server: {e}");
+            }
+        }
+    }
+
+    /// Wait until `deadline` for the reply to one of the DHT requests in
+    /// `waiting`, each given as the address and nonce it was sent with.
+    ///
+    /// Other messages are handled like in [`RendezvousClient::call`].
+    /// Returns `None` at the deadline.
+    pub fn recv_rpc(
+        &mut self,
+        waiting: &[(SocketAddr, u64)],
+        deadline: Instant,
+        serve: &mut dyn FnMut(&RendezvousMessage) -> Option<RendezvousMessage>,
+    ) -> Result<Option<RendezvousMessage>, Box<dyn std::error::Error>> {
+        loop {
+            while let Some((msg, from)) = self.recv_any()? {
+                if msg.is_rpc_request() {
+                    if let Some(reply) = serve(&msg) {
+                        self.transport.send_to(
+                            &protocol::encode(self.network, &reply)?,
+                            from,
+                        )?;
+                        if let Some(keepalives) = self.peer_keepalives.as_mut()
+                        {
+                            keepalives.sent(from, Instant::now());
+                        }
+                    }
+                    continue;
+                }
+                match msg {
+                    RendezvousMessage::Probe { ack: false, .. } => {
+                        self.probe(&[from], true)?;
+                    }
+                    msg if msg.rpc_nonce().is_some_and(|nonce| {
+                        waiting.contains(&(from, nonce))
+                    }) =>
+                    {
+                        return Ok(Some(msg));
+                    }
+                    _ => {}
+                }
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Take the DHT notices received so far, such as
+    /// [`RendezvousMessage::Leave`], with the address each came from.
+    ///
+    /// Notices expect no answer, so they are kept aside whichever call
+    /// received them, oldest first.
+    pub fn take_notices(&mut self) -> Vec<(RendezvousMessage, SocketAddr)> {
+        std::mem::take(&mut self.notices)
+    }
+
+    /// Requests still waiting for a reply, oldest first.
+    pub fn pending(&self) -> Vec<&PendingRequest> {
+        let mut pending: Vec<_> = self.pending.values().collect();
+        pending.sort_by_key(|p| p.sent_at);
+        pending
+    }
+
+    /// Number of requests waiting for an in-flight slot.
+    pub fn queued(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// Forget every pending and queued request and return how many were
+    /// dropped.
+    pub fn clear_pending(&mut self) -> usize {
+        let count = self.pending.len() + self.queued.len();
+        self.pending.clear();
+        self.queued.clear();
+        count
+    }
+
+    /// Wait up to `timeout` for a message from the server.
+    pub fn recv_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<RendezvousMessage>, Box<dyn std::error::Error>> {
+        let start = Instant::now();
+
+        loop {
+            if let Some(msg) = self.recv()? {
+                return Ok(Some(msg));
+            }
+            if start.elapsed() >= timeout {
+                self.transport.timed_out(self.server_addr);
+                return Ok(None);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Ask the server listening at `to`, its main or secondary port, which
+    /// address our datagrams come from.
+    ///
+    /// Waits up to `timeout` for the answer. Messages from the main server
+    /// port received meanwhile still resolve pending requests but are
+    /// otherwise dropped. Comparing the answers of both ports tells whether
+    /// the NAT maps each destination to a different public address.
+    pub fn what_is_my_addr(
+        &mut self,
+        to: SocketAddr,
+        timeout: Duration,
+    ) -> Result<Option<SocketAddr>, Box<dyn std::error::Error>> {
+        self.transport.send_to(
+            &protocol::encode(self.network, &RendezvousMessage::WhatIsMyAddr)?,
+            to,
+        )?;
+        let start = Instant::now();
+
+        loop {
+            while let Some((msg, from)) = self.recv_any()? {
+                if from == to
+                    && let RendezvousMessage::YourAddr { addr } = msg
+                {
+                    return Ok(Some(addr));
+                }
+            }
+            if start.elapsed() >= timeout {
+                self.transport.timed_out(to);
+                return Ok(None);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Receive the next message from the server, if one is available.
+    ///
+    /// Datagrams from other sources, overlong datagrams and undecodable
+    /// payloads are skipped.
+    pub fn recv(
+        &mut self,
+    ) -> Result<Option<RendezvousMessage>, Box<dyn std::error::Error>> {
+        while let Some((msg, from)) = self.recv_any()? {
+            if from == self.server_addr {
+                return Ok(Some(msg));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Receive the next message from any source. Messages from the server
+    /// resolve the pending requests they answer.
+    fn recv_any(
+        &mut self,
+    ) -> Result<
+        Option<(RendezvousMessage, SocketAddr)>,
+        Box<dyn std::error::Error>,
+    > {
+        if let Some(keepalive) = self.keepalive.as_mut()
+            && keepalive.poll(Instant::now())
+        {
+            self.heartbeat()?;
+        }
+        self.keep_peers_alive(Instant::now());
+
+        // Replies and expired slots both make room for queued requests.
+        self.send_queued()?;
+        self.relay_signals()?;
+        self.retransmit(Instant::now());
+
+        let mut buf = [0u8; protocol::RECV_BUFFER_SIZE];
+
+        loop {
+            match self.transport.recv_from(&mut buf) {
+                Ok((len, _)) if len > protocol::MAX_MESSAGE_SIZE => {
+                    warn!("Dropping overlong datagram ({len}+ bytes)");
+                }
+                Ok((len, from)) => {
+                    let decoded = protocol::decode(&buf[..len]);
+                    if let Err(e) = &decoded
+                        && let Some(reply) = e.version_reply(self.network)
+                    {
+                        debug!("Answering {from}: {e}");
+                        self.send_to(&reply, from)?;
+                    }
+                    if let Ok((network, msg)) = decoded {
+                        if network != self.network {
+                            self.reject(network, msg, from)?;
+                            continue;
+                        }
+                        if from != self.server_addr
+                            && !matches!(
+                                msg,
+                                RendezvousMessage::Heartbeat { .. }
+                            )
+                            && let Some(keepalives) =
+                                self.peer_keepalives.as_mut()
+                        {
+                            keepalives.active(from, Instant::now());
+                        }
+                        if !msg.is_rpc_request()
+                            && let Some(nonce) = msg.rpc_nonce()
+                            && let Some(transaction) =
+                                self.transactions.remove(&(from, nonce))
+                        {
+                            // Karn: a reply to a request sent again cannot
+                            // tell which send it answers.
+                            if transaction.sends == 1 {
+                                self.rtts.sample(
+                                    from,
+                                    transaction.last_sent.elapsed(),
+                                );
+                            }
+                            self.failures.record_success(from);
+                        }
+                        if let RendezvousMessage::Leave { .. } = msg {
+                            self.notices.push((msg, from));
+                            continue;
+                        }
+                        if from == self.server_addr
+                            && let RendezvousMessage::Relayed {
+                                from_peer_id,
+                                payload,
+                                ..
+                            } = &msg
+                            && self.transport.on_signal(from_peer_id, payload)
+                        {
+                            continue;
+                        }
+                        if from == self.server_addr {
+                            self.failures.record_success(from);
+                            self.resolve(&msg);
+                            match &msg {
+                                RendezvousMessage::HeartbeatAck { epoch } => {
+                                    if let Some(sent) =
+                                        self.heartbeat_sent.take()
+                                    {
+                                        self.rtts.sample(
+                                            self.server_addr,
+                                            sent.elapsed(),
+                                        );
+                                    }
+                                    self.observe_epoch(*epoch)?;
+                                }
+                                RendezvousMessage::Redirect { addresses } => {
+                                    self.follow_redirect(addresses)?;
+                                }
+                                RendezvousMessage::HelloAck {
+                                    version,
+                                    ..
+                                } => {
+                                    self.negotiation =
+                                        Negotiation::Agreed(*version);
+                                }
+                                RendezvousMessage::UnsupportedVersion {
+                                    min_version,
+                                    max_version,
+                                    ..
+                                } => {
+                                    warn!(
+                                        "Server speaks protocol versions \
+                                         {min_version} to {max_version}, \
+                                         not {}",
+                                        protocol::PROTOCOL_VERSION
+                                    );
+                                    self.negotiation = Negotiation::Refused {
+                                        min_version: *min_version,
+                                        max_version: *max_version,
+                                    };
+                                }
+                                _ => {}
+                            }
+                        }
+                        return Ok(Some((msg, from)));
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Handle `msg` sent from the foreign `network`: answer DHT requests
+    /// with [`RendezvousMessage::WrongNetwork`] and drop everything else.
+    fn reject(
+        &self,
+        network: NetworkId,
+        msg: RendezvousMessage,
+        from: SocketAddr,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match msg {
+            RendezvousMessage::WrongNetwork { network }
+                if from == self.server_addr =>
+            {
+                warn!(
+                    "Rendezvous server {from} refused us, it belongs to \
+                     network {network}, not {}",
+                    self.network
+                );
+            }
+            RendezvousMessage::WrongNetwork { network } => {
+                debug!(
+                    "Node {from} belongs to network {network}, ignoring it"
+                );
+            }
+            msg if msg.is_rpc_request() => {
+                debug!("Refusing a request from {from} of network {network}");
+                self.send_to(
+                    &RendezvousMessage::WrongNetwork { network: self.network },
+                    from,
+                )?;
+            }
+            _ => debug!("Dropping a message from {from} of network {network}"),
+        }
+        Ok(())
+    }
+
+    /// Record the server epoch, registering again and resending pending
+    /// requests when it changed.
+    fn observe_epoch(
+        &mut self,
+        epoch: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let previous = self.server_epoch.replace(epoch);
+        if previous.is_none_or(|previous| previous == epoch) {
+            return Ok(());
+        }
+
+        info!(
+            "Rendezvous server {} restarted, registering again",
+            self.server_addr
+        );
+        if let Some(private_addr) = self.private_addr {
+            self.register(private_addr)?;
+        }
+
+        let mut pending: Vec<_> = self.pending.values().cloned().collect();
+        pending.sort_by_key(|p| p.sent_at);
+        for request in pending {
+            self.submit(request.kind, request.target)?;
+        }
+
+        Ok(())
+    }
+
+    /// Move to the first of `addresses` suggested by a full server and
+    /// register there, resending pending requests.
+    ///
+    /// Only one redirect is followed, so two full servers pointing at each
+    /// other can not bounce the client around forever.
+    fn follow_redirect(
+        &mut self,
+        addresses: &[SocketAddr],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(&next) = addresses.iter().find(|&&a| a != self.server_addr)
+        else {
+            return Ok(());
+        };
+        if self.redirected {
+            warn!(
+                "Rendezvous server {} is full, not following a second \
+                 redirect to {}",
+                self.server_addr, next
+            );
+            return Ok(());
+        }
+
+        info!(
+            "Rendezvous server {} is full, moving to {}",
+            self.server_addr, next
+        );
+        self.redirected = true;
+        self.server_addr = next;
+        self.server_epoch = None;
+        if let Some(private_addr) = self.private_addr {
+            self.register(private_addr)?;
+        }
+
+        let mut pending: Vec<_> = self.pending.values().cloned().collect();
+        pending.sort_by_key(|p| p.sent_at);
+        for request in pending {
+            self.submit(request.kind, request.target)?;
+        }
+
+        Ok(())
+    }
+
+    /// Take the time based step of the hole punching attempt: request an
+    /// introduction, send probes, retry or give up.
+    fn advance(
+        &mut self,
+        now: Instant,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(conn) = self.connection.as_ref() else {
+            return Ok(());
+        };
+        let since_sent = now.duration_since(conn.last_sent);
+
+        let next = match &conn.state {
+            ConnectionState::Registering => {
+                let target = conn.target.clone();
+                self.initiate_connection(&target)?;
+                Some(ConnectionState::Initiating)
+            }
+            ConnectionState::Initiating
+                if since_sent >= INITIATE_RETRY_INTERVAL =>
+            {
+                if conn.attempts + 1 >= MAX_INITIATE_ATTEMPTS {
+                    Some(ConnectionState::Failed(format!(
+                        "no introduction to {} from the rendezvous server",
+                        conn.target
+                    )))
+                } else {
+                    let target = conn.target.clone();
+                    self.initiate_connection(&target)?;
+                    let conn = self.connection.as_mut().unwrap();
+                    conn.attempts += 1;
+                    conn.last_sent = now;
+                    None
+                }
+            }
+            ConnectionState::Exchanging { candidates } => {
+                let candidates = candidates.clone();
+                self.probe(&candidates, false)?;
+                Some(ConnectionState::Probing { candidates })
+            }
+            ConnectionState::Probing { candidates }
+                if now.duration_since(conn.entered) >= PROBE_TIMEOUT =>
+            {
+                // Symmetric NATs defeat the probes, but may let another
+                // path through.
+                let negotiating = candidates.iter().copied().find(|&addr| {
+                    self.transport.negotiate(&conn.target, addr)
+                });
+                Some(match negotiating {
+                    Some(addr) => ConnectionState::Negotiating { addr },
+                    None => ConnectionState::Failed(format!(
+                        "no reply to probes from {}",
+                        conn.target
+                    )),
+                })
+            }
+            ConnectionState::Probing { candidates }
+                if since_sent >= PROBE_INTERVAL =>
+            {
+                let candidates = candidates.clone();
+                self.probe(&candidates, false)?;
+                self.connection.as_mut().unwrap().last_sent = now;
+                None
+            }
+            ConnectionState::Negotiating { .. }
+                if now.duration_since(conn.entered) >= NEGOTIATE_TIMEOUT =>
+            {
+                Some(ConnectionState::Failed(format!(
+                    "no path to {} set up",
+                    conn.target
+                )))
+            }
+            ConnectionState::Negotiating { addr }
+                if since_sent >= PROBE_INTERVAL =>
+            {
+                let addr = *addr;
+                self.probe(&[addr], false)?;
+                self.connection.as_mut().unwrap().last_sent = now;
+                None
+            }
+            _ => None,
+        };
+
+        if let Some(state) = next {
+            self.enter(state, now);
+        }
+        Ok(())
+    }
+
+    /// Feed a received message to the hole punching attempt.
+    fn on_connection_message(
+        &mut self,
+        msg: RendezvousMessage,
+        from: SocketAddr,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let now = Instant::now();
+        let target = self.connection.as_ref().map(|c| c.target.as_str());
+        let state = self.connection_state();
+
+        let next = match msg {
+            RendezvousMessage::PeerInfo { peer }
+                if from == self.server_addr
+                    && Some(peer.peer_id.as_str()) == target
+                    && state == Some(&ConnectionState::Initiating) =>
+            {
+                Some(ConnectionState::Exchanging {
+                    candidates: candidates(
+                        &peer,
+                        self.reached.get(&peer.peer_id).copied(),
+                    ),
+                })
+            }
+            RendezvousMessage::Probe { from_peer_id, ack } => {
+                let punching = matches!(
+                    state,
+                    Some(
+                        ConnectionState::Exchanging { .. }
+                            | ConnectionState::Probing { .. }
+                            | ConnectionState::Negotiating { .. }
+                    )
+                );
+                let next = (punching && Some(from_peer_id.as_str()) == target)
+                    .then_some(ConnectionState::Connected(from));
+
+                if !ack {
+                    self.probe(&[from], true)?;
+                }
+                next
+            }
+            _ => None,
+        };
+
+        if let Some(ConnectionState::Connected(addr)) = &next
+            && let Some(conn) = &self.connection
+        {
+            self.reached.insert(conn.target.clone(), *addr);
+        }
+        if let Some(state) = next {
+            self.enter(state, now);
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self, state: ConnectionState, now: Instant) {
+        if let Some(conn) = self.connection.as_mut() {
+            conn.state = state;
+            conn.entered = now;
+            conn.last_sent = now;
+            conn.attempts = 0;
+        }
+    }
+
+    /// Send a probe, or a probe acknowledgement, to every address.
+    fn probe(
+        &self,
+        addrs: &[SocketAddr],
+        ack: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let probe = protocol::encode(
+            self.network,
+            &RendezvousMessage::Probe {
+                from_peer_id: self.peer_id.clone(),
+                ack,
+            },
+        )?;
+        // An address in a family this host cannot reach must not keep the
+        // others from being probed.
+        let mut sent = 0;
+        let mut failed = None;
+        for addr in addrs {
+            match self.transport.send_to(&probe, *addr) {
+                Ok(_) => sent += 1,
+                Err(e) => {
+                    debug!("Could not probe {addr}: {e}");
+                    failed = Some(e);
+                }
+            }
+        }
+        match failed {
+            Some(e) if sent == 0 => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Send a request, or queue it while [`Self::set_max_in_flight`]
+    /// requests are in flight. Sending a request again is a retry; it
+    /// reuses the slot the request holds, if any.
+    fn submit(
+        &mut self,
+        kind: RequestKind,
+        target: String,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let key = (kind, target);
+        let flying = self
+            .pending
+            .get(&key)
+            .is_some_and(|p| p.last_sent.elapsed() < IN_FLIGHT_TIMEOUT);
+
+        if !flying && self.in_flight() >= self.max_in_flight {
+            if !self.queued.contains(&key) {
+                self.queued.push_back(key);
+            }
+            return Ok(());
+        }
+
+        let (kind, target) = key;
+        let nonce = self.track(kind, target.clone());
+        let msg = self.request_message(kind, target, nonce);
+        self.send(&msg)
+    }
+
+    /// Send queued requests while in-flight slots are free.
+    fn send_queued(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        while self.in_flight() < self.max_in_flight
+            && let Some((kind, target)) = self.queued.pop_front()
+        {
+            self.submit(kind, target)?;
+        }
+        Ok(())
+    }
+
+    /// Number of requests sent recently enough to hold an in-flight slot.
+    fn in_flight(&self) -> usize {
+        self.pending
+            .values()
+            .filter(|p| p.last_sent.elapsed() < IN_FLIGHT_TIMEOUT)
+            .count()
+    }
+
+    fn request_message(
+        &self,
+        kind: RequestKind,
+        target: String,
+        nonce: u64,
+    ) -> RendezvousMessage {
+        match kind {
+            RequestKind::Query => {
+                RendezvousMessage::Query { target_peer_id: target }
+            }
+            RequestKind::InitiateConnection => {
+                RendezvousMessage::InitiateConnection {
+                    nonce,
+                    from_peer_id: self.peer_id.clone(),
+                    to_peer_id: target,
+                }
+            }
+            RequestKind::ListPeers => RendezvousMessage::ListPeers {
+                capabilities: target
+                    .split(',')
+                    .filter(|c| !c.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            },
+        }
+    }
+
+    /// Record a request about to be sent, counting a retry if it is
+    /// already pending. Returns the nonce to send it with.
+    fn track(&mut self, kind: RequestKind, target: String) -> u64 {
+        let now = Instant::now();
+        if let Some(pending) = self.pending.get_mut(&(kind, target.clone())) {
+            if pending.last_sent.elapsed() >= IN_FLIGHT_TIMEOUT {
+                self.transport.timed_out(self.server_addr);
+            }
+            pending.retries += 1;
+            pending.last_sent = now;
+            return pending.nonce;
+        }
+
+        let nonce = self.fresh_nonce();
+        self.pending.insert(
+            (kind, target.clone()),
+            PendingRequest {
+                kind,
+                target,
+                sent_at: now,
+                last_sent: now,
+                retries: 0,
+                nonce,
+            },
+        );
+        nonce
+    }
+
+    fn fresh_nonce(&mut self) -> u64 {
+        self.next_nonce = self.next_nonce.wrapping_add(1);
+        self.next_nonce
+    }
+
+    /// Drop the pending requests answered by `msg`.
+    fn resolve(&mut self, msg: &RendezvousMessage) {
+        match msg {
+            RendezvousMessage::PeerInfo { peer } => {
+                self.learn_capabilities(peer);
+                for kind in
+                    [RequestKind::Query, RequestKind::InitiateConnection]
+                {
+                    self.pending.remove(&(kind, peer.peer_id.clone()));
+                }
+            }
+            RendezvousMessage::PeerList { peers } => {
+                for peer in peers {
+                    self.learn_capabilities(peer);
+                }
+                // Replies do not echo the filter, so the oldest listing
+                // request is taken as the one being answered.
+                let oldest = self
+                    .pending
+                    .iter()
+                    .filter(|(_, p)| p.kind == RequestKind::ListPeers)
+                    .min_by_key(|(_, p)| p.sent_at)
+                    .map(|(key, _)| key.clone());
+                if let Some(key) = oldest {
+                    self.pending.remove(&key);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Relay the signals of the transport to the peers they are for, see
+    /// [`Transport::take_signals`].
+    fn relay_signals(&self) -> Result<(), Box<dyn std::error::Error>> {
+        for (peer_id, payload) in self.transport.take_signals() {
+            self.relay(&peer_id, payload)?;
+        }
+        Ok(())
+    }
+
+    /// Tell the transport what `peer` advertises, for each address it may
+    /// be reached at.
+    fn learn_capabilities(&self, peer: &PeerInfo) {
+        if peer.capabilities.is_empty() {
+            return;
+        }
+        for addr in candidates(peer, None) {
+            self.transport.learn_capabilities(addr, &peer.capabilities);
+        }
+    }
+
+    fn send(
+        &self,
+        msg: &RendezvousMessage,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.send_to(msg, self.server_addr)
+    }
+
+    fn send_to(
+        &self,
+        msg: &RendezvousMessage,
+        to: SocketAddr,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.transport.send_to(&protocol::encode(self.network, msg)?, to)?;
+        Ok(())
+    }
+}
+
+/// Addresses worth probing for `peer`: `reached`, where it last answered,
+/// then its public, private and candidate addresses.
+fn candidates(
+    peer: &PeerInfo,
+    reached: Option<SocketAddr>,
+) -> Vec<SocketAddr> {
+    let mut candidates = Vec::new();
+    let addrs = reached
+        .into_iter()
+        .chain([peer.public_addr])
+        .chain(peer.private_addr)
+        .chain(peer.candidates.iter().copied());
+    for addr in addrs {
+        if !candidates.contains(&addr) {
+            candidates.push(addr);
+        }
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::server::{RendezvousServer, ServerConfig};
+    use crate::transport::{MockNetwork, MockTransport};
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    /// A client registered with a mock server, and that server.
+    fn registered() -> (RendezvousClient<MockTransport>, MockTransport) {
+        let network = MockNetwork::new();
+        let server = network.bind(addr("10.0.0.1:7000")).unwrap();
+        let transport = network.bind(addr("10.0.0.2:4000")).unwrap();
+        let mut client = RendezvousClient::new(
+            transport,
+            server.local_addr().unwrap(),
+            "alice".to_string(),
+        );
+        client.register(addr("10.0.0.2:4000")).unwrap();
+        (client, server)
+    }
+
+    /// Next message received by `transport`.
+    fn receive(transport: &MockTransport) -> (RendezvousMessage, SocketAddr) {
+        let mut buf = [0u8; protocol::RECV_BUFFER_SIZE];
+        let (len, from) = transport.recv_from(&mut buf).unwrap();
+        (protocol::decode(&buf[..len]).unwrap().1, from)
+    }
+
+    fn send(
+        transport: &MockTransport,
+        msg: &RendezvousMessage,
+        to: SocketAddr,
+    ) {
+        let data = protocol::encode(NetworkId::MAIN, msg).unwrap();
+        transport.send_to(&data, to).unwrap();
+    }
+
+    #[test]
+    fn handshake_agrees_on_a_version() {
+        let (mut client, server) = registered();
+        assert_eq!(*client.negotiation(), Negotiation::Pending);
+
+        let (hello, from) = receive(&server);
+        let RendezvousMessage::Hello { nonce, min_version, max_version } =
+            hello
+        else {
+            panic!("expected a hello, got {hello:?}");
+        };
+        send(
+            &server,
+            &protocol::answer_hello(nonce, min_version, max_version),
+            from,
+        );
+        while client.recv().unwrap().is_some() {}
+
+        assert_eq!(
+            *client.negotiation(),
+            Negotiation::Agreed(protocol::PROTOCOL_VERSION)
+        );
+    }
+
+    #[test]
+    fn handshake_refused() {
+        let (mut client, server) = registered();
+        let (_, from) = receive(&server);
+        send(
+            &server,
+            &RendezvousMessage::UnsupportedVersion {
+                version: protocol::PROTOCOL_VERSION,
+                min_version: 5,
+                max_version: 6,
+            },
+            from,
+        );
+        while client.recv().unwrap().is_some() {}
+
+        assert_eq!(
+            *client.negotiation(),
+            Negotiation::Refused { min_version: 5, max_version: 6 }
+        );
+    }
+
+    #[test]
+    fn unknown_version_is_answered() {
+        let (mut client, server) = registered();
+        while receive_any(&server).is_some() {}
+
+        let mut data = protocol::encode(
+            NetworkId::MAIN,
+            &RendezvousMessage::Ping { nonce: 1 },
+        )
+        .unwrap();
+        data[2] = 9;
+        server
+            .send_to(&data, client.transport().local_addr().unwrap())
+            .unwrap();
+        while client.recv().unwrap().is_some() {}
+
+        let (reply, _) = receive(&server);
+        assert!(matches!(
+            reply,
+            RendezvousMessage::UnsupportedVersion {
+                version: 9,
+                min_version: protocol::MIN_PROTOCOL_VERSION,
+                max_version: protocol::PROTOCOL_VERSION,
+            }
+        ));
+    }
+
+    #[test]
+    fn unknown_version_reply_is_not_answered() {
+        let (mut client, server) = registered();
+        while receive_any(&server).is_some() {}
+
+        let mut data = protocol::encode(
+            NetworkId::MAIN,
+            &RendezvousMessage::UnsupportedVersion {
+                version: 2,
+                min_version: 9,
+                max_version: 9,
+            },
+        )
+        .unwrap();
+        data[2] = 9;
+        server
+            .send_to(&data, client.transport().local_addr().unwrap())
+            .unwrap();
+        while client.recv().unwrap().is_some() {}
+
+        assert!(receive_any(&server).is_none());
+    }
+
+    /// Next datagram received by `transport`, if any.
+    #[test]
+    fn oversize_message_is_not_sent() {
+        let (client, server) = registered();
+        while receive_any(&server).is_some() {}
+
+        let payload = vec![0; protocol::MAX_MESSAGE_SIZE];
+        assert!(client.relay("bob", payload).is_err());
+        assert!(receive_any(&server).is_none());
+    }
+
+    #[test]
+    fn overlong_datagram_is_dropped() {
+        let (mut client, server) = registered();
+        let (hello, from) = receive(&server);
+        let RendezvousMessage::Hello { nonce, min_version, max_version } =
+            hello
+        else {
+            panic!("expected a hello, got {hello:?}");
+        };
+        server.send_to(&[0; protocol::MAX_MESSAGE_SIZE + 1], from).unwrap();
+        send(
+            &server,
+            &protocol::answer_hello(nonce, min_version, max_version),
+            from,
+        );
+
+        assert!(matches!(
+            client.recv().unwrap(),
+            Some(RendezvousMessage::HelloAck { .. })
+        ));
+    }
+
+    /// Drain `transport`, returning the messages received.
+    fn drain(transport: &MockTransport) -> Vec<RendezvousMessage> {
+        let mut messages = Vec::new();
+        while let Some(data) = receive_any(transport) {
+            messages.push(protocol::decode(&data).unwrap().1);
+        }
+        messages
+    }
+
+    #[test]
+    fn connection_states_follow_the_punching_steps() {
+        let network = MockNetwork::new();
+        let server = network.bind(addr("10.0.0.1:7000")).unwrap();
+        let bob = network.bind(addr("10.0.0.3:4000")).unwrap();
+        let mut alice = RendezvousClient::new(
+            network.bind(addr("10.0.0.2:4000")).unwrap(),
+            server.local_addr().unwrap(),
+            "alice".to_string(),
+        );
+        alice.connect("bob", addr("10.0.0.2:4000")).unwrap();
+        assert_eq!(
+            alice.connection_state(),
+            Some(&ConnectionState::Registering)
+        );
+
+        assert_eq!(alice.drive().unwrap(), Some(&ConnectionState::Initiating));
+        assert!(drain(&server).iter().any(|msg| matches!(
+            msg,
+            RendezvousMessage::InitiateConnection { to_peer_id, .. }
+                if to_peer_id == "bob"
+        )));
+
+        let peer = PeerInfo {
+            peer_id: "bob".to_string(),
+            public_addr: bob.local_addr().unwrap(),
+            private_addr: None,
+            last_seen: std::time::SystemTime::now(),
+            capabilities: Vec::new(),
+            candidates: Vec::new(),
+        };
+        let from = addr("10.0.0.2:4000");
+        send(&server, &RendezvousMessage::PeerInfo { peer }, from);
+        let candidates = vec![bob.local_addr().unwrap()];
+        assert_eq!(
+            alice.drive().unwrap(),
+            Some(&ConnectionState::Exchanging {
+                candidates: candidates.clone()
+            })
+        );
+
+        assert_eq!(
+            alice.drive().unwrap(),
+            Some(&ConnectionState::Probing { candidates })
+        );
+        let (probe, _) = receive(&bob);
+        assert!(matches!(
+            probe,
+            RendezvousMessage::Probe { from_peer_id, ack: false }
+                if from_peer_id == "alice"
+        ));
+
+        let ack =
+            RendezvousMessage::Probe { from_peer_id: "bob".into(), ack: true };
+        send(&bob, &ack, from);
+        assert_eq!(
+            alice.drive().unwrap(),
+            Some(&ConnectionState::Connected(bob.local_addr().unwrap()))
+        );
+    }
+
+    #[test]
+    fn connection_fails_without_an_introduction() {
+        let (mut client, _server) = registered();
+        client.connect("bob", addr("10.0.0.2:4000")).unwrap();
+        let mut now = Instant::now();
+        client.advance(now).unwrap();
+
+        for _ in 0..MAX_INITIATE_ATTEMPTS {
+            now += INITIATE_RETRY_INTERVAL;
+            client.advance(now).unwrap();
+        }
+        let Some(ConnectionState::Failed(reason)) = client.connection_state()
+        else {
+            panic!("still {:?}", client.connection_state());
+        };
+        assert_eq!(
+            reason,
+            "no introduction to bob from the rendezvous server"
+        );
+    }
+
+    /// Transport setting up paths through signals, recording the calls.
+    #[derive(Default)]
+    struct Signaling {
+        inner: Option<MockTransport>,
+        negotiated: Mutex<Vec<(String, SocketAddr)>>,
+        outgoing: Mutex<Vec<(String, Vec<u8>)>>,
+        incoming: Mutex<Vec<(String, Vec<u8>)>>,
+    }
+
+    impl Transport for Signaling {
+        fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+            self.inner.as_ref().unwrap().send_to(buf, addr)
+        }
+
+        fn recv_from(
+            &self,
+            buf: &mut [u8],
+        ) -> io::Result<(usize, SocketAddr)> {
+            self.inner.as_ref().unwrap().recv_from(buf)
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.inner.as_ref().unwrap().local_addr()
+        }
+
+        fn capabilities(&self) -> Vec<String> {
+            vec!["signals".to_string()]
+        }
+
+        fn negotiate(&self, peer_id: &str, addr: SocketAddr) -> bool {
+            let mut negotiated = self.negotiated.lock().unwrap();
+            negotiated.push((peer_id.to_string(), addr));
+            true
+        }
+
+        fn take_signals(&self) -> Vec<(String, Vec<u8>)> {
+            std::mem::take(&mut self.outgoing.lock().unwrap())
+        }
+
+        fn on_signal(&self, peer_id: &str, payload: &[u8]) -> bool {
+            let mut incoming = self.incoming.lock().unwrap();
+            incoming.push((peer_id.to_string(), payload.to_vec()));
+            true
+        }
+    }
+
+    #[test]
+    fn unanswered_probes_fall_back_to_negotiating() {
+        let network = MockNetwork::new();
+        let server = network.bind(addr("10.0.0.1:7000")).unwrap();
+        let bob = network.bind(addr("10.0.0.3:4000")).unwrap();
+        let transport = Signaling {
+            inner: Some(network.bind(addr("10.0.0.2:4000")).unwrap()),
+            ..Default::default()
+        };
+        let mut alice = RendezvousClient::new(
+            transport,
+            server.local_addr().unwrap(),
+            "alice".to_string(),
+        );
+        alice.connect("bob", addr("10.0.0.2:4000")).unwrap();
+        alice.drive().unwrap();
+        assert!(drain(&server).iter().any(|msg| matches!(
+            msg,
+            RendezvousMessage::Register { capabilities, .. }
+                if capabilities == &["signals".to_string()]
+        )));
+
+        let peer = PeerInfo {
+            peer_id: "bob".to_string(),
+            public_addr: bob.local_addr().unwrap(),
+            private_addr: None,
+            last_seen: std::time::SystemTime::now(),
+            capabilities: Vec::new(),
+            candidates: Vec::new(),
+        };
+        send(
+            &server,
+            &RendezvousMessage::PeerInfo { peer },
+            addr("10.0.0.2:4000"),
+        );
+        alice.drive().unwrap();
+        alice.drive().unwrap();
+        alice.advance(Instant::now() + PROBE_TIMEOUT).unwrap();
+        let bob_addr = bob.local_addr().unwrap();
+        assert_eq!(
+            alice.connection_state(),
+            Some(&ConnectionState::Negotiating { addr: bob_addr })
+        );
+        assert_eq!(
+            *alice.transport().negotiated.lock().unwrap(),
+            [("bob".to_string(), bob_addr)]
+        );
+
+        // Signals go both ways through the server.
+        let offer = ("bob".to_string(), b"offer".to_vec());
+        alice.transport().outgoing.lock().unwrap().push(offer);
+        alice.drive().unwrap();
+        assert!(drain(&server).iter().any(|msg| matches!(
+            msg,
+            RendezvousMessage::Relay { to_peer_id, payload, .. }
+                if to_peer_id == "bob" && payload == b"offer"
+        )));
+        let answer = RendezvousMessage::Relayed {
+            from_peer_id: "bob".to_string(),
+            payload: b"answer".to_vec(),
+            hops: 0,
+        };
+        send(&server, &answer, addr("10.0.0.2:4000"));
+        assert!(alice.recv().unwrap().is_none());
+        assert_eq!(
+            *alice.transport().incoming.lock().unwrap(),
+            [("bob".to_string(), b"answer".to_vec())]
+        );
+
+        // Probes through the new path connect.
+        let ack =
+            RendezvousMessage::Probe { from_peer_id: "bob".into(), ack: true };
+        send(&bob, &ack, addr("10.0.0.2:4000"));
+        assert_eq!(
+            alice.drive().unwrap(),
+            Some(&ConnectionState::Connected(bob_addr))
+        );
+    }
+
+    #[test]
+    fn peers_connect_through_a_server_over_the_mock_transport() {
+        let network = MockNetwork::new();
+        let server_addr = addr("10.0.0.1:7000");
+        let mut server = RendezvousServer::with_transport(
+            network.bind(server_addr).unwrap(),
+            ServerConfig::default(),
+        );
+        let client = |at: &str, peer_id: &str| {
+            let transport = network.bind(addr(at)).unwrap();
+            RendezvousClient::new(transport, server_addr, peer_id.to_string())
+        };
+        let mut alice = client("10.0.0.2:4000", "alice");
+        let mut bob = client("10.0.0.3:4000", "bob");
+
+        bob.register(addr("10.0.0.3:4000")).unwrap();
+        server.poll().unwrap();
+        alice.connect("bob", addr("10.0.0.2:4000")).unwrap();
+        for _ in 0..10 {
+            server.poll().unwrap();
+            alice.drive().unwrap();
+            // Bob answers the probes.
+            bob.drive().unwrap();
+        }
+
+        assert_eq!(
+            alice.connection_state(),
+            Some(&ConnectionState::Connected(addr("10.0.0.3:4000")))
+        );
+    }
+
+    #[test]
+    fn epoch_change_registers_again() {
+        let (mut client, server) = registered();
+        client.query("bob").unwrap();
+        drain(&server);
+        let ack = |epoch| RendezvousMessage::HeartbeatAck { epoch };
+        let from = addr("10.0.0.2:4000");
+
+        send(&server, &ack(1), from);
+        send(&server, &ack(1), from);
+        while client.recv().unwrap().is_some() {}
+        assert_eq!(client.server_epoch(), Some(1));
+        assert!(drain(&server).is_empty());
+
+        send(&server, &ack(2), from);
+        while client.recv().unwrap().is_some() {}
+        assert_eq!(client.server_epoch(), Some(2));
+        let sent = drain(&server);
+        assert!(sent.iter().any(|msg| matches!(
+            msg,
+            RendezvousMessage::Register { peer_id, .. } if peer_id == "alice"
+        )));
+        // The pending query is sent again to the restarted server.
+        assert!(sent.iter().any(|msg| matches!(
+            msg,
+            RendezvousMessage::Query { target_peer_id, .. }
+                if target_peer_id == "bob"
+        )));
+    }
+
+    #[test]
+    fn restarted_server_learns_the_client_again() {
+        let network = MockNetwork::new();
+        let server_addr = addr("10.0.0.1:7000");
+        let start = || {
+            RendezvousServer::with_transport(
+                network.bind(server_addr).unwrap(),
+                ServerConfig::default(),
+            )
+        };
+        let mut client = RendezvousClient::new(
+            network.bind(addr("10.0.0.2:4000")).unwrap(),
+            server_addr,
+            "alice".to_string(),
+        );
+        let mut server = start();
+        client.register(addr("10.0.0.2:4000")).unwrap();
+        client.heartbeat().unwrap();
+        server.poll().unwrap();
+        while client.recv().unwrap().is_some() {}
+        let first = client.server_epoch().unwrap();
+
+        drop(server);
+        // Epochs are start times in milliseconds.
+        std::thread::sleep(Duration::from_millis(2));
+        let mut server = start();
+        assert_eq!(server.stats().peers, 0);
+
+        client.heartbeat().unwrap();
+        server.poll().unwrap();
+        while client.recv().unwrap().is_some() {}
+        assert_ne!(client.server_epoch(), Some(first));
+        server.poll().unwrap();
+        assert_eq!(server.stats().peers, 1);
+    }
+
+    fn receive_any(transport: &MockTransport) -> Option<Vec<u8>> {
+        let mut buf = [0u8; protocol::RECV_BUFFER_SIZE];
+        let (len, _) = transport.recv_from(&mut buf).ok()?;
+        Some(buf[..len].to_vec())
+    }
+}
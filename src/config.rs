@@ -0,0 +1,124 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Layered configuration.
+//!
+//! Every setting is a flat `key = value` pair that can come from, in
+//! increasing order of precedence: the built-in default, a TOML config
+//! file, a `TESSERAS_<KEY>` environment variable or a command line flag.
+//! [`Settings`] collects the raw values together with where each one came
+//! from; the component owning the setting parses them.
+
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+/// Prefix of environment variables holding settings, e.g. `TESSERAS_BIND`.
+pub const ENV_PREFIX: &str = "TESSERAS_";
+
+/// Where a setting's value came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Source {
+    Default,
+    File,
+    Env,
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File => write!(f, "file"),
+            Source::Env => write!(f, "env"),
+            Source::Cli => write!(f, "cli"),
+        }
+    }
+}
+
+/// Raw setting values and their sources.
+#[derive(Debug, Default)]
+pub struct Settings {
+    values: BTreeMap<String, (String, Source)>,
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `key` unless it already holds a value from a source with higher
+    /// precedence, so layers can be loaded in any order.
+    pub fn set(&mut self, key: &str, value: String, source: Source) {
+        match self.values.get(key) {
+            Some((_, current)) if *current > source => {}
+            _ => {
+                self.values.insert(key.to_string(), (value, source));
+            }
+        }
+    }
+
+    /// Load a flat TOML file. Keys not listed in `keys` are rejected so
+    /// typos do not go unnoticed.
+    pub fn load_file(
+        &mut self,
+        path: &Path,
+        keys: &[&str],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let table: toml::Table = fs::read_to_string(path)?.parse()?;
+
+        for (key, value) in table {
+            if !keys.contains(&key.as_str()) {
+                return Err(format!(
+                    "{}: unknown setting '{key}'",
+                    path.display()
+                )
+                .into());
+            }
+
+            let value = match value {
+                toml::Value::String(s) => s,
+                other => other.to_string(),
+            };
+            self.set(&key, value, Source::File);
+        }
+
+        Ok(())
+    }
+
+    /// Load `ENV_PREFIX`ed variables for the settings listed in `keys`.
+    pub fn load_env(
+        &mut self,
+        keys: &[&str],
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) {
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let key = key.to_lowercase();
+            if keys.contains(&key.as_str()) {
+                self.set(&key, value, Source::Env);
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<(&str, Source)> {
+        self.values.get(key).map(|(v, s)| (v.as_str(), *s))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str, Source)> {
+        self.values.iter().map(|(k, (v, s))| (k.as_str(), v.as_str(), *s))
+    }
+}
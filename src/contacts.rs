@@ -0,0 +1,162 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Routing table persistence.
+//!
+//! A node saves the nodes of its routing table, with their address and the
+//! time each was last seen, to a JSON file when it shuts down, so that it
+//! can rejoin through them after a restart instead of bootstrapping from
+//! scratch. Saved contacts are only hints: they should be pinged again
+//! before going back into the routing table.
+
+use std::{
+    fs, io,
+    net::SocketAddr,
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dht::Addresses, node_id::NodeId, protocol::Contact, routing::RoutingTable,
+};
+
+/// Version of the contacts file format written by this code.
+pub const VERSION: u32 = 1;
+
+/// SavedContact
+///
+/// A routing table node as read back from disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedContact {
+    pub contact: Contact,
+    pub last_seen: SystemTime,
+}
+
+/// On-disk form of the saved contacts.
+#[derive(Serialize, Deserialize)]
+struct Document {
+    version: u32,
+    /// Id of the node whose table this is, bucket ranges depend on it.
+    node_id: String,
+    contacts: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    node_id: String,
+    addr: SocketAddr,
+    /// Seconds since the Unix epoch.
+    last_seen: u64,
+}
+
+/// Write every node of `table` whose address is in `addrs` to `path`,
+/// replacing it atomically. Returns how many were saved.
+pub fn save(
+    path: impl AsRef<Path>,
+    table: &RoutingTable,
+    addrs: &Addresses,
+) -> io::Result<usize> {
+    let (now, wall) = (Instant::now(), SystemTime::now());
+    let contacts: Vec<Entry> = table
+        .nodes()
+        .filter_map(|id| {
+            let seen = table.last_seen(id)?;
+            let last_seen = wall - now.duration_since(seen);
+            Some(Entry {
+                node_id: NodeId::from(*id).to_string(),
+                addr: *addrs.get(id)?,
+                last_seen: last_seen
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            })
+        })
+        .collect();
+
+    let saved = contacts.len();
+    let document = Document {
+        version: VERSION,
+        node_id: NodeId::from(*table.local_id()).to_string(),
+        contacts,
+    };
+    let contents =
+        serde_json::to_string_pretty(&document).map_err(io::Error::other)?;
+
+    let path = path.as_ref();
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)?;
+    Ok(saved)
+}
+
+/// Read the contacts saved at `path` by the node `local_id`, least
+/// recently seen first. A missing file holds no contacts.
+pub fn load(
+    path: impl AsRef<Path>,
+    local_id: &[u8; 20],
+) -> io::Result<Vec<SavedContact>> {
+    let path = path.as_ref();
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let invalid = |reason: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {reason}", path.display()),
+        )
+    };
+
+    let document: Document =
+        serde_json::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+    if document.version != VERSION {
+        return Err(invalid(format!(
+            "unsupported version {} (expected {VERSION})",
+            document.version
+        )));
+    }
+    if document
+        .node_id
+        .parse::<NodeId>()
+        .map_err(|e| invalid(e.to_string()))?
+        != NodeId::from(*local_id)
+    {
+        return Err(invalid(format!(
+            "saved by node {}",
+            document.node_id.to_uppercase()
+        )));
+    }
+
+    let mut contacts = document
+        .contacts
+        .into_iter()
+        .map(|entry| {
+            let node_id = entry
+                .node_id
+                .parse::<NodeId>()
+                .map_err(|e| invalid(e.to_string()))?;
+            Ok(SavedContact {
+                contact: Contact { node_id: node_id.into(), addr: entry.addr },
+                last_seen: UNIX_EPOCH + Duration::from_secs(entry.last_seen),
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    contacts.retain(|saved| saved.contact.node_id != *local_id);
+    contacts.sort_by_key(|saved| saved.last_seen);
+    Ok(contacts)
+}
@@ -0,0 +1,115 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Request deduplication.
+//!
+//! UDP clients retry, so the same request may arrive more than once.
+//! Requests with side effects carry a nonce; the server remembers recently
+//! seen `(source, nonce)` pairs together with the replies it sent, and
+//! answers a duplicate by re-sending those replies instead of processing
+//! the request again.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+struct Entry {
+    seen_at: Instant,
+    replies: Vec<Vec<u8>>,
+}
+
+/// Time-windowed set of recently processed requests.
+#[derive(Debug)]
+pub struct DedupCache {
+    window: Duration,
+    capacity: usize,
+    entries: HashMap<(SocketAddr, u64), Entry>,
+    /// Keys in insertion order, oldest first.
+    order: VecDeque<(SocketAddr, u64)>,
+    duplicates: u64,
+}
+
+impl DedupCache {
+    /// Remember at most `capacity` requests for `window` each.
+    pub fn new(capacity: usize, window: Duration) -> Self {
+        DedupCache {
+            window,
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            duplicates: 0,
+        }
+    }
+
+    /// Return the replies cached for a request already processed within the
+    /// window, or `None` when the request is new.
+    pub fn replies(
+        &mut self,
+        from: SocketAddr,
+        nonce: u64,
+    ) -> Option<Vec<Vec<u8>>> {
+        self.expire();
+
+        let entry = self.entries.get(&(from, nonce))?;
+        self.duplicates += 1;
+        Some(entry.replies.clone())
+    }
+
+    /// Record a processed request and the replies sent back to `from`.
+    pub fn record(
+        &mut self,
+        from: SocketAddr,
+        nonce: u64,
+        replies: Vec<Vec<u8>>,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let key = (from, nonce);
+        let entry = Entry { seen_at: Instant::now(), replies };
+        if self.entries.insert(key, entry).is_none() {
+            self.order.push_back(key);
+        }
+
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    /// Number of duplicate requests detected so far.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    fn expire(&mut self) {
+        while let Some(key) = self.order.front() {
+            match self.entries.get(key) {
+                Some(entry) if entry.seen_at.elapsed() < self.window => break,
+                _ => {
+                    let key = *key;
+                    self.order.pop_front();
+                    self.entries.remove(&key);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,933 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! DHT remote procedure calls.
+//!
+//! A value is kept by the [`K`] nodes closest to the SHA-1 of its key, see
+//! [`key_id`]. Nodes talk to each other directly over the client's socket
+//! with the `Ping`, `FindNode`, `Store` and `FindValue` requests of
+//! [`RendezvousMessage`], using the addresses learnt through the
+//! rendezvous server.
+//!
+//! Every `Store` carries the time the record has left to live, at most
+//! [`RECORD_TTL`], so records age out once their originator stops
+//! storing them again. It also carries the sequence number the writer gave
+//! the value, and a node never replaces a value with an older one. A
+//! [`quorum_read`] asks several replicas for their value and writes the
+//! freshest back to those that missed it. Replicas also reconcile what
+//! they hold in the background, see [`merkle`].
+//!
+//! A value found by a lookup is also cached at the closest node that
+//! answered without it, so the next lookups for a popular key end sooner.
+//! The copy lives for [`CACHE_TTL`], halved for every bit of XOR distance
+//! that node is further from the key than the one holding the value.
+//!
+//! A node id is the hash of the node's public key, see [`identity`]. A
+//! `Pong` carries the key and a signature of the ping's nonce, so pinging
+//! a node proves it owns its id; nodes only met in lookup answers are
+//! taken at their word until they are pinged. A node leaving the network
+//! signs its `Leave` notices the same way, see [`leave`].
+//!
+//! Requests from other nodes are answered with [`answer`] while a call of
+//! our own is waiting for its reply, the same way hole punching probes
+//! are.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::{debug, warn};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    client::RendezvousClient,
+    identity::{self, Identity},
+    lookup::{self, Answer, Options},
+    merkle::{self, FANOUT, KEYS_BUDGET, LEVELS, MAX_NODES, Tree},
+    pins::PinError,
+    protocol::{self, Contact, KeyRange, KeyVersion, RendezvousMessage},
+    replication::{self, ReplicaAnswer},
+    routing::{ID_BITS, K, RoutingTable, distance, leading_zeros},
+    store::{Store, Value},
+    transport::Transport,
+};
+
+/// Prefix of the bytes signed in a `Pong`, so the signature cannot be
+/// passed off for anything else.
+const PONG_CONTEXT: &[u8] = b"tesseras pong";
+
+/// Prefix of the bytes signed in a `Leave`, see [`PONG_CONTEXT`].
+const LEAVE_CONTEXT: &[u8] = b"tesseras leave";
+
+/// Lifetime of a stored record, unless its originator stores it again.
+pub const RECORD_TTL: Duration = Duration::from_secs(24 * 3600);
+
+/// Lifetime of a cached copy at the distance of the node it was found at.
+pub const CACHE_TTL: Duration = Duration::from_secs(3600);
+
+/// Addresses of the nodes in the routing table, by node id.
+pub type Addresses = HashMap<[u8; 20], SocketAddr>;
+
+/// Answers to requests from other nodes, see [`RendezvousClient::call`].
+pub type Serve<'a> =
+    dyn FnMut(&RendezvousMessage) -> Option<RendezvousMessage> + 'a;
+
+/// Id under which `key` is stored in the DHT.
+pub fn key_id(key: &str) -> [u8; 20] {
+    Sha1::digest(key.as_bytes()).into()
+}
+
+/// Result of a lookup through the network.
+#[derive(Debug, Default)]
+pub struct Lookup {
+    /// The value, for [`find_value`] when a node held it.
+    pub value: Option<Value>,
+    /// Nodes that answered without the value, closest first, at most
+    /// [`K`].
+    pub closest: Vec<Contact>,
+    /// Every node that answered, worth adding to the routing table.
+    pub responded: Vec<Contact>,
+    /// The node that answered with the value, if any.
+    pub holder: Option<Contact>,
+    /// Lookup paths that found another value, see [`find_value`].
+    pub conflicts: usize,
+}
+
+/// Bytes a node signs to answer the ping carrying `nonce`.
+pub fn pong_challenge(nonce: u64) -> Vec<u8> {
+    [PONG_CONTEXT, &nonce.to_le_bytes()].concat()
+}
+
+/// Bytes a node signs in the departure notice carrying `nonce`.
+pub fn leave_challenge(nonce: u64) -> Vec<u8> {
+    [LEAVE_CONTEXT, &nonce.to_le_bytes()].concat()
+}
+
+/// Answer a DHT `request` from another node out of the local routing
+/// table and store, signing pings with `identity`. Returns `None` for
+/// messages that are not requests.
+pub fn answer(
+    request: &RendezvousMessage,
+    identity: &Identity,
+    table: &RoutingTable,
+    addrs: &Addresses,
+    store: &mut dyn Store,
+) -> Option<RendezvousMessage> {
+    match request {
+        RendezvousMessage::Ping { nonce } => Some(RendezvousMessage::Pong {
+            nonce: *nonce,
+            public_key: identity.public_key(),
+            signature: identity.sign(&pong_challenge(*nonce)).to_vec(),
+        }),
+        RendezvousMessage::Hello { nonce, min_version, max_version } => {
+            Some(protocol::answer_hello(*nonce, *min_version, *max_version))
+        }
+        RendezvousMessage::FindNode { nonce, target } => {
+            Some(RendezvousMessage::Nodes {
+                nonce: *nonce,
+                contacts: closest_contacts(table, addrs, target),
+            })
+        }
+        RendezvousMessage::Store { nonce, key, value, seq, ttl } => {
+            keep(store, key, value, *seq, Duration::from_secs(*ttl));
+            Some(RendezvousMessage::Stored { nonce: *nonce })
+        }
+        RendezvousMessage::Cache { nonce, key, value, ttl } => {
+            // Never cut short the life of a value held already.
+            let expires = SystemTime::now() + Duration::from_secs(*ttl);
+            if store.get(key).is_none()
+                || store.expires(key).is_some_and(|at| at < expires)
+            {
+                store.put_expiring(key.clone(), value.clone(), expires);
+            }
+            Some(RendezvousMessage::Stored { nonce: *nonce })
+        }
+        RendezvousMessage::FindValue { nonce, key } => {
+            Some(match store.get(key) {
+                Some(value) => RendezvousMessage::Found {
+                    nonce: *nonce,
+                    value,
+                    seq: store.seq(key),
+                },
+                None => RendezvousMessage::Nodes {
+                    nonce: *nonce,
+                    contacts: closest_contacts(table, addrs, &key_id(key)),
+                },
+            })
+        }
+        RendezvousMessage::SyncDigest { nonce, range, level, indexes } => {
+            let tree = Tree::of_store(store, range);
+            let hashes = indexes
+                .iter()
+                .take(MAX_NODES)
+                .flat_map(|i| tree.children(*level as usize, *i as usize))
+                .copied()
+                .collect();
+            Some(RendezvousMessage::Digests { nonce: *nonce, hashes })
+        }
+        RendezvousMessage::SyncKeys { nonce, range, leaves } => {
+            let mut budget = KEYS_BUDGET;
+            let versions = merkle::records(store, range)
+                .into_iter()
+                .filter(|(key, _)| {
+                    merkle::leaf(range, &key_id(key))
+                        .is_some_and(|leaf| leaves.contains(&(leaf as u16)))
+                })
+                .take_while(|(key, _)| {
+                    budget = budget.saturating_sub(key.len());
+                    budget > 0
+                })
+                .map(|(key, _)| KeyVersion {
+                    seq: store.seq(&key),
+                    ttl: remaining_ttl(store, &key).as_secs(),
+                    key,
+                })
+                .collect();
+            Some(RendezvousMessage::Keys { nonce: *nonce, versions })
+        }
+        _ => None,
+    }
+}
+
+/// Keep `value` under `key` with sequence number `seq` for `ttl`, unless
+/// `store` holds a fresher value.
+///
+/// The same value held already keeps the longer of both lives.
+pub fn keep(
+    store: &mut dyn Store,
+    key: &str,
+    value: &Value,
+    seq: u64,
+    ttl: Duration,
+) {
+    let expires = SystemTime::now() + ttl;
+    let outlives = store.expires(key).is_none_or(|at| at >= expires);
+    match store.get(key) {
+        Some(_) if seq < store.seq(key) => {}
+        Some(held) if held == *value && outlives => store.set_seq(key, seq),
+        _ => {
+            store.put_expiring(key.to_string(), value.clone(), expires);
+            store.set_seq(key, seq);
+        }
+    }
+}
+
+/// What is left of the lifetime of the record under `key`, a full
+/// [`RECORD_TTL`] for records that do not expire.
+pub fn remaining_ttl(store: &dyn Store, key: &str) -> Duration {
+    match store.expires(key) {
+        Some(at) => at.duration_since(SystemTime::now()).unwrap_or_default(),
+        None => RECORD_TTL,
+    }
+}
+
+/// Up to [`K`] contacts from `table` closest to `target`, skipping nodes
+/// without a known address.
+fn closest_contacts(
+    table: &RoutingTable,
+    addrs: &Addresses,
+    target: &[u8; 20],
+) -> Vec<Contact> {
+    table
+        .closest(target, K)
+        .into_iter()
+        .filter_map(|node_id| {
+            Some(Contact { node_id, addr: *addrs.get(&node_id)? })
+        })
+        .collect()
+}
+
+/// Ping the node at `addr`, returning its id if it answered and proved it
+/// owns it.
+pub fn ping<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    addr: SocketAddr,
+    serve: &mut Serve<'_>,
+) -> Option<[u8; 20]> {
+    let reply = client.call(
+        addr,
+        |nonce| RendezvousMessage::Ping { nonce },
+        client.rpc_timeout(addr),
+        serve,
+    );
+    pong_id(client, &reply.ok().flatten()?, addr)
+}
+
+/// Ping every node in `addrs` at once and return those that answered and
+/// proved their id, in the order they answered.
+pub fn ping_all<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    addrs: &[SocketAddr],
+    serve: &mut Serve<'_>,
+) -> Vec<Contact> {
+    let mut waiting: Vec<(SocketAddr, u64)> = addrs
+        .iter()
+        .filter_map(|&addr| {
+            match client
+                .send_rpc(addr, |nonce| RendezvousMessage::Ping { nonce })
+            {
+                Ok(nonce) => Some((addr, nonce)),
+                Err(e) => {
+                    debug!("Could not ping {addr}: {e}");
+                    None
+                }
+            }
+        })
+        .collect();
+
+    let deadline = Instant::now() + rpc_timeout(client, &waiting);
+    let mut alive = Vec::new();
+    while !waiting.is_empty() {
+        let Ok(Some(reply)) = client.recv_rpc(&waiting, deadline, serve)
+        else {
+            break;
+        };
+        let Some(i) =
+            waiting.iter().position(|(_, n)| reply.rpc_nonce() == Some(*n))
+        else {
+            continue;
+        };
+        let (addr, _) = waiting.swap_remove(i);
+        if let Some(node_id) = pong_id(client, &reply, addr) {
+            alive.push(Contact { node_id, addr });
+        }
+    }
+    for (addr, nonce) in waiting {
+        client.give_up(addr, nonce);
+    }
+    alive
+}
+
+/// How long to wait for the replies to the requests in `waiting`: the
+/// longest timeout among their destinations.
+pub fn rpc_timeout<T: Transport>(
+    client: &RendezvousClient<T>,
+    waiting: &[(SocketAddr, u64)],
+) -> Duration {
+    waiting
+        .iter()
+        .map(|&(addr, _)| client.rpc_timeout(addr))
+        .max()
+        .unwrap_or_default()
+}
+
+/// Id of the node at `addr` that sent `reply`, if it is a `Pong` whose
+/// signature proves the node owns it, signed with the key pinned for
+/// `addr`.
+fn pong_id<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    reply: &RendezvousMessage,
+    addr: SocketAddr,
+) -> Option<[u8; 20]> {
+    let RendezvousMessage::Pong { nonce, public_key, signature } = reply
+    else {
+        return None;
+    };
+
+    if !identity::verify(public_key, &pong_challenge(*nonce), signature) {
+        debug!("Node at {addr} answered with a bad signature");
+        return None;
+    }
+    if !pinned(client, addr, public_key) {
+        return None;
+    }
+    Some(*identity::node_id_of(public_key).as_bytes())
+}
+
+/// Check `public_key`, which signed a message from `addr`, against the
+/// key pinned for the address, pinning it on first use.
+///
+/// Node ids are derived from the keys, so the pin is what ties a node to
+/// where it is reached, like the host keys of SSH. A pin that could not
+/// be saved still holds for this run.
+fn pinned<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    addr: SocketAddr,
+    public_key: &[u8],
+) -> bool {
+    match client.verify_identity(&addr.to_string(), public_key) {
+        Ok(_) => true,
+        Err(PinError::IdentityMismatch { .. }) => false,
+        Err(e) => {
+            warn!("Could not save the identity pin of {addr}: {e}");
+            true
+        }
+    }
+}
+
+/// Tell the nodes at `addrs` this node is leaving the network, signing
+/// the notices with `identity`. Returns how many notices were sent; none
+/// is answered.
+pub fn leave<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    identity: &Identity,
+    addrs: &[SocketAddr],
+) -> usize {
+    addrs
+        .iter()
+        .filter(|&&addr| {
+            let sent =
+                client.send_rpc(addr, |nonce| RendezvousMessage::Leave {
+                    nonce,
+                    public_key: identity.public_key(),
+                    signature: identity.sign(&leave_challenge(nonce)).to_vec(),
+                });
+            if let Err(e) = &sent {
+                debug!("Could not tell {addr} we are leaving: {e}");
+            }
+            sent.is_ok()
+        })
+        .count()
+}
+
+/// Id of the node that sent `notice` from `from`, if it is a `Leave`
+/// whose signature proves the node owns it, signed with the key pinned
+/// for `from`.
+///
+/// A notice can be replayed, so it is only worth dropping the node from
+/// the routing table: it is added again the next time it answers.
+pub fn departed<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    notice: &RendezvousMessage,
+    from: SocketAddr,
+) -> Option<[u8; 20]> {
+    let RendezvousMessage::Leave { nonce, public_key, signature } = notice
+    else {
+        return None;
+    };
+
+    if !identity::verify(public_key, &leave_challenge(*nonce), signature) {
+        debug!("Dropping a departure notice with a bad signature");
+        return None;
+    }
+    if !pinned(client, from, public_key) {
+        return None;
+    }
+    Some(*identity::node_id_of(public_key).as_bytes())
+}
+
+/// Join the network through the nodes at `seeds`: ping them all, then
+/// look up `local_id` starting from those that answered, which fills the
+/// buckets near our own id.
+///
+/// [`Lookup::responded`] lists every node that answered, seeds included,
+/// for the caller to add to its routing table.
+pub fn bootstrap<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    local_id: &[u8; 20],
+    seeds: &[SocketAddr],
+    options: Options,
+    serve: &mut Serve<'_>,
+) -> Lookup {
+    let mut table = RoutingTable::new(*local_id);
+    let mut addrs = Addresses::new();
+    let mut seeded = ping_all(client, seeds, serve);
+    seeded.retain(|contact| {
+        if contact.node_id == *local_id {
+            debug!("Seed {} is this node, skipping it", contact.addr);
+        }
+        contact.node_id != *local_id
+    });
+    for contact in &seeded {
+        table.insert(contact.node_id, Instant::now());
+        addrs.insert(contact.node_id, contact.addr);
+    }
+
+    if seeded.is_empty() {
+        return Lookup::default();
+    }
+
+    let mut lookup =
+        find_node(client, &table, &addrs, local_id, options, serve);
+    for contact in seeded {
+        if !lookup.responded.contains(&contact) {
+            lookup.responded.push(contact);
+        }
+    }
+    lookup
+}
+
+/// Find the [`K`] nodes closest to `target` through the network, run as
+/// set by `options`.
+pub fn find_node<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    table: &RoutingTable,
+    addrs: &Addresses,
+    target: &[u8; 20],
+    options: Options,
+    serve: &mut Serve<'_>,
+) -> Lookup {
+    run(client, table, addrs, target, options, serve, |nonce| {
+        RendezvousMessage::FindNode { nonce, target: *target }
+    })
+}
+
+/// Look up the value stored under `key` through the network. When no node
+/// holds it, [`Lookup::closest`] lists the nodes closest to the key.
+///
+/// A value found is cached at the first of [`Lookup::closest`], see
+/// [`cache_ttl`]. With several paths, the value found by most of them is
+/// returned and [`Lookup::conflicts`] counts the others.
+pub fn find_value<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    table: &RoutingTable,
+    addrs: &Addresses,
+    key: &str,
+    options: Options,
+    serve: &mut Serve<'_>,
+) -> Lookup {
+    let target = key_id(key);
+    let lookup = run(client, table, addrs, &target, options, serve, |nonce| {
+        RendezvousMessage::FindValue { nonce, key: key.to_string() }
+    });
+
+    if let (Some(value), Some(holder), Some(closest)) =
+        (&lookup.value, &lookup.holder, lookup.closest.first())
+    {
+        let ttl =
+            cache_ttl(CACHE_TTL, &target, &holder.node_id, &closest.node_id);
+        let reply = client.call(
+            closest.addr,
+            |nonce| RendezvousMessage::Cache {
+                nonce,
+                key: key.to_string(),
+                value: value.clone(),
+                ttl: ttl.as_secs(),
+            },
+            client.rpc_timeout(closest.addr),
+            serve,
+        );
+        if !matches!(reply, Ok(Some(RendezvousMessage::Stored { .. }))) {
+            debug!("Could not cache '{key}' at {}", closest.addr);
+        }
+    }
+    lookup
+}
+
+/// How long a value found at `holder` should be cached at `node`: `base`
+/// halved for every bit `node` is further from `target` than `holder`.
+pub fn cache_ttl(
+    base: Duration,
+    target: &[u8; 20],
+    holder: &[u8; 20],
+    node: &[u8; 20],
+) -> Duration {
+    let bits = |id| ID_BITS - leading_zeros(&distance(id, target));
+    let halvings = bits(node).saturating_sub(bits(holder)).min(31);
+    base / (1 << halvings)
+}
+
+/// Ask every node in `contacts` to store `value` under `key` with sequence
+/// number `seq` for `ttl` and return the nodes that acknowledged it.
+pub fn store<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    contacts: &[Contact],
+    key: &str,
+    value: &Value,
+    seq: u64,
+    ttl: Duration,
+    serve: &mut Serve<'_>,
+) -> Vec<Contact> {
+    contacts
+        .iter()
+        .filter(|contact| {
+            let reply = client.call(
+                contact.addr,
+                |nonce| RendezvousMessage::Store {
+                    nonce,
+                    key: key.to_string(),
+                    value: value.clone(),
+                    seq,
+                    ttl: ttl.as_secs(),
+                },
+                client.rpc_timeout(contact.addr),
+                serve,
+            );
+            matches!(reply, Ok(Some(RendezvousMessage::Stored { .. })))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Ask `contact` for the value it holds under `key` and its sequence
+/// number.
+pub fn fetch<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    contact: &Contact,
+    key: &str,
+    serve: &mut Serve<'_>,
+) -> Option<(u64, Value)> {
+    let reply = client.call(
+        contact.addr,
+        |nonce| RendezvousMessage::FindValue { nonce, key: key.to_string() },
+        client.rpc_timeout(contact.addr),
+        serve,
+    );
+    match reply.ok()?? {
+        RendezvousMessage::Found { value, seq, .. } => Some((seq, value)),
+        _ => None,
+    }
+}
+
+/// Compare `tree`, built over the records held in `range`, with the one
+/// `contact` builds, and return the leaves that differ along with the
+/// versions `contact` holds of the keys in them. Returns `None` when
+/// `contact` does not answer.
+///
+/// At most [`MAX_NODES`] differing nodes are descended into at every
+/// level, the next sync takes care of the others.
+pub fn sync_versions<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    contact: &Contact,
+    range: KeyRange,
+    tree: &Tree,
+    serve: &mut Serve<'_>,
+) -> Option<(Vec<usize>, Vec<KeyVersion>)> {
+    let mut differing = vec![0];
+    for level in 0..LEVELS {
+        let indexes = differing.iter().map(|i| *i as u16).collect();
+        let reply = client.call(
+            contact.addr,
+            |nonce| RendezvousMessage::SyncDigest {
+                nonce,
+                range,
+                level: level as u8,
+                indexes,
+            },
+            client.rpc_timeout(contact.addr),
+            serve,
+        );
+        let RendezvousMessage::Digests { hashes, .. } = reply.ok()?? else {
+            return None;
+        };
+
+        let mut next = Vec::new();
+        for (i, index) in differing.iter().enumerate() {
+            let theirs = hashes.get(i * FANOUT..(i + 1) * FANOUT)?;
+            let ours = tree.children(level, *index);
+            next.extend(
+                (0..FANOUT)
+                    .filter(|c| ours.get(*c) != theirs.get(*c))
+                    .map(|c| index * FANOUT + c),
+            );
+        }
+        next.truncate(MAX_NODES);
+        if next.is_empty() {
+            return Some((Vec::new(), Vec::new()));
+        }
+        differing = next;
+    }
+
+    let leaves = differing.iter().map(|i| *i as u16).collect();
+    let reply = client.call(
+        contact.addr,
+        |nonce| RendezvousMessage::SyncKeys { nonce, range, leaves },
+        client.rpc_timeout(contact.addr),
+        serve,
+    );
+    match reply.ok()?? {
+        RendezvousMessage::Keys { versions, .. } => {
+            Some((differing, versions))
+        }
+        _ => None,
+    }
+}
+
+/// Result of a [`quorum_read`].
+#[derive(Debug, Default)]
+pub struct QuorumRead {
+    /// The freshest value found and its sequence number.
+    pub record: Option<(u64, Value)>,
+    /// The replicas asked, closest to the key first.
+    pub asked: Vec<Contact>,
+    /// What every replica that answered holds.
+    pub answers: Vec<ReplicaAnswer>,
+    /// The replicas the freshest value was written back to.
+    pub repaired: Vec<Contact>,
+    /// The lookup that found the replicas.
+    pub lookup: Lookup,
+}
+
+/// Ask the `replicas` nodes closest to `key` for their value and write the
+/// freshest one, by sequence number, back to those missing it or holding
+/// an older one.
+pub fn quorum_read<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    table: &RoutingTable,
+    addrs: &Addresses,
+    key: &str,
+    replicas: usize,
+    options: Options,
+    serve: &mut Serve<'_>,
+) -> QuorumRead {
+    let mut read =
+        read_replicas(client, table, addrs, key, replicas, options, serve);
+    if let Some(plan) = replication::plan_read_repair(&read.answers, replicas)
+    {
+        let stale: Vec<Contact> = read
+            .asked
+            .iter()
+            .filter(|c| plan.targets.contains(&c.node_id))
+            .cloned()
+            .collect();
+        read.repaired = store(
+            client,
+            &stale,
+            key,
+            &plan.value,
+            plan.version,
+            RECORD_TTL,
+            serve,
+        );
+        read.record = Some((plan.version, plan.value));
+    }
+    read
+}
+
+/// Ask the `replicas` nodes closest to `key` for their value, like
+/// [`quorum_read`], but leave the stale ones as they are.
+pub fn read_replicas<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    table: &RoutingTable,
+    addrs: &Addresses,
+    key: &str,
+    replicas: usize,
+    options: Options,
+    serve: &mut Serve<'_>,
+) -> QuorumRead {
+    let lookup = find_node(client, table, addrs, &key_id(key), options, serve);
+    let asked: Vec<Contact> =
+        lookup.closest.iter().take(replicas).cloned().collect();
+
+    let answers: Vec<ReplicaAnswer> = asked
+        .iter()
+        .filter_map(|contact| {
+            let timeout = client.rpc_timeout(contact.addr);
+            ask_replica(client, contact, key, timeout, serve)
+        })
+        .collect();
+
+    let record = answers
+        .iter()
+        .filter_map(|a| a.record.clone())
+        .max_by_key(|(seq, _)| *seq);
+
+    QuorumRead { record, asked, answers, repaired: Vec::new(), lookup }
+}
+
+/// Ask each of `contacts` in turn for the value it holds under `key`,
+/// until `budget` runs out. The nodes left when it does are not asked.
+pub fn ask_replicas<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    contacts: &[Contact],
+    key: &str,
+    budget: Duration,
+    serve: &mut Serve<'_>,
+) -> Vec<ReplicaAnswer> {
+    let deadline = Instant::now() + budget;
+    let mut answers = Vec::new();
+    for contact in contacts {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        let timeout = client.rpc_timeout(contact.addr).min(left);
+        answers.extend(ask_replica(client, contact, key, timeout, serve));
+    }
+    answers
+}
+
+/// `contact`'s answer to a `FindValue` for `key`, `None` when it does not
+/// answer within `timeout`.
+fn ask_replica<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    contact: &Contact,
+    key: &str,
+    timeout: Duration,
+    serve: &mut Serve<'_>,
+) -> Option<ReplicaAnswer> {
+    let reply = client.call(
+        contact.addr,
+        |nonce| RendezvousMessage::FindValue { nonce, key: key.to_string() },
+        timeout,
+        serve,
+    );
+    let record = match reply.ok()?? {
+        RendezvousMessage::Found { value, seq, .. } => Some((seq, value)),
+        RendezvousMessage::Nodes { .. } => None,
+        _ => return None,
+    };
+    Some(ReplicaAnswer { replica: contact.node_id, record })
+}
+
+/// A request of a lookup waiting for its reply.
+struct InFlight {
+    node_id: [u8; 20],
+    addr: SocketAddr,
+    nonce: u64,
+    deadline: Instant,
+}
+
+/// The [`lookup::Query`] of a lookup through the network, sending the
+/// request built by `request` to every queried node.
+struct Rpcs<'a, 'b, T: Transport, F> {
+    client: &'a mut RendezvousClient<T>,
+    addrs: &'a Addresses,
+    serve: &'a mut Serve<'b>,
+    request: F,
+    /// Addresses of the nodes met during this lookup, on top of `addrs`.
+    learnt: Addresses,
+    in_flight: Vec<InFlight>,
+    responded: Vec<Contact>,
+    /// Nodes that answered with a value, and the value.
+    found: Vec<(Contact, Value)>,
+}
+
+impl<T, F> lookup::Query for Rpcs<'_, '_, T, F>
+where
+    T: Transport,
+    F: Fn(u64) -> RendezvousMessage,
+{
+    fn send(&mut self, node_id: [u8; 20], _target: &[u8; 20]) -> bool {
+        let Some(&addr) =
+            self.learnt.get(&node_id).or_else(|| self.addrs.get(&node_id))
+        else {
+            return false;
+        };
+        match self.client.send_rpc(addr, &self.request) {
+            Ok(nonce) => {
+                let deadline = Instant::now() + self.client.rpc_timeout(addr);
+                self.in_flight.push(InFlight {
+                    node_id,
+                    addr,
+                    nonce,
+                    deadline,
+                });
+                true
+            }
+            Err(e) => {
+                debug!("Could not send a lookup request to {addr}: {e}");
+                false
+            }
+        }
+    }
+
+    fn recv(&mut self) -> Option<([u8; 20], Option<Answer>)> {
+        loop {
+            let now = Instant::now();
+            if let Some(i) =
+                self.in_flight.iter().position(|r| r.deadline <= now)
+            {
+                let expired = self.in_flight.swap_remove(i);
+                self.client.give_up(expired.addr, expired.nonce);
+                return Some((expired.node_id, None));
+            }
+
+            let deadline = self.in_flight.iter().map(|r| r.deadline).min()?;
+            let waiting: Vec<(SocketAddr, u64)> =
+                self.in_flight.iter().map(|r| (r.addr, r.nonce)).collect();
+            let reply =
+                match self.client.recv_rpc(&waiting, deadline, self.serve) {
+                    Ok(Some(reply)) => reply,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        debug!("Lookup interrupted: {e}");
+                        return None;
+                    }
+                };
+
+            let Some(i) = self
+                .in_flight
+                .iter()
+                .position(|r| reply.rpc_nonce() == Some(r.nonce))
+            else {
+                continue;
+            };
+            let InFlight { node_id, addr, .. } = self.in_flight.swap_remove(i);
+            self.responded.push(Contact { node_id, addr });
+
+            let answer = match reply {
+                RendezvousMessage::Found { value, .. } => {
+                    self.found
+                        .push((Contact { node_id, addr }, value.clone()));
+                    Some(Answer::Value(value))
+                }
+                RendezvousMessage::Nodes { contacts, .. } => {
+                    for contact in &contacts {
+                        self.learnt
+                            .entry(contact.node_id)
+                            .or_insert(contact.addr);
+                    }
+                    Some(Answer::Nodes(
+                        contacts.into_iter().map(|c| c.node_id).collect(),
+                    ))
+                }
+                _ => None,
+            };
+            return Some((node_id, answer));
+        }
+    }
+}
+
+/// Drive an iterative lookup for `target` as set by `options`, sending the
+/// request built by `request` to every queried node.
+fn run<T, F>(
+    client: &mut RendezvousClient<T>,
+    table: &RoutingTable,
+    addrs: &Addresses,
+    target: &[u8; 20],
+    options: Options,
+    serve: &mut Serve<'_>,
+    request: F,
+) -> Lookup
+where
+    T: Transport,
+    F: Fn(u64) -> RendezvousMessage,
+{
+    let mut rpcs = Rpcs {
+        client,
+        addrs,
+        serve,
+        request,
+        learnt: Addresses::new(),
+        in_flight: Vec::new(),
+        responded: Vec::new(),
+        found: Vec::new(),
+    };
+    let result = lookup::iterate(table, target, options, &mut rpcs);
+    let Rpcs { responded, found, .. } = rpcs;
+    let holder = found
+        .into_iter()
+        .find(|(_, value)| Some(value) == result.value.as_ref())
+        .map(|(contact, _)| contact);
+
+    let closest = result
+        .closest
+        .into_iter()
+        .filter_map(|node_id| {
+            responded.iter().find(|c| c.node_id == node_id).cloned()
+        })
+        .collect();
+
+    Lookup {
+        value: result.value,
+        closest,
+        responded,
+        holder,
+        conflicts: result.conflicts,
+    }
+}
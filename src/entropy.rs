@@ -0,0 +1,285 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Node id generation with a bounded wait on the entropy source.
+//!
+//! Reading the OS entropy source can block for a long time on freshly
+//! booted or headless machines. [`node_id`] and [`seed`] wait a short
+//! while for it and then, depending on the [`Fallback`], either keep
+//! waiting with a logged warning or settle for bytes derived from a weak
+//! seed.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read},
+    process,
+    str::FromStr,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+use sha1::{Digest, Sha1};
+
+use crate::io::{ReadError, read_full};
+
+/// What to do when the entropy source does not answer in time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fallback {
+    /// Keep waiting, logging a warning every timeout.
+    #[default]
+    Wait,
+    /// Derive the id from a weak seed, see [`Quality::Weak`].
+    Weak,
+}
+
+impl fmt::Display for Fallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fallback::Wait => write!(f, "wait"),
+            Fallback::Weak => write!(f, "weak"),
+        }
+    }
+}
+
+impl FromStr for Fallback {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wait" => Ok(Fallback::Wait),
+            "weak" => Ok(Fallback::Weak),
+            _ => Err(format!("expected 'wait' or 'weak', got '{s}'")),
+        }
+    }
+}
+
+/// Where generated ids and seeds came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    /// Read from the entropy source.
+    Strong,
+    /// Hashed from the clock and process details. Distinct between nodes
+    /// in practice but predictable, so it must not be relied on where an
+    /// attacker choosing ids matters.
+    Weak,
+}
+
+/// Generate a node id from `/dev/urandom`, waiting at most `timeout`
+/// before applying `fallback`.
+pub fn node_id(
+    timeout: Duration,
+    fallback: Fallback,
+) -> Result<([u8; 20], Quality), ReadError> {
+    node_id_from(|| File::open("/dev/urandom"), timeout, fallback)
+}
+
+/// Generate a node id from the source returned by `open`, waiting at most
+/// `timeout` before applying `fallback`.
+///
+/// The source is opened and read on its own thread, which is left behind
+/// if it never answers.
+pub fn node_id_from<R, F>(
+    open: F,
+    timeout: Duration,
+    fallback: Fallback,
+) -> Result<([u8; 20], Quality), ReadError>
+where
+    R: Read,
+    F: FnOnce() -> io::Result<R> + Send + 'static,
+{
+    bytes_from(open, timeout, fallback)
+}
+
+/// Generate a 32-byte key seed from `/dev/urandom`, waiting at most
+/// `timeout` before applying `fallback`.
+pub fn seed(
+    timeout: Duration,
+    fallback: Fallback,
+) -> Result<([u8; 32], Quality), ReadError> {
+    bytes_from(|| File::open("/dev/urandom"), timeout, fallback)
+}
+
+fn bytes_from<const N: usize, R, F>(
+    open: F,
+    timeout: Duration,
+    fallback: Fallback,
+) -> Result<([u8; N], Quality), ReadError>
+where
+    R: Read,
+    F: FnOnce() -> io::Result<R> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    thread::Builder::new().name("entropy".to_string()).spawn(move || {
+        let result = open().map_err(ReadError::Io).and_then(|mut source| {
+            let mut bytes = [0u8; N];
+            read_full(&mut source, &mut bytes)?;
+            Ok(bytes)
+        });
+        let _ = tx.send(result);
+    })?;
+
+    let mut waited = Duration::ZERO;
+    loop {
+        match rx.recv_timeout(timeout) {
+            Ok(result) => {
+                return result.map(|bytes| (bytes, Quality::Strong));
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(ReadError::Io(io::Error::other(
+                    "entropy reader exited",
+                )));
+            }
+            Err(RecvTimeoutError::Timeout) => waited += timeout,
+        }
+
+        match fallback {
+            Fallback::Wait => warn!(
+                "Entropy source has not answered for {waited:?}, still \
+                 waiting"
+            ),
+            Fallback::Weak => {
+                warn!(
+                    "Entropy source has not answered for {waited:?}, using \
+                     a weak seed instead"
+                );
+                return Ok((weak_bytes(), Quality::Weak));
+            }
+        }
+    }
+}
+
+/// Hash of whatever differs between runs and machines without touching
+/// the entropy source, stretched to `N` bytes with a block counter.
+fn weak_bytes<const N: usize>() -> [u8; N] {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let local = 0u8;
+
+    let mut hasher = Sha1::new();
+    hasher.update(now.as_nanos().to_le_bytes());
+    hasher.update(process::id().to_le_bytes());
+    hasher.update((&local as *const u8 as usize).to_le_bytes());
+    hasher.update(format!("{:?}", Instant::now()).as_bytes());
+    hasher.update(format!("{:?}", thread::current().id()).as_bytes());
+
+    let mut bytes = [0u8; N];
+    for (block, chunk) in bytes.chunks_mut(20).enumerate() {
+        let mut hasher = hasher.clone();
+        hasher.update((block as u32).to_le_bytes());
+        chunk.copy_from_slice(&hasher.finalize()[..chunk.len()]);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Entropy source filling reads with `byte` once `gate` receives,
+    /// blocking until then like a starved `/dev/random`.
+    struct Slow {
+        gate: mpsc::Receiver<()>,
+        byte: u8,
+    }
+
+    impl Read for Slow {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.gate.recv().is_err() {
+                return Ok(0);
+            }
+            buf.fill(self.byte);
+            Ok(buf.len())
+        }
+    }
+
+    /// A slow source and the sender opening its gate.
+    fn slow(byte: u8) -> (Slow, mpsc::Sender<()>) {
+        let (tx, gate) = mpsc::channel();
+        (Slow { gate, byte }, tx)
+    }
+
+    const TIMEOUT: Duration = Duration::from_millis(20);
+
+    #[test]
+    fn slow_source_falls_back_to_a_weak_id() {
+        let (source, _gate) = slow(7);
+        let start = Instant::now();
+
+        let (id, quality) =
+            node_id_from(move || Ok(source), TIMEOUT, Fallback::Weak).unwrap();
+
+        assert_eq!(quality, Quality::Weak);
+        assert_ne!(id, [7; 20]);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn slow_source_is_waited_for() {
+        let (source, gate) = slow(7);
+        let opener = thread::spawn(move || {
+            thread::sleep(TIMEOUT * 5);
+            gate.send(()).unwrap();
+        });
+        let start = Instant::now();
+
+        let (id, quality) =
+            node_id_from(move || Ok(source), TIMEOUT, Fallback::Wait).unwrap();
+
+        assert_eq!(quality, Quality::Strong);
+        assert_eq!(id, [7; 20]);
+        assert!(start.elapsed() >= TIMEOUT * 5);
+        opener.join().unwrap();
+    }
+
+    #[test]
+    fn prompt_source_is_used_with_either_fallback() {
+        for fallback in [Fallback::Wait, Fallback::Weak] {
+            let (source, gate) = slow(9);
+            gate.send(()).unwrap();
+            let (id, quality) = node_id_from(
+                move || Ok(source),
+                Duration::from_secs(5),
+                fallback,
+            )
+            .unwrap();
+            assert_eq!((id, quality), ([9; 20], Quality::Strong));
+        }
+    }
+
+    #[test]
+    fn source_errors_are_reported() {
+        let open = || Err::<Slow, _>(io::Error::other("no device"));
+        let error = node_id_from(open, Duration::from_secs(5), Fallback::Weak)
+            .unwrap_err();
+        assert!(
+            matches!(&error, ReadError::Io(e) if e.to_string() == "no device")
+        );
+    }
+
+    #[test]
+    fn exhausted_source_is_too_short() {
+        let open = || Ok(&[1u8; 8][..]);
+        let error = node_id_from(open, Duration::from_secs(5), Fallback::Weak)
+            .unwrap_err();
+        assert!(matches!(error, ReadError::TooShort { expected: 20, got: 8 }));
+    }
+
+    #[test]
+    fn weak_seeds_differ() {
+        assert_ne!(weak_bytes::<32>(), weak_bytes::<32>());
+    }
+}
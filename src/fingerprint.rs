@@ -0,0 +1,64 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Human-memorable fingerprints for node and peer ids.
+//!
+//! A fingerprint is a `color-adjective-noun` triple derived from the SHA-1
+//! of the id, e.g. `amber-quiet-otter`. It is only meant to tell ids apart
+//! at a glance; always keep the full id around for anything that matters.
+
+use sha1::{Digest, Sha1};
+
+use crate::node_id::NodeId;
+
+const COLORS: [&str; 16] = [
+    "amber", "azure", "black", "bronze", "coral", "crimson", "cyan", "gold",
+    "green", "indigo", "ivory", "jade", "lilac", "olive", "scarlet", "teal",
+];
+
+const ADJECTIVES: [&str; 16] = [
+    "bold", "brave", "calm", "clever", "eager", "fancy", "gentle", "happy",
+    "jolly", "lively", "lucky", "proud", "quiet", "rapid", "swift", "witty",
+];
+
+const NOUNS: [&str; 16] = [
+    "badger", "bison", "crane", "falcon", "fox", "gecko", "heron", "koala",
+    "lynx", "moose", "otter", "panda", "raven", "seal", "tiger", "wolf",
+];
+
+/// Derive the fingerprint of `id`.
+pub fn fingerprint(id: &[u8]) -> String {
+    let digest = Sha1::digest(id);
+
+    format!(
+        "{}-{}-{}",
+        COLORS[digest[0] as usize % COLORS.len()],
+        ADJECTIVES[digest[1] as usize % ADJECTIVES.len()],
+        NOUNS[digest[2] as usize % NOUNS.len()]
+    )
+}
+
+/// Derive the fingerprint of a peer id as carried by the rendezvous
+/// protocol.
+///
+/// Peer ids that are the hex encoding of a 20-byte node id get the same
+/// fingerprint as the node id itself.
+pub fn peer_fingerprint(peer_id: &str) -> String {
+    match peer_id.parse::<NodeId>() {
+        Ok(id) => fingerprint(id.as_bytes()),
+        Err(_) => fingerprint(peer_id.as_bytes()),
+    }
+}
@@ -0,0 +1,378 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Application level fragmentation.
+//!
+//! A datagram larger than the path MTU is fragmented by IP, and a single
+//! lost fragment or a middlebox dropping fragments loses all of it.
+//! [`FragmentingTransport`] cuts datagrams larger than a configurable
+//! maximum payload into [`RendezvousMessage::Fragment`]s small enough to
+//! cross the internet whole, and puts them back together on the other end.
+//! A datagram whose pieces do not all arrive within
+//! [`REASSEMBLY_TIMEOUT`] is dropped, and left to the retransmission of
+//! requests, see [`crate::rpc`].
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use bincode::error::EncodeError;
+
+use crate::{
+    batch::send_each,
+    protocol::{
+        self, ENVELOPE_SIZE, FRAGMENT_TYPE, MAGIC, MAX_MESSAGE_SIZE,
+        NetworkId, RendezvousMessage,
+    },
+    routing::random_id,
+    transport::Transport,
+};
+
+/// Default largest datagram sent in one piece. Leaves room for the IPv6
+/// and UDP headers within the 1280 bytes every IPv6 link carries, and
+/// within the MTU of tunnels and PPPoE links over IPv4.
+pub const DEFAULT_MAX_PAYLOAD: usize = 1200;
+
+/// Smallest maximum payload accepted, the 576 bytes every IPv4 host
+/// reassembles minus the IP and UDP headers, rounded down.
+pub const MIN_MAX_PAYLOAD: usize = 512;
+
+/// Bytes a [`RendezvousMessage::Fragment`] adds around its data, at most:
+/// the envelope, the message type, `id`, `index`, `count` and the length
+/// of `data`, in either integer encoding.
+pub const FRAGMENT_OVERHEAD: usize = ENVELOPE_SIZE + 24;
+
+/// Most fragments a datagram is cut into.
+pub const MAX_FRAGMENTS: usize =
+    MAX_MESSAGE_SIZE.div_ceil(MIN_MAX_PAYLOAD - FRAGMENT_OVERHEAD);
+
+/// How long the fragments of a datagram are kept waiting for the rest.
+pub const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of datagrams reassembled at once. Beyond this the oldest is
+/// dropped, so a sender of lone fragments can not exhaust memory.
+const MAX_PARTIALS: usize = 256;
+
+/// Whether `datagram` is a [`RendezvousMessage::Fragment`].
+pub fn is_fragment(datagram: &[u8]) -> bool {
+    datagram.starts_with(&MAGIC) && datagram.get(3) == Some(&FRAGMENT_TYPE)
+}
+
+/// Cut `datagram` into fragments of at most `max_payload` bytes, in the
+/// version and network of its envelope. A datagram fitting in
+/// `max_payload`, or which is not a message, is returned whole.
+pub fn fragment(
+    datagram: &[u8],
+    max_payload: usize,
+    id: u64,
+) -> Result<Vec<Vec<u8>>, EncodeError> {
+    let max_payload = max_payload.max(MIN_MAX_PAYLOAD);
+    let envelope = datagram
+        .first_chunk::<ENVELOPE_SIZE>()
+        .filter(|envelope| envelope.starts_with(&MAGIC));
+    let Some(envelope) = envelope.filter(|_| datagram.len() > max_payload)
+    else {
+        return Ok(vec![datagram.to_vec()]);
+    };
+    let version = envelope[2];
+    let network =
+        NetworkId([envelope[4], envelope[5], envelope[6], envelope[7]]);
+
+    let chunks = datagram.chunks(max_payload - FRAGMENT_OVERHEAD);
+    let count = u16::try_from(chunks.len())
+        .map_err(|_| EncodeError::Other("datagram has too many fragments"))?;
+    chunks
+        .enumerate()
+        .map(|(index, data)| {
+            let fragment = RendezvousMessage::Fragment {
+                id,
+                index: index as u16,
+                count,
+                data: data.to_vec(),
+            };
+            protocol::encode_version(version, network, &fragment)
+        })
+        .collect()
+}
+
+/// Fragments received so far of one datagram.
+#[derive(Debug)]
+struct Partial {
+    pieces: Vec<Option<Vec<u8>>>,
+    missing: usize,
+    bytes: usize,
+    started: Instant,
+}
+
+/// Datagrams being put back together from their fragments, by sender
+/// and fragment id.
+#[derive(Debug)]
+pub struct Reassembly {
+    partials: HashMap<(SocketAddr, u64), Partial>,
+    timeout: Duration,
+    /// Datagrams dropped before all their fragments arrived.
+    expired: u64,
+}
+
+impl Default for Reassembly {
+    fn default() -> Self {
+        Self::new(REASSEMBLY_TIMEOUT)
+    }
+}
+
+impl Reassembly {
+    /// Reassembly dropping datagrams incomplete after `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        Reassembly { partials: HashMap::new(), timeout, expired: 0 }
+    }
+
+    /// Take `datagram` received from `from`: a whole datagram is returned
+    /// as is, a fragment completing a datagram returns the datagram, and
+    /// any other fragment is kept, or dropped if malformed, returning
+    /// `None`.
+    pub fn receive<'a>(
+        &mut self,
+        datagram: &'a [u8],
+        from: SocketAddr,
+        now: Instant,
+    ) -> Option<Cow<'a, [u8]>> {
+        if !is_fragment(datagram) {
+            return Some(Cow::Borrowed(datagram));
+        }
+        let Ok((_, RendezvousMessage::Fragment { id, index, count, data })) =
+            protocol::decode(datagram)
+        else {
+            return None;
+        };
+        self.push(from, id, index, count, data, now).map(Cow::Owned)
+    }
+
+    /// Keep piece `index` of the `count` pieces of datagram `id` of
+    /// `from`, and return the datagram once every piece arrived.
+    pub fn push(
+        &mut self,
+        from: SocketAddr,
+        id: u64,
+        index: u16,
+        count: u16,
+        data: Vec<u8>,
+        now: Instant,
+    ) -> Option<Vec<u8>> {
+        let (index, count) = (index as usize, count as usize);
+        if index >= count || count > MAX_FRAGMENTS {
+            return None;
+        }
+
+        self.expire(now);
+        let key = (from, id);
+        if !self.partials.contains_key(&key)
+            && self.partials.len() >= MAX_PARTIALS
+            && let Some(oldest) = self
+                .partials
+                .iter()
+                .min_by_key(|(_, partial)| partial.started)
+                .map(|(key, _)| *key)
+        {
+            self.partials.remove(&oldest);
+            self.expired += 1;
+        }
+        let partial = self.partials.entry(key).or_insert_with(|| Partial {
+            pieces: vec![None; count],
+            missing: count,
+            bytes: 0,
+            started: now,
+        });
+        if partial.pieces.len() != count || partial.pieces[index].is_some() {
+            return None;
+        }
+        if partial.bytes + data.len() > MAX_MESSAGE_SIZE {
+            self.partials.remove(&key);
+            return None;
+        }
+        partial.bytes += data.len();
+        partial.pieces[index] = Some(data);
+        partial.missing -= 1;
+        if partial.missing > 0 {
+            return None;
+        }
+
+        let partial = self.partials.remove(&key)?;
+        Some(partial.pieces.into_iter().flatten().flatten().collect())
+    }
+
+    /// Drop the datagrams still incomplete after the timeout and return
+    /// how many there were.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.partials.len();
+        self.partials.retain(|_, partial| {
+            now.saturating_duration_since(partial.started) < self.timeout
+        });
+        let expired = before - self.partials.len();
+        self.expired += expired as u64;
+        expired
+    }
+
+    /// Datagrams waiting for more fragments.
+    pub fn pending(&self) -> usize {
+        self.partials.len()
+    }
+
+    /// Datagrams dropped before all their fragments arrived.
+    pub fn expired(&self) -> u64 {
+        self.expired
+    }
+}
+
+/// Transport cutting outbound datagrams larger than `max_payload` into
+/// fragments and reassembling inbound ones.
+#[derive(Debug)]
+pub struct FragmentingTransport<T: Transport> {
+    inner: T,
+    max_payload: usize,
+    next_id: AtomicU64,
+    reassembly: Mutex<Reassembly>,
+    /// Datagrams sent in fragments.
+    fragmented: AtomicU64,
+}
+
+impl<T: Transport> FragmentingTransport<T> {
+    /// Fragment datagrams larger than `max_payload` bytes, raised to
+    /// [`MIN_MAX_PAYLOAD`] if smaller.
+    pub fn new(inner: T, max_payload: usize) -> Self {
+        // Ids start at random so a restarted sender does not complete
+        // the datagrams it left behind.
+        let seed = random_id().map_or(0, |id| {
+            u64::from_be_bytes([
+                id[0], id[1], id[2], id[3], id[4], id[5], id[6], id[7],
+            ])
+        });
+        FragmentingTransport {
+            inner,
+            max_payload: max_payload.max(MIN_MAX_PAYLOAD),
+            next_id: AtomicU64::new(seed),
+            reassembly: Mutex::new(Reassembly::default()),
+            fragmented: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn max_payload(&self) -> usize {
+        self.max_payload
+    }
+
+    /// Take `datagram` received from `from` on another handle of the
+    /// inner transport, see [`Reassembly::receive`].
+    pub fn reassemble<'a>(
+        &self,
+        datagram: &'a [u8],
+        from: SocketAddr,
+    ) -> Option<Cow<'a, [u8]>> {
+        self.reassembly.lock().unwrap_or_else(|e| e.into_inner()).receive(
+            datagram,
+            from,
+            Instant::now(),
+        )
+    }
+
+    /// Datagrams sent in fragments.
+    pub fn fragmented(&self) -> u64 {
+        self.fragmented.load(Ordering::Relaxed)
+    }
+
+    /// Datagrams dropped before all their fragments arrived.
+    pub fn expired(&self) -> u64 {
+        self.reassembly.lock().unwrap_or_else(|e| e.into_inner()).expired()
+    }
+}
+
+impl<T: Transport> Transport for FragmentingTransport<T> {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if buf.len() <= self.max_payload {
+            return self.inner.send_to(buf, addr);
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let fragments = fragment(buf, self.max_payload, id)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if fragments.len() > 1 {
+            self.fragmented.fetch_add(1, Ordering::Relaxed);
+        }
+        for fragment in &fragments {
+            self.inner.send_to(fragment, addr)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        loop {
+            let (len, from) = self.inner.recv_from(buf)?;
+            match self.reassemble(&buf[..len], from) {
+                Some(Cow::Borrowed(_)) => return Ok((len, from)),
+                Some(Cow::Owned(datagram)) if datagram.len() <= buf.len() => {
+                    buf[..datagram.len()].copy_from_slice(&datagram);
+                    return Ok((datagram.len(), from));
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn send_batch(
+        &self,
+        datagrams: &[(&[u8], SocketAddr)],
+    ) -> io::Result<usize> {
+        if datagrams.iter().all(|(buf, _)| buf.len() <= self.max_payload) {
+            return self.inner.send_batch(datagrams);
+        }
+        send_each(self, datagrams)
+    }
+
+    fn timed_out(&self, addr: SocketAddr) {
+        self.inner.timed_out(addr);
+    }
+
+    fn capabilities(&self) -> Vec<String> {
+        self.inner.capabilities()
+    }
+
+    fn learn_capabilities(&self, addr: SocketAddr, capabilities: &[String]) {
+        self.inner.learn_capabilities(addr, capabilities);
+    }
+
+    fn negotiate(&self, peer_id: &str, addr: SocketAddr) -> bool {
+        self.inner.negotiate(peer_id, addr)
+    }
+
+    fn take_signals(&self) -> Vec<(String, Vec<u8>)> {
+        self.inner.take_signals()
+    }
+
+    fn on_signal(&self, peer_id: &str, payload: &[u8]) -> bool {
+        self.inner.on_signal(peer_id, payload)
+    }
+}
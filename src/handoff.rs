@@ -0,0 +1,161 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Hinted handoff.
+//!
+//! A put is replicated to the nodes closest to its key, and some of them
+//! may not answer at the time. Rather than settle for fewer replicas, the
+//! putting node keeps a hint naming the missing node and the key, and
+//! hands the record over once the node answers again. Hints are retried
+//! every retry interval and given up after [`DEFAULT_HINT_TTL`], by when
+//! the record would have expired on the node anyway.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::protocol::Contact;
+
+/// Default time after which an undelivered hint is dropped.
+pub const DEFAULT_HINT_TTL: Duration = Duration::from_secs(24 * 3600);
+
+/// Default time between two delivery attempts to the same node.
+pub const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Most hints kept at once; the oldest are dropped beyond it.
+pub const MAX_HINTS: usize = 1024;
+
+#[derive(Debug)]
+struct Pending {
+    contact: Contact,
+    /// Keys to hand over, each with the time the hint was taken.
+    keys: HashMap<String, Instant>,
+    last_attempt: Instant,
+}
+
+/// Hints
+///
+/// Keys owed to unreachable nodes, grouped by node.
+#[derive(Debug)]
+pub struct Hints {
+    nodes: HashMap<[u8; 20], Pending>,
+    ttl: Duration,
+    retry_interval: Duration,
+}
+
+impl Default for Hints {
+    fn default() -> Self {
+        Hints::new(DEFAULT_HINT_TTL, DEFAULT_RETRY_INTERVAL)
+    }
+}
+
+impl Hints {
+    pub fn new(ttl: Duration, retry_interval: Duration) -> Self {
+        Hints { nodes: HashMap::new(), ttl, retry_interval }
+    }
+
+    /// Remember that `contact` missed the record under `key` at `now`.
+    pub fn add(&mut self, contact: Contact, key: &str, now: Instant) {
+        if self.len() >= MAX_HINTS {
+            self.drop_oldest();
+        }
+        let pending =
+            self.nodes.entry(contact.node_id).or_insert_with(|| Pending {
+                contact: contact.clone(),
+                keys: HashMap::new(),
+                last_attempt: now,
+            });
+        pending.contact.addr = contact.addr;
+        pending.keys.insert(key.to_string(), now);
+    }
+
+    /// Nodes due for a delivery attempt as of `now`, each marked as tried
+    /// at `now`. Hints older than the TTL are dropped first.
+    pub fn due(&mut self, now: Instant) -> Vec<Contact> {
+        self.expire(now);
+        self.nodes
+            .values_mut()
+            .filter(|p| {
+                now.duration_since(p.last_attempt) >= self.retry_interval
+            })
+            .map(|p| {
+                p.last_attempt = now;
+                p.contact.clone()
+            })
+            .collect()
+    }
+
+    /// Keys owed to `node_id`, sorted.
+    pub fn keys(&self, node_id: &[u8; 20]) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .nodes
+            .get(node_id)
+            .map(|p| p.keys.keys().cloned().collect())
+            .unwrap_or_default();
+        keys.sort();
+        keys
+    }
+
+    /// Forget the hint for `key` owed to `node_id`, once delivered or no
+    /// longer deliverable.
+    pub fn remove(&mut self, node_id: &[u8; 20], key: &str) {
+        if let Some(pending) = self.nodes.get_mut(node_id) {
+            pending.keys.remove(key);
+            if pending.keys.is_empty() {
+                self.nodes.remove(node_id);
+            }
+        }
+    }
+
+    /// Drop the hints older than the TTL as of `now` and return how many
+    /// were dropped.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.len();
+        for pending in self.nodes.values_mut() {
+            pending
+                .keys
+                .retain(|_, taken| now.duration_since(*taken) < self.ttl);
+        }
+        self.nodes.retain(|_, p| !p.keys.is_empty());
+        before - self.len()
+    }
+
+    fn drop_oldest(&mut self) {
+        let oldest = self
+            .nodes
+            .iter()
+            .flat_map(|(id, p)| p.keys.iter().map(move |(k, t)| (*t, *id, k)))
+            .min()
+            .map(|(_, id, key)| (id, key.clone()));
+        if let Some((id, key)) = oldest {
+            self.remove(&id, &key);
+        }
+    }
+
+    /// Number of nodes owed at least one record.
+    pub fn nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Number of hints held.
+    pub fn len(&self) -> usize {
+        self.nodes.values().map(|p| p.keys.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
@@ -0,0 +1,238 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Node identity keys.
+//!
+//! A node owns an Ed25519 key pair and its [`NodeId`] is the SHA-1 of the
+//! public key, so any node can check that a peer owns the id it claims by
+//! having it sign a challenge, see [`verify`]. The private key is kept in
+//! a file holding its 32-byte seed in hex, so the id survives restarts.
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    io::{ReadError, read_full},
+    node_id::NodeId,
+};
+
+/// Identity
+///
+/// The key pair a node signs with. The seed is never printed by `Debug`.
+pub struct Identity {
+    key: SigningKey,
+}
+
+impl Identity {
+    /// Derive the identity whose private key is `seed`.
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        Identity { key: SigningKey::from_bytes(&seed) }
+    }
+
+    /// Load the identity saved at `path`, or save a new one made from
+    /// `seed` if there is none yet. Returns whether it was created.
+    ///
+    /// A truncated keyfile yields [`ReadError::TooShort`] and one that is
+    /// not a hex seed [`ReadError::Malformed`].
+    pub fn load_or_create<F>(
+        path: impl AsRef<Path>,
+        seed: F,
+    ) -> Result<(Self, bool), ReadError>
+    where
+        F: FnOnce() -> Result<[u8; 32], ReadError>,
+    {
+        let path = path.as_ref();
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let identity = Identity::from_seed(seed()?);
+                identity.save(path)?;
+                return Ok((identity, true));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut hex = [0u8; 64];
+        read_full(&mut file, &mut hex)?;
+        let mut rest = Vec::new();
+        file.read_to_end(&mut rest)?;
+        if !rest.iter().all(u8::is_ascii_whitespace) {
+            return Err(ReadError::Malformed(
+                "trailing data after the key".to_string(),
+            ));
+        }
+
+        let seed = std::str::from_utf8(&hex)
+            .ok()
+            .and_then(decode_hex)
+            .and_then(|seed| <[u8; 32]>::try_from(seed).ok())
+            .ok_or_else(|| {
+                ReadError::Malformed("key is not 64 hex digits".to_string())
+            })?;
+        Ok((Identity::from_seed(seed), false))
+    }
+
+    /// Write the seed to `path`, readable by the owner only, through a
+    /// temporary file so a crash never leaves it half written.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let tmp = path.with_extension("tmp");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&tmp)?;
+        writeln!(file, "{}", encode_hex(self.key.as_bytes()))?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.key.verifying_key().to_bytes()
+    }
+
+    /// The node id owned by this identity.
+    pub fn node_id(&self) -> NodeId {
+        node_id_of(&self.public_key())
+    }
+
+    /// Sign `message`, for [`verify`] against [`Identity::public_key`].
+    pub fn sign(&self, message: &[u8]) -> [u8; 64] {
+        self.key.sign(message).to_bytes()
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Identity").field("node_id", &self.node_id()).finish()
+    }
+}
+
+/// The node id owned by the holder of `public_key`.
+pub fn node_id_of(public_key: &[u8; 32]) -> NodeId {
+    let digest: [u8; 20] = Sha1::digest(public_key).into();
+    NodeId::from(digest)
+}
+
+/// Whether `signature` is a signature of `message` by the holder of
+/// `public_key`. Malformed keys and signatures never verify.
+pub fn verify(
+    public_key: &[u8; 32],
+    message: &[u8],
+    signature: &[u8],
+) -> bool {
+    let Ok(key) = VerifyingKey::from_bytes(public_key) else {
+        return false;
+    };
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+    key.verify(message, &signature).is_ok()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.is_empty() || !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A keyfile path unique to this process.
+    fn keyfile(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("tesseras-{}-{name}.key", std::process::id()))
+    }
+
+    #[test]
+    fn keyfile_is_created_then_loaded() {
+        let path = keyfile("created");
+        let (created, new) =
+            Identity::load_or_create(&path, || Ok([3; 32])).unwrap();
+        assert!(new);
+
+        let (loaded, new) = Identity::load_or_create(&path, || {
+            panic!("seed read for an existing keyfile")
+        })
+        .unwrap();
+        assert!(!new);
+        assert_eq!(loaded.node_id(), created.node_id());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn truncated_keyfile_is_too_short() {
+        let path = keyfile("truncated");
+        fs::write(&path, "0303030303").unwrap();
+
+        let result = Identity::load_or_create(&path, || Ok([3; 32]));
+
+        assert!(matches!(
+            result,
+            Err(ReadError::TooShort { expected: 64, got: 10 })
+        ));
+        // The damaged keyfile is not replaced by a new identity.
+        assert_eq!(fs::read_to_string(&path).unwrap(), "0303030303");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn garbled_keyfile_is_malformed() {
+        for (name, contents) in [
+            ("not-hex", format!("{}\n", "zz".repeat(32))),
+            ("trailing", format!("{}\nextra\n", "03".repeat(32))),
+        ] {
+            let path = keyfile(name);
+            fs::write(&path, contents).unwrap();
+            let result = Identity::load_or_create(&path, || Ok([3; 32]));
+            assert!(matches!(result, Err(ReadError::Malformed(_))), "{name}");
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn failing_seed_source_is_passed_up() {
+        let path = keyfile("no-seed");
+        let result = Identity::load_or_create(&path, || {
+            Err(ReadError::TooShort { expected: 32, got: 0 })
+        });
+        assert!(matches!(result, Err(ReadError::TooShort { got: 0, .. })));
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,191 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! I/O helpers shared by the loaders of node ids and key seeds, the
+//! identity keyfile and protocol frames.
+//!
+//! The loaders return [`ReadError`], so callers can tell corrupt or
+//! truncated data from a failing device. Callers bound to [`io::Error`]
+//! keep the reason: the conversion maps truncation to
+//! [`io::ErrorKind::UnexpectedEof`] and malformed data to
+//! [`io::ErrorKind::InvalidData`], with the [`ReadError`] as inner error.
+
+use std::{error, fmt, io};
+
+/// Error returned by [`read_full`].
+#[derive(Debug)]
+pub enum ReadError {
+    /// The source ended before the buffer was filled, so the data is
+    /// corrupt or truncated.
+    TooShort { expected: usize, got: usize },
+    /// The data was read in full but is not valid.
+    Malformed(String),
+    /// The underlying reader failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::TooShort { expected, got } => {
+                write!(f, "short read: expected {expected} bytes, got {got}")
+            }
+            ReadError::Malformed(reason) => {
+                write!(f, "malformed data: {reason}")
+            }
+            ReadError::Io(e) => write!(f, "I/O failure: {e}"),
+        }
+    }
+}
+
+impl error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ReadError::TooShort { .. } | ReadError::Malformed(_) => None,
+            ReadError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+impl From<ReadError> for io::Error {
+    fn from(e: ReadError) -> Self {
+        match e {
+            ReadError::Io(e) => e,
+            ReadError::TooShort { .. } => {
+                io::Error::new(io::ErrorKind::UnexpectedEof, e)
+            }
+            ReadError::Malformed(_) => {
+                io::Error::new(io::ErrorKind::InvalidData, e)
+            }
+        }
+    }
+}
+
+/// Fill `buf` completely from `reader`.
+///
+/// Short reads are retried until the buffer is full and interrupted
+/// syscalls are restarted. Reaching EOF early yields
+/// [`ReadError::TooShort`], any other failure yields [`ReadError::Io`].
+pub fn read_full<R: io::Read>(
+    reader: &mut R,
+    buf: &mut [u8],
+) -> Result<(), ReadError> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => {
+                return Err(ReadError::TooShort {
+                    expected: buf.len(),
+                    got: filled,
+                });
+            }
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(ReadError::Io(e)),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// A reader returning `reads` in turn, then EOF.
+    struct Scripted {
+        reads: VecDeque<io::Result<Vec<u8>>>,
+    }
+
+    impl Scripted {
+        fn new(reads: Vec<io::Result<Vec<u8>>>) -> Self {
+            Scripted { reads: reads.into() }
+        }
+    }
+
+    impl io::Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some(read) = self.reads.pop_front() else {
+                return Ok(0);
+            };
+            let data = read?;
+            assert!(data.len() <= buf.len(), "read past the buffer");
+            buf[..data.len()].copy_from_slice(&data);
+            Ok(data.len())
+        }
+    }
+
+    fn interrupted() -> io::Result<Vec<u8>> {
+        Err(io::ErrorKind::Interrupted.into())
+    }
+
+    #[test]
+    fn small_chunks_fill_the_buffer() {
+        let mut reader = Scripted::new(vec![
+            Ok(vec![1]),
+            Ok(vec![2, 3]),
+            Ok(vec![4]),
+            Ok(vec![5, 6]),
+        ]);
+        let mut buf = [0; 6];
+        read_full(&mut reader, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn interrupted_read_is_restarted() {
+        let mut reader = Scripted::new(vec![
+            interrupted(),
+            Ok(vec![1, 2]),
+            interrupted(),
+            Ok(vec![3, 4]),
+        ]);
+        let mut buf = [0; 4];
+        read_full(&mut reader, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn early_eof_is_too_short() {
+        let mut reader = Scripted::new(vec![Ok(vec![1, 2]), Ok(vec![3])]);
+        let mut buf = [0; 8];
+        let error = read_full(&mut reader, &mut buf).unwrap_err();
+        assert!(matches!(error, ReadError::TooShort { expected: 8, got: 3 }));
+    }
+
+    #[test]
+    fn other_errors_are_io_failures() {
+        let mut reader = Scripted::new(vec![
+            Ok(vec![1]),
+            Err(io::ErrorKind::PermissionDenied.into()),
+        ]);
+        let mut buf = [0; 4];
+        let error = read_full(&mut reader, &mut buf).unwrap_err();
+        assert!(matches!(
+            error,
+            ReadError::Io(e) if e.kind() == io::ErrorKind::PermissionDenied
+        ));
+    }
+}
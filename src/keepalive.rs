@@ -0,0 +1,299 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Heartbeat scheduling.
+//!
+//! Clients started together and heartbeating on the same interval hit the
+//! server at the same moments. Every interval is therefore stretched or
+//! shrunk by a random jitter of up to a configured percentage, spreading
+//! heartbeats out over time.
+//!
+//! NATs also drop the mapping of a peer reached by hole punching after
+//! some 30 seconds of silence. [`PeerKeepalives`] decides when a peer
+//! still in use is owed a keep-alive to hold its mapping open, and stops
+//! once the peer is idle.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Default time between heartbeats.
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Default jitter, in percent of the interval either way.
+pub const DEFAULT_JITTER_PERCENT: u8 = 10;
+
+/// Default longest silence towards a peer in use, half the time after
+/// which the most eager NATs drop a UDP mapping.
+pub const DEFAULT_PEER_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Default time without traffic after which a peer is idle and no longer
+/// kept alive.
+pub const DEFAULT_IDLE_AFTER: Duration = Duration::from_secs(120);
+
+/// Number of peers kept alive at once. Beyond this the least recently
+/// used one is dropped.
+const MAX_KEPT_ALIVE: usize = 1024;
+
+/// Keepalive
+///
+/// Decides when the next heartbeat is due. The jitter comes from a small
+/// seeded generator, so a schedule can be replayed with
+/// [`Keepalive::with_seed`].
+#[derive(Debug, Clone)]
+pub struct Keepalive {
+    interval: Duration,
+    jitter_percent: u8,
+    state: u64,
+    next: Instant,
+}
+
+impl Keepalive {
+    /// Heartbeat every `interval`, give or take `jitter_percent` percent,
+    /// starting one interval after `now`.
+    pub fn new(interval: Duration, jitter_percent: u8, now: Instant) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default()
+            ^ u64::from(std::process::id());
+        Self::with_seed(interval, jitter_percent, seed, now)
+    }
+
+    /// Like [`Self::new`] with a fixed jitter seed.
+    pub fn with_seed(
+        interval: Duration,
+        jitter_percent: u8,
+        seed: u64,
+        now: Instant,
+    ) -> Self {
+        let mut keepalive = Keepalive {
+            interval,
+            jitter_percent: jitter_percent.min(100),
+            state: seed,
+            next: now,
+        };
+        keepalive.next = now + keepalive.delay();
+        keepalive
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn jitter_percent(&self) -> u8 {
+        self.jitter_percent
+    }
+
+    /// When the next heartbeat is due.
+    pub fn next_heartbeat(&self) -> Instant {
+        self.next
+    }
+
+    /// Return whether a heartbeat is due at `now`, scheduling the next one
+    /// if so.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        if now < self.next {
+            return false;
+        }
+        self.next = now + self.delay();
+        true
+    }
+
+    /// The interval with a fresh jitter applied.
+    fn delay(&mut self) -> Duration {
+        let interval = self.interval.as_nanos();
+        let span = interval * u128::from(self.jitter_percent) / 100;
+        let offset = u128::from(self.next_random()) % (2 * span + 1);
+        let nanos = interval - span + offset;
+        Duration::from_nanos(nanos.min(u128::from(u64::MAX)) as u64)
+    }
+
+    /// splitmix64.
+    fn next_random(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Traffic with a peer kept alive.
+#[derive(Debug, Clone, Copy)]
+struct Binding {
+    /// Last traffic with the peer other than keep-alives.
+    active: Instant,
+    /// Last datagram sent to the peer, keep-alives included.
+    sent: Instant,
+}
+
+/// PeerKeepalives
+///
+/// Decides which peers are owed a keep-alive: those with traffic in the
+/// last `idle_after` that were sent nothing for `interval`. Keep-alives
+/// themselves are not traffic, so they pause on their own once a peer
+/// falls idle, and resume with the next exchange.
+#[derive(Debug, Clone)]
+pub struct PeerKeepalives {
+    interval: Duration,
+    idle_after: Duration,
+    peers: HashMap<SocketAddr, Binding>,
+}
+
+impl Default for PeerKeepalives {
+    fn default() -> Self {
+        Self::new(DEFAULT_PEER_KEEPALIVE_INTERVAL, DEFAULT_IDLE_AFTER)
+    }
+}
+
+impl PeerKeepalives {
+    /// Keep alive every peer sent nothing for `interval`, until
+    /// `idle_after` without traffic with it.
+    pub fn new(interval: Duration, idle_after: Duration) -> Self {
+        PeerKeepalives { interval, idle_after, peers: HashMap::new() }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn idle_after(&self) -> Duration {
+        self.idle_after
+    }
+
+    /// Record traffic with `addr` at `now`, other than a keep-alive.
+    pub fn active(&mut self, addr: SocketAddr, now: Instant) {
+        if let Some(binding) = self.peers.get_mut(&addr) {
+            binding.active = now;
+            return;
+        }
+        if self.peers.len() >= MAX_KEPT_ALIVE
+            && let Some(oldest) = self
+                .peers
+                .iter()
+                .min_by_key(|(_, binding)| binding.active)
+                .map(|(addr, _)| *addr)
+        {
+            self.peers.remove(&oldest);
+        }
+        // Whatever was heard, the NAT saw a datagram go out to set up
+        // the mapping.
+        self.peers.insert(addr, Binding { active: now, sent: now });
+    }
+
+    /// Record a datagram sent to `addr` at `now`.
+    pub fn sent(&mut self, addr: SocketAddr, now: Instant) {
+        if let Some(binding) = self.peers.get_mut(&addr) {
+            binding.sent = now;
+        }
+    }
+
+    /// Stop keeping `addr` alive, e.g. once it is declared dead.
+    pub fn remove(&mut self, addr: SocketAddr) {
+        self.peers.remove(&addr);
+    }
+
+    /// Forget the peers idle at `now` and return those owed a keep-alive,
+    /// recording it as sent.
+    pub fn due(&mut self, now: Instant) -> Vec<SocketAddr> {
+        self.peers.retain(|_, binding| {
+            now.saturating_duration_since(binding.active) < self.idle_after
+        });
+        let mut due = Vec::new();
+        for (addr, binding) in &mut self.peers {
+            if now.saturating_duration_since(binding.sent) >= self.interval {
+                binding.sent = now;
+                due.push(*addr);
+            }
+        }
+        due
+    }
+
+    /// Number of peers kept alive.
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(30);
+
+    /// The first `n` heartbeat delays, polling exactly when each is due.
+    fn delays(
+        keepalive: &mut Keepalive,
+        start: Instant,
+        n: usize,
+    ) -> Vec<Duration> {
+        let mut last = start;
+        (0..n)
+            .map(|_| {
+                let due = keepalive.next_heartbeat();
+                assert!(!keepalive.poll(due - Duration::from_millis(1)));
+                assert!(keepalive.poll(due));
+                let delay = due - last;
+                last = due;
+                delay
+            })
+            .collect()
+    }
+
+    #[test]
+    fn seeded_jitter_is_deterministic() {
+        let now = Instant::now();
+        let mut a = Keepalive::with_seed(INTERVAL, 10, 42, now);
+        let mut b = Keepalive::with_seed(INTERVAL, 10, 42, now);
+        let mut c = Keepalive::with_seed(INTERVAL, 10, 43, now);
+
+        let schedule = delays(&mut a, now, 20);
+        assert_eq!(schedule, delays(&mut b, now, 20));
+        assert_ne!(schedule, delays(&mut c, now, 20));
+    }
+
+    #[test]
+    fn jitter_stays_within_its_percentage() {
+        let now = Instant::now();
+        let mut keepalive = Keepalive::with_seed(INTERVAL, 10, 7, now);
+
+        let schedule = delays(&mut keepalive, now, 200);
+        let (low, high) = (INTERVAL * 9 / 10, INTERVAL * 11 / 10);
+        assert!(schedule.iter().all(|d| (low..=high).contains(d)));
+        // Spread out, not stuck on one value.
+        assert!(schedule.iter().any(|d| *d < INTERVAL));
+        assert!(schedule.iter().any(|d| *d > INTERVAL));
+    }
+
+    #[test]
+    fn no_jitter_keeps_the_interval() {
+        let now = Instant::now();
+        let mut keepalive = Keepalive::with_seed(INTERVAL, 0, 7, now);
+        assert_eq!(delays(&mut keepalive, now, 5), [INTERVAL; 5]);
+    }
+
+    #[test]
+    fn jitter_is_capped_at_the_interval() {
+        let keepalive = Keepalive::with_seed(INTERVAL, 250, 7, Instant::now());
+        assert_eq!(keepalive.jitter_percent(), 100);
+    }
+}
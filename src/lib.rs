@@ -0,0 +1,64 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Tesseras networking library.
+//!
+//! Shared building blocks used by the `tesseras` CLI and the `rendezvous`
+//! server.
+
+pub mod access;
+pub mod batch;
+pub mod client;
+pub mod config;
+pub mod contacts;
+pub mod dedup;
+pub mod dht;
+pub mod entropy;
+pub mod fingerprint;
+pub mod fragment;
+pub mod handoff;
+pub mod identity;
+pub mod io;
+pub mod keepalive;
+pub mod liveness;
+pub mod lookup;
+pub mod merkle;
+pub mod multihome;
+pub mod naming;
+pub mod node_id;
+pub mod peers;
+pub mod pins;
+pub mod pool;
+pub mod protocol;
+pub mod quic;
+pub mod ratelimit;
+pub mod refresh;
+pub mod relay;
+pub mod replication;
+pub mod republish;
+pub mod resolve;
+pub mod routing;
+pub mod rpc;
+pub mod runtime;
+pub mod server;
+pub mod snapshot;
+pub mod socks;
+pub mod store;
+pub mod tcp;
+pub mod transport;
+pub mod watchdog;
+pub mod webrtc;
+pub mod websocket;
@@ -0,0 +1,175 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Liveness probing of routing table nodes.
+//!
+//! Nodes can go away without telling anyone. Every node in the table is
+//! pinged periodically and evicted once it fails a number of probes in a
+//! row; a node that answers has its failures cleared.
+
+use std::{
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::debug;
+
+use crate::routing::RoutingTable;
+
+/// Default time between two probes of the same node.
+pub const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Default number of consecutive failed probes before a node is evicted.
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+/// Ping every node in `table` once and record the outcomes, evicting the
+/// nodes that reach `threshold` consecutive failures. Returns the evicted
+/// ids.
+///
+/// The table lock is not held while `ping` runs.
+pub fn probe<F>(
+    table: &Mutex<RoutingTable>,
+    threshold: u32,
+    mut ping: F,
+) -> Vec<[u8; 20]>
+where
+    F: FnMut([u8; 20]) -> bool,
+{
+    let nodes: Vec<[u8; 20]> =
+        table.lock().unwrap().nodes().copied().collect();
+
+    let mut evicted = Vec::new();
+    for id in nodes {
+        let alive = ping(id);
+
+        let mut table = table.lock().unwrap();
+        if alive {
+            table.record_success(&id, Instant::now());
+        } else if table.record_failure(&id, threshold) {
+            evicted.push(id);
+        }
+    }
+
+    evicted
+}
+
+/// Spawn a thread probing every node of `table` every `interval`, calling
+/// `on_evict` for each node evicted after `threshold` failures.
+///
+/// The thread exits once every other handle to `table` has been dropped.
+pub fn spawn<F, E>(
+    table: &Arc<Mutex<RoutingTable>>,
+    interval: Duration,
+    threshold: u32,
+    mut ping: F,
+    mut on_evict: E,
+) -> JoinHandle<()>
+where
+    F: FnMut([u8; 20]) -> bool + Send + 'static,
+    E: FnMut([u8; 20]) + Send + 'static,
+{
+    let table = Arc::downgrade(table);
+
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+
+            let Some(table) = table.upgrade() else {
+                break;
+            };
+            let evicted = probe(&table, threshold, &mut ping);
+            drop(table);
+
+            if !evicted.is_empty() {
+                debug!("Evicted {} unresponsive nodes", evicted.len());
+            }
+            evicted.into_iter().for_each(&mut on_evict);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::mpsc};
+
+    use super::*;
+
+    const ALIVE: [u8; 20] = [0x80; 20];
+    const DEAD: [u8; 20] = [0x40; 20];
+
+    fn table() -> Mutex<RoutingTable> {
+        let mut table = RoutingTable::new([0; 20]);
+        table.insert(ALIVE, Instant::now());
+        table.insert(DEAD, Instant::now());
+        Mutex::new(table)
+    }
+
+    #[test]
+    fn peer_failing_every_ping_is_evicted() {
+        let table = table();
+        let ping = |id| id == ALIVE;
+
+        for failures in 1..3 {
+            assert!(probe(&table, 3, ping).is_empty());
+            assert_eq!(table.lock().unwrap().failures(&DEAD), failures);
+        }
+        assert_eq!(probe(&table, 3, ping), [DEAD]);
+
+        let table = table.lock().unwrap();
+        assert!(table.contains(&ALIVE));
+        assert!(!table.contains(&DEAD));
+        assert_eq!(table.failures(&ALIVE), 0);
+    }
+
+    #[test]
+    fn only_consecutive_failures_count() {
+        let table = table();
+        let mut answers = [false, false, true, false, false].into_iter();
+
+        for _ in 0..5 {
+            let alive = answers.next().unwrap();
+            probe(&table, 3, |id| id == ALIVE || alive);
+        }
+
+        let table = table.lock().unwrap();
+        assert!(table.contains(&DEAD));
+        assert_eq!(table.failures(&DEAD), 2);
+    }
+
+    #[test]
+    fn probing_thread_evicts_the_dead_peer() {
+        let table = Arc::new(table());
+        let (tx, rx) = mpsc::channel();
+        let pinged = Arc::new(Mutex::new(HashSet::new()));
+        let seen = Arc::clone(&pinged);
+        let _probing = spawn(
+            &table,
+            Duration::from_millis(5),
+            2,
+            move |id| {
+                seen.lock().unwrap().insert(id);
+                id == ALIVE
+            },
+            move |id| {
+                let _ = tx.send(id);
+            },
+        );
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(2)).unwrap(), DEAD);
+        assert!(table.lock().unwrap().contains(&ALIVE));
+        assert!(pinged.lock().unwrap().contains(&ALIVE));
+    }
+}
@@ -0,0 +1,474 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Iterative node lookup.
+//!
+//! A lookup starts from the closest nodes in the local routing table and
+//! keeps up to alpha queries in flight, each asking a node it has not
+//! queried yet for the nodes it knows closest to the target. Contacts they
+//! return are merged into a shortlist ordered by XOR distance, and every
+//! answer frees a slot for the closest node still pending. The lookup
+//! converges once the [`K`] closest nodes that did not fail have all been
+//! queried.
+//!
+//! A value lookup works the same way, except that queried nodes holding
+//! the value answer with it and end the lookup.
+//!
+//! A lookup can also be split into several disjoint paths, as in
+//! S/Kademlia: the closest nodes of the routing table are dealt out among
+//! the paths, and every node met is only ever queried on the path that met
+//! it first. Each path runs as a lookup of its own, so a few malicious
+//! nodes can only mislead the paths they sit on, and the values the paths
+//! found are checked against each other.
+//!
+//! The transport is left to the caller: queries go through a [`Query`],
+//! or a closure for [`find_node`] and [`find_value`], so lookups can run
+//! over any protocol, or none at all in simulations.
+
+use std::collections::{HashSet, VecDeque};
+
+use log::{debug, warn};
+
+use crate::{
+    routing::{K, RoutingTable, distance},
+    store::Value,
+};
+
+/// Default number of queries in flight at once.
+pub const ALPHA: usize = 3;
+
+/// How a lookup is run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    /// Queries kept in flight on each path. Zero is treated as one.
+    pub alpha: usize,
+    /// Disjoint paths the lookup is split into. Zero is treated as one.
+    pub paths: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { alpha: ALPHA, paths: 1 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Pending,
+    InFlight,
+    Responded,
+    /// Answered with the value.
+    Found,
+    Failed,
+}
+
+/// A queried node's answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Answer {
+    /// The nodes it knows closest to the target.
+    Nodes(Vec<[u8; 20]>),
+    /// The value looked up, only returned by [`find_value`] queries.
+    Value(Value),
+}
+
+/// Result of [`find_value`].
+#[derive(Debug, Default)]
+pub struct ValueLookup {
+    /// The value, `None` when no queried node held it.
+    pub value: Option<Value>,
+    /// Nodes that answered without the value, closest first, at most
+    /// [`K`].
+    pub closest: Vec<[u8; 20]>,
+    /// Paths that found a different value than [`value`](Self::value),
+    /// which is the one found by most paths. Anything but zero means some
+    /// nodes answered with a forged value.
+    pub conflicts: usize,
+}
+
+/// Queries of a lookup, sent and answered separately so several can be in
+/// flight at once.
+pub trait Query {
+    /// Send the query for `target` to `node`. Returns `false` when it
+    /// could not be sent, which counts as a failed query.
+    fn send(&mut self, node: [u8; 20], target: &[u8; 20]) -> bool;
+
+    /// Wait for the outcome of one of the queries in flight: the node
+    /// queried and its answer, `None` when it did not answer in time.
+    ///
+    /// Only called while queries are in flight. Returning `None` gives up
+    /// on all of them.
+    fn recv(&mut self) -> Option<([u8; 20], Option<Answer>)>;
+}
+
+/// A [`Query`] answering each query on the spot with a closure.
+struct Serial<F> {
+    query: F,
+    answers: VecDeque<([u8; 20], Option<Answer>)>,
+}
+
+impl<F> Query for Serial<F>
+where
+    F: FnMut([u8; 20], &[u8; 20]) -> Option<Answer>,
+{
+    fn send(&mut self, node: [u8; 20], target: &[u8; 20]) -> bool {
+        let answer = (self.query)(node, target);
+        self.answers.push_back((node, answer));
+        true
+    }
+
+    fn recv(&mut self) -> Option<([u8; 20], Option<Answer>)> {
+        self.answers.pop_front()
+    }
+}
+
+/// Find the [`K`] nodes closest to `target`, closest first.
+///
+/// `query(node, target)` asks `node` for the nodes it knows closest to
+/// `target` and returns `None` when it did not answer. Only nodes that
+/// answered are returned; the local id is never queried nor returned.
+pub fn find_node<F>(
+    table: &RoutingTable,
+    target: &[u8; 20],
+    mut query: F,
+) -> Vec<[u8; 20]>
+where
+    F: FnMut([u8; 20], &[u8; 20]) -> Option<Vec<[u8; 20]>>,
+{
+    find_value(table, target, |id, target| {
+        query(id, target).map(Answer::Nodes)
+    })
+    .closest
+}
+
+/// Look up the value stored under `target`, the id of its key.
+///
+/// Runs like [`find_node`] but `query` may answer with the value, which
+/// ends the lookup.
+pub fn find_value<F>(
+    table: &RoutingTable,
+    target: &[u8; 20],
+    query: F,
+) -> ValueLookup
+where
+    F: FnMut([u8; 20], &[u8; 20]) -> Option<Answer>,
+{
+    let mut serial = Serial { query, answers: VecDeque::new() };
+    iterate(table, target, Options::default(), &mut serial)
+}
+
+/// One of the disjoint paths of a lookup.
+#[derive(Default)]
+struct Path {
+    shortlist: Vec<([u8; 20], State)>,
+    in_flight: usize,
+    /// The value, once a node on this path answered with it.
+    value: Option<Value>,
+}
+
+impl Path {
+    /// Whether the path still waits for answers.
+    fn is_running(&self) -> bool {
+        self.value.is_none() && self.in_flight > 0
+    }
+
+    /// Send queries until `alpha` are in flight or no candidate is left.
+    /// Returns the number of queries sent.
+    fn fill<Q: Query + ?Sized>(
+        &mut self,
+        target: &[u8; 20],
+        alpha: usize,
+        query: &mut Q,
+    ) -> usize {
+        let mut sent = 0;
+        while self.value.is_none() && self.in_flight < alpha.max(1) {
+            // Only the K closest live candidates are worth querying,
+            // anything further away cannot make it into the result.
+            let Some(entry) = self
+                .shortlist
+                .iter_mut()
+                .filter(|(_, state)| *state != State::Failed)
+                .take(K)
+                .find(|(_, state)| *state == State::Pending)
+            else {
+                break;
+            };
+
+            if query.send(entry.0, target) {
+                entry.1 = State::InFlight;
+                self.in_flight += 1;
+                sent += 1;
+            } else {
+                entry.1 = State::Failed;
+            }
+        }
+        sent
+    }
+
+    /// Position of `id` in the shortlist if it has a query in flight on
+    /// this path.
+    fn waiting(&self, id: &[u8; 20]) -> Option<usize> {
+        self.shortlist
+            .iter()
+            .position(|(n, state)| n == id && *state == State::InFlight)
+    }
+}
+
+/// Run a lookup for `target` as set by `options`.
+///
+/// Answers from nodes that have no query in flight are ignored, so a late
+/// or repeated answer is never counted twice. A path that found the value
+/// stops there, the lookup ends once every path did or converged.
+pub fn iterate<Q: Query + ?Sized>(
+    table: &RoutingTable,
+    target: &[u8; 20],
+    options: Options,
+    query: &mut Q,
+) -> ValueLookup {
+    let local_id = *table.local_id();
+    let mut seen: HashSet<[u8; 20]> = HashSet::new();
+    let mut paths: Vec<Path> =
+        (0..options.paths.max(1)).map(|_| Path::default()).collect();
+
+    let count = paths.len();
+    for (i, id) in table.closest(target, K).into_iter().enumerate() {
+        seen.insert(id);
+        paths[i % count].shortlist.push((id, State::Pending));
+    }
+
+    let mut queried = 0;
+    loop {
+        for path in &mut paths {
+            queried += path.fill(target, options.alpha, query);
+        }
+
+        if !paths.iter().any(Path::is_running) {
+            break;
+        }
+        let Some((id, answer)) = query.recv() else {
+            for (_, state) in paths.iter_mut().flat_map(|p| &mut p.shortlist) {
+                if *state == State::InFlight {
+                    *state = State::Failed;
+                }
+            }
+            break;
+        };
+        let Some((path, i)) = paths
+            .iter_mut()
+            .filter(|path| path.value.is_none())
+            .find_map(|path| {
+                let i = path.waiting(&id)?;
+                Some((path, i))
+            })
+        else {
+            continue;
+        };
+        path.in_flight -= 1;
+
+        let (state, contacts) = match answer {
+            Some(Answer::Nodes(contacts)) => (State::Responded, contacts),
+            Some(Answer::Value(found)) => {
+                path.value = Some(found);
+                (State::Found, Vec::new())
+            }
+            None => (State::Failed, Vec::new()),
+        };
+        path.shortlist[i].1 = state;
+
+        for contact in contacts {
+            if contact != local_id && seen.insert(contact) {
+                path.shortlist.push((contact, State::Pending));
+            }
+        }
+        path.shortlist.sort_by_key(|(id, _)| distance(id, target));
+    }
+
+    debug!(
+        "lookup converged after {queried} queries on {count} path(s), {} \
+         node(s) seen",
+        seen.len()
+    );
+
+    // The value found by most paths wins. On a tie, the one held closest
+    // to the target, where honest nodes store it.
+    let mut tally: Vec<(Value, usize, [u8; 20])> = Vec::new();
+    for path in &paths {
+        let Some(found) = &path.value else {
+            continue;
+        };
+        let Some((holder, _)) =
+            path.shortlist.iter().find(|(_, state)| *state == State::Found)
+        else {
+            continue;
+        };
+        let far = distance(holder, target);
+        match tally.iter_mut().find(|(value, ..)| value == found) {
+            Some((_, n, nearest)) => {
+                *n += 1;
+                *nearest = far.min(*nearest);
+            }
+            None => tally.push((found.clone(), 1, far)),
+        }
+    }
+    let found: usize = tally.iter().map(|(_, n, _)| n).sum();
+    let best =
+        tally.into_iter().max_by(|a, b| a.1.cmp(&b.1).then(b.2.cmp(&a.2)));
+    let agreed = best.as_ref().map_or(0, |(_, n, _)| *n);
+    let value = best.map(|(value, ..)| value);
+    let conflicts = found - agreed;
+    if conflicts > 0 {
+        warn!("{conflicts} of {found} lookup path(s) found another value");
+    }
+
+    let mut closest: Vec<[u8; 20]> = paths
+        .into_iter()
+        .flat_map(|path| path.shortlist)
+        .filter(|(_, state)| *state == State::Responded)
+        .map(|(id, _)| id)
+        .collect();
+    closest.sort_by_key(|id| distance(id, target));
+    closest.truncate(K);
+
+    ValueLookup { value, closest, conflicts }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    /// Pseudo random ids, the same on every run.
+    fn ids(count: usize) -> Vec<[u8; 20]> {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        (0..count)
+            .map(|_| {
+                let mut id = [0u8; 20];
+                for byte in &mut id {
+                    state = state
+                        .wrapping_mul(6_364_136_223_846_793_005)
+                        .wrapping_add(1_442_695_040_888_963_407);
+                    *byte = (state >> 56) as u8;
+                }
+                id
+            })
+            .collect()
+    }
+
+    /// Simulated network answering with the closest nodes it knows,
+    /// recording how many queries are outstanding at once. Every fifth
+    /// node never answers and every seventh can not be reached at all, so
+    /// slots are freed by failures too.
+    struct Instrumented {
+        nodes: Vec<[u8; 20]>,
+        outstanding: Vec<[u8; 20]>,
+        max_outstanding: usize,
+        sent: usize,
+    }
+
+    impl Instrumented {
+        fn new(nodes: Vec<[u8; 20]>) -> Self {
+            Self {
+                nodes,
+                outstanding: Vec::new(),
+                max_outstanding: 0,
+                sent: 0,
+            }
+        }
+
+        fn index(&self, node: &[u8; 20]) -> usize {
+            self.nodes.iter().position(|n| n == node).unwrap()
+        }
+    }
+
+    impl Query for Instrumented {
+        fn send(&mut self, node: [u8; 20], _target: &[u8; 20]) -> bool {
+            if self.index(&node) % 7 == 3 {
+                return false;
+            }
+            self.sent += 1;
+            self.outstanding.push(node);
+            self.max_outstanding =
+                self.max_outstanding.max(self.outstanding.len());
+            true
+        }
+
+        fn recv(&mut self) -> Option<([u8; 20], Option<Answer>)> {
+            // Answer the latest query first, so answers arrive out of
+            // order.
+            let node = self.outstanding.pop()?;
+            let index = self.index(&node);
+            if index % 5 == 4 {
+                return Some((node, None));
+            }
+
+            let mut known = self.nodes.clone();
+            known.sort_by_key(|id| distance(id, &node));
+            known.truncate(8);
+            Some((node, Some(Answer::Nodes(known))))
+        }
+    }
+
+    fn table(local_id: [u8; 20], nodes: &[[u8; 20]]) -> RoutingTable {
+        let mut table = RoutingTable::new(local_id);
+        let now = Instant::now();
+        for id in nodes {
+            table.insert(*id, now);
+        }
+        table
+    }
+
+    #[test]
+    fn outstanding_queries_never_exceed_alpha() {
+        let nodes = ids(300);
+        let table = table([0; 20], &nodes[..40]);
+        let target = ids(301)[300];
+
+        for alpha in [1, 2, ALPHA, 5] {
+            let mut network = Instrumented::new(nodes.clone());
+            let options = Options { alpha, paths: 1 };
+            iterate(&table, &target, options, &mut network);
+
+            assert!(network.sent > alpha, "alpha {alpha}: too few queries");
+            assert_eq!(network.max_outstanding, alpha);
+        }
+    }
+
+    #[test]
+    fn every_path_keeps_its_own_alpha() {
+        let nodes = ids(300);
+        let table = table([0; 20], &nodes[..40]);
+        let target = ids(301)[300];
+        let mut network = Instrumented::new(nodes);
+
+        let options = Options { alpha: 2, paths: 3 };
+        iterate(&table, &target, options, &mut network);
+
+        assert!(network.max_outstanding <= 6);
+        assert!(network.max_outstanding > 2);
+    }
+
+    #[test]
+    fn zero_alpha_still_sends_one_query() {
+        let nodes = ids(50);
+        let table = table([0; 20], &nodes[..10]);
+        let mut network = Instrumented::new(nodes);
+
+        let options = Options { alpha: 0, paths: 1 };
+        iterate(&table, &[0xff; 20], options, &mut network);
+
+        assert_eq!(network.max_outstanding, 1);
+        assert!(network.sent > 0);
+    }
+}
@@ -14,97 +14,1072 @@
 // OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
 //
 
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, Read, Write};
-
-/// Simple representation of CLI commands.
-#[derive(Debug)]
-enum Command {
-    Info,
-    Stats,
-    Put { key: String, value: String },
-    Get { key: String },
-    Ping,
-    Quit,
-    Empty,
-    Unknown(String),
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use tesseras::client::{Negotiation, PingResult, RendezvousClient};
+use tesseras::config::{Settings, Source};
+use tesseras::contacts::{self, SavedContact};
+use tesseras::dht::{self, Addresses, Lookup};
+use tesseras::entropy::{self, Fallback, Quality};
+use tesseras::fingerprint::{fingerprint, peer_fingerprint};
+use tesseras::fragment::{DEFAULT_MAX_PAYLOAD, FragmentingTransport};
+use tesseras::handoff::Hints;
+use tesseras::identity::Identity;
+use tesseras::keepalive::{
+    DEFAULT_HEARTBEAT_INTERVAL, DEFAULT_IDLE_AFTER, DEFAULT_JITTER_PERCENT,
+    DEFAULT_PEER_KEEPALIVE_INTERVAL, Keepalive, PeerKeepalives,
+};
+use tesseras::lookup;
+use tesseras::merkle::{self, DEFAULT_SYNC_INTERVAL, Tree};
+use tesseras::multihome::MultiHomedTransport;
+use tesseras::naming::{self, Policy};
+use tesseras::node_id::{NodeId, ParseNodeIdError};
+use tesseras::pins::{PinError, PinStore};
+use tesseras::protocol::{
+    Contact, NetworkId, PeerInfo, RendezvousMessage, RendezvousStats,
+};
+use tesseras::quic::QuicTransport;
+use tesseras::ratelimit::{RateLimit, RateLimitedTransport};
+use tesseras::refresh::{self, DEFAULT_REFRESH_INTERVAL};
+use tesseras::replication::{
+    self, DEFAULT_REPLICATION_FACTOR, MAX_REPAIRS_PER_GET, Repairs,
+};
+use tesseras::republish::{Origin, Republisher};
+use tesseras::resolve::{Ladder, Tier};
+use tesseras::routing::{K, Layout, RoutingTable, distance};
+use tesseras::runtime::{self, Event};
+use tesseras::snapshot::Snapshot;
+use tesseras::socks::Socks5Proxy;
+use tesseras::store::{
+    DEFAULT_TOMBSTONE_GRACE, MemoryStore, Page, Scan, Store, Value,
+};
+use tesseras::tcp::{FallbackTransport, TcpTransport};
+use tesseras::transport::{
+    self, MeteredTransport, SocketOptions, TrafficCounters, Transport,
+    UdpTransport,
+};
+use tesseras::webrtc::WebRtcTransport;
+use tesseras::websocket::WebSocketTransport;
+
+/// How long to wait for the entropy source before applying the fallback.
+const ENTROPY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Environment variable choosing the entropy fallback, `wait` or `weak`.
+const ENTROPY_FALLBACK_VAR: &str = "TESSERAS_ENTROPY_FALLBACK";
+
+/// Environment variable naming the identity key file.
+const IDENTITY_VAR: &str = "TESSERAS_IDENTITY";
+
+/// Identity key file, relative to the home directory, used when neither
+/// `--identity` nor [`IDENTITY_VAR`] name one.
+const DEFAULT_IDENTITY_PATH: &str = ".tesseras/identity";
+
+/// File the routing table is saved to on exit, relative to the home
+/// directory, used unless `--contacts` names one.
+const DEFAULT_CONTACTS_PATH: &str = ".tesseras/contacts.json";
+
+/// File the identity keys pinned for peer addresses are kept in, relative
+/// to the home directory, used unless `--pins` names one.
+const DEFAULT_PINS_PATH: &str = ".tesseras/pins";
+
+/// How long to wait for a reply from the rendezvous server.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Time budget of the local tier of a network get.
+const LOCAL_TIER_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Time budget of the replica tier of a network get.
+const REPLICA_TIER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Most read repairs sent by one [`maintain`] run.
+const REPAIRS_PER_ROUND: usize = 8;
+
+/// Time budget of the lookup tier of a network get.
+const LOOKUP_TIER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum number of alias expansions applied to a single line.
+const MAX_ALIAS_DEPTH: usize = 16;
+
+/// Default maximum number of entries printed by a prefix lookup.
+const DEFAULT_SCAN_LIMIT: usize = 50;
+
+/// How long `/ping-all` waits for each peer to answer.
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Default number of peers `/ping-all` probes at a time.
+const DEFAULT_PING_CONCURRENCY: usize = 8;
+
+/// Default time budget of a whole `/ping-all`.
+const DEFAULT_PING_DEADLINE: Duration = Duration::from_secs(5);
+
+/// Number of entries fetched from the store at a time by `/keys`.
+const KEYS_PAGE_SIZE: usize = 100;
+
+/// How often the prompt answers DHT requests while waiting for a line.
+const IDLE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How often the prompt runs routing table and store upkeep while idle.
+const MAINTAIN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Transport of [`Client`].
+type Links = FragmentingTransport<
+    RateLimitedTransport<
+        MeteredTransport<MultiHomedTransport<FallbackTransport>>,
+    >,
+>;
+
+/// Documentation addresses (RFC 5737, RFC 3849), whose route tells the
+/// address this host reaches the internet from in each family.
+const ROUTE_PROBES: [IpAddr; 2] = [
+    IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+];
+
+/// Settings of the file given with `--config`, also read from
+/// `TESSERAS_<KEY>` environment variables. Flags take precedence.
+const CONFIG_KEYS: &[&str] = &["proxy", "quic", "webrtc"];
+
+/// Rendezvous client whose traffic is counted in the node metrics.
+type Client = RendezvousClient<Links>;
+
+/// How the client reaches other nodes, set on the command line.
+#[derive(Debug, Clone)]
+struct LinkOptions {
+    /// Listen on TCP too and fall back to it for nodes UDP does not
+    /// reach, cleared with `--no-tcp`.
+    tcp: bool,
+    /// Outbound limits, set with `--rate-limit` and `--peer-rate-limit`.
+    rate_limit: RateLimit,
+    /// Largest datagram sent in one piece, set with `--max-payload`.
+    max_payload: usize,
+    /// Time between heartbeats to the server, set with `--heartbeat`.
+    heartbeat: Duration,
+    /// Longest silence towards a peer in use, set with `--keepalive`.
+    /// `None` lets the NAT mappings towards peers lapse.
+    keepalive: Option<Duration>,
+    /// Addresses to listen on, set with `--bind` once per interface. Empty
+    /// listens on every address, in both families where available.
+    bind: Vec<IpAddr>,
+    /// Port to listen on, set with `--port`. Zero lets the OS pick one.
+    port: u16,
+    /// Options of the UDP socket, set with `--recv-buffer`,
+    /// `--send-buffer`, `--reuse-address`, `--reuse-port` and `--ttl`.
+    socket: SocketOptions,
+    /// Proxy TCP and WebSocket connections go through, set with `--proxy`
+    /// or the `proxy` setting of the config file.
+    proxy: Option<Socks5Proxy>,
+    /// Port to listen on for QUIC, set with `--quic` or the `quic`
+    /// setting of the config file. Zero lets the OS pick one, `None`
+    /// leaves QUIC off.
+    quic: Option<u16>,
+    /// STUN and TURN servers WebRTC channels are negotiated through, set
+    /// with `--webrtc` or the `webrtc` setting of the config file, `on`
+    /// for none. `None` leaves WebRTC off.
+    webrtc: Option<Vec<String>>,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let node_id = generate_random_node_id()?;
-    print_banner(&node_id);
+impl Default for LinkOptions {
+    fn default() -> Self {
+        LinkOptions {
+            tcp: true,
+            rate_limit: RateLimit::default(),
+            max_payload: DEFAULT_MAX_PAYLOAD,
+            heartbeat: DEFAULT_HEARTBEAT_INTERVAL,
+            keepalive: Some(DEFAULT_PEER_KEEPALIVE_INTERVAL),
+            bind: Vec::new(),
+            port: 0,
+            socket: SocketOptions {
+                reuse_address: false,
+                ..SocketOptions::default()
+            },
+            proxy: None,
+            quic: None,
+            webrtc: None,
+        }
+    }
+}
+
+/// Cumulative store counters shown by `/metrics`.
+#[derive(Debug, Default)]
+struct Metrics {
+    puts: u64,
+    gets: u64,
+    hits: u64,
+    misses: u64,
+}
 
-    let mut store: HashMap<String, String> = HashMap::new();
-    let stdin = io::stdin();
+/// Which store `/put` and `/get` operate on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Local scratch store, never shared with anyone.
+    Mock,
+    /// The node's own record store, the one it serves to the network.
+    Network,
+}
 
-    loop {
-        print!("tesseras> ");
-        io::stdout().flush()?;
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Mock => write!(f, "mock"),
+            Mode::Network => write!(f, "network"),
+        }
+    }
+}
 
-        let mut line = String::new();
-        let bytes = stdin.read_line(&mut line)?;
+/// How `/get` prints a value.
+#[derive(Debug, Clone, Copy)]
+enum ValueFormat {
+    /// Text quoted, binary values as a truncated hex dump.
+    Display,
+    /// Base64 of the raw bytes.
+    Base64,
+    /// Hex of the raw bytes.
+    Hex,
+}
 
-        if bytes == 0 {
-            println!();
-            break;
+/// Shells `tesseras completions` can write a script for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(format!("unsupported shell: {s} (bash, zsh or fish)")),
+        }
+    }
+}
+
+/// Subcommands accepted on the command line, with their descriptions.
+///
+/// Without one the interactive prompt starts.
+const SUBCOMMANDS: &[(&str, &str)] =
+    &[("completions", "Print a shell completion script")];
+
+/// The mock and network stores.
+#[derive(Default)]
+struct Stores {
+    mock: MemoryStore,
+    network: MemoryStore,
+}
+
+impl Stores {
+    /// The store `/put` and `/get` operate on in `mode`.
+    fn active(&mut self, mode: Mode) -> &mut dyn Store {
+        match mode {
+            Mode::Mock => &mut self.mock,
+            Mode::Network => &mut self.network,
         }
+    }
+}
+
+/// State shared by the command handlers.
+///
+/// Generic over the transport of the rendezvous client so the node logic
+/// does not depend on UDP, the CLI connects over [`Client`].
+struct Node<T: Transport = Links> {
+    /// Key pair the node id is derived from.
+    identity: Identity,
+    /// Id of [`Node::identity`].
+    node_id: NodeId,
+    /// Network joined on `/connect`, set with `--network`.
+    network: NetworkId,
+    links: LinkOptions,
+    mode: Mode,
+    stores: Stores,
+    aliases: BTreeMap<String, String>,
+    /// Metadata advertised on the next registration, see
+    /// [`advertised_capabilities`].
+    metadata: BTreeMap<String, String>,
+    client: Option<RendezvousClient<T>>,
+    metrics: Metrics,
+    traffic: Arc<TrafficCounters>,
+    /// Peers with node ids learnt from the rendezvous server.
+    routing: RoutingTable,
+    /// Addresses of the nodes in `routing`.
+    addrs: Addresses,
+    /// Nodes to join the network through, see [`handle_bootstrap`].
+    seeds: Vec<SocketAddr>,
+    /// Number of nodes a network put is replicated to.
+    replicas: usize,
+    /// How lookups are run, set with `/alpha` and `/paths`.
+    lookup: lookup::Options,
+    /// Origin and publishing schedule of the network store's records.
+    republisher: Republisher,
+    /// Records owed to replicas that missed a put, see [`deliver_hints`].
+    hints: Hints,
+    /// Records owed to stale replicas found by gets, see [`send_repairs`].
+    repairs: Repairs,
+    /// When the records were last reconciled with the neighbours, see
+    /// [`sync_replicas`].
+    synced: Instant,
+    /// Scripts being run by `/batch`, outermost first.
+    scripts: Vec<PathBuf>,
+    /// File the routing table is saved to on exit.
+    contacts_path: Option<PathBuf>,
+    /// Contacts saved by the previous run, not pinged yet.
+    saved: Vec<SavedContact>,
+    /// Identity keys pinned for peer addresses, handed to the client on
+    /// `/connect`, see [`Node::pins`].
+    pins: PinStore,
+    /// Routing table nodes already offered the records they should hold,
+    /// see [`migrate_records`].
+    migrated: HashSet<[u8; 20]>,
+}
 
-        let cmd = parse_command(&line);
+/// How [`Node::read_replicas`] asks the replicas of a key.
+type ReplicaRead<T> = fn(
+    &mut RendezvousClient<T>,
+    &RoutingTable,
+    &Addresses,
+    &str,
+    usize,
+    lookup::Options,
+    &mut dht::Serve<'_>,
+) -> dht::QuorumRead;
 
-        match cmd {
-            Command::Empty => {
-                continue;
+impl<T: Transport> Node<T> {
+    /// Identity pins in use: the client's while connected.
+    fn pins(&self) -> &PinStore {
+        self.client.as_ref().map_or(&self.pins, RendezvousClient::pins)
+    }
+
+    /// Forget the identity key pinned for `peer`, see
+    /// [`RendezvousClient::reset_pin`].
+    fn reset_pin(&mut self, peer: &str) -> Result<bool, PinError> {
+        match self.client.as_mut() {
+            Some(client) => client.reset_pin(peer),
+            None => self.pins.reset(peer),
+        }
+    }
+
+    /// Read `key` from the [`Node::replicas`] nodes closest to it, see
+    /// [`dht::quorum_read`]. Returns `None` when not connected.
+    fn get_quorum(&mut self, key: &str) -> Option<dht::QuorumRead> {
+        self.read_replicas(key, dht::quorum_read)
+    }
+
+    /// Read `key` from the [`Node::replicas`] nodes closest to it without
+    /// repairing them, see [`dht::read_replicas`]. Returns `None` when not
+    /// connected.
+    fn get_replicas(&mut self, key: &str) -> Option<dht::QuorumRead> {
+        self.read_replicas(key, dht::read_replicas)
+    }
+
+    fn read_replicas(
+        &mut self,
+        key: &str,
+        read: ReplicaRead<T>,
+    ) -> Option<dht::QuorumRead> {
+        let client = self.client.as_mut()?;
+        let (routing, addrs) = (&mut self.routing, &mut self.addrs);
+        let (identity, store) = (&self.identity, &mut self.stores.network);
+
+        let read = {
+            let mut serve = |msg: &RendezvousMessage| {
+                dht::answer(msg, identity, routing, addrs, store)
+            };
+            read(
+                client,
+                routing,
+                addrs,
+                key,
+                self.replicas,
+                self.lookup,
+                &mut serve,
+            )
+        };
+        learn_lookup(routing, addrs, &dht::key_id(key), &read.lookup);
+        Some(read)
+    }
+}
+
+/// Why a command could not run.
+#[derive(Debug, PartialEq, Eq)]
+enum CommandError {
+    /// The verb is neither a command nor an alias.
+    UnknownCommand { verb: String, suggestion: Option<String> },
+    /// A required argument was not given.
+    MissingArg(&'static str),
+    /// An argument is malformed or not accepted by the command.
+    InvalidArg(String),
+    /// The command needs a connection to a rendezvous server.
+    NotConnected,
+    /// The command was understood but could not be carried out.
+    Failed(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::UnknownCommand { verb, suggestion: Some(s) } => {
+                write!(f, "Unknown command: '{verb}'. Did you mean '{s}'?")
+            }
+            CommandError::UnknownCommand { verb, suggestion: None } => {
+                write!(f, "Unknown command: '{verb}'")
+            }
+            CommandError::MissingArg(what) => write!(f, "Missing {what}"),
+            CommandError::InvalidArg(reason) => {
+                write!(f, "Invalid argument: {reason}")
+            }
+            CommandError::NotConnected => {
+                write!(f, "Not connected. Use /connect <addr> first.")
+            }
+            CommandError::Failed(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+/// What the REPL does after a command.
+#[derive(Debug, PartialEq, Eq)]
+enum Flow {
+    Continue,
+    Quit,
+}
+
+/// Parse a command's arguments and run it. Invalid arguments are reported
+/// as an error message.
+type Handler = fn(&mut Node, &[&str]) -> Result<Flow, CommandError>;
+
+/// A CLI command.
+struct CommandSpec {
+    /// Verbs invoking the command. Aliases can not shadow these.
+    verbs: &'static [&'static str],
+    /// Usage and description lines shown by `/help`.
+    help: &'static [(&'static str, &'static str)],
+    handler: Handler,
+}
+
+/// Every CLI command, in `/help` order. Parsing, dispatch, help and the
+/// alias checks all read this table.
+const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        verbs: &["help"],
+        help: &[("/help", "Show information about this CLI")],
+        handler: run_help,
+    },
+    CommandSpec {
+        verbs: &["stats"],
+        help: &[("/stats", "Show node stats")],
+        handler: run_stats,
+    },
+    CommandSpec {
+        verbs: &["metrics"],
+        help: &[("/metrics [--json]", "Show cumulative counters, [--reset]")],
+        handler: run_metrics,
+    },
+    CommandSpec {
+        verbs: &["put"],
+        help: &[
+            ("/put <key> <value>", "Store a key/value pair"),
+            ("/put --b64 <k> <v>", "Store base64 encoded bytes"),
+        ],
+        handler: run_put,
+    },
+    CommandSpec {
+        verbs: &["cas"],
+        help: &[(
+            "/cas <k> <old> <v>",
+            "Put only if the value is <old>|--nil",
+        )],
+        handler: run_cas,
+    },
+    CommandSpec {
+        verbs: &["put-bytes"],
+        help: &[("/put-bytes <k> <h>", "Store hex encoded bytes")],
+        handler: run_put_bytes,
+    },
+    CommandSpec {
+        verbs: &["get"],
+        help: &[
+            ("/get <key>", "Retrieve a value by key"),
+            ("/get --all <key>", "Show the value of every replica"),
+            ("/get --quorum <key>", "Read the freshest value of replicas"),
+            ("/get --b64 <key>", "Retrieve a value as base64"),
+            ("/get --raw <key>", "Retrieve a value as hex"),
+            ("/get <prefix>*", "List keys starting with a prefix"),
+        ],
+        handler: run_get,
+    },
+    CommandSpec {
+        verbs: &["scan"],
+        help: &[("/scan <prefix>", "Same, with [--limit <n>]")],
+        handler: run_scan,
+    },
+    CommandSpec {
+        verbs: &["keys"],
+        help: &[("/keys", "List every stored key")],
+        handler: run_keys,
+    },
+    CommandSpec {
+        verbs: &["delete"],
+        help: &[("/delete <key>", "Delete a key")],
+        handler: run_delete,
+    },
+    CommandSpec {
+        verbs: &["compact"],
+        help: &[("/compact [<secs>]", "Purge tombstones older than 1 day")],
+        handler: run_compact,
+    },
+    CommandSpec {
+        verbs: &["name"],
+        help: &[("/name <name>", "Publish a name for this node, [--force]")],
+        handler: run_name,
+    },
+    CommandSpec {
+        verbs: &["resolve"],
+        help: &[("/resolve <name>", "Look up the peer id behind a name")],
+        handler: run_resolve,
+    },
+    CommandSpec {
+        verbs: &["ping"],
+        help: &[("/ping", "Ping the local node")],
+        handler: run_ping,
+    },
+    CommandSpec {
+        verbs: &["ping-all"],
+        help: &[("/ping-all", "Ping every peer, [--concurrency <n>]")],
+        handler: run_ping_all,
+    },
+    CommandSpec {
+        verbs: &["reset-pin"],
+        help: &[("/reset-pin <addr>", "Trust the next key of a peer")],
+        handler: run_reset_pin,
+    },
+    CommandSpec {
+        verbs: &["whoami"],
+        help: &[("/whoami", "Show the local node id")],
+        handler: run_whoami,
+    },
+    CommandSpec {
+        verbs: &["distance"],
+        help: &[(
+            "/distance <id> [<id>]",
+            "Show the XOR distance between two node ids",
+        )],
+        handler: run_distance,
+    },
+    CommandSpec {
+        verbs: &["selftest"],
+        help: &[("/selftest", "Check the store and rendezvous server")],
+        handler: run_self_test,
+    },
+    CommandSpec {
+        verbs: &["bench-store"],
+        help: &[("/bench-store <n>", "Measure store put/get latency")],
+        handler: run_bench_store,
+    },
+    CommandSpec {
+        verbs: &["connect"],
+        help: &[
+            ("/connect <addr>", "Register with a rendezvous server"),
+            ("/connect ws://<addr>", "Register over WebSocket"),
+        ],
+        handler: run_connect,
+    },
+    CommandSpec {
+        verbs: &["bootstrap"],
+        help: &[("/bootstrap [<addr>...]", "Join the network through seeds")],
+        handler: run_bootstrap,
+    },
+    CommandSpec {
+        verbs: &["find"],
+        help: &[("/find [--cap <t>]", "Find peers advertising capabilities")],
+        handler: run_find,
+    },
+    CommandSpec {
+        verbs: &["pending"],
+        help: &[("/pending [--clear]", "List or clear unanswered requests")],
+        handler: run_pending,
+    },
+    CommandSpec {
+        verbs: &["set-meta"],
+        help: &[("/set-meta <k> <v>", "Set metadata advertised on register")],
+        handler: run_set_meta,
+    },
+    CommandSpec {
+        verbs: &["unset-meta"],
+        help: &[("/unset-meta <key>", "Stop advertising a metadata field")],
+        handler: run_unset_meta,
+    },
+    CommandSpec {
+        verbs: &["get-meta"],
+        help: &[("/get-meta", "Show the advertised metadata")],
+        handler: run_get_meta,
+    },
+    CommandSpec {
+        verbs: &["mock"],
+        help: &[("/mock on|off", "Switch between mock and network mode")],
+        handler: run_mock,
+    },
+    CommandSpec {
+        verbs: &["tombstones"],
+        help: &[("/tombstones on|off", "Keep tombstones for deleted keys")],
+        handler: run_tombstones,
+    },
+    CommandSpec {
+        verbs: &["replicas"],
+        help: &[("/replicas [<n>]", "Show or set the replication factor")],
+        handler: run_replicas,
+    },
+    CommandSpec {
+        verbs: &["alpha"],
+        help: &[("/alpha [<n>]", "Show or set the lookup parallelism")],
+        handler: run_alpha,
+    },
+    CommandSpec {
+        verbs: &["paths"],
+        help: &[("/paths [<d>]", "Show or set the disjoint lookup paths")],
+        handler: run_paths,
+    },
+    CommandSpec {
+        verbs: &["republish"],
+        help: &[("/republish [<min>]", "Show or set the republish interval")],
+        handler: run_republish,
+    },
+    CommandSpec {
+        verbs: &["sync"],
+        help: &[("/sync", "Reconcile records with the closest nodes")],
+        handler: run_sync,
+    },
+    CommandSpec {
+        verbs: &["snapshot"],
+        help: &[("/snapshot <path>", "Save the node id and store to a file")],
+        handler: run_snapshot,
+    },
+    CommandSpec {
+        verbs: &["restore"],
+        help: &[("/restore <path>", "Load a snapshot into this fresh node")],
+        handler: run_restore,
+    },
+    CommandSpec {
+        verbs: &["alias"],
+        help: &[("/alias [<n> <cmd>]", "Define or list command aliases")],
+        handler: run_alias,
+    },
+    CommandSpec {
+        verbs: &["batch", "source"],
+        help: &[(
+            "/batch <path> [--continue]",
+            "Run the commands in a file, one per line",
+        )],
+        handler: run_batch,
+    },
+    CommandSpec {
+        verbs: &["quit", "bye", "exit"],
+        help: &[("/quit | /bye", "Exit the CLI")],
+        handler: run_quit,
+    },
+];
+
+/// A parsed command: what to run and its arguments.
+struct Invocation {
+    spec: &'static CommandSpec,
+    args: Vec<String>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or("warn"),
+    )
+    .format_timestamp(None)
+    .init();
+
+    let mut seeds = Vec::new();
+    let mut identity_path = None;
+    let mut contacts_path = None;
+    let mut pins_path = None;
+    let mut paths = 1;
+    let mut layout = Layout::Split;
+    let mut network = NetworkId::MAIN;
+    let mut links = LinkOptions::default();
+    let mut settings = Settings::new();
+    let mut config_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "completions" => {
+                let shell = args
+                    .next()
+                    .ok_or("usage: tesseras completions <bash|zsh|fish>")?
+                    .parse::<Shell>()?;
+                print!("{}", completion_script(shell));
+                return Ok(());
+            }
+            "--seed" => {
+                let addr =
+                    args.next().ok_or("usage: tesseras [--seed <addr>]...")?;
+                seeds.push(parse_seed(&addr)?);
+            }
+            "--identity" => {
+                let path = args
+                    .next()
+                    .ok_or("usage: tesseras [--identity <path>]")?;
+                identity_path = Some(PathBuf::from(path));
             }
-            Command::Info => {
-                handle_info();
+            "--contacts" => {
+                let path = args
+                    .next()
+                    .ok_or("usage: tesseras [--contacts <path>]")?;
+                contacts_path = Some(PathBuf::from(path));
             }
-            Command::Stats => {
-                handle_stats(&store);
+            "--pins" => {
+                let path =
+                    args.next().ok_or("usage: tesseras [--pins <path>]")?;
+                pins_path = Some(PathBuf::from(path));
             }
-            Command::Put { key, value } => {
-                handle_put(&mut store, key, value);
+            "--network" => {
+                network = args
+                    .next()
+                    .ok_or("usage: tesseras [--network <name>]")?
+                    .parse()?;
             }
-            Command::Get { key } => {
-                handle_get(&store, key);
+            "--paths" => {
+                let n = args.next().ok_or("usage: tesseras [--paths <d>]")?;
+                paths = parse_paths(&n)?;
             }
-            Command::Ping => {
-                handle_ping();
+            "--flat-buckets" => layout = Layout::Flat,
+            "--config" => {
+                let path =
+                    args.next().ok_or("usage: tesseras [--config <path>]")?;
+                config_path = Some(PathBuf::from(path));
             }
-            Command::Quit => {
-                println!("Bye 👋");
-                break;
+            "--proxy" => {
+                let proxy = args
+                    .next()
+                    .ok_or("usage: tesseras [--proxy <socks5://host:port>]")?;
+                settings.set("proxy", proxy, Source::Cli);
             }
-            Command::Unknown(raw) => {
-                eprintln!("Unknown command: {raw}");
-                println!("Type /help to see basic information.");
+            "--quic" => {
+                let port =
+                    args.next().ok_or("usage: tesseras [--quic <port>]")?;
+                settings.set("quic", port, Source::Cli);
             }
+            "--webrtc" => {
+                let servers = args
+                    .next()
+                    .ok_or("usage: tesseras [--webrtc <url>[,<url>]...|on]")?;
+                settings.set("webrtc", servers, Source::Cli);
+            }
+            "--no-tcp" => links.tcp = false,
+            "--rate-limit" => {
+                let rate = args
+                    .next()
+                    .ok_or("usage: tesseras [--rate-limit <n>[/<burst>]]")?;
+                links.rate_limit.global = Some(rate.parse()?);
+            }
+            "--peer-rate-limit" => {
+                let rate = args.next().ok_or(
+                    "usage: tesseras [--peer-rate-limit <n>[/<burst>]]",
+                )?;
+                links.rate_limit.per_peer = Some(rate.parse()?);
+            }
+            "--max-payload" => {
+                let bytes = args
+                    .next()
+                    .ok_or("usage: tesseras [--max-payload <bytes>]")?;
+                links.max_payload = bytes
+                    .parse()
+                    .map_err(|e| format!("bad max payload {bytes}: {e}"))?;
+            }
+            "--bind" => {
+                let ip = args.next().ok_or("usage: tesseras [--bind <ip>]")?;
+                links.bind.push(
+                    ip.parse()
+                        .map_err(|e| format!("bad address {ip}: {e}"))?,
+                );
+            }
+            "--port" => {
+                let port =
+                    args.next().ok_or("usage: tesseras [--port <port>]")?;
+                links.port = port
+                    .parse()
+                    .map_err(|e| format!("bad port {port}: {e}"))?;
+            }
+            "--recv-buffer" => {
+                let bytes = args
+                    .next()
+                    .ok_or("usage: tesseras [--recv-buffer <bytes>]")?;
+                links.socket.recv_buffer_size = Some(parse_bytes(&bytes)?);
+            }
+            "--send-buffer" => {
+                let bytes = args
+                    .next()
+                    .ok_or("usage: tesseras [--send-buffer <bytes>]")?;
+                links.socket.send_buffer_size = Some(parse_bytes(&bytes)?);
+            }
+            "--reuse-address" => links.socket.reuse_address = true,
+            "--reuse-port" => links.socket.reuse_port = true,
+            "--ttl" => {
+                let ttl =
+                    args.next().ok_or("usage: tesseras [--ttl <hops>]")?;
+                links.socket.ttl = Some(
+                    ttl.parse()
+                        .ok()
+                        .filter(|ttl| (1..=255).contains(ttl))
+                        .ok_or_else(|| {
+                            format!("ttl must be between 1 and 255: {ttl}")
+                        })?,
+                );
+            }
+            "--heartbeat" => {
+                let secs = args
+                    .next()
+                    .ok_or("usage: tesseras [--heartbeat <secs>]")?;
+                links.heartbeat = parse_interval(&secs)?;
+            }
+            "--keepalive" => {
+                let secs = args
+                    .next()
+                    .ok_or("usage: tesseras [--keepalive <secs>|off]")?;
+                links.keepalive = match secs.as_str() {
+                    "off" => None,
+                    secs => Some(parse_interval(secs)?),
+                };
+            }
+            other => {
+                return Err(format!("unknown subcommand: {other}").into());
+            }
+        }
+    }
+
+    if let Some(path) = &config_path {
+        settings.load_file(path, CONFIG_KEYS)?;
+    }
+    settings.load_env(CONFIG_KEYS, std::env::vars());
+    if let Some((proxy, _)) = settings.get("proxy") {
+        links.proxy = Some(proxy.parse()?);
+    }
+    if let Some((port, _)) = settings.get("quic") {
+        links.quic = Some(
+            port.parse().map_err(|e| format!("bad QUIC port {port}: {e}"))?,
+        );
+    }
+    if let Some((servers, _)) = settings.get("webrtc") {
+        links.webrtc = Some(parse_ice_servers(servers)?);
+    }
+
+    let (identity, quality) = load_identity(identity_path)?;
+    let node_id = identity.node_id();
+    print_banner(&node_id);
+    if quality == Some(Quality::Weak) {
+        println!(
+            "Warning: the identity key was derived from a weak seed, the \
+             entropy source did not answer in time."
+        );
+    }
+
+    let contacts_path =
+        contacts_path.or_else(|| home_path(DEFAULT_CONTACTS_PATH));
+    let saved = match &contacts_path {
+        Some(path) => {
+            contacts::load(path, node_id.as_bytes()).unwrap_or_else(|e| {
+                println!("Warning: ignoring saved contacts: {e}");
+                Vec::new()
+            })
         }
+        None => Vec::new(),
+    };
+    if !saved.is_empty() {
+        println!(
+            "Loaded {} saved contact(s), pinged on /connect.",
+            saved.len()
+        );
     }
+    let pins = match pins_path.or_else(|| home_path(DEFAULT_PINS_PATH)) {
+        Some(path) => PinStore::open(&path)
+            .map_err(|e| format!("identity pins {}: {e}", path.display()))?,
+        None => PinStore::new(),
+    };
+
+    let mut node = Node {
+        identity,
+        node_id,
+        network,
+        links,
+        mode: Mode::Mock,
+        stores: Stores::default(),
+        aliases: BTreeMap::new(),
+        metadata: BTreeMap::new(),
+        client: None,
+        metrics: Metrics::default(),
+        traffic: Arc::new(TrafficCounters::default()),
+        routing: RoutingTable::with_layout(*node_id.as_bytes(), layout),
+        addrs: Addresses::new(),
+        seeds,
+        replicas: DEFAULT_REPLICATION_FACTOR,
+        lookup: lookup::Options { paths, ..Default::default() },
+        republisher: Republisher::default(),
+        hints: Hints::default(),
+        repairs: Repairs::default(),
+        synced: Instant::now(),
+        scripts: Vec::new(),
+        contacts_path,
+        saved,
+        pins,
+        migrated: HashSet::new(),
+    };
+    // Stdin is read by a thread that can not be cancelled, so the runtime
+    // does not wait for it.
+    let runtime = runtime::build()?;
+    let result = runtime.block_on(repl(&mut node));
+    runtime.shutdown_background();
+    result?;
 
+    save_contacts(&node);
+    if let Err(e) = node.pins().save() {
+        eprintln!("Could not save identity pins: {e}");
+    }
     Ok(())
 }
 
-/// Generate a random 20-byte NodeId by reading from /dev/urandom.
-/// Returns [u8; 20].
-fn generate_random_node_id() -> Result<[u8; 20], Box<dyn std::error::Error>> {
-    let mut file = File::open("/dev/urandom")?;
-    let mut buf = [0u8; 20];
-    file.read_exact(&mut buf)?;
-    Ok(buf)
+/// Read commands until `/quit` or the end of the input.
+///
+/// While waiting for a line the node keeps answering DHT requests every
+/// [`IDLE_INTERVAL`] and runs [`maintain`] every [`MAINTAIN_INTERVAL`],
+/// besides before each command.
+async fn repl(node: &mut Node) -> Result<(), Box<dyn std::error::Error>> {
+    let mut lines =
+        runtime::read_lines(tokio::io::BufReader::new(tokio::io::stdin()));
+    let mut idle = runtime::interval(IDLE_INTERVAL);
+    let mut maintained = Instant::now();
+
+    loop {
+        print!("tesseras> ");
+        io::stdout().flush()?;
+
+        let line = loop {
+            match runtime::next_event(&mut lines, &mut idle).await {
+                Event::Received(line) => break line,
+                Event::Tick => {
+                    serve_idle(node);
+                    if maintained.elapsed() >= MAINTAIN_INTERVAL {
+                        maintain(node);
+                        maintained = Instant::now();
+                    }
+                }
+            }
+        };
+        let Some(line) = line else {
+            println!();
+            return Ok(());
+        };
+
+        maintain(node);
+        maintained = Instant::now();
+        if run_line(node, &line) == Flow::Quit {
+            return Ok(());
+        }
+    }
+}
+
+/// Answer the DHT requests received while no command runs.
+fn serve_idle(node: &mut Node) {
+    let Some(client) = node.client.as_mut() else {
+        return;
+    };
+    let (routing, addrs) = (&mut node.routing, &mut node.addrs);
+    let (identity, store) = (&node.identity, &mut node.stores.network);
+    let mut serve = |msg: &RendezvousMessage| {
+        dht::answer(msg, identity, routing, addrs, store)
+    };
+    if let Err(e) = client.recv_rpc(&[], Instant::now(), &mut serve) {
+        println!("Error serving requests: {e}");
+    }
+}
+
+/// `relative` under the home directory, if there is one.
+fn home_path(relative: &str) -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(relative))
+}
+
+/// Save the routing table for the next run, see [`rejoin`].
+///
+/// An empty table is not saved, so a run that never connected keeps the
+/// contacts of the previous one.
+fn save_contacts(node: &Node) {
+    let Some(path) = &node.contacts_path else {
+        return;
+    };
+    if node.routing.is_empty() {
+        return;
+    }
+
+    let saved = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| contacts::save(path, &node.routing, &node.addrs));
+    match saved {
+        Ok(count) => {
+            println!("Saved {count} contact(s) to {}", path.display())
+        }
+        Err(e) => eprintln!("Could not save contacts: {e}"),
+    }
 }
 
-/// Convert a 20-byte ID into uppercase hexadecimal and return as String.
-fn node_id_to_hex(id: &[u8; 20]) -> String {
-    let mut out = String::with_capacity(40);
-    for byte in id {
-        out.push_str(&format!("{:02X}", byte));
+/// Load the identity key from `path`, the file named by [`IDENTITY_VAR`]
+/// or [`DEFAULT_IDENTITY_PATH`] under the home directory, creating it on
+/// the first run. Without a home directory the key only lasts this run.
+///
+/// The seed of a new key is read from /dev/urandom. If it does not answer
+/// within [`ENTROPY_TIMEOUT`] the fallback named by
+/// [`ENTROPY_FALLBACK_VAR`] applies, waiting by default. Returns the
+/// quality of the seed when the key was created.
+fn load_identity(
+    path: Option<PathBuf>,
+) -> Result<(Identity, Option<Quality>), Box<dyn std::error::Error>> {
+    let fallback = match std::env::var(ENTROPY_FALLBACK_VAR) {
+        Ok(value) => value
+            .parse()
+            .map_err(|e| format!("invalid {ENTROPY_FALLBACK_VAR}: {e}"))?,
+        Err(_) => Fallback::default(),
+    };
+    let mut quality = None;
+    let mut seed = || {
+        let (seed, q) = entropy::seed(ENTROPY_TIMEOUT, fallback)?;
+        quality = Some(q);
+        Ok(seed)
+    };
+
+    let path = path
+        .or_else(|| std::env::var_os(IDENTITY_VAR).map(PathBuf::from))
+        .or_else(|| home_path(DEFAULT_IDENTITY_PATH));
+    let Some(path) = path else {
+        println!(
+            "Warning: no home directory, the node id only lasts this run."
+        );
+        return Ok((Identity::from_seed(seed()?), quality));
+    };
+
+    let (identity, created) = Identity::load_or_create(&path, &mut seed)
+        .map_err(|e| format!("identity key {}: {e}", path.display()))?;
+    if created {
+        println!("Created identity key {}", path.display());
     }
-    out
+    Ok((identity, quality))
 }
 
 /// Print the Tesseras banner.
-fn print_banner(node_id: &[u8; 20]) {
+fn print_banner(node_id: &NodeId) {
     let banner = format!(
         r#"
      ████████╗███████╗███████╗███████╗███████╗██████╗  █████╗ ███████╗
@@ -114,11 +1089,17 @@ fn print_banner(node_id: &[u8; 20]) {
         ██║   ███████╗███████║███████║███████╗██║  ██║██║  ██║███████║
         ╚═╝   ╚══════╝╚══════╝╚══════╝╚══════╝╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝
 
-                    ID: {}
-             PUBLIC IP: 123.456.789.101:1222
+                    ID: {:X} ({})
+             ADDRESSES: {}
                STORAGE: 5GB
 "#,
-        node_id_to_hex(node_id)
+        node_id,
+        fingerprint(node_id.as_bytes()),
+        local_ips()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
     );
 
     const HELP: &str = r#"
@@ -129,109 +1110,4532 @@ Type /help for information or /quit to exit.
     println!("{banner}{HELP}");
 }
 
-/// Parse a raw input line into a Command.
+/// Run every command in a raw input line.
+///
+/// Commands are separated by `;` and run in order, see
+/// [`split_commands`]. Empty segments are skipped.
+fn run_line(node: &mut Node, input: &str) -> Flow {
+    for segment in split_commands(input) {
+        match run_command(node, segment) {
+            Ok(Some(Flow::Quit)) => return Flow::Quit,
+            Ok(_) => {}
+            Err((e, spec)) => report_error(&e, spec),
+        }
+    }
+
+    Flow::Continue
+}
+
+/// Parse and run a single command, `None` if it was empty. Errors come
+/// with the command they are for, when it was recognized.
+fn run_command(
+    node: &mut Node,
+    input: &str,
+) -> Result<Option<Flow>, (CommandError, Option<&'static CommandSpec>)> {
+    let Some(Invocation { spec, args }) =
+        parse_command(input, &node.aliases).map_err(|e| (e, None))?
+    else {
+        return Ok(None);
+    };
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    (spec.handler)(node, &args).map(Some).map_err(|e| (e, Some(spec)))
+}
+
+/// Print a failed command, with the hints of [`error_hints`].
+fn report_error(error: &CommandError, spec: Option<&CommandSpec>) {
+    eprintln!("{error}");
+    for hint in error_hints(error, spec) {
+        println!("{hint}");
+    }
+}
+
+/// Lines printed after a failed command: the usage of `spec` when its
+/// arguments were at fault, or where to find the commands for an unknown
+/// one.
+fn error_hints(
+    error: &CommandError,
+    spec: Option<&CommandSpec>,
+) -> Vec<String> {
+    match (error, spec) {
+        (
+            CommandError::MissingArg(_) | CommandError::InvalidArg(_),
+            Some(spec),
+        ) => spec
+            .help
+            .iter()
+            .map(|(usage, _)| format!("Usage: {usage}"))
+            .collect(),
+        (CommandError::UnknownCommand { .. }, _) => {
+            vec!["Type /help to see basic information.".to_string()]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Split a line into its commands at every `;` outside quotes and not
+/// escaped with `\`. Quotes and escapes are kept for [`split_words`].
+fn split_commands(line: &str) -> Vec<&str> {
+    let mut commands = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, c) in line.char_indices() {
+        match (c, quote) {
+            _ if escaped => escaped = false,
+            ('\\', _) => escaped = true,
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (';', None) => {
+                commands.push(&line[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    commands.push(&line[start..]);
+    commands
+}
+
+/// Split a command into words at whitespace. Single or double quotes keep
+/// a word together, and `\` takes the next character as is.
+fn split_words(line: &str) -> Result<Vec<String>, CommandError> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', _) => {
+                let escaped = chars.next().ok_or_else(|| {
+                    CommandError::InvalidArg("trailing '\\'".to_string())
+                })?;
+                word.get_or_insert_default().push(escaped);
+            }
+            ('\'' | '"', None) => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            (c, Some(q)) if c == q => quote = None,
+            (c, None) if c.is_whitespace() => words.extend(word.take()),
+            (c, _) => word.get_or_insert_default().push(c),
+        }
+    }
+    if let Some(q) = quote {
+        return Err(CommandError::InvalidArg(format!("unterminated {q}")));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Quote `word` so that [`split_words`] reads it back unchanged.
+fn quote_word(word: &str) -> String {
+    let plain =
+        |c: char| !c.is_whitespace() && !matches!(c, '\'' | '"' | '\\' | ';');
+    if !word.is_empty() && word.chars().all(plain) {
+        return word.to_string();
+    }
+
+    let mut quoted = String::from('"');
+    for c in word.chars() {
+        if matches!(c, '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Parse a raw command into the command to run and its arguments, or
+/// `None` for an empty command.
 ///
 /// Supported forms:
 ///   /put key value
 ///   put key value
+///   put key "a value; with spaces"
 ///   > /put key value
-fn parse_command(input: &str) -> Command {
-    let mut line = input.trim().to_string();
+///
+/// Any run of leading `>` and `/` characters, as left behind by pasted
+/// prompts, is ignored. A leading alias is expanded before the line is
+/// split into words, see [`split_words`].
+fn parse_command(
+    input: &str,
+    aliases: &BTreeMap<String, String>,
+) -> Result<Option<Invocation>, CommandError> {
+    let line = input
+        .trim_start_matches(|c: char| {
+            c == '>' || c == '/' || c.is_whitespace()
+        })
+        .trim_end()
+        .to_string();
 
-    if let Some(stripped) = line.strip_prefix('>') {
-        line = stripped.trim_start().to_string();
+    if line.is_empty() {
+        return Ok(None);
     }
 
-    if let Some(stripped) = line.strip_prefix('/') {
-        line = stripped.trim_start().to_string();
-    }
+    let line = expand_alias(&line, aliases).map_err(CommandError::Failed)?;
+    let mut parts = split_words(&line)?.into_iter();
+    let Some(verb) = parts.next().map(|verb| verb.to_lowercase()) else {
+        return Ok(None);
+    };
 
-    if line.is_empty() {
-        return Command::Empty;
-    }
-
-    let mut parts = line.split_whitespace();
-    let cmd = parts.next().unwrap().to_lowercase();
-
-    match cmd.as_str() {
-        "help" => Command::Info,
-        "stats" => Command::Stats,
-        "ping" => Command::Ping,
-        "quit" | "bye" | "exit" => Command::Quit,
-        "put" => {
-            let key = match parts.next() {
-                Some(k) => k.to_string(),
-                None => {
-                    return Command::Unknown("missing key for put".into());
-                }
-            };
+    let Some(spec) = find_command(&verb) else {
+        return Err(CommandError::UnknownCommand {
+            suggestion: suggest_verb(&verb, aliases).map(str::to_string),
+            verb,
+        });
+    };
 
-            let value = parts.collect::<Vec<_>>().join(" ");
-            if value.is_empty() {
-                return Command::Unknown("missing value for put".into());
-            }
+    Ok(Some(Invocation { spec, args: parts.collect() }))
+}
 
-            Command::Put { key, value }
-        }
-        "get" => {
-            let key = match parts.next() {
-                Some(k) => k.to_string(),
-                None => {
-                    return Command::Unknown("missing key for get".into());
-                }
-            };
+/// The command invoked by `verb`.
+fn find_command(verb: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.verbs.contains(&verb))
+}
+
+/// Handle `/help`.
+fn run_help(node: &mut Node, _args: &[&str]) -> Result<Flow, CommandError> {
+    handle_info(node.mode);
+    Ok(Flow::Continue)
+}
+
+/// Handle `/stats`.
+fn run_stats(node: &mut Node, _args: &[&str]) -> Result<Flow, CommandError> {
+    let store = node.stores.active(node.mode);
+    handle_stats(
+        store,
+        node.mode,
+        &node.routing,
+        node.network,
+        node.client.as_mut(),
+    );
+    Ok(Flow::Continue)
+}
 
-            Command::Get { key }
+/// Handle `/metrics [--json] [--reset]`.
+fn run_metrics(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let mut json = false;
+    let mut reset = false;
+
+    for arg in args {
+        match *arg {
+            "--json" => json = true,
+            "--reset" => reset = true,
+            _ => return Err(unexpected(arg)),
         }
-        _ => Command::Unknown(line),
     }
+
+    for line in handle_metrics(&mut node.metrics, &node.traffic, json, reset) {
+        println!("{line}");
+    }
+    Ok(Flow::Continue)
 }
 
-/// Handle `/help` command.
-fn handle_info() {
-    println!("Tesseras - Networking");
-    println!("This CLI is currently running in local MOCK mode.");
-    println!("Available commands:");
-    println!("  /help              - Show information about this CLI");
-    println!("  /stats             - Show mock stats");
-    println!("  /put <key> <value> - Store a key/value pair (local mock)");
-    println!("  /get <key>         - Retrieve a value by key (local mock)");
-    println!("  /ping              - Ping the local node");
-    println!("  /quit | /bye       - Exit the CLI");
+/// Handle `/put [--b64] <key> <value>`.
+fn run_put(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let mut args = args.iter().copied().peekable();
+    let b64 = args.next_if_eq(&"--b64").is_some();
+
+    let key = args.next().ok_or(CommandError::MissingArg("key"))?;
+    check_key(key)?;
+
+    let value = args.collect::<Vec<_>>().join(" ");
+    if value.is_empty() {
+        return Err(CommandError::MissingArg("value"));
+    }
+
+    let value = if b64 {
+        let bytes = BASE64.decode(&value).map_err(|e| {
+            CommandError::InvalidArg(format!("bad base64 value: {e}"))
+        })?;
+        Value::Bytes(bytes)
+    } else {
+        Value::Utf8(value)
+    };
+
+    put_value(node, key, value);
+    Ok(Flow::Continue)
 }
 
-/// Handle `/stats` command.
-fn handle_stats(store: &HashMap<String, String>) {
-    println!("--- Tesseras Stats (mock) ---");
-    println!("Stored keys (local mock): {}", store.len());
-    println!("Routing table nodes      : <not implemented yet>");
-    println!("Network ID               : <not implemented yet>");
-    println!("------------------------------");
+/// Put `value` in the active store and, in network mode, replicate it to
+/// the nodes closest to `key`.
+fn put_value(node: &mut Node, key: &str, value: Value) {
+    let replica = (node.mode == Mode::Network).then(|| value.clone());
+    let previous = node.stores.network.seq(key);
+    let store = node.stores.active(node.mode);
+    handle_put(store, &mut node.metrics, node.mode, key.to_string(), value);
+
+    if let Some(value) = replica {
+        node.stores.network.set_seq(key, next_seq(previous));
+        node.republisher.originated(key, Instant::now());
+        handle_replicate(node, key, &value);
+    }
 }
 
-/// Handle `/put` command.
-fn handle_put(
-    store: &mut HashMap<String, String>,
-    key: String,
-    value: String,
-) {
-    store.insert(key.clone(), value.clone());
-    println!("Stored (mock): key='{key}', value='{value}'");
+/// Handle `/cas <key> <expected>|--nil <value>`.
+fn run_cas(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let (key, expected, new) = match args {
+        [] => return Err(CommandError::MissingArg("key")),
+        [_] => return Err(CommandError::MissingArg("expected value")),
+        [_, _] => return Err(CommandError::MissingArg("value")),
+        [key, expected, new @ ..] => (key, expected, new),
+    };
+    check_key(key)?;
+
+    let expected = match *expected {
+        "--nil" => None,
+        expected => Some(Value::Utf8(expected.to_string())),
+    };
+
+    let store = node.stores.active(node.mode);
+    handle_cas(
+        store,
+        &mut node.metrics,
+        node.mode,
+        key,
+        expected,
+        Value::Utf8(new.join(" ")),
+    );
+    Ok(Flow::Continue)
 }
 
-/// Handle `/get` command.
-fn handle_get(store: &HashMap<String, String>, key: String) {
-    match store.get(&key) {
-        Some(value) => {
-            println!("Found (mock): key='{key}', value='{value}'");
+/// Refuse keys in the namespace reserved for name records.
+fn check_key(key: &str) -> Result<(), CommandError> {
+    if key.starts_with(naming::NAMESPACE) {
+        return Err(CommandError::InvalidArg(format!(
+            "keys under '{}' are reserved, use /name",
+            naming::NAMESPACE
+        )));
+    }
+    Ok(())
+}
+
+/// Handle `/put-bytes <key> <hex>`.
+fn run_put_bytes(
+    node: &mut Node,
+    args: &[&str],
+) -> Result<Flow, CommandError> {
+    let (key, hex) = match args {
+        [] => return Err(CommandError::MissingArg("key")),
+        [_] => return Err(CommandError::MissingArg("hex value")),
+        [key, hex] => (key, hex),
+        [_, _, extra, ..] => return Err(unexpected(extra)),
+    };
+    check_key(key)?;
+
+    let bytes = decode_hex(hex).ok_or_else(|| {
+        CommandError::InvalidArg(format!("bad hex value: {hex}"))
+    })?;
+
+    put_value(node, key, Value::Bytes(bytes));
+    Ok(Flow::Continue)
+}
+
+/// Handle `/get [--all|--quorum] [--b64|--raw] <key>` and `/get <prefix>*
+/// [--limit <n>]`.
+fn run_get<T: Transport>(
+    node: &mut Node<T>,
+    args: &[&str],
+) -> Result<Flow, CommandError> {
+    let mut key = None;
+    let mut all = false;
+    let mut format = ValueFormat::Display;
+    let mut limit = None;
+    let mut quorum = false;
+
+    let mut args = args.iter().copied();
+    while let Some(arg) = args.next() {
+        match arg {
+            "--all" => all = true,
+            "--quorum" => quorum = true,
+            "--b64" => format = ValueFormat::Base64,
+            "--raw" => format = ValueFormat::Hex,
+            "--limit" => limit = Some(parse_limit(args.next())?),
+            _ if key.is_none() => key = Some(arg.to_string()),
+            _ => return Err(unexpected(arg)),
+        }
+    }
+
+    let key = key.ok_or(CommandError::MissingArg("key"))?;
+    if all && quorum {
+        return Err(CommandError::InvalidArg(
+            "--all and --quorum can not be combined".into(),
+        ));
+    }
+    // In mock mode there is a single replica, so --all is a normal get.
+    if all && node.mode == Mode::Network {
+        if key.ends_with('*') || limit.is_some() {
+            return Err(CommandError::InvalidArg(
+                "--all only applies to single keys".into(),
+            ));
+        }
+        for line in handle_get_all(node, &key, format)? {
+            println!("{line}");
+        }
+        return Ok(Flow::Continue);
+    }
+    if quorum {
+        if key.ends_with('*') || limit.is_some() {
+            return Err(CommandError::InvalidArg(
+                "--quorum only applies to single keys".into(),
+            ));
+        }
+        if node.mode != Mode::Network {
+            return Err(CommandError::InvalidArg(
+                "--quorum only applies in network mode".into(),
+            ));
+        }
+        return run_get_quorum(node, &key, format);
+    }
+    let mode = node.mode;
+    let store = node.stores.active(mode);
+
+    match key.strip_suffix('*') {
+        Some(prefix) => {
+            let limit = limit.unwrap_or(DEFAULT_SCAN_LIMIT);
+            handle_scan(store, mode, prefix.to_string(), limit);
+        }
+        None if limit.is_some() => {
+            return Err(CommandError::InvalidArg(
+                "--limit only applies to prefix lookups".into(),
+            ));
         }
         None => {
-            println!("Key '{key}' not found (mock).");
+            let network = node.client.as_mut().map(|client| Reach {
+                client,
+                identity: &node.identity,
+                routing: &mut node.routing,
+                addrs: &mut node.addrs,
+                options: node.lookup,
+                replicas: node.replicas,
+                repairs: &mut node.repairs,
+            });
+            handle_get(store, network, &mut node.metrics, mode, key, format);
         }
     }
+    Ok(Flow::Continue)
 }
 
-/// Handle `/ping` command.
-fn handle_ping() {
-    println!("PONG (mock)");
+/// Handle `/scan [<prefix>] [--limit <n>]`.
+fn run_scan(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let mut prefix = None;
+    let mut limit = DEFAULT_SCAN_LIMIT;
+
+    let mut args = args.iter().copied();
+    while let Some(arg) = args.next() {
+        match arg {
+            "--limit" => limit = parse_limit(args.next())?,
+            _ if prefix.is_none() => prefix = Some(arg.to_string()),
+            _ => return Err(unexpected(arg)),
+        }
+    }
+
+    let store = node.stores.active(node.mode);
+    handle_scan(store, node.mode, prefix.unwrap_or_default(), limit);
+    Ok(Flow::Continue)
+}
+
+/// Handle `/keys`.
+fn run_keys(node: &mut Node, _args: &[&str]) -> Result<Flow, CommandError> {
+    handle_keys(node.stores.active(node.mode), node.mode);
+    Ok(Flow::Continue)
+}
+
+/// Handle `/delete <key>`.
+fn run_delete(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let key = args.first().ok_or(CommandError::MissingArg("key"))?;
+    handle_delete(node.stores.active(node.mode), node.mode, key);
+    Ok(Flow::Continue)
+}
+
+/// Handle `/compact [<secs>]`.
+fn run_compact(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let grace = match args.first().map(|s| s.parse::<u64>()) {
+        None => DEFAULT_TOMBSTONE_GRACE,
+        Some(Ok(secs)) => Duration::from_secs(secs),
+        Some(Err(_)) => {
+            return Err(CommandError::InvalidArg(
+                "the grace period must be a number of seconds".into(),
+            ));
+        }
+    };
+
+    handle_compact(node.stores.active(node.mode), node.mode, grace);
+    Ok(Flow::Continue)
+}
+
+/// Handle `/name <name> [--force]`.
+fn run_name(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let (name, policy) = match args {
+        [name] => (name, Policy::FirstCome),
+        [name, "--force"] | ["--force", name] => (name, Policy::LastWriter),
+        [] => return Err(CommandError::MissingArg("name")),
+        [_, extra, ..] => return Err(unexpected(extra)),
+    };
+
+    println!(
+        "{}",
+        handle_name(
+            node.stores.active(node.mode),
+            node.mode,
+            node.client.as_mut(),
+            &node.node_id,
+            name,
+            policy,
+        )
+    );
+    Ok(Flow::Continue)
+}
+
+/// Handle `/resolve <name>`.
+fn run_resolve(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let name = args.first().ok_or(CommandError::MissingArg("name"))?;
+    println!(
+        "{}",
+        handle_resolve(node.stores.active(node.mode), node.mode, name)
+    );
+    Ok(Flow::Continue)
+}
+
+/// Handle `/ping`.
+fn run_ping(_node: &mut Node, _args: &[&str]) -> Result<Flow, CommandError> {
+    handle_ping();
+    Ok(Flow::Continue)
+}
+
+/// Handle `/ping-all [--concurrency <n>] [--deadline <secs>]`.
+fn run_ping_all(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let mut concurrency = DEFAULT_PING_CONCURRENCY;
+    let mut deadline = DEFAULT_PING_DEADLINE;
+
+    let mut args = args.iter().copied();
+    while let Some(arg) = args.next() {
+        let value = args.next().and_then(|v| v.parse::<u64>().ok());
+        match (arg, value) {
+            ("--concurrency", Some(n)) if n > 0 => concurrency = n as usize,
+            ("--deadline", Some(secs)) => {
+                deadline = Duration::from_secs(secs);
+            }
+            ("--concurrency" | "--deadline", _) => {
+                return Err(CommandError::InvalidArg(format!(
+                    "{arg} expects a positive number"
+                )));
+            }
+            _ => return Err(unexpected(arg)),
+        }
+    }
+
+    let client = node.client.as_mut().ok_or(CommandError::NotConnected)?;
+    handle_ping_all(
+        client,
+        &mut node.routing,
+        &mut node.addrs,
+        concurrency,
+        deadline,
+    );
+    Ok(Flow::Continue)
+}
+
+/// Handle `/whoami`.
+fn run_whoami(node: &mut Node, _args: &[&str]) -> Result<Flow, CommandError> {
+    handle_whoami(&node.identity);
+    Ok(Flow::Continue)
+}
+
+/// Handle `/distance <id> [<id>]`.
+///
+/// With a single id the distance is taken from the local node id.
+fn run_distance(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let (a, b) = match args {
+        [a] => (node.node_id, parse_node_id(a)?),
+        [a, b] => (parse_node_id(a)?, parse_node_id(b)?),
+        [] => return Err(CommandError::MissingArg("node id")),
+        [_, _, extra, ..] => return Err(unexpected(extra)),
+    };
+
+    for line in handle_distance(&a, &b) {
+        println!("{line}");
+    }
+    Ok(Flow::Continue)
+}
+
+/// Handle `/selftest`.
+fn run_self_test(
+    node: &mut Node,
+    _args: &[&str],
+) -> Result<Flow, CommandError> {
+    let store = node.stores.active(node.mode);
+    for line in handle_self_test(store, node.mode, node.client.as_mut()) {
+        println!("{line}");
+    }
+    Ok(Flow::Continue)
+}
+
+/// Handle `/bench-store <n>`.
+fn run_bench_store(
+    node: &mut Node,
+    args: &[&str],
+) -> Result<Flow, CommandError> {
+    let n = match args.first().map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n > 0 => n,
+        Some(_) => {
+            return Err(CommandError::InvalidArg(
+                "the operation count must be a positive number".into(),
+            ));
+        }
+        None => return Err(CommandError::MissingArg("operation count")),
+    };
+
+    handle_bench_store(node.stores.active(node.mode), n);
+    Ok(Flow::Continue)
+}
+
+/// Handle `/reset-pin <addr>`.
+fn run_reset_pin(
+    node: &mut Node,
+    args: &[&str],
+) -> Result<Flow, CommandError> {
+    let peer = args.first().ok_or(CommandError::MissingArg("address"))?;
+    let peer = peer.parse().map_err(|e| {
+        CommandError::InvalidArg(format!("bad peer address {peer}: {e}"))
+    })?;
+    println!("{}", handle_reset_pin(node, peer));
+    Ok(Flow::Continue)
+}
+
+/// Handle `/connect <addr>`.
+fn run_connect(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let addr = args.first().ok_or(CommandError::MissingArg("address"))?;
+    // The pins move to the new client, or back to the previous one if
+    // it is kept.
+    if let Some(client) = node.client.as_mut() {
+        node.pins = client.take_pins();
+    }
+    handle_connect(
+        &mut node.client,
+        &node.traffic,
+        &node.node_id,
+        node.network,
+        node.links.clone(),
+        &node.metadata,
+        addr.to_string(),
+    );
+    if let Some(client) = node.client.as_mut() {
+        client.set_pins(std::mem::take(&mut node.pins));
+    }
+    rejoin(node);
+
+    if let Some(client) = node.client.as_mut()
+        && !node.seeds.is_empty()
+    {
+        handle_bootstrap(
+            client,
+            &mut node.routing,
+            &mut node.addrs,
+            &mut node.stores.network,
+            &node.identity,
+            &node.seeds,
+            node.lookup,
+        );
+    }
+    Ok(Flow::Continue)
+}
+
+/// Handle `/bootstrap [<addr>...]`.
+///
+/// Addresses given replace the seeds set with `--seed`.
+fn run_bootstrap(
+    node: &mut Node,
+    args: &[&str],
+) -> Result<Flow, CommandError> {
+    if !args.is_empty() {
+        node.seeds = args
+            .iter()
+            .map(|arg| parse_seed(arg).map_err(CommandError::InvalidArg))
+            .collect::<Result<_, _>>()?;
+    }
+    if node.seeds.is_empty() {
+        return Err(CommandError::MissingArg("seed address"));
+    }
+
+    let client = node.client.as_mut().ok_or(CommandError::NotConnected)?;
+    handle_bootstrap(
+        client,
+        &mut node.routing,
+        &mut node.addrs,
+        &mut node.stores.network,
+        &node.identity,
+        &node.seeds,
+        node.lookup,
+    );
+    Ok(Flow::Continue)
+}
+
+/// Handle `/find [--cap <tag>]...`.
+fn run_find<T: Transport>(
+    node: &mut Node<T>,
+    args: &[&str],
+) -> Result<Flow, CommandError> {
+    let mut capabilities = Vec::new();
+
+    let mut args = args.iter().copied();
+    while let Some(arg) = args.next() {
+        match (arg, args.next()) {
+            ("--cap", Some(tag)) => capabilities.push(tag.to_string()),
+            ("--cap", None) => {
+                return Err(CommandError::MissingArg("tag for --cap"));
+            }
+            _ => return Err(unexpected(arg)),
+        }
+    }
+
+    let client = node.client.as_mut().ok_or(CommandError::NotConnected)?;
+    match handle_find(client, &mut node.routing, &mut node.addrs, capabilities)
+    {
+        Ok(lines) => lines.iter().for_each(|line| println!("{line}")),
+        Err(e) => eprintln!("Failed to query rendezvous server: {e}"),
+    }
+    Ok(Flow::Continue)
+}
+
+/// Handle `/pending [--clear]`.
+fn run_pending(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let clear = match args.first() {
+        None => false,
+        Some(&"--clear") => true,
+        Some(arg) => return Err(unexpected(arg)),
+    };
+
+    let client = node.client.as_mut().ok_or(CommandError::NotConnected)?;
+    for line in handle_pending(client, clear) {
+        println!("{line}");
+    }
+    Ok(Flow::Continue)
+}
+
+/// Handle `/set-meta <key> <value>`.
+fn run_set_meta(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let Some((key, value)) = args.split_first() else {
+        return Err(CommandError::MissingArg("key"));
+    };
+
+    if key.contains('=') {
+        return Err(CommandError::InvalidArg(format!(
+            "metadata keys can not contain '=': {key}"
+        )));
+    }
+    if value.is_empty() {
+        return Err(CommandError::MissingArg("value"));
+    }
+
+    handle_set_meta(
+        &mut node.metadata,
+        node.client.as_mut(),
+        key.to_string(),
+        Some(value.join(" ")),
+    );
+    Ok(Flow::Continue)
+}
+
+/// Handle `/unset-meta <key>`.
+fn run_unset_meta(
+    node: &mut Node,
+    args: &[&str],
+) -> Result<Flow, CommandError> {
+    let key = args.first().ok_or(CommandError::MissingArg("key"))?;
+    handle_set_meta(
+        &mut node.metadata,
+        node.client.as_mut(),
+        key.to_string(),
+        None,
+    );
+    Ok(Flow::Continue)
+}
+
+/// Handle `/get-meta`.
+fn run_get_meta(
+    node: &mut Node,
+    _args: &[&str],
+) -> Result<Flow, CommandError> {
+    handle_get_meta(&node.metadata);
+    Ok(Flow::Continue)
+}
+
+/// Handle `/mock on|off`.
+fn run_mock(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let network = match args.first() {
+        Some(&"on") => false,
+        Some(&"off") => true,
+        Some(arg) => return Err(unexpected(arg)),
+        None => return Err(CommandError::MissingArg("'on' or 'off'")),
+    };
+
+    node.mode = handle_set_mode(node.mode, network);
+    Ok(Flow::Continue)
+}
+
+/// Handle `/tombstones on|off`.
+fn run_tombstones(
+    node: &mut Node,
+    args: &[&str],
+) -> Result<Flow, CommandError> {
+    let enabled = match args.first() {
+        Some(&"on") => true,
+        Some(&"off") => false,
+        Some(arg) => return Err(unexpected(arg)),
+        None => return Err(CommandError::MissingArg("'on' or 'off'")),
+    };
+
+    handle_tombstones(&mut node.stores, enabled);
+    Ok(Flow::Continue)
+}
+
+/// Handle `/replicas [<n>]`.
+fn run_replicas(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    match args {
+        [] => {}
+        [n] => {
+            node.replicas =
+                n.parse().ok().filter(|n| (1..=K).contains(n)).ok_or_else(
+                    || {
+                        CommandError::InvalidArg(format!(
+                            "replica count must be between 1 and {K}: {n}"
+                        ))
+                    },
+                )?;
+        }
+        [_, extra, ..] => return Err(unexpected(extra)),
+    }
+
+    println!("Network puts are replicated to {} node(s).", node.replicas);
+    Ok(Flow::Continue)
+}
+
+/// Handle `/alpha [<n>]`.
+fn run_alpha(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    match args {
+        [] => {}
+        [n] => {
+            node.lookup.alpha =
+                n.parse().ok().filter(|n| (1..=K).contains(n)).ok_or_else(
+                    || {
+                        CommandError::InvalidArg(format!(
+                            "alpha must be between 1 and {K}: {n}"
+                        ))
+                    },
+                )?;
+        }
+        [_, extra, ..] => return Err(unexpected(extra)),
+    }
+
+    println!(
+        "Lookups keep up to {} request(s) in flight per path.",
+        node.lookup.alpha
+    );
+    Ok(Flow::Continue)
+}
+
+/// Handle `/paths [<d>]`.
+fn run_paths(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    match args {
+        [] => {}
+        [n] => {
+            node.lookup.paths =
+                parse_paths(n).map_err(CommandError::InvalidArg)?;
+        }
+        [_, extra, ..] => return Err(unexpected(extra)),
+    }
+
+    println!("Lookups run on {} disjoint path(s).", node.lookup.paths);
+    Ok(Flow::Continue)
+}
+
+/// Parse a socket buffer size in bytes.
+fn parse_bytes(arg: &str) -> Result<usize, String> {
+    arg.parse()
+        .ok()
+        .filter(|bytes| *bytes > 0)
+        .ok_or_else(|| format!("size must be a number of bytes: {arg}"))
+}
+
+/// Parse the comma separated STUN and TURN server URLs of `--webrtc`,
+/// `on` for none.
+fn parse_ice_servers(arg: &str) -> Result<Vec<String>, String> {
+    if arg == "on" {
+        return Ok(Vec::new());
+    }
+    arg.split(',')
+        .map(str::trim)
+        .map(|url| {
+            let known = ["stun:", "stuns:", "turn:", "turns:"];
+            match known.iter().any(|scheme| url.starts_with(scheme)) {
+                true => Ok(url.to_string()),
+                false => Err(format!(
+                    "bad ICE server {url}: not a STUN or TURN URL"
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Parse a keep-alive interval in whole seconds.
+fn parse_interval(arg: &str) -> Result<Duration, String> {
+    arg.parse()
+        .ok()
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("interval must be a number of seconds: {arg}"))
+}
+
+/// Parse a number of disjoint lookup paths.
+fn parse_paths(arg: &str) -> Result<usize, String> {
+    arg.parse()
+        .ok()
+        .filter(|n| (1..=K).contains(n))
+        .ok_or_else(|| format!("paths must be between 1 and {K}: {arg}"))
+}
+
+/// Handle `/sync`.
+fn run_sync(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    if let Some(extra) = args.first() {
+        return Err(unexpected(extra));
+    }
+
+    let sync = sync_replicas(node).ok_or(CommandError::NotConnected)?;
+    println!(
+        "Synced with {}/{} node(s): pulled {}, pushed {} record(s).",
+        sync.answered, sync.asked, sync.pulled, sync.pushed
+    );
+    Ok(Flow::Continue)
+}
+
+/// Handle `/republish [<minutes>]`.
+fn run_republish(
+    node: &mut Node,
+    args: &[&str],
+) -> Result<Flow, CommandError> {
+    match args {
+        [] => {}
+        [minutes] => {
+            let minutes: u64 =
+                minutes.parse().ok().filter(|m| *m > 0).ok_or_else(|| {
+                    CommandError::InvalidArg(format!(
+                        "bad interval: {minutes} (expected minutes)"
+                    ))
+                })?;
+            node.republisher
+                .set_origin_interval(Duration::from_secs(minutes * 60));
+        }
+        [_, extra, ..] => return Err(unexpected(extra)),
+    }
+
+    handle_republish(&node.republisher, &node.hints);
+    Ok(Flow::Continue)
+}
+
+/// Handle `/snapshot <path>`.
+fn run_snapshot(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let path = args.first().ok_or(CommandError::MissingArg("path"))?;
+    handle_snapshot(
+        &node.node_id,
+        &node.routing,
+        &node.stores.network,
+        Path::new(path),
+    );
+    Ok(Flow::Continue)
+}
+
+/// Handle `/restore <path>`.
+fn run_restore(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let path = args.first().ok_or(CommandError::MissingArg("path"))?;
+    if node.client.is_some() {
+        return Err(CommandError::Failed(
+            "Restore before /connect, the server knows this node by its \
+             current id"
+                .into(),
+        ));
+    }
+
+    handle_restore(
+        &node.node_id,
+        &mut node.routing,
+        &mut node.addrs,
+        &mut node.stores.network,
+        Path::new(path),
+    );
+    Ok(Flow::Continue)
+}
+
+/// Handle `/alias [<name> <expansion>]`.
+fn run_alias(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let Some((name, expansion)) = args.split_first() else {
+        handle_list_aliases(&node.aliases);
+        return Ok(Flow::Continue);
+    };
+
+    if expansion.is_empty() {
+        return Err(CommandError::MissingArg("expansion"));
+    }
+
+    let expansion: Vec<String> =
+        expansion.iter().map(|word| quote_word(word)).collect();
+    handle_alias(&mut node.aliases, name.to_string(), expansion.join(" "))?;
+    Ok(Flow::Continue)
+}
+
+/// Handle `/batch <path> [--continue]`.
+fn run_batch(node: &mut Node, args: &[&str]) -> Result<Flow, CommandError> {
+    let (path, keep_going) = match args {
+        [path] => (path, false),
+        ["--continue", path] | [path, "--continue"] => (path, true),
+        [] => return Err(CommandError::MissingArg("path")),
+        [_, extra, ..] => return Err(unexpected(extra)),
+    };
+
+    handle_batch(node, Path::new(path), keep_going)
+}
+
+/// Handle `/quit`.
+fn run_quit(node: &mut Node, _args: &[&str]) -> Result<Flow, CommandError> {
+    leave(node);
+    println!("Bye 👋");
+    Ok(Flow::Quit)
+}
+
+/// Largest edit distance for which a verb is suggested.
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// The built-in verb or alias closest to an unknown `verb`, if it is
+/// within [`MAX_SUGGESTION_DISTANCE`] edits.
+fn suggest_verb<'a>(
+    verb: &str,
+    aliases: &'a BTreeMap<String, String>,
+) -> Option<&'a str> {
+    COMMANDS
+        .iter()
+        .flat_map(|spec| spec.verbs.iter().copied())
+        .chain(aliases.keys().map(String::as_str))
+        .map(|candidate| (edit_distance(verb, candidate), candidate))
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Parse the value of a `--limit` flag.
+fn parse_limit(value: Option<&str>) -> Result<usize, CommandError> {
+    match value.map(str::parse::<usize>) {
+        Some(Ok(n)) if n > 0 => Ok(n),
+        Some(_) => Err(CommandError::InvalidArg(
+            "--limit expects a positive number".into(),
+        )),
+        None => Err(CommandError::MissingArg("value for --limit")),
+    }
+}
+
+/// Error for an argument a command does not take.
+fn unexpected(arg: &str) -> CommandError {
+    CommandError::InvalidArg(format!("unexpected '{arg}'"))
+}
+
+/// Expand a leading alias in `line`, following aliases that expand to
+/// other aliases. Fails when an alias ends up referring to itself.
+fn expand_alias(
+    line: &str,
+    aliases: &BTreeMap<String, String>,
+) -> Result<String, String> {
+    let mut line = line.to_string();
+    let mut seen = HashSet::new();
+
+    loop {
+        let (verb, rest) = match line.split_once(char::is_whitespace) {
+            Some((verb, rest)) => (verb, rest.trim_start()),
+            None => (line.as_str(), ""),
+        };
+
+        let Some(expansion) = aliases.get(verb) else {
+            return Ok(line);
+        };
+
+        if !seen.insert(verb.to_string()) || seen.len() > MAX_ALIAS_DEPTH {
+            return Err(format!("recursive alias '{verb}'"));
+        }
+
+        line = if rest.is_empty() {
+            expansion.clone()
+        } else {
+            format!("{expansion} {rest}")
+        };
+    }
+}
+
+/// Handle `/help` command.
+fn handle_info(mode: Mode) {
+    println!("Tesseras - Networking");
+    println!(
+        "This CLI is currently running in {} mode.",
+        mode.to_string().to_uppercase()
+    );
+    println!("Available commands:");
+    for spec in COMMANDS {
+        for (usage, description) in spec.help {
+            println!("  {usage:<18} - {description}");
+        }
+    }
+}
+
+/// Handle `/stats` command.
+///
+/// When connected, the rendezvous server's counters are shown as well.
+fn handle_stats(
+    store: &dyn Store,
+    mode: Mode,
+    routing: &RoutingTable,
+    network: NetworkId,
+    client: Option<&mut Client>,
+) {
+    println!("--- Tesseras Stats ({mode}) ---");
+    println!("Stored keys              : {}", store.len());
+    println!("Routing table nodes      : {}", routing.len());
+    match routing.estimated_size() {
+        Some(size) => println!("Estimated network size   : ~{size} nodes"),
+        None => println!("Estimated network size   : unknown"),
+    }
+    println!("Network ID               : {network}");
+
+    if let Some(client) = client {
+        let interfaces = client.transport().inner().inner().inner();
+        println!(
+            "Local addresses          : {}",
+            interfaces
+                .local_addrs()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let tcp: Vec<_> = interfaces
+            .links()
+            .iter()
+            .filter_map(|link| link.tcp().map(|tcp| (link, tcp)))
+            .collect();
+        if tcp.is_empty() {
+            println!("TCP connections          : disabled");
+        } else {
+            println!(
+                "TCP connections          : {} ({} by fallback)",
+                tcp.iter().map(|(_, tcp)| tcp.connections()).sum::<usize>(),
+                tcp.iter()
+                    .map(|(link, _)| link.over_tcp().len())
+                    .sum::<usize>()
+            );
+        }
+        if let Some(websocket) = interfaces.primary().websocket() {
+            println!("WebSocket connections    : {}", websocket.connections());
+        }
+        let limited = client.transport().inner();
+        if limited.limit() != RateLimit::default() {
+            println!(
+                "Rate-limited datagrams   : {} dropped",
+                limited.dropped()
+            );
+        }
+        println!("Kept-alive peers         : {}", client.kept_alive());
+        println!(
+            "Fragmented datagrams     : {} sent, {} incomplete dropped",
+            client.transport().fragmented(),
+            client.transport().expired()
+        );
+        match fetch_server_stats(client) {
+            Ok(stats) => {
+                println!("Server peers             : {}", stats.peers);
+                println!(
+                    "Server bytes in          : {} ({}/s)",
+                    stats.bytes_in, stats.bytes_in_per_sec
+                );
+                println!(
+                    "Server bytes out         : {} ({}/s)",
+                    stats.bytes_out, stats.bytes_out_per_sec
+                );
+                println!(
+                    "Server cache hit rate    : {:.1}% ({} of {} queries)",
+                    stats.negative_cache_hit_rate() * 100.0,
+                    stats.negative_cache_hits,
+                    stats.negative_cache_lookups
+                );
+            }
+            Err(e) => println!("Server stats             : {e}"),
+        }
+        match client.negotiation() {
+            Negotiation::Pending => {
+                println!("Protocol version         : not negotiated")
+            }
+            Negotiation::Agreed(version) => {
+                println!("Protocol version         : {version}")
+            }
+            Negotiation::Refused { min_version, max_version } => println!(
+                "Protocol version         : none, server speaks \
+                 {min_version} to {max_version}"
+            ),
+        }
+    }
+    println!("------------------------------");
+}
+
+/// Ask the rendezvous server for its counters.
+fn fetch_server_stats(
+    client: &mut Client,
+) -> Result<RendezvousStats, Box<dyn std::error::Error>> {
+    client.request_stats()?;
+
+    loop {
+        match client.recv_timeout(SERVER_TIMEOUT)? {
+            Some(RendezvousMessage::Stats { stats }) => return Ok(stats),
+            Some(_) => continue,
+            None => return Err("no reply from rendezvous server".into()),
+        }
+    }
+}
+
+/// Handle `/put` command.
+fn handle_put(
+    store: &mut dyn Store,
+    metrics: &mut Metrics,
+    mode: Mode,
+    key: String,
+    value: Value,
+) {
+    println!("Stored ({mode}): key='{key}', value={value}");
+    store.put(key, value);
+    metrics.puts += 1;
+}
+
+/// Sequence number of a value replacing one numbered `previous`: the
+/// current time in milliseconds, so writers need not share a counter, or
+/// `previous + 1` if the clock is behind.
+fn next_seq(previous: u64) -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+    now.max(previous + 1)
+}
+
+/// Store a value put in network mode on the [`Node::replicas`] nodes
+/// closest to its key, found with an iterative lookup, and report how many
+/// acknowledged it.
+fn handle_replicate(node: &mut Node, key: &str, value: &Value) {
+    let replicas = node.replicas;
+    let Some((found, stored)) = replicate(node, key, value, dht::RECORD_TTL)
+    else {
+        println!("Not connected, the value is only held by this node.");
+        return;
+    };
+
+    if found == 0 {
+        println!("No other node found, the value is only held by this node.");
+    } else if found < replicas {
+        println!(
+            "Replicated to {stored}/{replicas} node(s), only {found} found."
+        );
+    } else {
+        println!("Replicated to {stored}/{replicas} node(s).");
+    }
+}
+
+/// Store `value` under `key` for `ttl` on the [`Node::replicas`] nodes
+/// currently closest to the key. Returns how many nodes were found and how
+/// many acknowledged, `None` when not connected.
+///
+/// A hint is kept for every node that did not acknowledge, and for every
+/// node of the routing table that would rank among the closest but did
+/// not answer the lookup, so they get the record once they answer again.
+fn replicate(
+    node: &mut Node,
+    key: &str,
+    value: &Value,
+    ttl: Duration,
+) -> Option<(usize, usize)> {
+    let client = node.client.as_mut()?;
+    let (routing, addrs) = (&mut node.routing, &mut node.addrs);
+    let (identity, store) = (&node.identity, &mut node.stores.network);
+
+    let target = dht::key_id(key);
+    let lookup = {
+        let mut serve = |msg: &RendezvousMessage| {
+            dht::answer(msg, identity, routing, addrs, store)
+        };
+        dht::find_node(
+            client,
+            routing,
+            addrs,
+            &target,
+            node.lookup,
+            &mut serve,
+        )
+    };
+    learn_lookup(routing, addrs, &target, &lookup);
+
+    let targets = &lookup.closest[..node.replicas.min(lookup.closest.len())];
+    let mut closest: Vec<Contact> = routing
+        .closest(&target, node.replicas)
+        .into_iter()
+        .filter_map(|node_id| {
+            Some(Contact { node_id, addr: *addrs.get(&node_id)? })
+        })
+        .filter(|c| !lookup.responded.contains(c))
+        .chain(targets.iter().cloned())
+        .collect();
+    closest.sort_by_key(|c| distance(&c.node_id, &target));
+    closest.truncate(node.replicas);
+    let seq = store.seq(key);
+    let mut serve = |msg: &RendezvousMessage| {
+        dht::answer(msg, identity, routing, addrs, store)
+    };
+    let stored = dht::store(client, targets, key, value, seq, ttl, &mut serve);
+
+    let now = Instant::now();
+    for missed in closest.iter().filter(|c| !stored.contains(c)) {
+        node.hints.add(missed.clone(), key, now);
+    }
+    Some((targets.len(), stored.len()))
+}
+
+/// Handle `/cas` command.
+fn handle_cas(
+    store: &mut dyn Store,
+    metrics: &mut Metrics,
+    mode: Mode,
+    key: &str,
+    expected: Option<Value>,
+    new: Value,
+) {
+    let shown = new.to_string();
+    match store.compare_and_swap(key, expected.as_ref(), new) {
+        Ok(()) => {
+            metrics.puts += 1;
+            println!("Swapped ({mode}): key='{key}', value={shown}");
+        }
+        Err(Some(current)) => {
+            println!("Not swapped ({mode}): key='{key}' holds {current}");
+        }
+        Err(None) => println!("Not swapped ({mode}): key='{key}' is absent"),
+    }
+}
+
+/// Handle `/get --quorum`: read `key` from the replicas closest to it and
+/// report the freshest value and the stale replicas repaired.
+fn run_get_quorum<T: Transport>(
+    node: &mut Node<T>,
+    key: &str,
+    format: ValueFormat,
+) -> Result<Flow, CommandError> {
+    let read = node.get_quorum(key).ok_or(CommandError::NotConnected)?;
+    node.metrics.gets += 1;
+
+    let source = format!(
+        "network, {}/{} replica(s) answered",
+        read.answers.len(),
+        read.asked.len()
+    );
+    match &read.record {
+        Some((seq, value)) => {
+            node.metrics.hits += 1;
+            println!(
+                "Found ({source}): key='{key}', value={}, seq={seq}",
+                format_value(value, format)
+            );
+        }
+        None => {
+            node.metrics.misses += 1;
+            println!("Key '{key}' not found ({source}).");
+        }
+    }
+    if !read.repaired.is_empty() {
+        println!("Repaired {} stale replica(s).", read.repaired.len());
+    }
+    Ok(Flow::Continue)
+}
+
+/// Handle `/get` command.
+///
+/// In network mode the key is resolved through the tiers of
+/// [`network_ladder`] and the tier that answered is reported. When
+/// connected, a key missing locally is asked to the replicas known of,
+/// then looked up through the network, and the nodes closest to it are
+/// listed if no node holds it. Replicas found stale are repaired later,
+/// see [`send_repairs`]. A value held here that
+/// expires is shown with the seconds it has left.
+fn handle_get<T: Transport>(
+    store: &mut dyn Store,
+    network: Option<Reach<'_, T>>,
+    metrics: &mut Metrics,
+    mode: Mode,
+    key: String,
+    format: ValueFormat,
+) {
+    metrics.gets += 1;
+
+    let mut lookup = Lookup::default();
+    let mut expires = None;
+    let (value, source) = match mode {
+        Mode::Mock => {
+            expires = store.expires(&key);
+            (store.get(&key), String::new())
+        }
+        Mode::Network => {
+            let store = RefCell::new(store);
+            let network = network.map(RefCell::new);
+            let resolution =
+                network_ladder(&store, network.as_ref(), &mut lookup)
+                    .resolve(&key);
+
+            if let Some(Reach { routing, addrs, .. }) =
+                network.map(RefCell::into_inner)
+            {
+                learn_lookup(routing, addrs, &dht::key_id(&key), &lookup);
+            }
+            if lookup.conflicts > 0 {
+                println!(
+                    "Warning: {} lookup path(s) found a different value, \
+                     some nodes may be lying.",
+                    lookup.conflicts
+                );
+            }
+
+            let tried: Vec<String> = resolution
+                .attempts
+                .iter()
+                .map(|a| format!("{} {}", a.tier, a.outcome))
+                .collect();
+            match resolution.value {
+                Some((tier, value)) => {
+                    if tier == Tier::Local {
+                        expires = store.borrow().expires(&key);
+                    }
+                    (Some(value), format!(", {tier}"))
+                }
+                None => (None, format!(", tried {}", tried.join(", "))),
+            }
+        }
+    };
+
+    let Some(value) = value else {
+        metrics.misses += 1;
+        println!("Key '{key}' not found ({mode}{source}).");
+        if !lookup.closest.is_empty() {
+            println!("Closest nodes to the key:");
+            for contact in &lookup.closest {
+                println!(
+                    "  {:X} ({})  {}",
+                    NodeId::from(contact.node_id),
+                    fingerprint(&contact.node_id),
+                    contact.addr
+                );
+            }
+        }
+        return;
+    };
+    metrics.hits += 1;
+
+    let shown = format_value(&value, format);
+    match expires {
+        Some(at) => {
+            let ttl = at.duration_since(SystemTime::now()).unwrap_or_default();
+            println!(
+                "Found ({mode}{source}): key='{key}', value={shown}, ttl={}s",
+                ttl.as_secs()
+            );
+        }
+        None => println!("Found ({mode}{source}): key='{key}', value={shown}"),
+    }
+}
+
+/// Handle `/get --all`: ask every replica of `key` for its value, without
+/// repairing them, and return the report, one line per replica and a last
+/// one saying whether they agree.
+fn handle_get_all<T: Transport>(
+    node: &mut Node<T>,
+    key: &str,
+    format: ValueFormat,
+) -> Result<Vec<String>, CommandError> {
+    let read = node.get_replicas(key).ok_or(CommandError::NotConnected)?;
+    node.metrics.gets += 1;
+    match read.record {
+        Some(_) => node.metrics.hits += 1,
+        None => node.metrics.misses += 1,
+    }
+
+    let mut lines = vec![format!(
+        "Replicas of '{key}' ({}/{} answered):",
+        read.answers.len(),
+        read.asked.len()
+    )];
+    for contact in &read.asked {
+        let answer =
+            read.answers.iter().find(|a| a.replica == contact.node_id);
+        let state = match answer.map(|a| &a.record) {
+            None => "no answer".to_string(),
+            Some(None) => "no value".to_string(),
+            Some(Some((seq, value))) => {
+                format!("value={}, seq={seq}", format_value(value, format))
+            }
+        };
+        lines.push(format!(
+            "  {:X} ({}): {state}",
+            NodeId::from(contact.node_id),
+            contact.addr
+        ));
+    }
+
+    let mut records = Vec::new();
+    for answer in &read.answers {
+        if !records.contains(&&answer.record) {
+            records.push(&answer.record);
+        }
+    }
+    lines.push(match &read.record {
+        Some((seq, _)) if records.len() > 1 => format!(
+            "Replicas disagree: {} different answers, the freshest has \
+             seq={seq}.",
+            records.len()
+        ),
+        Some(_) => "All replicas agree.".to_string(),
+        None => format!("Key '{key}' not found on any replica."),
+    });
+    Ok(lines)
+}
+
+/// `value` shown as `format` asks.
+fn format_value(value: &Value, format: ValueFormat) -> String {
+    match format {
+        ValueFormat::Display => value.to_string(),
+        ValueFormat::Base64 => BASE64.encode(value.as_bytes()),
+        ValueFormat::Hex => encode_hex(value.as_bytes()),
+    }
+}
+
+/// What a network get needs to look keys up through the DHT.
+struct Reach<'a, T: Transport = Links> {
+    client: &'a mut RendezvousClient<T>,
+    identity: &'a Identity,
+    routing: &'a mut RoutingTable,
+    addrs: &'a mut Addresses,
+    options: lookup::Options,
+    /// Number of routing table nodes asked by the replica tier.
+    replicas: usize,
+    /// Where the replica tier queues the repairs of the stale replicas.
+    repairs: &'a mut Repairs,
+}
+
+/// Tiers a network get is resolved from: the node's own store, then, when
+/// `network` is given, the [`Reach::replicas`] routing table nodes closest
+/// to the key, and last an iterative lookup whose outcome is kept in
+/// `lookup`.
+fn network_ladder<'a, T: Transport>(
+    store: &'a RefCell<&mut dyn Store>,
+    network: Option<&'a RefCell<Reach<'_, T>>>,
+    lookup: &'a mut Lookup,
+) -> Ladder<'a> {
+    let ladder = Ladder::new().tier(
+        Tier::Local,
+        LOCAL_TIER_TIMEOUT,
+        Box::new(|key, _| Ok(store.borrow().get(key))),
+    );
+
+    let Some(network) = network else {
+        return ladder;
+    };
+    ladder
+        .tier(
+            Tier::Replicas,
+            REPLICA_TIER_TIMEOUT,
+            Box::new(move |key, budget| {
+                let reach = &mut *network.borrow_mut();
+                let (routing, addrs) = (&*reach.routing, &*reach.addrs);
+                let contacts: Vec<Contact> = routing
+                    .closest(&dht::key_id(key), reach.replicas)
+                    .into_iter()
+                    .filter_map(|node_id| {
+                        Some(Contact { node_id, addr: *addrs.get(&node_id)? })
+                    })
+                    .collect();
+                let mut serve = |msg: &RendezvousMessage| {
+                    let store = &mut **store.borrow_mut();
+                    dht::answer(msg, reach.identity, routing, addrs, store)
+                };
+                let answers = dht::ask_replicas(
+                    reach.client,
+                    &contacts,
+                    key,
+                    budget,
+                    &mut serve,
+                );
+                let plan = replication::plan_read_repair(
+                    &answers,
+                    MAX_REPAIRS_PER_GET,
+                );
+                if let Some(plan) = plan {
+                    reach.repairs.add(key, &plan, &contacts);
+                }
+                Ok(answers
+                    .into_iter()
+                    .filter_map(|a| a.record)
+                    .max_by_key(|(seq, _)| *seq)
+                    .map(|(_, value)| value))
+            }),
+        )
+        .tier(
+            Tier::Lookup,
+            LOOKUP_TIER_TIMEOUT,
+            Box::new(move |key, _| {
+                let reach = &mut *network.borrow_mut();
+                let (routing, addrs) = (&*reach.routing, &*reach.addrs);
+                let mut serve = |msg: &RendezvousMessage| {
+                    let store = &mut **store.borrow_mut();
+                    dht::answer(msg, reach.identity, routing, addrs, store)
+                };
+                *lookup = dht::find_value(
+                    reach.client,
+                    routing,
+                    addrs,
+                    key,
+                    reach.options,
+                    &mut serve,
+                );
+                Ok(lookup.value.clone())
+            }),
+        )
+}
+
+/// Handle `/scan <prefix>` and `/get <prefix>*` commands.
+fn handle_scan(store: &dyn Store, mode: Mode, prefix: String, limit: usize) {
+    let Scan { entries, truncated } = store.scan(&prefix, limit);
+
+    if entries.is_empty() {
+        println!("No keys start with '{prefix}' ({mode}).");
+        return;
+    }
+
+    println!("Keys starting with '{prefix}' ({mode}):");
+    for (key, value) in &entries {
+        println!("  {key} = {value}");
+    }
+    if truncated {
+        println!("(showing the first {limit}, use --limit to see more)");
+    }
+}
+
+/// Handle `/name` command.
+///
+/// When connected, the address the rendezvous server sees us at is
+/// published along with the node id.
+fn handle_name(
+    store: &mut dyn Store,
+    mode: Mode,
+    client: Option<&mut Client>,
+    node_id: &NodeId,
+    name: &str,
+    policy: Policy,
+) -> String {
+    let addr = client.and_then(|client| {
+        let server = client.server_addr();
+        client.what_is_my_addr(server, SERVER_TIMEOUT).ok().flatten()
+    });
+
+    match naming::publish(store, name, &format!("{node_id:X}"), addr, policy) {
+        Ok(record) => format!(
+            "Published ({mode}): {name} -> {} (version {})",
+            record.peer_id, record.version
+        ),
+        Err(e) => format!("Could not publish '{name}' ({mode}): {e}"),
+    }
+}
+
+/// Handle `/resolve` command.
+fn handle_resolve(store: &dyn Store, mode: Mode, name: &str) -> String {
+    match naming::resolve(store, name) {
+        Ok(Some(record)) => {
+            let addr = record
+                .addr
+                .map_or_else(|| "unknown".to_string(), |a| a.to_string());
+            format!(
+                "{name} ({mode}): {} ({})  addr={addr}  version={}",
+                record.peer_id,
+                peer_fingerprint(&record.peer_id),
+                record.version
+            )
+        }
+        Ok(None) => format!("Name '{name}' not found ({mode})."),
+        Err(e) => format!("Could not resolve '{name}' ({mode}): {e}"),
+    }
+}
+
+/// Handle `/delete` command.
+fn handle_delete(store: &mut dyn Store, mode: Mode, key: &str) {
+    let found = store.delete(key, SystemTime::now());
+    let kind = match store.tombstone(key) {
+        Some(_) => ", tombstone",
+        None => "",
+    };
+
+    if found {
+        println!("Deleted ({mode}{kind}): key='{key}'");
+    } else {
+        println!("Key '{key}' not found ({mode}{kind}).");
+    }
+}
+
+/// Handle `/compact` command.
+fn handle_compact(store: &mut dyn Store, mode: Mode, grace: Duration) {
+    let purged = store.compact(SystemTime::now(), grace);
+    println!(
+        "Purged {purged} tombstone(s) older than {}s ({mode}).",
+        grace.as_secs()
+    );
+}
+
+/// Handle `/tombstones on|off` command.
+///
+/// The mode applies to both stores. Turning it off keeps the existing
+/// tombstones until they are compacted.
+fn handle_tombstones(stores: &mut Stores, enabled: bool) {
+    stores.mock.set_tombstone_mode(enabled);
+    stores.network.set_tombstone_mode(enabled);
+
+    let held = stores.mock.tombstones() + stores.network.tombstones();
+    let state = if enabled { "on" } else { "off" };
+    println!("Tombstone mode {state} ({held} tombstone(s) held).");
+}
+
+/// Handle `/republish` command.
+fn handle_republish(republisher: &Republisher, hints: &Hints) {
+    println!(
+        "Records put here       : {} (republished every {} min)",
+        republisher.count(Origin::Local),
+        republisher.origin_interval().as_secs() / 60
+    );
+    println!(
+        "Records held for others: {} (re-replicated every {} min)",
+        republisher.count(Origin::Remote),
+        republisher.replicate_interval().as_secs() / 60
+    );
+    println!(
+        "Hints pending          : {} for {} node(s)",
+        hints.len(),
+        hints.nodes()
+    );
+}
+
+/// Handle `/snapshot` command.
+///
+/// Snapshots the network store, the one the node serves.
+fn handle_snapshot(
+    node_id: &NodeId,
+    routing: &RoutingTable,
+    store: &dyn Store,
+    path: &Path,
+) {
+    let result = Snapshot::capture(*node_id.as_bytes(), Some(routing), store)
+        .and_then(|snapshot| {
+            snapshot.save(path)?;
+            Ok(snapshot)
+        });
+
+    match result {
+        Ok(snapshot) => println!(
+            "Saved snapshot of {} entries to {}",
+            snapshot.entries.len(),
+            path.display()
+        ),
+        Err(e) => println!("Could not save snapshot: {e}"),
+    }
+}
+
+/// Handle `/restore` command.
+///
+/// Takes over the snapshot's routing table and fills the network store,
+/// which must still be empty. The node id is bound to the identity key, so
+/// only snapshots of this node can be restored.
+fn handle_restore(
+    node_id: &NodeId,
+    routing: &mut RoutingTable,
+    addrs: &mut Addresses,
+    store: &mut dyn Store,
+    path: &Path,
+) {
+    let snapshot = match Snapshot::load(path) {
+        Ok(snapshot) if snapshot.node_id != *node_id.as_bytes() => {
+            println!(
+                "Could not restore {}: it belongs to node {:X}",
+                path.display(),
+                NodeId::from(snapshot.node_id)
+            );
+            return;
+        }
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            println!("Could not restore {}: {e}", path.display());
+            return;
+        }
+    };
+    if let Err(e) = snapshot.restore(store) {
+        println!("Could not restore {}: {e}", path.display());
+        return;
+    }
+
+    *routing = snapshot.routing_table(routing.layout());
+    addrs.retain(|id, _| routing.contains(id));
+    println!(
+        "Restored node {:X} ({}) with {} entries and {} routing table node(s)",
+        node_id,
+        fingerprint(node_id.as_bytes()),
+        snapshot.entries.len(),
+        routing.len()
+    );
+}
+
+/// Handle `/keys` command.
+///
+/// Walks the store a page at a time, so only one page of entries is held
+/// in memory however large the store is.
+fn handle_keys(store: &dyn Store, mode: Mode) {
+    let mut cursor = None;
+    let mut count = 0;
+
+    loop {
+        let Page { entries, next } =
+            store.page(cursor.as_deref(), KEYS_PAGE_SIZE);
+        for (key, value) in &entries {
+            println!("  {key} ({} bytes)", value.len());
+        }
+        count += entries.len();
+
+        match next {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+
+    println!("{count} key(s) ({mode}).");
+}
+
+/// Lowercase hex encoding of `bytes`.
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Decode a hex string, returning `None` if it is malformed.
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Build the completion script for `shell`.
+///
+/// Only the command line is completed, the verbs of the interactive
+/// prompt are listed by `/help`.
+fn completion_script(shell: Shell) -> String {
+    let names: Vec<&str> = SUBCOMMANDS.iter().map(|(name, _)| *name).collect();
+    let names = names.join(" ");
+    let shells = "bash zsh fish";
+
+    match shell {
+        Shell::Bash => format!(
+            "_tesseras() {{
+    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"
+    case \"$COMP_CWORD\" in
+        1) COMPREPLY=($(compgen -W \"{names}\" -- \"$cur\")) ;;
+        2) [ \"${{COMP_WORDS[1]}}\" = completions ] &&
+               COMPREPLY=($(compgen -W \"{shells}\" -- \"$cur\")) ;;
+    esac
+}}
+complete -F _tesseras tesseras
+"
+        ),
+        Shell::Zsh => {
+            let described: String = SUBCOMMANDS
+                .iter()
+                .map(|(name, desc)| format!("        '{name}:{desc}'\n"))
+                .collect();
+            format!(
+                "#compdef tesseras
+
+_tesseras() {{
+    local -a subcommands
+    subcommands=(
+{described}    )
+    _arguments '1: :->subcommand' '2: :->argument'
+    case $state in
+        subcommand) _describe 'subcommand' subcommands ;;
+        argument)
+            [[ $words[2] == completions ]] && _values 'shell' {shells} ;;
+    esac
+}}
+
+_tesseras \"$@\"
+"
+            )
+        }
+        Shell::Fish => {
+            let mut script = String::from("complete -c tesseras -f\n");
+            for (name, desc) in SUBCOMMANDS {
+                script.push_str(&format!(
+                    "complete -c tesseras -n __fish_use_subcommand \
+                     -a {name} -d '{desc}'\n"
+                ));
+            }
+            script.push_str(&format!(
+                "complete -c tesseras \
+                 -n '__fish_seen_subcommand_from completions' -a '{shells}'\n"
+            ));
+            script
+        }
+    }
+}
+
+/// Parse a node id given as 40 hex characters.
+fn parse_node_id(s: &str) -> Result<NodeId, CommandError> {
+    s.parse()
+        .map_err(|e: ParseNodeIdError| CommandError::InvalidArg(e.to_string()))
+}
+
+/// Handle `/metrics [--json] [--reset]` command and return the lines to
+/// print.
+///
+/// With `--reset` the counters are zeroed after being reported.
+fn handle_metrics(
+    metrics: &mut Metrics,
+    traffic: &TrafficCounters,
+    json: bool,
+    reset: bool,
+) -> Vec<String> {
+    let mut lines = if json {
+        let snapshot = serde_json::json!({
+            "puts": metrics.puts,
+            "gets": metrics.gets,
+            "hits": metrics.hits,
+            "misses": metrics.misses,
+            "network_errors": traffic.errors(),
+            "bytes_in": traffic.bytes_in(),
+            "bytes_out": traffic.bytes_out(),
+        });
+        vec![snapshot.to_string()]
+    } else {
+        vec![
+            "--- Tesseras Metrics ---".to_string(),
+            format!("Puts                     : {}", metrics.puts),
+            format!("Gets                     : {}", metrics.gets),
+            format!("Hits                     : {}", metrics.hits),
+            format!("Misses                   : {}", metrics.misses),
+            format!("Network errors           : {}", traffic.errors()),
+            format!("Bytes in                 : {}", traffic.bytes_in()),
+            format!("Bytes out                : {}", traffic.bytes_out()),
+            "------------------------------".to_string(),
+        ]
+    };
+
+    if reset {
+        *metrics = Metrics::default();
+        traffic.reset();
+        lines.push("Metrics reset.".to_string());
+    }
+    lines
+}
+
+/// Handle `/mock on|off` command and return the new mode.
+fn handle_set_mode(current: Mode, network: bool) -> Mode {
+    let mode = if network { Mode::Network } else { Mode::Mock };
+
+    if mode == current {
+        println!("Already in {mode} mode.");
+    } else {
+        println!("Switched to {mode} mode.");
+        println!(
+            "Note: mock data and network data are kept in separate stores."
+        );
+    }
+
+    mode
+}
+
+/// Handle `/ping` command.
+fn handle_ping() {
+    println!("PONG (mock)");
+}
+
+/// Handle `/selftest` command and return the report, one line per step
+/// and a last one with the overall result.
+///
+/// Always exercises the active store. In network mode it also registers
+/// with the rendezvous server, checks the registration is visible, lists
+/// the other peers and pings the first of them.
+fn handle_self_test<T: Transport>(
+    store: &mut dyn Store,
+    mode: Mode,
+    client: Option<&mut RendezvousClient<T>>,
+) -> Vec<String> {
+    let mut lines = vec![format!("--- Self-test ({mode}) ---")];
+    let mut passed =
+        run_step(&mut lines, "store put/get", || self_test_store(store));
+
+    if mode == Mode::Network {
+        match client {
+            Some(client) => {
+                passed &= run_step(&mut lines, "register", || {
+                    self_test_register(client)
+                });
+                let mut others = Vec::new();
+                passed &= run_step(&mut lines, "peer discovery", || {
+                    others = self_test_discovery(client)?;
+                    Ok(format!("{} other peer(s)", others.len()))
+                });
+                match others.into_iter().next() {
+                    Some(peer) => {
+                        passed &= run_step(&mut lines, "ping peer", || {
+                            self_test_ping(client, peer)
+                        });
+                    }
+                    None => lines.push(skipped("ping peer", "no other peer")),
+                }
+            }
+            None => {
+                lines.push(skipped("register", "not connected, use /connect"))
+            }
+        }
+    }
+
+    lines.push("------------------------------".to_string());
+    lines.push(format!("Result: {}", if passed { "PASS" } else { "FAIL" }));
+    lines
+}
+
+/// Run one `/selftest` step, add its outcome and timing to `lines` and
+/// return whether it passed.
+fn run_step(
+    lines: &mut Vec<String>,
+    name: &str,
+    step: impl FnOnce() -> Result<String, Box<dyn std::error::Error>>,
+) -> bool {
+    let start = Instant::now();
+    let result = step();
+    let ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    lines.push(match &result {
+        Ok(detail) => format!("[PASS] {name:<16} {ms:>8.2} ms  {detail}"),
+        Err(e) => format!("[FAIL] {name:<16} {ms:>8.2} ms  {e}"),
+    });
+    result.is_ok()
+}
+
+/// Report line of a `/selftest` step not run, and why.
+fn skipped(name: &str, why: &str) -> String {
+    format!("[SKIP] {name:<16} {:>11}  {why}", "-")
+}
+
+/// Put, get and remove a temporary key.
+fn self_test_store(
+    store: &mut dyn Store,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let key = format!("__selftest_{}__", std::process::id());
+    let value = Value::Utf8("tesseras".to_string());
+
+    store.put(key.clone(), value.clone());
+    let found = store.get(&key);
+    store.remove(&key);
+
+    match found {
+        Some(found) if found == value => Ok("value round-tripped".into()),
+        Some(_) => Err("value changed".into()),
+        None => Err("value missing".into()),
+    }
+}
+
+/// Register again and look ourselves up on the server.
+fn self_test_register<T: Transport>(
+    client: &mut RendezvousClient<T>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let private_addr = match client.private_addr() {
+        Some(addr) => addr,
+        None => client.transport().local_addr()?,
+    };
+    client.register(private_addr)?;
+
+    let peer_id = client.peer_id().to_string();
+    client.query(&peer_id)?;
+
+    loop {
+        match client.recv_timeout(SERVER_TIMEOUT)? {
+            Some(RendezvousMessage::PeerInfo { peer })
+                if peer.peer_id == peer_id =>
+            {
+                return Ok(format!("public address {}", peer.public_addr));
+            }
+            Some(_) => continue,
+            None => return Err("no reply from rendezvous server".into()),
+        }
+    }
+}
+
+/// List the other peers registered on the server.
+fn self_test_discovery<T: Transport>(
+    client: &mut RendezvousClient<T>,
+) -> Result<Vec<PeerInfo>, Box<dyn std::error::Error>> {
+    client.list_peers(&[])?;
+
+    loop {
+        match client.recv_timeout(SERVER_TIMEOUT)? {
+            Some(RendezvousMessage::PeerList { peers }) => {
+                return Ok(peers
+                    .into_iter()
+                    .filter(|p| p.peer_id != client.peer_id())
+                    .collect());
+            }
+            Some(_) => continue,
+            None => return Err("no reply from rendezvous server".into()),
+        }
+    }
+}
+
+/// Ping `peer` and report its round trip time.
+fn self_test_ping<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    peer: PeerInfo,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let peer_id = peer.peer_id.clone();
+    let results =
+        client.ping_peers(vec![peer], 1, PING_TIMEOUT, PING_TIMEOUT)?;
+    match results.first().and_then(|r| r.rtt) {
+        Some(rtt) => Ok(format!(
+            "{peer_id} answered in {:.2} ms",
+            rtt.as_secs_f64() * 1000.0
+        )),
+        None => Err(format!("{peer_id} did not answer").into()),
+    }
+}
+
+/// Handle `/bench-store <n>` command.
+///
+/// Runs `n` puts followed by `n` gets under a temporary key prefix and
+/// removes every key it created afterwards.
+fn handle_bench_store(store: &mut dyn Store, n: usize) {
+    let nonce = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let keys: Vec<String> =
+        (0..n).map(|i| format!("__bench_{nonce}__:{i}")).collect();
+
+    let mut puts = Vec::with_capacity(n);
+    for key in &keys {
+        let start = Instant::now();
+        store.put(key.clone(), Value::Utf8("x".repeat(32)));
+        puts.push(start.elapsed());
+    }
+
+    let mut gets = Vec::with_capacity(n);
+    for key in &keys {
+        let start = Instant::now();
+        let _ = store.get(key);
+        gets.push(start.elapsed());
+    }
+
+    for key in &keys {
+        store.remove(key);
+    }
+
+    println!("--- Store benchmark ({n} ops) ---");
+    println!(
+        "{:<4} {:>10} {:>10} {:>10} {:>12}",
+        "op", "min(us)", "avg(us)", "p99(us)", "ops/sec"
+    );
+    print_latency_row("put", &mut puts);
+    print_latency_row("get", &mut gets);
+    println!("------------------------------");
+}
+
+/// Print one row of the `/bench-store` summary table.
+fn print_latency_row(op: &str, samples: &mut [Duration]) {
+    samples.sort();
+
+    let total: Duration = samples.iter().sum();
+    let min = samples[0];
+    let avg = total / samples.len() as u32;
+    let p99 = samples[(samples.len() * 99 / 100).min(samples.len() - 1)];
+    let ops = samples.len() as f64 / total.as_secs_f64().max(f64::EPSILON);
+
+    let us = |d: Duration| d.as_secs_f64() * 1e6;
+    println!(
+        "{:<4} {:>10.2} {:>10.2} {:>10.2} {:>12.0}",
+        op,
+        us(min),
+        us(avg),
+        us(p99),
+        ops
+    );
+}
+
+/// Handle `/whoami` command.
+fn handle_whoami(identity: &Identity) {
+    let node_id = identity.node_id();
+    println!("Node ID    : {node_id:X}");
+    println!("Fingerprint: {}", fingerprint(node_id.as_bytes()));
+    println!("Public key : {}", encode_hex(&identity.public_key()));
+}
+
+/// Handle `/distance` command.
+///
+/// The bucket is the one `b` falls into in a routing table owned by `a`.
+fn handle_distance(a: &NodeId, b: &NodeId) -> Vec<String> {
+    let distance = a.distance(b);
+    let zeros = distance.leading_zeros();
+
+    let bucket = match NodeId::BITS.checked_sub(zeros + 1) {
+        Some(bucket) => bucket.to_string(),
+        None => "none (same id)".to_string(),
+    };
+    vec![
+        format!("Distance     : {distance:X}"),
+        format!("Leading zeros: {zeros}"),
+        format!("Bucket       : {bucket}"),
+    ]
+}
+
+/// Handle `/connect <addr>` command.
+fn handle_connect(
+    client: &mut Option<Client>,
+    traffic: &Arc<TrafficCounters>,
+    node_id: &NodeId,
+    network: NetworkId,
+    links: LinkOptions,
+    metadata: &BTreeMap<String, String>,
+    addr: String,
+) {
+    // A fixed port is only free once the previous client is gone.
+    if links.port != 0 || links.socket.reuse_port {
+        *client = None;
+    }
+    let result = open_client(
+        &addr,
+        traffic,
+        format!("{node_id:X}"),
+        network,
+        links,
+        advertised_capabilities(metadata),
+    );
+
+    match result {
+        Ok(new_client) => {
+            println!(
+                "Registered with rendezvous server {}",
+                new_client.server_addr()
+            );
+            *client = Some(new_client);
+        }
+        Err(e) => eprintln!("Failed to connect to {addr}: {e}"),
+    }
+}
+
+/// Handle `/bootstrap` command, also run on `/connect` when seeds are
+/// set.
+///
+/// Pings every seed, adds those that answer to the routing table and looks
+/// up our own id through them to fill the nearby buckets.
+fn handle_bootstrap(
+    client: &mut Client,
+    routing: &mut RoutingTable,
+    addrs: &mut Addresses,
+    store: &mut dyn Store,
+    identity: &Identity,
+    seeds: &[SocketAddr],
+    options: lookup::Options,
+) {
+    let local_id = *routing.local_id();
+    let lookup = {
+        let mut serve = |msg: &RendezvousMessage| {
+            dht::answer(msg, identity, routing, addrs, store)
+        };
+        dht::bootstrap(client, &local_id, seeds, options, &mut serve)
+    };
+
+    if lookup.responded.is_empty() {
+        println!("No seed answered, the routing table is unchanged.");
+        return;
+    }
+    learn_lookup(routing, addrs, &local_id, &lookup);
+    println!(
+        "Bootstrapped from {} seed(s): {} node(s) answered, {} in the \
+         routing table.",
+        seeds.len(),
+        lookup.responded.len(),
+        routing.len()
+    );
+}
+
+/// Ping the contacts saved by the previous run and put those that still
+/// answer with the same id back into the routing table, least recently
+/// seen first. Then look up our own id through them to learn about the
+/// nodes that joined meanwhile.
+fn rejoin(node: &mut Node) {
+    let Some(client) = node.client.as_mut() else {
+        return;
+    };
+    if node.saved.is_empty() {
+        return;
+    }
+    let saved = std::mem::take(&mut node.saved);
+
+    let store = &mut node.stores.network;
+    let addrs: Vec<SocketAddr> =
+        saved.iter().map(|s| s.contact.addr).collect();
+    let alive = {
+        let mut serve = |msg: &RendezvousMessage| {
+            dht::answer(msg, &node.identity, &node.routing, &node.addrs, store)
+        };
+        dht::ping_all(client, &addrs, &mut serve)
+    };
+    for SavedContact { contact, .. } in &saved {
+        if alive.contains(contact) {
+            learn_contact(&mut node.routing, &mut node.addrs, contact);
+        }
+    }
+    println!(
+        "{}/{} saved contact(s) answered.",
+        node.routing.len(),
+        saved.len()
+    );
+    if node.routing.is_empty() {
+        return;
+    }
+
+    let local_id = *node.routing.local_id();
+    let lookup = {
+        let mut serve = |msg: &RendezvousMessage| {
+            dht::answer(msg, &node.identity, &node.routing, &node.addrs, store)
+        };
+        dht::find_node(
+            client,
+            &node.routing,
+            &node.addrs,
+            &local_id,
+            node.lookup,
+            &mut serve,
+        )
+    };
+    learn_lookup(&mut node.routing, &mut node.addrs, &local_id, &lookup);
+    println!("Routing table holds {} node(s).", node.routing.len());
+}
+
+/// Parse a seed node address given as `ip:port`.
+fn parse_seed(s: &str) -> Result<SocketAddr, String> {
+    s.parse().map_err(|e| format!("bad seed address {s}: {e}"))
+}
+
+/// Bind a local UDP socket on each of `links.bind` and `links.port` with
+/// `links.socket`, and a TCP listener on the same port unless `links.tcp`
+/// is false, and register with the server at `addr`, advertising every
+/// local address and `capabilities`. Outbound datagrams are held to
+/// `links.rate_limit` and cut into fragments above `links.max_payload`.
+/// The server gets a heartbeat every `links.heartbeat`, and peers in use a
+/// keep-alive every `links.keepalive`.
+///
+/// A `ws://` address reaches the server over WebSocket only, for networks
+/// letting nothing but HTTP through. With `links.proxy`, TCP and WebSocket
+/// connections go through the proxy and the server is reached over TCP
+/// unless that fails.
+fn open_client(
+    addr: &str,
+    traffic: &Arc<TrafficCounters>,
+    peer_id: String,
+    network: NetworkId,
+    links: LinkOptions,
+    capabilities: Vec<String>,
+) -> Result<Client, Box<dyn std::error::Error>> {
+    let (addr, websocket) = match addr.strip_prefix("ws://") {
+        Some(addr) => (addr.trim_end_matches('/'), true),
+        None => (addr, false),
+    };
+    let server_addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("could not resolve {addr}"))?;
+
+    // Dual-stack where IPv6 is available, see `SocketOptions::dual_stack`.
+    let bind = |ip: IpAddr| {
+        UdpTransport::bind_with(SocketAddr::new(ip, links.port), &links.socket)
+    };
+    let sockets = if links.bind.is_empty() {
+        vec![
+            bind(Ipv6Addr::UNSPECIFIED.into())
+                .or_else(|_| bind(Ipv4Addr::UNSPECIFIED.into()))?,
+        ]
+    } else {
+        links.bind.iter().map(|&ip| bind(ip)).collect::<io::Result<_>>()?
+    };
+
+    let mut addrs: Vec<SocketAddr> = Vec::new();
+    let mut interfaces = Vec::new();
+    for transport in sockets {
+        transport.socket().set_nonblocking(true)?;
+        let local_addr = transport.local_addr()?;
+        // Bound to a single address, it is the only one answering.
+        let answering = if local_addr.ip().is_unspecified() {
+            local_ips()
+                .into_iter()
+                .filter(|ip| local_addr.is_ipv6() || ip.is_ipv4())
+                .map(|ip| SocketAddr::new(ip, local_addr.port()))
+                .collect()
+        } else {
+            vec![local_addr]
+        };
+        for addr in answering {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+        let tcp = if links.tcp {
+            // UDP still works without it, only the fallback is lost.
+            TcpTransport::bind(local_addr)
+                .inspect_err(|e| {
+                    println!(
+                        "Warning: not listening on TCP at {local_addr}: {e}"
+                    )
+                })
+                .ok()
+                .map(|tcp| match &links.proxy {
+                    Some(proxy) => tcp.with_proxy(proxy.clone()),
+                    None => tcp,
+                })
+        } else {
+            None
+        };
+        let mut link = FallbackTransport::new(transport, tcp);
+        if websocket && interfaces.is_empty() {
+            let mut websocket = WebSocketTransport::new();
+            if let Some(proxy) = &links.proxy {
+                websocket = websocket.with_proxy(proxy.clone());
+            }
+            link = link.with_websocket(websocket);
+        }
+        if let Some(port) = links.quic
+            && interfaces.is_empty()
+        {
+            // Peers still reach this one over UDP without it.
+            match QuicTransport::bind(SocketAddr::new(local_addr.ip(), port)) {
+                Ok(quic) => link = link.with_quic(quic),
+                Err(e) => println!("Warning: not listening on QUIC: {e}"),
+            }
+        }
+        if let Some(servers) = &links.webrtc
+            && interfaces.is_empty()
+        {
+            match WebRtcTransport::bind(local_addr.ip()) {
+                Ok(webrtc) => {
+                    link = link
+                        .with_webrtc(webrtc.with_ice_servers(servers.clone()))
+                }
+                Err(e) => println!("Warning: WebRTC unavailable: {e}"),
+            }
+        }
+        interfaces.push(link);
+    }
+
+    let transport = MultiHomedTransport::new(interfaces)?;
+    let private_addr = if addrs.is_empty() {
+        transport.local_addr()?
+    } else {
+        addrs.remove(0)
+    };
+    if websocket {
+        transport.primary().route_over_websocket(server_addr);
+        transport.pin(server_addr, 0);
+    } else if let Some(proxy) = &links.proxy {
+        // UDP is likely blocked where a proxy is needed, so the server is
+        // reached over TCP from the start.
+        if transport.primary().tcp().is_none() {
+            println!("Warning: proxy {proxy} unused, TCP is disabled");
+        }
+        transport.primary().route_over_tcp(server_addr);
+        transport.pin(server_addr, 0);
+    }
+    let transport = MeteredTransport::new(transport, Arc::clone(traffic));
+    let transport = RateLimitedTransport::new(transport, links.rate_limit);
+    let transport = FragmentingTransport::new(transport, links.max_payload);
+    let mut client = RendezvousClient::new(transport, server_addr, peer_id);
+    client.set_network(network);
+    client.set_capabilities(capabilities);
+    client.set_candidates(addrs);
+    client.register(private_addr)?;
+    client.set_keepalive(Some(Keepalive::new(
+        links.heartbeat,
+        DEFAULT_JITTER_PERCENT,
+        Instant::now(),
+    )));
+    client.set_peer_keepalive(
+        links
+            .keepalive
+            .map(|interval| PeerKeepalives::new(interval, DEFAULT_IDLE_AFTER)),
+    );
+    Ok(client)
+}
+
+/// Addresses this host reaches the internet from, IPv4 first.
+fn local_ips() -> Vec<IpAddr> {
+    ROUTE_PROBES.into_iter().filter_map(transport::source_address).collect()
+}
+
+/// Capability tags advertising `metadata`.
+///
+/// The `capabilities` field is a comma separated list of plain tags, every
+/// other field is advertised as a `key=value` tag, so peers can be found by
+/// metadata with `/find --cap key=value`.
+fn advertised_capabilities(
+    metadata: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut tags = Vec::new();
+
+    for (key, value) in metadata {
+        if key == "capabilities" {
+            tags.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(str::to_string),
+            );
+        } else {
+            tags.push(format!("{key}={value}"));
+        }
+    }
+
+    tags
+}
+
+/// Handle `/set-meta` and `/unset-meta` commands. A `None` value removes
+/// the field.
+///
+/// The change is advertised on the next registration: the next `/connect`,
+/// or the automatic one after a server restart.
+fn handle_set_meta(
+    metadata: &mut BTreeMap<String, String>,
+    client: Option<&mut Client>,
+    key: String,
+    value: Option<String>,
+) {
+    match value {
+        Some(value) => {
+            println!("Metadata set: {key} = {value}");
+            metadata.insert(key, value);
+        }
+        None => match metadata.remove(&key) {
+            Some(_) => println!("Metadata removed: {key}"),
+            None => {
+                println!("No metadata field '{key}'.");
+                return;
+            }
+        },
+    }
+
+    if let Some(client) = client {
+        client.set_capabilities(advertised_capabilities(metadata));
+    }
+    println!("Advertised on the next registration.");
+}
+
+/// Handle `/get-meta` command.
+fn handle_get_meta(metadata: &BTreeMap<String, String>) {
+    if metadata.is_empty() {
+        println!("No metadata set.");
+        return;
+    }
+
+    for (key, value) in metadata {
+        println!("  {key} = {value}");
+    }
+    println!(
+        "Advertised as: [{}]",
+        advertised_capabilities(metadata).join(", ")
+    );
+}
+
+/// Handle `/ping-all` command.
+///
+/// Asks the server for every registered peer, probes them all and prints
+/// which answered and how fast.
+fn handle_ping_all(
+    client: &mut Client,
+    routing: &mut RoutingTable,
+    addrs: &mut Addresses,
+    concurrency: usize,
+    deadline: Duration,
+) {
+    if let Err(e) = client.list_peers(&[]) {
+        eprintln!("Failed to query rendezvous server: {e}");
+        return;
+    }
+
+    let mut peers = loop {
+        match client.recv_timeout(SERVER_TIMEOUT) {
+            Ok(Some(RendezvousMessage::PeerList { peers })) => break peers,
+            Ok(Some(_)) => continue,
+            Ok(None) => {
+                println!("No reply from rendezvous server.");
+                return;
+            }
+            Err(e) => {
+                eprintln!("Failed to query rendezvous server: {e}");
+                return;
+            }
+        }
+    };
+
+    let own_id = client.peer_id().to_string();
+    peers.retain(|peer| peer.peer_id != own_id);
+    if peers.is_empty() {
+        println!("No other peers registered.");
+        return;
+    }
+
+    let total = peers.len();
+    let results =
+        match client.ping_peers(peers, concurrency, PING_TIMEOUT, deadline) {
+            Ok(results) => results,
+            Err(e) => {
+                eprintln!("Failed to ping peers: {e}");
+                return;
+            }
+        };
+
+    println!("  {:<40}  {:<22}  RTT", "PEER", "FINGERPRINT");
+    let mut answered = 0;
+    for PingResult { peer, rtt } in &results {
+        let rtt = match rtt {
+            Some(rtt) => {
+                answered += 1;
+                learn_peer(routing, addrs, peer);
+                format!("{:.1}ms", rtt.as_secs_f64() * 1000.0)
+            }
+            None => "timeout".to_string(),
+        };
+        println!(
+            "  {:<40}  {:<22}  {rtt}",
+            peer.peer_id,
+            peer_fingerprint(&peer.peer_id)
+        );
+    }
+    println!("{answered}/{total} peer(s) responded.");
+}
+
+/// Handle `/find [--cap <tag>]...` command and return the peers found,
+/// one per line.
+///
+/// Multiple capabilities are combined with AND.
+fn handle_find<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    routing: &mut RoutingTable,
+    addrs: &mut Addresses,
+    capabilities: Vec<String>,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    client.list_peers(&capabilities)?;
+
+    let peers = loop {
+        match client.recv_timeout(SERVER_TIMEOUT)? {
+            Some(RendezvousMessage::PeerList { peers }) => break peers,
+            Some(_) => continue,
+            None => return Err("no reply".into()),
+        }
+    };
+
+    if peers.is_empty() {
+        return Ok(vec![if capabilities.is_empty() {
+            "No peers registered.".to_string()
+        } else {
+            format!("No peers advertise: {}", capabilities.join(", "))
+        }]);
+    }
+
+    let mut lines = Vec::new();
+    for peer in peers {
+        learn_peer(routing, addrs, &peer);
+        lines.push(format!(
+            "  {} ({})  public={}  caps=[{}]",
+            peer.peer_id,
+            peer_fingerprint(&peer.peer_id),
+            peer.public_addr,
+            peer.capabilities.join(", ")
+        ));
+    }
+    Ok(lines)
+}
+
+/// Add a peer to the routing table if its id is a node id.
+fn learn_peer(
+    routing: &mut RoutingTable,
+    addrs: &mut Addresses,
+    peer: &PeerInfo,
+) {
+    if let Ok(id) = peer.peer_id.parse::<NodeId>() {
+        learn_contact(
+            routing,
+            addrs,
+            &Contact { node_id: id.into(), addr: peer.public_addr },
+        );
+    }
+}
+
+/// Learn the nodes that answered a lookup for `target`, and mark the
+/// bucket covering `target` as looked up so it is not refreshed.
+fn learn_lookup(
+    routing: &mut RoutingTable,
+    addrs: &mut Addresses,
+    target: &[u8; 20],
+    lookup: &Lookup,
+) {
+    if let Some(index) = routing.bucket_index(target) {
+        routing.touch(index, Instant::now());
+    }
+    for contact in &lookup.responded {
+        learn_contact(routing, addrs, contact);
+    }
+}
+
+/// Routing table and store upkeep run between commands.
+fn maintain(node: &mut Node) {
+    let now = SystemTime::now();
+    node.stores.mock.expire(now);
+    node.stores.network.expire(now);
+    forget_departed(node);
+    forget_dead(node);
+    evict_unresponsive(node);
+    refresh_buckets(node);
+    migrate_records(node);
+    deliver_hints(node);
+    send_repairs(node);
+    if node.synced.elapsed() >= DEFAULT_SYNC_INTERVAL {
+        sync_replicas(node);
+    }
+    republish_records(node);
+}
+
+/// Outcome of [`sync_replicas`].
+struct Sync {
+    asked: usize,
+    answered: usize,
+    pulled: usize,
+    pushed: usize,
+}
+
+/// Reconcile the records held with each of the [`Node::replicas`] nodes
+/// closest to this one, see [`merkle`]: pull the records a neighbour
+/// holds a fresher copy of, and push those it misses or holds an older
+/// copy of. Returns `None` when not connected.
+fn sync_replicas(node: &mut Node) -> Option<Sync> {
+    let client = node.client.as_mut()?;
+    node.synced = Instant::now();
+    let local_id = *node.routing.local_id();
+    let (routing, addrs) = (&node.routing, &node.addrs);
+    let (identity, store) = (&node.identity, &mut node.stores.network);
+
+    let neighbours: Vec<Contact> = routing
+        .closest(&local_id, node.replicas)
+        .into_iter()
+        .filter_map(|node_id| {
+            Some(Contact { node_id, addr: *addrs.get(&node_id)? })
+        })
+        .collect();
+    let mut sync =
+        Sync { asked: neighbours.len(), answered: 0, pulled: 0, pushed: 0 };
+
+    for contact in &neighbours {
+        let range = merkle::shared_range(&local_id, &contact.node_id);
+        let tree = Tree::of_store(&*store, &range);
+        let synced = {
+            let mut serve = |msg: &RendezvousMessage| {
+                dht::answer(msg, identity, routing, addrs, store)
+            };
+            dht::sync_versions(client, contact, range, &tree, &mut serve)
+        };
+        let Some((leaves, theirs)) = synced else {
+            continue;
+        };
+        sync.answered += 1;
+
+        let ours: HashMap<String, u64> = merkle::records(&*store, &range)
+            .into_iter()
+            .filter(|(key, _)| {
+                merkle::leaf(&range, &dht::key_id(key))
+                    .is_some_and(|leaf| leaves.contains(&leaf))
+            })
+            .map(|(key, _)| {
+                let seq = store.seq(&key);
+                (key, seq)
+            })
+            .collect();
+
+        for version in &theirs {
+            if ours.get(&version.key).is_some_and(|seq| *seq >= version.seq) {
+                continue;
+            }
+            let fetched = {
+                let mut serve = |msg: &RendezvousMessage| {
+                    dht::answer(msg, identity, routing, addrs, store)
+                };
+                dht::fetch(client, contact, &version.key, &mut serve)
+            };
+            if let Some((seq, value)) = fetched {
+                let ttl = Duration::from_secs(version.ttl);
+                dht::keep(store, &version.key, &value, seq, ttl);
+                sync.pulled += 1;
+            }
+        }
+
+        for (key, seq) in &ours {
+            let stale = theirs
+                .iter()
+                .find(|v| v.key == *key)
+                .is_none_or(|v| v.seq < *seq);
+            let Some(value) = store.get(key).filter(|_| stale) else {
+                continue;
+            };
+            let ttl = dht::remaining_ttl(&*store, key);
+            let mut serve = |msg: &RendezvousMessage| {
+                dht::answer(msg, identity, routing, addrs, store)
+            };
+            let stored = dht::store(
+                client,
+                std::slice::from_ref(contact),
+                key,
+                &value,
+                *seq,
+                ttl,
+                &mut serve,
+            );
+            sync.pushed += stored.len();
+        }
+    }
+    Some(sync)
+}
+
+/// Ping the nodes owed records by hints due for a retry, and hand the
+/// records over to those that answer.
+///
+/// A hint is dropped once delivered, or when its record left the store.
+fn deliver_hints(node: &mut Node) {
+    let Some(client) = node.client.as_mut() else {
+        return;
+    };
+    let due = node.hints.due(Instant::now());
+    if due.is_empty() {
+        return;
+    }
+
+    let (routing, addrs) = (&node.routing, &node.addrs);
+    let (identity, store) = (&node.identity, &mut node.stores.network);
+    let alive = {
+        let targets: Vec<SocketAddr> = due.iter().map(|c| c.addr).collect();
+        let mut serve = |msg: &RendezvousMessage| {
+            dht::answer(msg, identity, routing, addrs, store)
+        };
+        dht::ping_all(client, &targets, &mut serve)
+    };
+
+    for contact in due.iter().filter(|c| alive.contains(c)) {
+        for key in node.hints.keys(&contact.node_id) {
+            let Some(value) = store.get(&key) else {
+                node.hints.remove(&contact.node_id, &key);
+                continue;
+            };
+            let (seq, ttl) =
+                (store.seq(&key), dht::remaining_ttl(store, &key));
+            let mut serve = |msg: &RendezvousMessage| {
+                dht::answer(msg, identity, routing, addrs, store)
+            };
+            let stored = dht::store(
+                client,
+                std::slice::from_ref(contact),
+                &key,
+                &value,
+                seq,
+                ttl,
+                &mut serve,
+            );
+            if !stored.is_empty() {
+                node.hints.remove(&contact.node_id, &key);
+            }
+        }
+    }
+}
+
+/// Push the newest records found by gets to the replicas that missed them
+/// or held an older version, at most [`REPAIRS_PER_ROUND`] at a time.
+fn send_repairs<T: Transport>(node: &mut Node<T>) {
+    let Some(client) = node.client.as_mut() else {
+        return;
+    };
+    let (routing, addrs) = (&node.routing, &node.addrs);
+    let (identity, store) = (&node.identity, &mut node.stores.network);
+    for repair in node.repairs.take(REPAIRS_PER_ROUND) {
+        let mut serve = |msg: &RendezvousMessage| {
+            dht::answer(msg, identity, routing, addrs, store)
+        };
+        dht::store(
+            client,
+            std::slice::from_ref(&repair.contact),
+            &repair.key,
+            &repair.value,
+            repair.version,
+            dht::RECORD_TTL,
+            &mut serve,
+        );
+    }
+}
+
+/// Push the records this node is responsible for to the nodes that joined
+/// the routing table since the last run and are among the
+/// [`Node::replicas`] closest to their keys, so they hold them right away
+/// rather than at the next republish.
+///
+/// The node is responsible for a record when it is one of the replicas
+/// closest to the key it knows of, counting the newcomers.
+fn migrate_records(node: &mut Node) {
+    let Some(client) = node.client.as_mut() else {
+        return;
+    };
+    let newcomers: Vec<[u8; 20]> = node
+        .routing
+        .nodes()
+        .filter(|id| !node.migrated.contains(*id))
+        .copied()
+        .collect();
+    node.migrated = node.routing.nodes().copied().collect();
+    if newcomers.is_empty() {
+        return;
+    }
+    let local_id = *node.routing.local_id();
+
+    let mut pushes = Vec::new();
+    let mut cursor = None;
+    loop {
+        let Page { entries, next } =
+            node.stores.network.page(cursor.as_deref(), KEYS_PAGE_SIZE);
+        for (key, value) in entries {
+            let target = dht::key_id(&key);
+            let mut closest = node.routing.closest(&target, node.replicas);
+            closest.push(local_id);
+            closest.sort_by_key(|id| distance(id, &target));
+            closest.truncate(node.replicas);
+            if !closest.contains(&local_id) {
+                continue;
+            }
+            let contacts: Vec<Contact> = newcomers
+                .iter()
+                .filter(|id| closest.contains(id))
+                .filter_map(|id| {
+                    let addr = *node.addrs.get(id)?;
+                    Some(Contact { node_id: *id, addr })
+                })
+                .collect();
+            if !contacts.is_empty() {
+                pushes.push((key, value, contacts));
+            }
+        }
+        match next {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+
+    let (routing, addrs) = (&mut node.routing, &mut node.addrs);
+    let (identity, store) = (&node.identity, &mut node.stores.network);
+    for (key, value, contacts) in pushes {
+        let (seq, ttl) = (store.seq(&key), dht::remaining_ttl(store, &key));
+        let mut serve = |msg: &RendezvousMessage| {
+            dht::answer(msg, identity, routing, addrs, store)
+        };
+        dht::store(client, &contacts, &key, &value, seq, ttl, &mut serve);
+    }
+}
+
+/// Push the records due for republishing to the nodes now closest to
+/// their keys.
+///
+/// Records that showed up in the network store since the last run were
+/// stored by other nodes and start being tracked as such. Records put here
+/// are pushed with a fresh [`dht::RECORD_TTL`], the others with what is
+/// left of theirs, so they age out once their originator stops
+/// republishing them.
+fn republish_records(node: &mut Node) {
+    if node.client.is_none() {
+        return;
+    }
+    let now = Instant::now();
+
+    let mut cursor = None;
+    loop {
+        let Page { entries, next } =
+            node.stores.network.page(cursor.as_deref(), KEYS_PAGE_SIZE);
+        for (key, _) in &entries {
+            node.republisher.track(key, now);
+        }
+        match next {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+
+    for (key, origin) in node.republisher.due(now) {
+        let Some(value) = node.stores.network.get(&key) else {
+            node.republisher.forget(&key);
+            continue;
+        };
+        let ttl = match (origin, node.stores.network.expires(&key)) {
+            (Origin::Remote, Some(expires)) => {
+                expires.duration_since(SystemTime::now()).unwrap_or_default()
+            }
+            _ => dht::RECORD_TTL,
+        };
+        replicate(node, &key, &value, ttl);
+    }
+}
+
+/// Handle `/reset-pin` command: forget the identity key pinned for the
+/// peer at `peer`, e.g. after it legitimately changed its key.
+fn handle_reset_pin<T: Transport>(
+    node: &mut Node<T>,
+    peer: SocketAddr,
+) -> String {
+    match node.reset_pin(&peer.to_string()) {
+        Ok(true) => format!(
+            "Reset the identity pin of {peer}, its next key is trusted."
+        ),
+        Ok(false) => format!("No identity key pinned for {peer}."),
+        Err(e) => format!("Could not reset the identity pin of {peer}: {e}"),
+    }
+}
+
+/// Drop the nodes that announced they are leaving from the routing table.
+///
+/// A notice only counts when it comes from the address the node is known
+/// at.
+fn forget_departed(node: &mut Node) {
+    let Some(client) = node.client.as_mut() else {
+        return;
+    };
+
+    for (notice, from) in client.take_notices() {
+        let Some(id) = dht::departed(client, &notice, from) else {
+            continue;
+        };
+        if node.addrs.get(&id) == Some(&from) && node.routing.remove(&id) {
+            node.addrs.remove(&id);
+        }
+    }
+}
+
+/// Drop the nodes the client declared dead from the routing table.
+fn forget_dead(node: &mut Node) {
+    let Some(client) = node.client.as_mut() else {
+        return;
+    };
+
+    let server = client.server_addr();
+    for addr in client.take_dead() {
+        if addr == server {
+            println!("Rendezvous server {addr} stopped answering.");
+            continue;
+        }
+        let ids: Vec<[u8; 20]> = node
+            .addrs
+            .iter()
+            .filter(|(_, known)| **known == addr)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids {
+            node.routing.remove(&id);
+            node.addrs.remove(&id);
+        }
+    }
+}
+
+/// Hand the records this node is responsible for over to the nodes next
+/// closest to their keys, then tell the routing table neighbours it is
+/// leaving, so neither the records nor the contact linger until they time
+/// out.
+///
+/// The node is responsible for a record when it is one of the
+/// [`Node::replicas`] closest to the key it knows of.
+fn leave(node: &mut Node) {
+    if node.client.is_none() || node.routing.is_empty() {
+        return;
+    }
+    let local_id = *node.routing.local_id();
+
+    let mut records = Vec::new();
+    let mut cursor = None;
+    loop {
+        let Page { entries, next } =
+            node.stores.network.page(cursor.as_deref(), KEYS_PAGE_SIZE);
+        for (key, value) in entries {
+            let target = dht::key_id(&key);
+            let closer = node
+                .routing
+                .closest(&target, node.replicas)
+                .into_iter()
+                .filter(|id| {
+                    distance(id, &target) < distance(&local_id, &target)
+                })
+                .count();
+            if closer < node.replicas {
+                records.push((key, value));
+            }
+        }
+        match next {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+
+    let mut handed = 0;
+    for (key, value) in &records {
+        let ttl = dht::remaining_ttl(&node.stores.network, key);
+        if let Some((_, stored)) = replicate(node, key, value, ttl)
+            && stored > 0
+        {
+            handed += 1;
+        }
+    }
+
+    let addrs: Vec<SocketAddr> = node
+        .routing
+        .nodes()
+        .filter_map(|id| node.addrs.get(id).copied())
+        .collect();
+    let Some(client) = node.client.as_mut() else {
+        return;
+    };
+    let told = dht::leave(client, &node.identity, &addrs);
+    println!(
+        "Handed {handed}/{} record(s) over, told {told} node(s) we are \
+         leaving.",
+        records.len()
+    );
+}
+
+/// Ping the least recently seen node of every full bucket with
+/// replacements waiting, and evict it if it does not answer so the newest
+/// replacement takes its place.
+fn evict_unresponsive(node: &mut Node) {
+    let Some(client) = node.client.as_mut() else {
+        return;
+    };
+
+    let store = &mut node.stores.network;
+    for id in node.routing.eviction_candidates() {
+        let alive = match node.addrs.get(&id) {
+            Some(&addr) => {
+                let mut serve = |msg: &RendezvousMessage| {
+                    dht::answer(
+                        msg,
+                        &node.identity,
+                        &node.routing,
+                        &node.addrs,
+                        store,
+                    )
+                };
+                dht::ping(client, addr, &mut serve) == Some(id)
+            }
+            None => false,
+        };
+
+        if alive {
+            node.routing.record_success(&id, Instant::now());
+        } else {
+            node.routing.remove(&id);
+        }
+    }
+    node.addrs.retain(|id, _| node.routing.knows(id));
+}
+
+/// Refresh the buckets not touched within [`DEFAULT_REFRESH_INTERVAL`]
+/// by looking up a random id in their range.
+///
+/// Runs between commands, while connected and once some node is known to
+/// ask.
+fn refresh_buckets(node: &mut Node) {
+    let Some(client) = node.client.as_mut() else {
+        return;
+    };
+    if node.routing.is_empty() {
+        return;
+    }
+
+    let targets = match refresh::stale_targets(
+        &mut node.routing,
+        Instant::now(),
+        DEFAULT_REFRESH_INTERVAL,
+    ) {
+        Ok(targets) => targets,
+        Err(e) => {
+            eprintln!("Bucket refresh failed: {e}");
+            return;
+        }
+    };
+
+    let store = &mut node.stores.network;
+    for target in targets {
+        let lookup = {
+            let mut serve = |msg: &RendezvousMessage| {
+                dht::answer(
+                    msg,
+                    &node.identity,
+                    &node.routing,
+                    &node.addrs,
+                    store,
+                )
+            };
+            dht::find_node(
+                client,
+                &node.routing,
+                &node.addrs,
+                &target,
+                node.lookup,
+                &mut serve,
+            )
+        };
+        learn_lookup(&mut node.routing, &mut node.addrs, &target, &lookup);
+    }
+}
+
+/// Add a node met through the DHT to the routing table, or to its
+/// bucket's replacement cache when the bucket is full.
+fn learn_contact(
+    routing: &mut RoutingTable,
+    addrs: &mut Addresses,
+    contact: &Contact,
+) {
+    routing.insert(contact.node_id, Instant::now());
+    if routing.knows(&contact.node_id) {
+        addrs.insert(contact.node_id, contact.addr);
+    }
+}
+
+/// Handle `/pending [--clear]` command and return the report, one line
+/// per request waiting for a reply.
+fn handle_pending<T: Transport>(
+    client: &mut RendezvousClient<T>,
+    clear: bool,
+) -> Vec<String> {
+    // Collect replies that arrived since the last command.
+    while let Ok(Some(_)) = client.recv() {}
+
+    if clear {
+        let count = client.clear_pending();
+        return vec![format!("Cleared {count} pending request(s).")];
+    }
+
+    let pending = client.pending();
+    let queued = client.queued();
+    if pending.is_empty() && queued == 0 {
+        return vec!["No pending requests.".to_string()];
+    }
+
+    let mut lines = vec![format!(
+        "{:<20} {:<42} {:>10} {:>8}",
+        "kind", "target", "elapsed", "retries"
+    )];
+    for request in pending {
+        lines.push(format!(
+            "{:<20} {:<42} {:>9.1}s {:>8}",
+            format!("{:?}", request.kind),
+            request.target,
+            request.sent_at.elapsed().as_secs_f64(),
+            request.retries
+        ));
+    }
+    if queued > 0 {
+        lines.push(format!(
+            "{queued} more request(s) queued, waiting for a free slot."
+        ));
+    }
+    lines
+}
+
+/// Handle `/batch` command.
+///
+/// Lines are run like typed input; empty lines and lines starting with `#`
+/// are skipped. Stops at the first failing command unless `keep_going`. A
+/// script may run other scripts, but not one that is already running.
+fn handle_batch(
+    node: &mut Node,
+    path: &Path,
+    keep_going: bool,
+) -> Result<Flow, CommandError> {
+    let script = path.canonicalize().map_err(|e| {
+        CommandError::Failed(format!("Cannot open {}: {e}", path.display()))
+    })?;
+    if node.scripts.contains(&script) {
+        return Err(CommandError::Failed(format!(
+            "{} is already running",
+            path.display()
+        )));
+    }
+    let contents = std::fs::read_to_string(&script).map_err(|e| {
+        CommandError::Failed(format!("Cannot read {}: {e}", path.display()))
+    })?;
+
+    node.scripts.push(script);
+    let (mut ran, mut failed) = (0, 0);
+    let mut flow = Flow::Continue;
+
+    'lines: for (number, line) in contents.lines().enumerate() {
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        for segment in split_commands(line) {
+            match run_command(node, segment) {
+                Ok(None) => continue,
+                Ok(Some(Flow::Continue)) => ran += 1,
+                Ok(Some(Flow::Quit)) => {
+                    ran += 1;
+                    flow = Flow::Quit;
+                    break 'lines;
+                }
+                Err((e, spec)) => {
+                    ran += 1;
+                    failed += 1;
+                    eprint!("{}:{}: ", path.display(), number + 1);
+                    report_error(&e, spec);
+                    if !keep_going {
+                        break 'lines;
+                    }
+                }
+            }
+        }
+    }
+    node.scripts.pop();
+
+    println!("Ran {ran} command(s) from {}, {failed} failed.", path.display());
+    Ok(flow)
+}
+
+/// Handle `/alias <name> <expansion>` command.
+fn handle_alias(
+    aliases: &mut BTreeMap<String, String>,
+    name: String,
+    expansion: String,
+) -> Result<(), CommandError> {
+    if find_command(&name.to_lowercase()).is_some() {
+        return Err(CommandError::InvalidArg(format!(
+            "alias '{name}' would shadow a built-in command"
+        )));
+    }
+
+    let previous = aliases.insert(name.clone(), expansion.clone());
+
+    if let Err(e) = expand_alias(&name, aliases) {
+        match previous {
+            Some(old) => aliases.insert(name, old),
+            None => aliases.remove(&name),
+        };
+        return Err(CommandError::InvalidArg(e));
+    }
+
+    println!("Alias defined: {name} -> {expansion}");
+    Ok(())
+}
+
+/// Handle `/alias` command without arguments.
+fn handle_list_aliases(aliases: &BTreeMap<String, String>) {
+    if aliases.is_empty() {
+        println!("No aliases defined.");
+        return;
+    }
+
+    for line in alias_lines(aliases) {
+        println!("{line}");
+    }
+}
+
+/// One line per alias for `/alias`, in name order.
+fn alias_lines(aliases: &BTreeMap<String, String>) -> Vec<String> {
+    aliases
+        .iter()
+        .map(|(name, expansion)| format!("  {name} -> {expansion}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+
+    use tesseras::protocol::{self, Contact};
+    use tesseras::resolve::{Outcome, Resolution};
+    use tesseras::server::{RendezvousServer, ServerConfig};
+    use tesseras::store::MemoryStore;
+    use tesseras::transport::{MockNetwork, MockTransport};
+
+    use super::*;
+
+    /// A node in mock mode, not connected.
+    fn node<T: Transport>() -> Node<T> {
+        let identity = Identity::from_seed([7; 32]);
+        let node_id = identity.node_id();
+        Node {
+            identity,
+            node_id,
+            network: NetworkId::MAIN,
+            links: LinkOptions::default(),
+            mode: Mode::Mock,
+            stores: Stores::default(),
+            aliases: BTreeMap::new(),
+            metadata: BTreeMap::new(),
+            client: None,
+            metrics: Metrics::default(),
+            traffic: Arc::new(TrafficCounters::default()),
+            routing: RoutingTable::new(*node_id.as_bytes()),
+            addrs: Addresses::new(),
+            seeds: Vec::new(),
+            replicas: DEFAULT_REPLICATION_FACTOR,
+            lookup: lookup::Options::default(),
+            republisher: Republisher::default(),
+            hints: Hints::default(),
+            repairs: Repairs::default(),
+            synced: Instant::now(),
+            scripts: Vec::new(),
+            contacts_path: None,
+            saved: Vec::new(),
+            pins: PinStore::new(),
+            migrated: HashSet::new(),
+        }
+    }
+
+    /// A DHT node answering requests on a mock network from its own
+    /// thread, until dropped.
+    struct Replica {
+        contact: Contact,
+        store: Arc<Mutex<MemoryStore>>,
+        /// Number of requests received.
+        requests: Arc<AtomicUsize>,
+        stop: Arc<AtomicBool>,
+        thread: Option<thread::JoinHandle<()>>,
+    }
+
+    impl Replica {
+        fn spawn(network: &MockNetwork, addr: &str, seed: u8) -> Self {
+            Replica::knowing(network, addr, seed, &[])
+        }
+
+        /// A replica with `known` in its routing table.
+        fn knowing(
+            network: &MockNetwork,
+            addr: &str,
+            seed: u8,
+            known: &[Contact],
+        ) -> Self {
+            let identity = Identity::from_seed([seed; 32]);
+            let transport = network.bind(addr.parse().unwrap()).unwrap();
+            let contact = Contact {
+                node_id: *identity.node_id().as_bytes(),
+                addr: transport.local_addr().unwrap(),
+            };
+            let store = Arc::new(Mutex::new(MemoryStore::default()));
+            let requests = Arc::new(AtomicUsize::new(0));
+            let stop = Arc::new(AtomicBool::new(false));
+            let mut table = RoutingTable::new(contact.node_id);
+            let mut addrs = Addresses::new();
+            for known in known {
+                table.insert(known.node_id, Instant::now());
+                addrs.insert(known.node_id, known.addr);
+            }
+            let thread = thread::spawn({
+                let store = Arc::clone(&store);
+                let (requests, stop) =
+                    (Arc::clone(&requests), Arc::clone(&stop));
+                move || {
+                    let mut buf = [0u8; protocol::RECV_BUFFER_SIZE];
+                    while !stop.load(Ordering::Relaxed) {
+                        let Ok((len, from)) = transport.recv_from(&mut buf)
+                        else {
+                            thread::sleep(Duration::from_millis(1));
+                            continue;
+                        };
+                        let Ok((network, msg)) = protocol::decode(&buf[..len])
+                        else {
+                            continue;
+                        };
+                        requests.fetch_add(1, Ordering::Relaxed);
+                        let mut store = store.lock().unwrap();
+                        if let Some(reply) = dht::answer(
+                            &msg,
+                            &identity,
+                            &table,
+                            &addrs,
+                            &mut *store,
+                        ) {
+                            let data =
+                                protocol::encode(network, &reply).unwrap();
+                            transport.send_to(&data, from).unwrap();
+                        }
+                    }
+                }
+            });
+            Replica { contact, store, requests, stop, thread: Some(thread) }
+        }
+
+        /// Hold `value` under `key` with sequence number `seq`.
+        fn put(&self, key: &str, value: &str, seq: u64) {
+            let mut store = self.store.lock().unwrap();
+            store.put(key.to_string(), Value::Utf8(value.to_string()));
+            store.set_seq(key, seq);
+        }
+
+        /// Value and sequence number held under `key`.
+        fn get(&self, key: &str) -> Option<(u64, Value)> {
+            let store = self.store.lock().unwrap();
+            store.get(key).map(|value| (store.seq(key), value))
+        }
+
+        /// Number of requests received so far.
+        fn requests(&self) -> usize {
+            self.requests.load(Ordering::Relaxed)
+        }
+    }
+
+    impl Drop for Replica {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+
+    /// A rendezvous server on a mock network at the address [`connected`]
+    /// nodes use, serving from its own thread until dropped.
+    struct Rendezvous {
+        network: MockNetwork,
+        stop: Arc<AtomicBool>,
+        thread: Option<thread::JoinHandle<()>>,
+    }
+
+    impl Rendezvous {
+        fn spawn(network: &MockNetwork) -> Self {
+            let transport =
+                network.bind("10.0.0.254:7000".parse().unwrap()).unwrap();
+            let stop = Arc::new(AtomicBool::new(false));
+            let thread = thread::spawn({
+                let stop = Arc::clone(&stop);
+                move || {
+                    let mut server = RendezvousServer::with_transport(
+                        transport,
+                        ServerConfig::default(),
+                    );
+                    while !stop.load(Ordering::Relaxed) {
+                        if server.poll().unwrap() == 0 {
+                            thread::sleep(Duration::from_millis(1));
+                        }
+                    }
+                }
+            });
+            Rendezvous { network: network.clone(), stop, thread: Some(thread) }
+        }
+
+        /// Register `peer_id` from `addr`, advertising `capabilities`.
+        fn register(&self, addr: &str, peer_id: &str, capabilities: &[&str]) {
+            let transport = self.network.bind(addr.parse().unwrap()).unwrap();
+            let mut client = RendezvousClient::new(
+                transport,
+                "10.0.0.254:7000".parse().unwrap(),
+                peer_id.to_string(),
+            );
+            client.set_capabilities(
+                capabilities.iter().map(|c| c.to_string()).collect(),
+            );
+            client.register(addr.parse().unwrap()).unwrap();
+        }
+    }
+
+    impl Drop for Rendezvous {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+
+    /// A node in network mode on `network`, knowing `replicas`.
+    fn connected(
+        network: &MockNetwork,
+        replicas: &[&Replica],
+    ) -> Node<MockTransport> {
+        let mut node = node();
+        let transport =
+            network.bind("10.0.0.1:4000".parse().unwrap()).unwrap();
+        node.client = Some(RendezvousClient::new(
+            transport,
+            "10.0.0.254:7000".parse().unwrap(),
+            "alice".to_string(),
+        ));
+        node.mode = Mode::Network;
+        for replica in replicas {
+            let Contact { node_id, addr } = replica.contact;
+            node.routing.insert(node_id, Instant::now());
+            node.addrs.insert(node_id, addr);
+        }
+        node
+    }
+
+    /// Run `input` on `node`, without the command of the error.
+    fn run(
+        node: &mut Node,
+        input: &str,
+    ) -> Result<Option<Flow>, CommandError> {
+        run_command(node, input).map_err(|(e, _)| e)
+    }
+
+    #[test]
+    fn put_without_value_shows_usage() {
+        let mut node = node();
+        let Err((error, spec)) = run_command(&mut node, "put k") else {
+            panic!("put without a value succeeded");
+        };
+        assert_eq!(error, CommandError::MissingArg("value"));
+        assert_eq!(
+            error_hints(&error, spec),
+            ["Usage: /put <key> <value>", "Usage: /put --b64 <k> <v>"]
+        );
+    }
+
+    #[test]
+    fn unknown_verb() {
+        let mut node = node();
+        let Err((error, spec)) = run_command(&mut node, "frobnicate x") else {
+            panic!("unknown verb succeeded");
+        };
+        assert_eq!(
+            error,
+            CommandError::UnknownCommand {
+                verb: "frobnicate".to_string(),
+                suggestion: None
+            }
+        );
+        assert!(spec.is_none());
+        assert_eq!(
+            error_hints(&error, spec),
+            ["Type /help to see basic information."]
+        );
+    }
+
+    #[test]
+    fn every_verb_has_a_handler_and_help() {
+        let mut seen = std::collections::BTreeSet::new();
+        for spec in COMMANDS {
+            assert!(!spec.verbs.is_empty());
+            assert!(!spec.help.is_empty(), "{:?} has no help", spec.verbs);
+            for (usage, description) in spec.help {
+                assert!(!description.is_empty(), "{usage} has no description");
+                assert!(
+                    spec.verbs.iter().any(|verb| {
+                        usage
+                            .strip_prefix('/')
+                            .and_then(|rest| rest.strip_prefix(verb))
+                            .is_some_and(|rest| {
+                                rest.is_empty() || rest.starts_with(' ')
+                            })
+                    }),
+                    "{usage} does not document {:?}",
+                    spec.verbs
+                );
+            }
+            for verb in spec.verbs {
+                assert!(seen.insert(*verb), "{verb} is registered twice");
+                let Ok(Some(invocation)) =
+                    parse_command(verb, &BTreeMap::new())
+                else {
+                    panic!("{verb} does not parse");
+                };
+                assert_eq!(invocation.spec.verbs, spec.verbs);
+            }
+        }
+    }
+
+    fn suggestion(node: &mut Node, line: &str) -> Option<String> {
+        match run(node, line) {
+            Err(CommandError::UnknownCommand { suggestion, .. }) => suggestion,
+            other => panic!("{line:?} was not unknown: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn typos_suggest_the_nearest_verb() {
+        let mut node = node();
+        assert_eq!(suggestion(&mut node, "putt k v").as_deref(), Some("put"));
+        assert_eq!(suggestion(&mut node, "gett k").as_deref(), Some("get"));
+        assert_eq!(suggestion(&mut node, "/hlep").as_deref(), Some("help"));
+        assert_eq!(suggestion(&mut node, "xyzzyplugh"), None);
+    }
+
+    #[test]
+    fn typos_suggest_aliases() {
+        let mut node = node();
+        assert_eq!(
+            run(&mut node, "alias fetch get"),
+            Ok(Some(Flow::Continue))
+        );
+        assert_eq!(suggestion(&mut node, "fetc k").as_deref(), Some("fetch"));
+        assert_eq!(
+            CommandError::UnknownCommand {
+                verb: "fetc".to_string(),
+                suggestion: Some("fetch".to_string()),
+            }
+            .to_string(),
+            "Unknown command: 'fetc'. Did you mean 'fetch'?"
+        );
+    }
+
+    /// Verb and arguments of every command in `line`, `None` for empty
+    /// ones.
+    fn parse_line(line: &str) -> Vec<Option<(&'static str, Vec<String>)>> {
+        split_commands(line)
+            .into_iter()
+            .map(|segment| {
+                parse_command(segment, &BTreeMap::new())
+                    .unwrap()
+                    .map(|inv| (inv.spec.verbs[0], inv.args))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_get_all() {
+        assert_eq!(
+            parse_line("get k --all"),
+            [Some(("get", vec!["k".to_string(), "--all".to_string()]))]
+        );
+
+        // In mock mode it reads the only replica.
+        let mut node = node();
+        run(&mut node, "put k v").unwrap();
+        assert_eq!(run(&mut node, "get k --all"), Ok(Some(Flow::Continue)));
+        assert!(matches!(
+            run(&mut node, "get --all --quorum k"),
+            Err(CommandError::InvalidArg(_))
+        ));
+    }
+
+    #[test]
+    fn get_all_reports_disagreeing_replicas() {
+        let network = MockNetwork::new();
+        let a = Replica::spawn(&network, "10.0.0.2:4000", 1);
+        let b = Replica::spawn(&network, "10.0.0.3:4000", 2);
+        a.put("k", "old", 1);
+        b.put("k", "new", 2);
+        let mut node = connected(&network, &[&a, &b]);
+
+        let lines =
+            handle_get_all(&mut node, "k", ValueFormat::Display).unwrap();
+        assert_eq!(lines[0], "Replicas of 'k' (2/2 answered):");
+        assert!(lines.iter().any(|l| l.ends_with(": value='old', seq=1")));
+        assert!(lines.iter().any(|l| l.ends_with(": value='new', seq=2")));
+        assert_eq!(
+            lines.last().unwrap(),
+            "Replicas disagree: 2 different answers, the freshest has seq=2."
+        );
+        // A diagnostic read leaves the stale replica as it is.
+        assert_eq!(a.get("k"), Some((1, Value::Utf8("old".to_string()))));
+
+        b.put("k", "old", 1);
+        let lines =
+            handle_get_all(&mut node, "k", ValueFormat::Display).unwrap();
+        assert_eq!(lines.last().unwrap(), "All replicas agree.");
+    }
+
+    /// Resolve `key` through the tiers of a network get on `node`.
+    fn resolve(node: &mut Node<MockTransport>, key: &str) -> Resolution {
+        let store = RefCell::new(&mut node.stores.network as &mut dyn Store);
+        let network = node.client.as_mut().map(|client| {
+            RefCell::new(Reach {
+                client,
+                identity: &node.identity,
+                routing: &mut node.routing,
+                addrs: &mut node.addrs,
+                options: node.lookup,
+                replicas: node.replicas,
+                repairs: &mut node.repairs,
+            })
+        });
+        let mut lookup = Lookup::default();
+        network_ladder(&store, network.as_ref(), &mut lookup).resolve(key)
+    }
+
+    /// Tiers tried by `resolution` and what each found.
+    fn tried(resolution: &Resolution) -> Vec<(Tier, Outcome)> {
+        resolution
+            .attempts
+            .iter()
+            .map(|a| (a.tier, a.outcome.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn local_key_resolves_without_traffic() {
+        let network = MockNetwork::new();
+        let a = Replica::spawn(&network, "10.0.0.2:4000", 1);
+        let mut node = connected(&network, &[&a]);
+        node.stores.network.put("k".into(), Value::Utf8("v".into()));
+
+        let resolution = resolve(&mut node, "k");
+        assert_eq!(
+            resolution.value,
+            Some((Tier::Local, Value::Utf8("v".into())))
+        );
+        assert_eq!(tried(&resolution), [(Tier::Local, Outcome::Found)]);
+        assert_eq!(a.requests(), 0);
+    }
+
+    #[test]
+    fn known_replica_answers_before_a_lookup() {
+        let network = MockNetwork::new();
+        let a = Replica::spawn(&network, "10.0.0.2:4000", 1);
+        a.put("k", "v", 1);
+        let mut node = connected(&network, &[&a]);
+
+        let resolution = resolve(&mut node, "k");
+        assert_eq!(
+            resolution.value,
+            Some((Tier::Replicas, Value::Utf8("v".into())))
+        );
+        assert_eq!(a.requests(), 1);
+    }
+
+    #[test]
+    fn cold_key_falls_through_to_the_lookup() {
+        let network = MockNetwork::new();
+        let c = Replica::spawn(&network, "10.0.0.4:4000", 3);
+        c.put("k", "v", 1);
+        // The node only knows a, which does not hold the key but knows c.
+        let a = Replica::knowing(
+            &network,
+            "10.0.0.2:4000",
+            1,
+            std::slice::from_ref(&c.contact),
+        );
+        let mut node = connected(&network, &[&a]);
+
+        let resolution = resolve(&mut node, "k");
+        assert_eq!(
+            resolution.value,
+            Some((Tier::Lookup, Value::Utf8("v".into())))
+        );
+        assert_eq!(
+            tried(&resolution),
+            [
+                (Tier::Local, Outcome::Missing),
+                (Tier::Replicas, Outcome::Missing),
+                (Tier::Lookup, Outcome::Found),
+            ]
+        );
+    }
+
+    #[test]
+    fn get_repairs_a_stale_replica() {
+        let network = MockNetwork::new();
+        let a = Replica::spawn(&network, "10.0.0.2:4000", 1);
+        let b = Replica::spawn(&network, "10.0.0.3:4000", 2);
+        a.put("k", "old", 1);
+        b.put("k", "new", 2);
+        let mut node = connected(&network, &[&a, &b]);
+
+        run_get(&mut node, &["k"]).unwrap();
+        // The get answers before the stale replica is written to.
+        assert_eq!(node.repairs.len(), 1);
+        assert_eq!(a.get("k"), Some((1, Value::Utf8("old".to_string()))));
+
+        send_repairs(&mut node);
+        assert!(node.repairs.is_empty());
+        let client = node.client.as_mut().unwrap();
+        assert_eq!(
+            dht::fetch(client, &a.contact, "k", &mut |_| None),
+            Some((2, Value::Utf8("new".to_string())))
+        );
+    }
+
+    #[test]
+    fn self_test_passes_in_mock_mode() {
+        let mut node = node::<MockTransport>();
+        let store = node.stores.active(Mode::Mock);
+        let lines = handle_self_test(store, Mode::Mock, node.client.as_mut());
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "--- Self-test (mock) ---");
+        assert!(lines[1].starts_with("[PASS] store put/get "));
+        assert!(lines[1].ends_with(" ms  value round-tripped"));
+        assert_eq!(lines[3], "Result: PASS");
+        assert!(node.stores.mock.scan("__selftest_", 1).entries.is_empty());
+    }
+
+    #[test]
+    fn self_test_skips_the_network_when_not_connected() {
+        let mut node = node::<MockTransport>();
+        let store = node.stores.active(Mode::Network);
+        let lines =
+            handle_self_test(store, Mode::Network, node.client.as_mut());
+
+        assert!(lines[1].starts_with("[PASS] store put/get "));
+        assert!(lines[2].starts_with("[SKIP] register "));
+        assert!(lines[2].ends_with("not connected, use /connect"));
+        assert_eq!(lines.last().unwrap(), "Result: PASS");
+    }
+
+    /// `/find` with `capabilities` on `node`.
+    fn find(
+        node: &mut Node<MockTransport>,
+        capabilities: &[&str],
+    ) -> Vec<String> {
+        handle_find(
+            node.client.as_mut().unwrap(),
+            &mut node.routing,
+            &mut node.addrs,
+            capabilities.iter().map(|c| c.to_string()).collect(),
+        )
+        .unwrap()
+    }
+
+    /// Peer ids listed by `/find`.
+    fn found(lines: &[String]) -> Vec<&str> {
+        let mut ids: Vec<&str> = lines
+            .iter()
+            .filter_map(|l| l.trim_start().split(' ').next())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn find_peers_by_capability() {
+        let network = MockNetwork::new();
+        let server = Rendezvous::spawn(&network);
+        server.register("10.0.0.2:4000", "relay", &["relay", "ipv6"]);
+        server.register("10.0.0.3:4000", "ipv6-only", &["ipv6"]);
+        server.register("10.0.0.4:4000", "bare", &[]);
+        let mut node = connected(&network, &[]);
+
+        let lines = find(&mut node, &["relay"]);
+        assert_eq!(found(&lines), ["relay"]);
+        assert!(
+            lines[0].ends_with("public=10.0.0.2:4000  caps=[relay, ipv6]")
+        );
+        assert_eq!(found(&find(&mut node, &["ipv6"])), ["ipv6-only", "relay"]);
+    }
+
+    #[test]
+    fn find_combines_capabilities_with_and() {
+        let network = MockNetwork::new();
+        let server = Rendezvous::spawn(&network);
+        server.register("10.0.0.2:4000", "relay", &["relay", "ipv6"]);
+        server.register("10.0.0.3:4000", "ipv6-only", &["ipv6"]);
+        let mut node = connected(&network, &[]);
+
+        assert_eq!(found(&find(&mut node, &["ipv6", "relay"])), ["relay"]);
+        assert_eq!(found(&find(&mut node, &["relay", "ipv6"])), ["relay"]);
+    }
+
+    #[test]
+    fn find_reports_no_match() {
+        let network = MockNetwork::new();
+        let server = Rendezvous::spawn(&network);
+        server.register("10.0.0.2:4000", "relay", &["relay"]);
+        let mut node = connected(&network, &[]);
+
+        assert_eq!(
+            find(&mut node, &["relay", "storage"]),
+            ["No peers advertise: relay, storage"]
+        );
+    }
+
+    #[test]
+    fn find_needs_a_connection() {
+        let mut node = node();
+        assert_eq!(
+            run(&mut node, "find --cap relay"),
+            Err(CommandError::NotConnected)
+        );
+        assert_eq!(
+            run(&mut node, "find --cap"),
+            Err(CommandError::MissingArg("tag for --cap"))
+        );
+    }
+
+    #[test]
+    fn stuck_request_is_listed_then_cleared() {
+        let network = MockNetwork::new();
+        // Nobody answers at the server address, so the query is stuck.
+        let mut node = connected(&network, &[]);
+        let client = node.client.as_mut().unwrap();
+        client.query("ghost").unwrap();
+
+        let lines = handle_pending(client, false);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("kind "));
+        let row: Vec<&str> = lines[1].split_whitespace().collect();
+        assert_eq!(row[..2], ["Query", "ghost"]);
+        assert_eq!(row[3], "0");
+
+        assert_eq!(
+            handle_pending(client, true),
+            ["Cleared 1 pending request(s)."]
+        );
+        assert_eq!(handle_pending(client, false), ["No pending requests."]);
+    }
+
+    /// `/metrics --json` on `node`, parsed.
+    fn metrics(node: &mut Node) -> serde_json::Value {
+        let lines =
+            handle_metrics(&mut node.metrics, &node.traffic, true, false);
+        serde_json::from_str(&lines[0]).unwrap()
+    }
+
+    #[test]
+    fn metrics_count_puts_and_gets() {
+        let mut node = node();
+        for input in ["put a 1", "put b 2", "put a 3", "get a", "get nope"] {
+            run(&mut node, input).unwrap();
+        }
+
+        let counters = metrics(&mut node);
+        assert_eq!(counters["puts"], 3);
+        assert_eq!(counters["gets"], 2);
+        assert_eq!(counters["hits"], 1);
+        assert_eq!(counters["misses"], 1);
+        assert_eq!(counters["network_errors"], 0);
+    }
+
+    #[test]
+    fn metrics_reset_zeroes_the_counters() {
+        let mut node = node();
+        run(&mut node, "put a 1").unwrap();
+        run(&mut node, "get a").unwrap();
+        let network = MockNetwork::new();
+        let addr = "10.0.0.1:4000".parse().unwrap();
+        let transport = MeteredTransport::new(
+            network.bind(addr).unwrap(),
+            Arc::clone(&node.traffic),
+        );
+        transport.send_to(b"0123456789", addr).unwrap();
+        transport.recv_from(&mut [0; 16]).unwrap();
+
+        let lines =
+            handle_metrics(&mut node.metrics, &node.traffic, false, true);
+        assert_eq!(lines[1], "Puts                     : 1");
+        assert_eq!(lines[6], "Bytes in                 : 10");
+        assert_eq!(lines.last().unwrap(), "Metrics reset.");
+
+        let counters = metrics(&mut node);
+        let counters = counters.as_object().unwrap();
+        for (counter, value) in counters {
+            assert_eq!(value, 0, "{counter}");
+        }
+    }
+
+    /// Write `contents` to a temporary file unique to this process.
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("tesseras-{}-{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn batch_runs_puts_and_gets() {
+        let mut node = node();
+        let path = temp_file(
+            "puts-and-gets",
+            "# fill the store\nput a 1\n\nput b 2; get a\nget b\n",
+        );
+
+        let result = run(&mut node, &format!("source {}", path.display()));
+
+        assert_eq!(result, Ok(Some(Flow::Continue)));
+        assert_eq!(node.stores.mock.get("a"), Some(Value::Utf8("1".into())));
+        assert_eq!(node.stores.mock.get("b"), Some(Value::Utf8("2".into())));
+        assert_eq!((node.metrics.puts, node.metrics.gets), (2, 2));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn batch_stops_at_the_first_error_unless_continuing() {
+        let path = temp_file("failing", "put a 1\nfrobnicate\nput b 2\n");
+
+        let mut stopped = node();
+        run(&mut stopped, &format!("batch {}", path.display())).unwrap();
+        assert!(stopped.stores.mock.get("a").is_some());
+        assert_eq!(stopped.stores.mock.get("b"), None);
+
+        let mut continued = node();
+        run(&mut continued, &format!("batch --continue {}", path.display()))
+            .unwrap();
+        assert!(continued.stores.mock.get("b").is_some());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn batch_can_not_source_itself() {
+        let mut node = node();
+        let path = temp_file("recursive", "");
+        std::fs::write(&path, format!("source {}\nput a 1\n", path.display()))
+            .unwrap();
+
+        run(&mut node, &format!("source {}", path.display())).unwrap();
+
+        assert_eq!(node.stores.mock.get("a"), None);
+        assert!(node.scripts.is_empty());
+        assert!(matches!(
+            run(&mut node, "batch /nonexistent/script"),
+            Err(CommandError::Failed(_))
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn snapshot_restores_into_a_fresh_node() {
+        let path = temp_file("snapshot", "");
+        let mut source = node();
+        for (key, value) in [("a", "1"), ("b", "2")] {
+            source
+                .stores
+                .network
+                .put(key.to_string(), Value::Utf8(value.to_string()));
+        }
+        for byte in [0x80, 0x40, 0x20] {
+            source.routing.insert([byte; 20], Instant::now());
+        }
+        run(&mut source, &format!("snapshot {}", path.display())).unwrap();
+
+        let mut fresh = node();
+        run(&mut fresh, &format!("restore {}", path.display())).unwrap();
+
+        let entries = |node: &Node| node.stores.network.page(None, 10).entries;
+        assert_eq!(entries(&fresh), entries(&source));
+        let nodes = |node: &Node| {
+            let mut nodes: Vec<_> = node.routing.nodes().copied().collect();
+            nodes.sort();
+            nodes
+        };
+        assert_eq!(nodes(&fresh), nodes(&source));
+
+        // A second restore would overwrite the data now held.
+        fresh.routing = RoutingTable::new(*fresh.node_id.as_bytes());
+        run(&mut fresh, &format!("restore {}", path.display())).unwrap();
+        assert!(fresh.routing.is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn completion_script_for_each_shell() {
+        for name in ["bash", "zsh", "fish"] {
+            let script = completion_script(name.parse().unwrap());
+            assert!(script.ends_with('\n'), "{name}");
+            assert!(script.contains("tesseras"), "{name}");
+            for (subcommand, _) in SUBCOMMANDS {
+                assert!(script.contains(subcommand), "{name}: {subcommand}");
+            }
+            assert!(script.contains("bash zsh fish"), "{name}");
+        }
+        assert_eq!(
+            "tcsh".parse::<Shell>(),
+            Err("unsupported shell: tcsh (bash, zsh or fish)".to_string())
+        );
+    }
+
+    fn id(hex: &str) -> NodeId {
+        hex.parse().unwrap()
+    }
+
+    #[test]
+    fn distance_validates_its_ids() {
+        let mut node = node();
+        let a = "00000000000000000000000000000000000000FF";
+
+        assert_eq!(
+            parse_line(&format!("distance {a} {a}")),
+            [Some(("distance", vec![a.to_string(), a.to_string()]))]
+        );
+        assert_eq!(
+            run(&mut node, &format!("distance {a}")),
+            Ok(Some(Flow::Continue))
+        );
+        assert_eq!(
+            run(&mut node, &format!("distance {a} {a}")),
+            Ok(Some(Flow::Continue))
+        );
+        assert_eq!(
+            run(&mut node, "distance"),
+            Err(CommandError::MissingArg("node id"))
+        );
+        assert!(matches!(
+            run(&mut node, &format!("distance {a} {a} {a}")),
+            Err(CommandError::InvalidArg(_))
+        ));
+        for bad in ["abc", &format!("{}G", &a[..39]), &format!("{a}00")] {
+            assert_eq!(
+                run(&mut node, &format!("distance {bad}")),
+                Err(CommandError::InvalidArg(format!(
+                    "invalid node id: {bad} (expected 40 hex chars)"
+                ))),
+            );
+        }
+    }
+
+    #[test]
+    fn distance_counts_leading_zero_bits() {
+        let a = id("0000000000000000000000000000000000000000");
+        let b = id("00F0000000000000000000000000000000000001");
+        assert_eq!(
+            handle_distance(&a, &b),
+            [
+                "Distance     : 00F0000000000000000000000000000000000001",
+                "Leading zeros: 8",
+                "Bucket       : 151",
+            ]
+        );
+
+        let c = id("8000000000000000000000000000000000000000");
+        assert_eq!(handle_distance(&b, &c)[1], "Leading zeros: 0");
+        assert_eq!(handle_distance(&b, &c)[2], "Bucket       : 159");
+        assert_eq!(
+            handle_distance(&c, &c)[1..],
+            ["Leading zeros: 160", "Bucket       : none (same id)"]
+        );
+    }
+
+    #[test]
+    fn cas_command_swaps_only_on_a_match() {
+        let mut node = node();
+        let value = |node: &Node, key: &str| node.stores.mock.get(key);
+
+        assert_eq!(run(&mut node, "cas k --nil 1"), Ok(Some(Flow::Continue)));
+        assert_eq!(value(&node, "k"), Some(Value::Utf8("1".to_string())));
+
+        run(&mut node, "cas k 2 3").unwrap();
+        run(&mut node, "cas k --nil 3").unwrap();
+        assert_eq!(value(&node, "k"), Some(Value::Utf8("1".to_string())));
+
+        run(&mut node, "cas k 1 two words").unwrap();
+        assert_eq!(
+            value(&node, "k"),
+            Some(Value::Utf8("two words".to_string()))
+        );
+        assert_eq!(node.metrics.puts, 2);
+    }
+
+    #[test]
+    fn published_name_resolves_to_the_node() {
+        let mut node: Node = node();
+        let node_id = node.node_id;
+        let id = format!("{node_id:X}");
+        let store = node.stores.active(node.mode);
+
+        assert_eq!(
+            handle_name(
+                store,
+                Mode::Mock,
+                None,
+                &node_id,
+                "alice",
+                Policy::FirstCome
+            ),
+            format!("Published (mock): alice -> {id} (version 1)")
+        );
+        assert_eq!(
+            handle_resolve(store, Mode::Mock, "alice"),
+            format!(
+                "alice (mock): {id} ({})  addr=unknown  version=1",
+                peer_fingerprint(&id)
+            )
+        );
+        assert_eq!(
+            handle_resolve(store, Mode::Mock, "bob"),
+            "Name 'bob' not found (mock)."
+        );
+    }
+
+    #[test]
+    fn parse_several_commands() {
+        assert_eq!(
+            parse_line("put a 1; get a"),
+            [
+                Some(("put", vec!["a".to_string(), "1".to_string()])),
+                Some(("get", vec!["a".to_string()])),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_stray_prompts() {
+        assert_eq!(
+            parse_line(">> > />put a 1"),
+            [Some(("put", vec!["a".to_string(), "1".to_string()]))]
+        );
+    }
+
+    #[test]
+    fn parse_empty_segment() {
+        assert_eq!(
+            parse_line("put a 1;; get a"),
+            [
+                Some(("put", vec!["a".to_string(), "1".to_string()])),
+                None,
+                Some(("get", vec!["a".to_string()])),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_quoted_semicolon() {
+        assert_eq!(
+            parse_line(r#"put k "a;b c"; put k 'x;y'; put k a\;b"#),
+            [
+                Some(("put", vec!["k".to_string(), "a;b c".to_string()])),
+                Some(("put", vec!["k".to_string(), "x;y".to_string()])),
+                Some(("put", vec!["k".to_string(), "a;b".to_string()])),
+            ]
+        );
+        assert!(matches!(
+            parse_command("put k \"a", &BTreeMap::new()),
+            Err(CommandError::InvalidArg(_))
+        ));
+    }
+
+    #[test]
+    fn alias_is_defined_used_and_listed() {
+        let mut node = node();
+        assert_eq!(run(&mut node, "alias p put"), Ok(Some(Flow::Continue)));
+        assert_eq!(run(&mut node, "alias g get"), Ok(Some(Flow::Continue)));
+
+        assert_eq!(run(&mut node, "p k v"), Ok(Some(Flow::Continue)));
+        assert_eq!(
+            node.stores.mock.get("k"),
+            Some(Value::Utf8("v".to_string()))
+        );
+        assert_eq!(alias_lines(&node.aliases), ["  g -> get", "  p -> put"]);
+    }
+
+    #[test]
+    fn parse_mock_toggle() {
+        assert_eq!(
+            parse_line("mock on; /mock off"),
+            [
+                Some(("mock", vec!["on".to_string()])),
+                Some(("mock", vec!["off".to_string()])),
+            ]
+        );
+
+        let mut node = node();
+        assert_eq!(
+            run(&mut node, "mock"),
+            Err(CommandError::MissingArg("'on' or 'off'"))
+        );
+        assert!(matches!(
+            run(&mut node, "mock maybe"),
+            Err(CommandError::InvalidArg(_))
+        ));
+        assert_eq!(node.mode, Mode::Mock);
+    }
+
+    #[test]
+    fn puts_follow_the_mode() {
+        let mut node = node();
+        let utf8 = |s: &str| Some(Value::Utf8(s.to_string()));
+
+        run(&mut node, "mock off").unwrap();
+        assert_eq!(node.mode, Mode::Network);
+        run(&mut node, "put k network").unwrap();
+        assert_eq!(node.stores.network.get("k"), utf8("network"));
+        assert_eq!(node.stores.mock.get("k"), None);
+
+        run(&mut node, "mock on").unwrap();
+        assert_eq!(node.mode, Mode::Mock);
+        run(&mut node, "put k mock").unwrap();
+        assert_eq!(node.stores.mock.get("k"), utf8("mock"));
+        assert_eq!(node.stores.network.get("k"), utf8("network"));
+
+        // Switching to the mode already active changes nothing.
+        assert_eq!(handle_set_mode(Mode::Mock, false), Mode::Mock);
+    }
+
+    #[test]
+    fn b64_round_trips_binary_values() {
+        let mut node: Node = node();
+        let bytes = vec![0xff, 0x00, 0xfe];
+        assert!(String::from_utf8(bytes.clone()).is_err());
+
+        run(&mut node, "put --b64 bin /wD+").unwrap();
+        let value = node.stores.mock.get("bin").unwrap();
+        assert_eq!(value, Value::Bytes(bytes));
+        assert_eq!(format_value(&value, ValueFormat::Base64), "/wD+");
+        assert!(run(&mut node, "get --b64 bin").is_ok());
+
+        // Invalid base64 is refused and nothing is stored.
+        assert!(matches!(
+            run(&mut node, "put --b64 bad not*base64"),
+            Err(CommandError::InvalidArg(_))
+        ));
+        assert_eq!(node.stores.mock.get("bad"), None);
+    }
+
+    #[test]
+    fn alias_keeps_quoted_arguments() {
+        let mut node = node();
+        run(&mut node, r#"alias s put "a b" 'say "hi"; \\bye'"#).unwrap();
+        assert_eq!(
+            alias_lines(&node.aliases),
+            [r#"  s -> put "a b" "say \"hi\"; \\bye""#]
+        );
+
+        assert_eq!(run(&mut node, "s"), Ok(Some(Flow::Continue)));
+        assert_eq!(
+            node.stores.mock.get("a b"),
+            Some(Value::Utf8(r#"say "hi"; \bye"#.to_string()))
+        );
+    }
+
+    #[test]
+    fn quoted_words_split_back_unchanged() {
+        for word in ["plain", "", "two words", "semi;colon", "it's", "\\"] {
+            assert_eq!(split_words(&quote_word(word)).unwrap(), [word]);
+        }
+    }
+
+    #[test]
+    fn recursive_alias_is_rejected() {
+        let mut node = node();
+        run(&mut node, "alias a b").unwrap();
+        let Err(error) = run(&mut node, "alias b a") else {
+            panic!("recursive alias accepted");
+        };
+        assert_eq!(
+            error,
+            CommandError::InvalidArg("recursive alias 'b'".to_string())
+        );
+        assert_eq!(alias_lines(&node.aliases), ["  a -> b"]);
+    }
+
+    #[test]
+    fn alias_can_not_shadow_a_command() {
+        let mut node = node();
+        let Err(error) = run(&mut node, "alias put get") else {
+            panic!("alias shadowing put accepted");
+        };
+        assert!(matches!(error, CommandError::InvalidArg(_)));
+        assert!(node.aliases.is_empty());
+    }
+
+    /// A departure notice signed by the node with the key of `seed`.
+    fn leave_notice(seed: u8) -> (RendezvousMessage, [u8; 20]) {
+        let identity = Identity::from_seed([seed; 32]);
+        let notice = RendezvousMessage::Leave {
+            nonce: 1,
+            public_key: identity.public_key(),
+            signature: identity.sign(&dht::leave_challenge(1)).to_vec(),
+        };
+        (notice, *identity.node_id().as_bytes())
+    }
+
+    #[test]
+    fn first_key_seen_at_an_address_is_pinned() {
+        let network = MockNetwork::new();
+        let replica = Replica::spawn(&network, "10.0.0.2:4000", 1);
+        let mut node = connected(&network, &[&replica]);
+        let Contact { node_id, addr } = replica.contact;
+
+        let mut serve = |_: &RendezvousMessage| None;
+        let client = node.client.as_mut().unwrap();
+        assert_eq!(dht::ping(client, addr, &mut serve), Some(node_id));
+        assert_eq!(
+            node.pins().get("10.0.0.2:4000"),
+            Some(&Identity::from_seed([1; 32]).public_key()[..])
+        );
+    }
+
+    #[test]
+    fn another_key_at_a_pinned_address_is_refused_until_reset() {
+        let network = MockNetwork::new();
+        let mut node = connected(&network, &[]);
+        let from: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        let (first, first_id) = leave_notice(1);
+        let (other, other_id) = leave_notice(2);
+
+        let client = node.client.as_mut().unwrap();
+        assert_eq!(dht::departed(client, &first, from), Some(first_id));
+        assert_eq!(dht::departed(client, &other, from), None);
+        let other_key = Identity::from_seed([2; 32]).public_key();
+        assert!(matches!(
+            client.verify_identity(&from.to_string(), &other_key),
+            Err(PinError::IdentityMismatch { .. })
+        ));
+
+        assert_eq!(
+            handle_reset_pin(&mut node, from),
+            "Reset the identity pin of 10.0.0.2:4000, its next key is trusted."
+        );
+        let client = node.client.as_mut().unwrap();
+        assert_eq!(dht::departed(client, &other, from), Some(other_id));
+        assert_eq!(dht::departed(client, &first, from), None);
+    }
+
+    #[test]
+    fn reset_pin_without_a_pin() {
+        let mut node = node();
+        run(&mut node, "reset-pin 10.0.0.2:4000").unwrap();
+        assert_eq!(
+            handle_reset_pin(&mut node, "10.0.0.2:4000".parse().unwrap()),
+            "No identity key pinned for 10.0.0.2:4000."
+        );
+        assert!(matches!(
+            run(&mut node, "reset-pin alice"),
+            Err(CommandError::InvalidArg(_))
+        ));
+    }
+
+    #[test]
+    fn ice_servers_are_parsed() {
+        assert_eq!(parse_ice_servers("on"), Ok(Vec::new()));
+        assert_eq!(
+            parse_ice_servers("stun:a.example:3478, turn:b.example"),
+            Ok(vec![
+                "stun:a.example:3478".to_string(),
+                "turn:b.example".to_string()
+            ])
+        );
+        assert!(parse_ice_servers("http://a.example").is_err());
+    }
 }
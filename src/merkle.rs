@@ -0,0 +1,211 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Merkle trees over the records of a key range, compared between
+//! replicas by anti-entropy sync.
+//!
+//! Replicas miss records while they are unreachable, and hold stale ones
+//! after missing an update. Every [`DEFAULT_SYNC_INTERVAL`] a node
+//! compares the records it shares with each of its closest neighbours:
+//! both build a tree over the records held in the key range the two are
+//! nearest to, see [`shared_range`], and compare it top down, only asking
+//! for the children of nodes that differ. The versions of the keys in the
+//! leaves that differ are then traded, and each side pulls what it misses
+//! or holds an older copy of.
+//!
+//! A tree has [`LEVELS`] levels below its root, every node having
+//! [`FANOUT`] children. A record falls in the leaf numbered by the bits of
+//! its key id following the range prefix. A record hashes its key,
+//! sequence number and value, but not its lifetime. A node hashes the
+//! concatenation of its children, or is zero when they all are, so empty
+//! subtrees compare equal without hashing anything.
+
+use std::time::Duration;
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    dht::key_id,
+    protocol::KeyRange,
+    routing::{ID_BITS, distance, leading_zeros},
+    store::{Store, Value},
+};
+
+/// Bits of key id consumed by each level of a tree.
+pub const FANOUT_BITS: usize = 4;
+
+/// Children of every inner node.
+pub const FANOUT: usize = 1 << FANOUT_BITS;
+
+/// Levels below the root; the last one holds the leaves.
+pub const LEVELS: usize = 2;
+
+/// Deepest range prefix, leaving the bits of the leaf index in a key id.
+pub const MAX_DEPTH: usize = ID_BITS - FANOUT_BITS * LEVELS;
+
+/// Most differing nodes descended into at each level of a sync. The
+/// others are left for the next sync.
+pub const MAX_NODES: usize = 64;
+
+/// Bytes of keys a `Keys` reply carries at most.
+pub const KEYS_BUDGET: usize = 32 * 1024;
+
+/// Default time between two syncs with the same neighbour.
+pub const DEFAULT_SYNC_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Number of records read from a store at a time.
+const PAGE_SIZE: usize = 256;
+
+/// The smallest range holding both `a` and `b`, at most [`MAX_DEPTH`]
+/// bits deep: the keys both nodes are nearer to than most others.
+pub fn shared_range(a: &[u8; 20], b: &[u8; 20]) -> KeyRange {
+    let depth = leading_zeros(&distance(a, b)).min(MAX_DEPTH);
+    let mut prefix = [0u8; 20];
+    for (i, byte) in prefix.iter_mut().enumerate() {
+        let first = i * 8;
+        if first + 8 <= depth {
+            *byte = a[i];
+        } else if first < depth {
+            *byte = a[i] & !(0xff >> (depth - first));
+        }
+    }
+    KeyRange { prefix, depth: depth as u8 }
+}
+
+/// Leaf of a tree over `range` that `id` falls in, `None` when it is
+/// outside of the range or the range is deeper than [`MAX_DEPTH`].
+pub fn leaf(range: &KeyRange, id: &[u8; 20]) -> Option<usize> {
+    let depth = range.depth as usize;
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    let bit = |i: usize| id[i / 8] & (0x80 >> (i % 8)) != 0;
+    let prefix = |i: usize| range.prefix[i / 8] & (0x80 >> (i % 8)) != 0;
+    if (0..depth).any(|i| bit(i) != prefix(i)) {
+        return None;
+    }
+    Some(
+        (depth..depth + FANOUT_BITS * LEVELS)
+            .fold(0, |leaf, i| leaf << 1 | bit(i) as usize),
+    )
+}
+
+/// Every live record of `store` in `range`.
+pub fn records(store: &dyn Store, range: &KeyRange) -> Vec<(String, Value)> {
+    let mut records = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = store.page(cursor.as_deref(), PAGE_SIZE);
+        records.extend(
+            page.entries
+                .into_iter()
+                .filter(|(key, _)| leaf(range, &key_id(key)).is_some()),
+        );
+        match page.next {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+    records
+}
+
+/// Hash of a record.
+fn record_hash(key: &str, seq: u64, value: &Value) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    hasher.update((key.len() as u64).to_le_bytes());
+    hasher.update(key.as_bytes());
+    hasher.update(seq.to_le_bytes());
+    hasher.update(value.as_bytes());
+    hasher.finalize().into()
+}
+
+/// Hash of a node with the given children, zero when they all are.
+fn node_hash<'a>(
+    children: impl IntoIterator<Item = &'a [u8; 20]>,
+) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    let mut empty = true;
+    for child in children {
+        empty &= *child == [0; 20];
+        hasher.update(child);
+    }
+    if empty { [0; 20] } else { hasher.finalize().into() }
+}
+
+/// Tree
+///
+/// The hashes of a Merkle tree over the records of a key range, level by
+/// level from the root.
+#[derive(Debug, Clone)]
+pub struct Tree {
+    levels: Vec<Vec<[u8; 20]>>,
+}
+
+impl Tree {
+    /// Build the tree of `range` over `records`, given as key, sequence
+    /// number and value. Records outside of the range are skipped.
+    pub fn build<'a>(
+        range: &KeyRange,
+        records: impl IntoIterator<Item = (&'a str, u64, &'a Value)>,
+    ) -> Self {
+        let mut leaves = vec![Vec::new(); FANOUT.pow(LEVELS as u32)];
+        for (key, seq, value) in records {
+            let id = key_id(key);
+            if let Some(leaf) = leaf(range, &id) {
+                leaves[leaf].push((id, record_hash(key, seq, value)));
+            }
+        }
+
+        let bottom: Vec<[u8; 20]> = leaves
+            .into_iter()
+            .map(|mut hashes| {
+                hashes.sort();
+                node_hash(hashes.iter().map(|(_, hash)| hash))
+            })
+            .collect();
+        let mut levels = vec![bottom];
+        while levels[0].len() > 1 {
+            let parents = levels[0].chunks(FANOUT).map(node_hash).collect();
+            levels.insert(0, parents);
+        }
+        Tree { levels }
+    }
+
+    /// Build the tree of `range` over the records of `store`.
+    pub fn of_store(store: &dyn Store, range: &KeyRange) -> Self {
+        let records = records(store, range);
+        Tree::build(
+            range,
+            records
+                .iter()
+                .map(|(key, value)| (key.as_str(), store.seq(key), value)),
+        )
+    }
+
+    pub fn root(&self) -> [u8; 20] {
+        self.levels[0][0]
+    }
+
+    /// Hashes of the children of node `index` at `level`, empty when there
+    /// is no such inner node.
+    pub fn children(&self, level: usize, index: usize) -> &[[u8; 20]] {
+        self.levels
+            .get(level + 1)
+            .and_then(|hashes| {
+                hashes.get(index * FANOUT..(index + 1) * FANOUT)
+            })
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,237 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Transport over several local interfaces.
+//!
+//! A host with a LAN and a VPN interface, say, reaches some peers only
+//! through one of them. [`MultiHomedTransport`] listens on a link bound to
+//! each local address and sends to every peer through the link it last
+//! heard that peer on, or else through the one bound to the address the
+//! OS would send from, see [`transport::source_address`]. A peer that
+//! stops answering is tried through the other links in turn.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{
+        Mutex, MutexGuard,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use crate::transport::{self, Transport};
+
+/// Peers whose route is remembered. Once full, routes are learned again.
+const MAX_ROUTES: usize = 4096;
+
+#[derive(Debug, Default)]
+struct Routes {
+    /// Link each peer was last heard on or sent to.
+    learned: HashMap<SocketAddr, usize>,
+    /// Links set with [`MultiHomedTransport::pin`], never relearned.
+    pinned: HashMap<SocketAddr, usize>,
+}
+
+/// Transport sending and receiving over several links, each bound to its
+/// own local address.
+///
+/// `recv_from` reads every link in turn, which must be nonblocking.
+#[derive(Debug)]
+pub struct MultiHomedTransport<T: Transport> {
+    links: Vec<T>,
+    /// Address each link is bound to.
+    local: Vec<SocketAddr>,
+    /// Link read first by the next `recv_from`, so a busy link does not
+    /// starve the others.
+    next: AtomicUsize,
+    routes: Mutex<Routes>,
+}
+
+impl<T: Transport> MultiHomedTransport<T> {
+    /// Send and receive over `links`. The first is the primary link, whose
+    /// address is the [`Transport::local_addr`] of the whole.
+    pub fn new(links: Vec<T>) -> io::Result<Self> {
+        if links.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no link to send over",
+            ));
+        }
+        let local = links
+            .iter()
+            .map(Transport::local_addr)
+            .collect::<io::Result<_>>()?;
+        Ok(MultiHomedTransport {
+            links,
+            local,
+            next: AtomicUsize::new(0),
+            routes: Mutex::new(Routes::default()),
+        })
+    }
+
+    pub fn links(&self) -> &[T] {
+        &self.links
+    }
+
+    pub fn primary(&self) -> &T {
+        &self.links[0]
+    }
+
+    /// Addresses the links are bound to, the primary one first.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local
+    }
+
+    /// Always send to `addr` through link number `link`, e.g. the only one
+    /// knowing how to reach it.
+    pub fn pin(&self, addr: SocketAddr, link: usize) {
+        if link < self.links.len() {
+            self.routes().pinned.insert(addr, link);
+        }
+    }
+
+    /// Address of the link datagrams to `addr` go through.
+    pub fn route_to(&self, addr: SocketAddr) -> SocketAddr {
+        self.local[self.route(addr)]
+    }
+
+    /// Link to send to `addr` through: the pinned or learned one, else the
+    /// one bound to the address the OS sends from to reach `addr`, else the
+    /// first one of its family.
+    fn route(&self, addr: SocketAddr) -> usize {
+        if self.links.len() == 1 {
+            return 0;
+        }
+        let mut routes = self.routes();
+        if let Some(&link) =
+            routes.pinned.get(&addr).or_else(|| routes.learned.get(&addr))
+        {
+            return link;
+        }
+        let source = transport::source_address(addr.ip());
+        let link = self
+            .local
+            .iter()
+            .position(|local| Some(local.ip()) == source)
+            .or_else(|| {
+                self.local.iter().position(|local| reaches(*local, addr))
+            })
+            .unwrap_or(0);
+        routes.learn(addr, link);
+        link
+    }
+
+    fn routes(&self) -> MutexGuard<'_, Routes> {
+        self.routes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Routes {
+    fn learn(&mut self, addr: SocketAddr, link: usize) {
+        if self.learned.len() >= MAX_ROUTES
+            && !self.learned.contains_key(&addr)
+        {
+            self.learned.clear();
+        }
+        self.learned.insert(addr, link);
+    }
+}
+
+impl<T: Transport> Transport for MultiHomedTransport<T> {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.links[self.route(addr)].send_to(buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let first = self.next.fetch_add(1, Ordering::Relaxed);
+        for i in 0..self.links.len() {
+            let link = (first + i) % self.links.len();
+            match self.links[link].recv_from(buf) {
+                Ok((len, from)) => {
+                    // Answer through the link the peer reached.
+                    if self.links.len() > 1 {
+                        self.routes().learn(from, link);
+                    }
+                    return Ok((len, from));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local[0])
+    }
+
+    /// Also moves `addr` to the next link able to reach it, unless pinned,
+    /// so every path is tried until one gets answers.
+    fn timed_out(&self, addr: SocketAddr) {
+        let link = self.route(addr);
+        self.links[link].timed_out(addr);
+
+        let mut routes = self.routes();
+        if routes.pinned.contains_key(&addr) {
+            return;
+        }
+        let next = (1..self.links.len())
+            .map(|i| (link + i) % self.links.len())
+            .find(|&next| reaches(self.local[next], addr));
+        if let Some(next) = next {
+            routes.learn(addr, next);
+        }
+    }
+
+    /// Those of the first link, whose address is the one registered.
+    fn capabilities(&self) -> Vec<String> {
+        self.links[0].capabilities()
+    }
+
+    fn learn_capabilities(&self, addr: SocketAddr, capabilities: &[String]) {
+        for link in &self.links {
+            link.learn_capabilities(addr, capabilities);
+        }
+    }
+
+    /// Through the first link able to, which `addr` is then pinned to so
+    /// the path set up is the one used.
+    fn negotiate(&self, peer_id: &str, addr: SocketAddr) -> bool {
+        let link =
+            self.links.iter().position(|link| link.negotiate(peer_id, addr));
+        if let Some(link) = link
+            && self.links.len() > 1
+        {
+            self.pin(addr, link);
+        }
+        link.is_some()
+    }
+
+    fn take_signals(&self) -> Vec<(String, Vec<u8>)> {
+        self.links.iter().flat_map(|link| link.take_signals()).collect()
+    }
+
+    fn on_signal(&self, peer_id: &str, payload: &[u8]) -> bool {
+        self.links.iter().any(|link| link.on_signal(peer_id, payload))
+    }
+}
+
+/// Whether a link bound to `local` can send to `addr`: same family, or a
+/// dual-stack socket.
+fn reaches(local: SocketAddr, addr: SocketAddr) -> bool {
+    local.is_ipv4() == addr.is_ipv4()
+        || (local.is_ipv6() && local.ip().is_unspecified())
+}
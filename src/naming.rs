@@ -0,0 +1,206 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Human-readable names for peer ids.
+//!
+//! A name is published as a [`NameRecord`] stored under [`NAMESPACE`] in
+//! the record store. Every publication bumps the record version. Under
+//! [`Policy::FirstCome`] a name belongs to the first peer id publishing it
+//! and only that peer can update it; under [`Policy::LastWriter`] anyone
+//! can take it over.
+
+use std::{fmt, net::SocketAddr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::store::{Store, Value};
+
+/// Key prefix reserved for name records.
+pub const NAMESPACE: &str = "@names/";
+
+/// A published name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NameRecord {
+    pub peer_id: String,
+    /// Address the peer can be reached at, if it published one.
+    pub addr: Option<SocketAddr>,
+    /// Starts at 1 and grows with every publication.
+    pub version: u64,
+}
+
+/// Who may publish a name that is already taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// Only the peer that published the name first.
+    #[default]
+    FirstCome,
+    /// Anyone; the latest publication wins.
+    LastWriter,
+}
+
+/// Error returned by [`publish`] and [`resolve`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameError {
+    /// The name is empty or contains whitespace.
+    Invalid,
+    /// The name belongs to another peer.
+    Taken { owner: String },
+    /// The stored record could not be decoded.
+    Corrupt(String),
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameError::Invalid => write!(f, "invalid name"),
+            NameError::Taken { owner } => {
+                write!(f, "name already taken by {owner}")
+            }
+            NameError::Corrupt(e) => write!(f, "corrupt name record: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for NameError {}
+
+/// Store key of the record for `name`.
+pub fn key(name: &str) -> String {
+    format!("{NAMESPACE}{name}")
+}
+
+/// Publish `name` for `peer_id`, reachable at `addr`, and return the new
+/// record.
+pub fn publish(
+    store: &mut dyn Store,
+    name: &str,
+    peer_id: &str,
+    addr: Option<SocketAddr>,
+    policy: Policy,
+) -> Result<NameRecord, NameError> {
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(NameError::Invalid);
+    }
+
+    let version = match resolve(store, name)? {
+        Some(current)
+            if policy == Policy::FirstCome && current.peer_id != peer_id =>
+        {
+            return Err(NameError::Taken { owner: current.peer_id });
+        }
+        Some(current) => current.version + 1,
+        None => 1,
+    };
+
+    let record = NameRecord { peer_id: peer_id.to_string(), addr, version };
+    let encoded = serde_json::to_string(&record)
+        .map_err(|e| NameError::Corrupt(e.to_string()))?;
+    store.put(key(name), Value::Utf8(encoded));
+
+    Ok(record)
+}
+
+/// Look up the record published for `name`.
+pub fn resolve(
+    store: &dyn Store,
+    name: &str,
+) -> Result<Option<NameRecord>, NameError> {
+    let Some(value) = store.get(&key(name)) else {
+        return Ok(None);
+    };
+
+    serde_json::from_slice(value.as_bytes())
+        .map(Some)
+        .map_err(|e| NameError::Corrupt(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    const ALICE: &str = "8F732D6748E20C80141405F5008B400B25D8166F";
+    const BOB: &str = "0123456789ABCDEF0123456789ABCDEF01234567";
+
+    #[test]
+    fn published_name_resolves() {
+        let mut store = MemoryStore::new();
+        let addr = "192.0.2.1:4000".parse().unwrap();
+
+        let record =
+            publish(&mut store, "alice", ALICE, Some(addr), Policy::FirstCome)
+                .unwrap();
+
+        assert_eq!(
+            record,
+            NameRecord {
+                peer_id: ALICE.to_string(),
+                addr: Some(addr),
+                version: 1
+            }
+        );
+        assert_eq!(resolve(&store, "alice"), Ok(Some(record)));
+        assert!(store.get("@names/alice").is_some());
+    }
+
+    #[test]
+    fn unknown_name_resolves_to_nothing() {
+        let store = MemoryStore::new();
+        assert_eq!(resolve(&store, "nobody"), Ok(None));
+    }
+
+    #[test]
+    fn first_come_keeps_the_owner() {
+        let mut store = MemoryStore::new();
+        publish(&mut store, "alice", ALICE, None, Policy::FirstCome).unwrap();
+
+        assert_eq!(
+            publish(&mut store, "alice", BOB, None, Policy::FirstCome),
+            Err(NameError::Taken { owner: ALICE.to_string() })
+        );
+        let update =
+            publish(&mut store, "alice", ALICE, None, Policy::FirstCome);
+        assert_eq!(update.unwrap().version, 2);
+    }
+
+    #[test]
+    fn last_writer_takes_the_name_over() {
+        let mut store = MemoryStore::new();
+        publish(&mut store, "alice", ALICE, None, Policy::FirstCome).unwrap();
+
+        let record =
+            publish(&mut store, "alice", BOB, None, Policy::LastWriter)
+                .unwrap();
+
+        assert_eq!((record.peer_id.as_str(), record.version), (BOB, 2));
+        assert_eq!(resolve(&store, "alice").unwrap(), Some(record));
+    }
+
+    #[test]
+    fn invalid_and_corrupt_names_are_errors() {
+        let mut store = MemoryStore::new();
+        for name in ["", "two words"] {
+            assert_eq!(
+                publish(&mut store, name, ALICE, None, Policy::FirstCome),
+                Err(NameError::Invalid)
+            );
+        }
+
+        store.put(key("broken"), Value::Utf8("not json".to_string()));
+        assert!(matches!(
+            resolve(&store, "broken"),
+            Err(NameError::Corrupt(_))
+        ));
+    }
+}
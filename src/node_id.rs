@@ -0,0 +1,132 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Node identifiers.
+//!
+//! A [`NodeId`] is 160 bits. Closeness between ids is their XOR distance,
+//! itself expressed as a [`NodeId`] so distances compare like ids do.
+
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+/// Error returned when parsing a [`NodeId`] from hex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseNodeIdError(String);
+
+impl fmt::Display for ParseNodeIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid node id: {} (expected 40 hex chars)", self.0)
+    }
+}
+
+impl std::error::Error for ParseNodeIdError {}
+
+/// NodeId
+///
+/// A 20-byte node id. Formats as lowercase hex, `{:X}` gives uppercase.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId([u8; 20]);
+
+impl NodeId {
+    /// Number of bits in an id.
+    pub const BITS: usize = 160;
+
+    pub const fn new(bytes: [u8; 20]) -> Self {
+        NodeId(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    /// XOR distance between `self` and `other`.
+    pub fn distance(&self, other: &NodeId) -> NodeId {
+        let mut out = [0u8; 20];
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = self.0[i] ^ other.0[i];
+        }
+        NodeId(out)
+    }
+
+    /// Number of leading zero bits, [`Self::BITS`] for the zero id.
+    pub fn leading_zeros(&self) -> usize {
+        let mut zeros = 0;
+        for byte in self.0 {
+            if byte != 0 {
+                return zeros + byte.leading_zeros() as usize;
+            }
+            zeros += 8;
+        }
+        zeros
+    }
+
+    /// Compare `a` and `b` by their distance to `self`, closer first.
+    pub fn cmp_distance(&self, a: &NodeId, b: &NodeId) -> Ordering {
+        self.distance(a).cmp(&self.distance(b))
+    }
+}
+
+impl From<[u8; 20]> for NodeId {
+    fn from(bytes: [u8; 20]) -> Self {
+        NodeId(bytes)
+    }
+}
+
+impl From<NodeId> for [u8; 20] {
+    fn from(id: NodeId) -> Self {
+        id.0
+    }
+}
+
+impl FromStr for NodeId {
+    type Err = ParseNodeIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseNodeIdError(s.to_string());
+        if s.len() != 40 {
+            return Err(err());
+        }
+
+        let mut out = [0u8; 20];
+        for (i, byte) in out.iter_mut().enumerate() {
+            let pair = s.get(i * 2..i * 2 + 2).ok_or_else(err)?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| err())?;
+        }
+        Ok(NodeId(out))
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(self, f)
+    }
+}
+
+impl fmt::LowerHex for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+impl fmt::UpperHex for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02X}"))
+    }
+}
+
+impl fmt::Debug for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NodeId({self})")
+    }
+}
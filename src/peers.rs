@@ -0,0 +1,230 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Registered peer table.
+//!
+//! Peers are dropped when they have not been seen for longer than the
+//! configured TTL ("pruned") or when the table is full and room is needed
+//! for a new peer, in which case the least recently seen peer goes
+//! ("evicted"). Observers registered with [`PeerTable::add_observer`] are
+//! told about every such removal so dependent state can be cleaned up.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use crate::protocol::PeerInfo;
+
+/// Why a peer left the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Removal {
+    /// Not seen within the TTL.
+    Pruned,
+    /// Dropped to make room for another peer.
+    Evicted,
+}
+
+/// Callback invoked with every removed peer.
+///
+/// Observers run synchronously inside the server loop and must be cheap.
+pub type PeerObserver = Box<dyn FnMut(&PeerInfo, Removal) + Send>;
+
+/// Peers known to the server, keyed by peer id.
+#[derive(Default)]
+pub struct PeerTable {
+    peers: HashMap<String, PeerInfo>,
+    ttl: Option<Duration>,
+    max_peers: Option<usize>,
+    observers: Vec<PeerObserver>,
+}
+
+impl PeerTable {
+    /// Create a table whose entries expire after `ttl` and which holds at
+    /// most `max_peers` entries. `None` disables either limit.
+    pub fn new(ttl: Option<Duration>, max_peers: Option<usize>) -> Self {
+        PeerTable { ttl, max_peers, ..Self::default() }
+    }
+
+    /// Register a callback invoked whenever a peer is pruned or evicted.
+    pub fn add_observer(&mut self, observer: PeerObserver) {
+        self.observers.push(observer);
+    }
+
+    /// Insert or refresh a peer, evicting the least recently seen peer if
+    /// the table is full.
+    pub fn insert(&mut self, peer: PeerInfo) {
+        if let Some(max) = self.max_peers
+            && !self.peers.contains_key(&peer.peer_id)
+        {
+            while self.peers.len() >= max.max(1) {
+                let Some(oldest) = self
+                    .peers
+                    .values()
+                    .min_by_key(|p| p.last_seen)
+                    .map(|p| p.peer_id.clone())
+                else {
+                    break;
+                };
+                self.remove(&oldest, Removal::Evicted);
+            }
+        }
+
+        self.peers.insert(peer.peer_id.clone(), peer);
+    }
+
+    pub fn get(&self, peer_id: &str) -> Option<&PeerInfo> {
+        self.peers.get(peer_id)
+    }
+
+    /// Mark a peer as seen at `now`. Returns `false` if it is unknown.
+    pub fn touch(&mut self, peer_id: &str, now: SystemTime) -> bool {
+        match self.peers.get_mut(peer_id) {
+            Some(peer) => {
+                peer.last_seen = now;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &PeerInfo> {
+        self.peers.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Whether inserting a new peer would evict another one.
+    pub fn is_full(&self) -> bool {
+        self.max_peers.is_some_and(|max| self.peers.len() >= max.max(1))
+    }
+
+    /// Drop every peer not seen within the TTL as of `now` and return how
+    /// many were pruned.
+    pub fn prune(&mut self, now: SystemTime) -> usize {
+        let Some(ttl) = self.ttl else {
+            return 0;
+        };
+
+        let expired: Vec<String> = self
+            .peers
+            .values()
+            .filter(|p| {
+                now.duration_since(p.last_seen).is_ok_and(|age| age > ttl)
+            })
+            .map(|p| p.peer_id.clone())
+            .collect();
+
+        for peer_id in &expired {
+            self.remove(peer_id, Removal::Pruned);
+        }
+
+        expired.len()
+    }
+
+    fn remove(&mut self, peer_id: &str, reason: Removal) {
+        if let Some(peer) = self.peers.remove(peer_id) {
+            for observer in &mut self.observers {
+                observer(&peer, reason);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// A peer last seen `age` before `now`.
+    fn peer(peer_id: &str, now: SystemTime, age: u64) -> PeerInfo {
+        PeerInfo {
+            peer_id: peer_id.to_string(),
+            public_addr: "192.0.2.1:4000".parse().unwrap(),
+            private_addr: None,
+            last_seen: now - Duration::from_secs(age),
+            capabilities: Vec::new(),
+            candidates: Vec::new(),
+        }
+    }
+
+    /// `table` with an observer recording every removal.
+    fn observed(table: &mut PeerTable) -> Arc<Mutex<Vec<(String, Removal)>>> {
+        let removed = Arc::new(Mutex::new(Vec::new()));
+        table.add_observer(Box::new({
+            let removed = Arc::clone(&removed);
+            move |peer, reason| {
+                removed.lock().unwrap().push((peer.peer_id.clone(), reason));
+            }
+        }));
+        removed
+    }
+
+    #[test]
+    fn prune_observer_gets_the_expired_peer() {
+        let now = SystemTime::now();
+        let mut table = PeerTable::new(Some(Duration::from_secs(60)), None);
+        let removed = observed(&mut table);
+        table.insert(peer("stale", now, 120));
+        table.insert(peer("fresh", now, 10));
+
+        assert_eq!(table.prune(now), 1);
+        assert_eq!(
+            *removed.lock().unwrap(),
+            [("stale".to_string(), Removal::Pruned)]
+        );
+        assert!(table.get("stale").is_none());
+        assert!(table.get("fresh").is_some());
+    }
+
+    #[test]
+    fn eviction_observer_gets_the_least_recently_seen_peer() {
+        let now = SystemTime::now();
+        let mut table = PeerTable::new(None, Some(2));
+        let removed = observed(&mut table);
+        table.insert(peer("old", now, 30));
+        table.insert(peer("recent", now, 10));
+        // Refreshing a known peer evicts nobody.
+        table.insert(peer("recent", now, 0));
+        assert!(removed.lock().unwrap().is_empty());
+
+        table.insert(peer("new", now, 0));
+        assert_eq!(
+            *removed.lock().unwrap(),
+            [("old".to_string(), Removal::Evicted)]
+        );
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn every_observer_fires() {
+        let now = SystemTime::now();
+        let mut table = PeerTable::new(Some(Duration::from_secs(60)), None);
+        let (first, second) = (observed(&mut table), observed(&mut table));
+        table.insert(peer("stale", now, 120));
+        table.prune(now);
+
+        assert_eq!(first.lock().unwrap().len(), 1);
+        assert_eq!(second.lock().unwrap().len(), 1);
+    }
+}
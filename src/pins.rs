@@ -0,0 +1,267 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Trust-on-first-use pinning of peer identity keys.
+//!
+//! The first key seen for a peer id is pinned. A later message claiming
+//! the same peer id with another key is refused as a possible
+//! impersonation, until the pin is explicitly reset. Pins can be kept in a
+//! text file holding one `<peer_id> <hex key>` line per peer.
+
+use std::{
+    collections::BTreeMap,
+    error, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+
+/// Error returned by [`PinStore::verify`] and [`PinStore::reset`].
+#[derive(Debug)]
+pub enum PinError {
+    /// The peer presented a different key than the pinned one.
+    IdentityMismatch { peer_id: String, pinned: Vec<u8>, presented: Vec<u8> },
+    /// The pin file could not be written.
+    Io(io::Error),
+}
+
+impl fmt::Display for PinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinError::IdentityMismatch { peer_id, pinned, presented } => {
+                write!(
+                    f,
+                    "identity mismatch for peer {peer_id}: pinned key {}, \
+                     presented key {}",
+                    encode_hex(pinned),
+                    encode_hex(presented)
+                )
+            }
+            PinError::Io(e) => write!(f, "I/O failure: {e}"),
+        }
+    }
+}
+
+impl error::Error for PinError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PinError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PinError {
+    fn from(e: io::Error) -> Self {
+        PinError::Io(e)
+    }
+}
+
+/// Pinned identity keys, keyed by peer id.
+#[derive(Debug, Default)]
+pub struct PinStore {
+    pins: BTreeMap<String, Vec<u8>>,
+    /// File the pins are saved to on every change, if any.
+    path: Option<PathBuf>,
+}
+
+impl PinStore {
+    /// Create an empty store that is not persisted.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the pins saved at `path`, which is created on the first pin if
+    /// it does not exist yet.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut pins = BTreeMap::new();
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let key = line
+                .split_once(' ')
+                .and_then(|(peer_id, key)| Some((peer_id, decode_hex(key)?)));
+            let Some((peer_id, key)) = key else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: malformed pin", path.display(), n + 1),
+                ));
+            };
+            pins.insert(peer_id.to_string(), key);
+        }
+
+        Ok(PinStore { pins, path: Some(path) })
+    }
+
+    /// Check `key` against the key pinned for `peer_id`, pinning it if the
+    /// peer was never seen. Returns whether the key was newly pinned.
+    pub fn verify(
+        &mut self,
+        peer_id: &str,
+        key: &[u8],
+    ) -> Result<bool, PinError> {
+        match self.pins.get(peer_id) {
+            Some(pinned) if pinned == key => Ok(false),
+            Some(pinned) => {
+                warn!(
+                    "peer {peer_id} presented an unknown identity key, \
+                     possible impersonation"
+                );
+                Err(PinError::IdentityMismatch {
+                    peer_id: peer_id.to_string(),
+                    pinned: pinned.clone(),
+                    presented: key.to_vec(),
+                })
+            }
+            None => {
+                self.pins.insert(peer_id.to_string(), key.to_vec());
+                self.save()?;
+                info!("pinned identity key of peer {peer_id}");
+                Ok(true)
+            }
+        }
+    }
+
+    /// Forget the key pinned for `peer_id`, so the next key it presents is
+    /// trusted. Returns whether a pin was removed.
+    pub fn reset(&mut self, peer_id: &str) -> Result<bool, PinError> {
+        if self.pins.remove(peer_id).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        info!("reset identity pin of peer {peer_id}");
+        Ok(true)
+    }
+
+    pub fn get(&self, peer_id: &str) -> Option<&[u8]> {
+        self.pins.get(peer_id).map(Vec::as_slice)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+
+    /// Rewrite the pin file, through a temporary file so a crash never
+    /// leaves it half written. Changes are saved as they are made, so this
+    /// only matters to make sure the file is up to date.
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut contents = String::new();
+        for (peer_id, key) in &self.pins {
+            contents.push_str(&format!("{peer_id} {}\n", encode_hex(key)));
+        }
+
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.is_empty() || !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A pin file path unique to this process.
+    fn pin_file(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("tesseras-{}-{name}.pins", std::process::id()))
+    }
+
+    #[test]
+    fn first_key_is_pinned_and_others_refused() {
+        let mut pins = PinStore::new();
+        assert!(pins.verify("10.0.0.2:4000", &[1; 32]).unwrap());
+        assert!(!pins.verify("10.0.0.2:4000", &[1; 32]).unwrap());
+
+        let Err(PinError::IdentityMismatch { peer_id, pinned, presented }) =
+            pins.verify("10.0.0.2:4000", &[2; 32])
+        else {
+            panic!("another key was accepted");
+        };
+        assert_eq!(peer_id, "10.0.0.2:4000");
+        assert_eq!((pinned, presented), (vec![1; 32], vec![2; 32]));
+        assert_eq!(pins.get("10.0.0.2:4000"), Some(&[1; 32][..]));
+    }
+
+    #[test]
+    fn reset_trusts_the_next_key() {
+        let mut pins = PinStore::new();
+        pins.verify("alice", &[1; 32]).unwrap();
+
+        assert!(pins.reset("alice").unwrap());
+        assert!(!pins.reset("alice").unwrap());
+        assert!(pins.verify("alice", &[2; 32]).unwrap());
+        assert_eq!(pins.get("alice"), Some(&[2; 32][..]));
+    }
+
+    #[test]
+    fn pins_survive_reopening() {
+        let path = pin_file("reopened");
+        let mut pins = PinStore::open(&path).unwrap();
+        pins.verify("alice", &[1; 32]).unwrap();
+        pins.verify("bob", &[2; 32]).unwrap();
+        pins.reset("bob").unwrap();
+
+        let pins = PinStore::open(&path).unwrap();
+        assert_eq!(pins.len(), 1);
+        assert_eq!(pins.get("alice"), Some(&[1; 32][..]));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn malformed_pin_file_is_refused() {
+        let path = pin_file("malformed");
+        fs::write(&path, "# pins\nalice 0g\n").unwrap();
+        let error = PinStore::open(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(path).unwrap();
+    }
+}
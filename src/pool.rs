@@ -0,0 +1,81 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Reusable packet buffers.
+//!
+//! The server receives and answers every datagram in a buffer of its own,
+//! queued between threads or batched before it is sent. Allocating those
+//! per packet costs more than handling most messages, so they are taken
+//! from a [`BufferPool`] and given back once sent or handled: past warm
+//! up, moving packets allocates nothing.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Default number of idle buffers kept by a [`BufferPool`].
+pub const DEFAULT_POOL_SIZE: usize = 4096;
+
+/// A pool of byte buffers shared between threads. Clones share the pool.
+#[derive(Debug, Clone)]
+pub struct BufferPool {
+    idle: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_idle: usize,
+}
+
+impl BufferPool {
+    /// A pool keeping up to `max_idle` buffers given back, dropping the
+    /// others.
+    pub fn new(max_idle: usize) -> Self {
+        BufferPool { idle: Arc::new(Mutex::new(Vec::new())), max_idle }
+    }
+
+    /// An empty buffer, reusing the allocation of one given back if any.
+    pub fn take(&self) -> Vec<u8> {
+        self.idle().pop().unwrap_or_default()
+    }
+
+    /// An idle buffer holding a copy of `data`.
+    pub fn copy(&self, data: &[u8]) -> Vec<u8> {
+        let mut buf = self.take();
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    /// Give `buf` back for reuse.
+    pub fn put(&self, mut buf: Vec<u8>) {
+        if buf.capacity() == 0 {
+            return;
+        }
+        buf.clear();
+        let mut idle = self.idle();
+        if idle.len() < self.max_idle {
+            idle.push(buf);
+        }
+    }
+
+    /// Number of buffers waiting to be reused.
+    pub fn idle_buffers(&self) -> usize {
+        self.idle().len()
+    }
+
+    fn idle(&self) -> MutexGuard<'_, Vec<Vec<u8>>> {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_POOL_SIZE)
+    }
+}
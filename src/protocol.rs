@@ -0,0 +1,1006 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Rendezvous wire protocol.
+//!
+//! Messages are encoded with bincode using [`CODEC`], behind an envelope
+//! holding [`MAGIC`], the protocol version, the message type and the
+//! [`NetworkId`] of the sender, and carried one per datagram. Stream
+//! transports wrap each message in a frame, see [`write_frame`] and
+//! [`read_frame`].
+//!
+//! The byte layout and golden encodings of every message are kept in
+//! `docs/wire-format.md`. Changing the format means updating them.
+//!
+//! https://en.wikipedia.org/wiki/Rendezvous_protocol
+
+use std::{error, fmt, io, net::SocketAddr, str::FromStr, time::SystemTime};
+
+use bincode::{
+    Decode, Encode,
+    config::{Configuration, LittleEndian, NoLimit},
+    error::{DecodeError, EncodeError},
+};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    io::{ReadError, read_full},
+    store::Value,
+};
+
+#[cfg(not(feature = "fixed-int"))]
+type IntEncoding = bincode::config::Varint;
+
+#[cfg(feature = "fixed-int")]
+type IntEncoding = bincode::config::Fixint;
+
+/// Bincode configuration type used on the wire, see [`CODEC`].
+pub type Codec = Configuration<LittleEndian, IntEncoding, NoLimit>;
+
+/// Bincode configuration shared by every encoder and decoder.
+///
+/// Integers are little-endian and variable-length by default. With the
+/// `fixed-int` feature they are always written at their full width, which
+/// is simpler to implement outside Rust. Both ends must agree on the
+/// encoding. The resulting layouts are described in `docs/wire-format.md`.
+pub const CODEC: Codec = {
+    #[cfg(not(feature = "fixed-int"))]
+    {
+        bincode::config::standard()
+    }
+    #[cfg(feature = "fixed-int")]
+    {
+        bincode::config::standard().with_fixed_int_encoding()
+    }
+};
+
+/// Largest encoded message, in bytes, that client and server exchange.
+///
+/// This is the largest payload a UDP datagram can carry over IPv4.
+/// Encoding a bigger message fails and received datagrams above this size
+/// are rejected.
+pub const MAX_MESSAGE_SIZE: usize = 65507;
+
+/// Size of receive buffers. One byte larger than [`MAX_MESSAGE_SIZE`] so an
+/// overlong datagram is detected instead of being silently truncated.
+pub const RECV_BUFFER_SIZE: usize = MAX_MESSAGE_SIZE + 1;
+
+/// Hop budget of a newly sent forwardable message.
+///
+/// Every server forwarding the message decrements its `hops` field and
+/// drops it once the budget is spent, so a forwarding loop can not keep a
+/// message alive forever.
+pub const DEFAULT_HOPS: u8 = 8;
+
+/// Maximum number of peers returned in a single [`RendezvousMessage::PeerList`].
+pub const MAX_PEER_LIST: usize = 64;
+
+/// Maximum number of candidate addresses kept per peer, see
+/// [`PeerInfo::candidates`].
+pub const MAX_CANDIDATES: usize = 4;
+
+/// Maximum number of capability tags kept per peer, see
+/// [`PeerInfo::capabilities`].
+pub const MAX_CAPABILITIES: usize = 16;
+
+/// Longest capability tag, in bytes, kept in a registration. Longer tags
+/// are dropped.
+pub const MAX_CAPABILITY_LEN: usize = 64;
+
+/// Bytes opening every message, so stray datagrams are told apart from
+/// messages of an unknown version.
+pub const MAGIC: [u8; 2] = *b"ts";
+
+/// Protocol version spoken by this build.
+pub const PROTOCOL_VERSION: u8 = 2;
+
+/// Oldest protocol version this build still understands. Version 2 added
+/// the candidate addresses of `Register` and `PeerInfo`, which version 1
+/// lays out differently.
+pub const MIN_PROTOCOL_VERSION: u8 = 2;
+
+/// Size of the envelope in front of the message: [`MAGIC`], version,
+/// message type and [`NetworkId`]. Its layout is the same in every
+/// version, so a message of an unknown version can still be answered.
+pub const ENVELOPE_SIZE: usize = MAGIC.len() + 2 + size_of::<NetworkId>();
+
+/// Bytes of peers a [`RendezvousMessage::PeerList`] carries at most,
+/// counted with [`PeerInfo::decoded_size`]: a [`MAX_MESSAGE_SIZE`] message
+/// less the envelope, the message type and the length of the list, which
+/// take 5 and 9 bytes at most.
+pub const PEER_LIST_BUDGET: usize = MAX_MESSAGE_SIZE - ENVELOPE_SIZE - 5 - 9;
+
+/// Message type of [`RendezvousMessage::UnsupportedVersion`], which is
+/// never answered, whatever its version.
+pub const UNSUPPORTED_VERSION_TYPE: u8 = 31;
+
+/// Message type of [`RendezvousMessage::Fragment`], told apart from the
+/// envelope alone so whole datagrams are passed on without decoding.
+pub const FRAGMENT_TYPE: u8 = 34;
+
+/// Highest protocol version spoken by this build and by a peer speaking
+/// `min_version` to `max_version`, if there is one.
+pub fn negotiate(min_version: u8, max_version: u8) -> Option<u8> {
+    let version = max_version.min(PROTOCOL_VERSION);
+    (version >= min_version.max(MIN_PROTOCOL_VERSION)).then_some(version)
+}
+
+/// Answer to a [`RendezvousMessage::Hello`] from a peer speaking
+/// `min_version` to `max_version`.
+pub fn answer_hello(
+    nonce: u64,
+    min_version: u8,
+    max_version: u8,
+) -> RendezvousMessage {
+    match negotiate(min_version, max_version) {
+        Some(version) => RendezvousMessage::HelloAck { nonce, version },
+        None => RendezvousMessage::UnsupportedVersion {
+            version: max_version,
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+        },
+    }
+}
+
+/// Default upper bound for the payload of a single frame.
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Identifier of the network a message belongs to.
+///
+/// Every message is sent behind the network id of its sender, and nodes
+/// drop messages of other networks, so a test network and the main one can
+/// not merge by accident. A peer registering from or asking a node of
+/// another network is told so with [`RendezvousMessage::WrongNetwork`].
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    Encode,
+    Decode,
+)]
+pub struct NetworkId(pub [u8; 4]);
+
+impl NetworkId {
+    /// The main network.
+    pub const MAIN: NetworkId = NetworkId(*b"tess");
+
+    /// Id of the network called `name`: the first four bytes of its SHA-1.
+    pub fn from_name(name: &str) -> Self {
+        let digest = Sha1::digest(name.as_bytes());
+        NetworkId([digest[0], digest[1], digest[2], digest[3]])
+    }
+}
+
+impl Default for NetworkId {
+    fn default() -> Self {
+        NetworkId::MAIN
+    }
+}
+
+impl fmt::Display for NetworkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+impl FromStr for NetworkId {
+    type Err = String;
+
+    /// Parse `main`, an id as 8 hex digits, or any other network name, see
+    /// [`NetworkId::from_name`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("empty network name".to_string());
+        }
+        if s == "main" {
+            return Ok(NetworkId::MAIN);
+        }
+        if s.len() == 8 && s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            let mut id = [0u8; 4];
+            for (i, byte) in id.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16)
+                    .map_err(|e| e.to_string())?;
+            }
+            return Ok(NetworkId(id));
+        }
+        Ok(NetworkId::from_name(s))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub public_addr: SocketAddr,
+    pub private_addr: Option<SocketAddr>,
+    pub last_seen: SystemTime,
+    /// Free-form capability tags advertised by the peer, e.g. `relay`.
+    pub capabilities: Vec<String>,
+    /// Further addresses the peer may answer at, typically in the other
+    /// address family than `public_addr`, at most [`MAX_CANDIDATES`].
+    pub candidates: Vec<SocketAddr>,
+}
+
+impl PeerInfo {
+    /// Returns true when the peer advertises every tag in `capabilities`.
+    pub fn has_capabilities(&self, capabilities: &[String]) -> bool {
+        capabilities.iter().all(|c| self.capabilities.contains(c))
+    }
+
+    /// Bytes the peer counts at most against the limit [`decode`] puts on
+    /// a message, which is more than it takes encoded.
+    ///
+    /// Decoding claims about the size of the peer in memory, and twice
+    /// that while the peer is under construction.
+    pub fn decoded_size(&self) -> usize {
+        let tags: usize = self
+            .capabilities
+            .iter()
+            .map(|tag| 2 * size_of::<String>() + tag.len())
+            .sum();
+        2 * size_of::<Self>()
+            + self.peer_id.len()
+            + tags
+            + 2 * self.candidates.len() * size_of::<SocketAddr>()
+    }
+}
+
+/// A DHT node and the address it answers on.
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encode, Decode,
+)]
+pub struct Contact {
+    pub node_id: [u8; 20],
+    pub addr: SocketAddr,
+}
+
+/// The key ids starting with the first `depth` bits of `prefix`, whose
+/// other bits are zero.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Encode, Decode,
+)]
+pub struct KeyRange {
+    pub prefix: [u8; 20],
+    pub depth: u8,
+}
+
+/// A key held by a node, with the sequence number of its value and the
+/// seconds it has left to live.
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encode, Decode,
+)]
+pub struct KeyVersion {
+    pub key: String,
+    pub seq: u64,
+    pub ttl: u64,
+}
+
+/// Server counters reported in [`RendezvousMessage::Stats`].
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    Encode,
+    Decode,
+)]
+pub struct RendezvousStats {
+    /// Registered peers.
+    pub peers: u64,
+    /// Bytes received since the server started.
+    pub bytes_in: u64,
+    /// Bytes sent since the server started.
+    pub bytes_out: u64,
+    /// Bytes received during the last full second.
+    pub bytes_in_per_sec: u64,
+    /// Bytes sent during the last full second.
+    pub bytes_out_per_sec: u64,
+    /// Queries answered from the negative cache, see
+    /// [`RendezvousStats::negative_cache_hit_rate`].
+    pub negative_cache_hits: u64,
+    /// Queries looked up in the negative cache, zero when it is disabled.
+    pub negative_cache_lookups: u64,
+}
+
+impl RendezvousStats {
+    /// Fraction of queries answered from the negative cache.
+    pub fn negative_cache_hit_rate(&self) -> f64 {
+        if self.negative_cache_lookups == 0 {
+            0.0
+        } else {
+            self.negative_cache_hits as f64
+                / self.negative_cache_lookups as f64
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+pub enum RendezvousMessage {
+    Register {
+        nonce: u64,
+        peer_id: String,
+        private_addr: SocketAddr,
+        capabilities: Vec<String>,
+        /// Addresses of the peer in other address families, as far as it
+        /// knows them, see [`PeerInfo::candidates`].
+        candidates: Vec<SocketAddr>,
+    },
+    Query {
+        target_peer_id: String,
+    },
+    PeerInfo {
+        peer: PeerInfo,
+    },
+    InitiateConnection {
+        nonce: u64,
+        from_peer_id: String,
+        to_peer_id: String,
+    },
+    /// Ask for registered peers advertising all of `capabilities`.
+    ListPeers {
+        capabilities: Vec<String>,
+    },
+    /// Reply to [`RendezvousMessage::ListPeers`], at most
+    /// [`MAX_PEER_LIST`] entries.
+    PeerList {
+        peers: Vec<PeerInfo>,
+    },
+    /// Ask the server to forward `payload` to `to_peer_id`.
+    Relay {
+        to_peer_id: String,
+        payload: Vec<u8>,
+        /// Forwards left, see [`DEFAULT_HOPS`].
+        hops: u8,
+    },
+    /// A payload forwarded by the server on behalf of `from_peer_id`.
+    Relayed {
+        from_peer_id: String,
+        payload: Vec<u8>,
+        /// Forwards left, one less than in the [`RendezvousMessage::Relay`].
+        hops: u8,
+    },
+    /// Hole punching probe sent directly between peers. A probe with
+    /// `ack` unset is answered with one that has it set.
+    Probe {
+        from_peer_id: String,
+        ack: bool,
+    },
+    /// Periodic liveness check from a registered peer. Also refreshes the
+    /// peer's registration. Sent unanswered to other peers in use too, to
+    /// hold the NAT mappings towards them open, see
+    /// [`crate::keepalive::PeerKeepalives`].
+    Heartbeat {
+        peer_id: String,
+    },
+    /// Reply to [`RendezvousMessage::Heartbeat`]. `epoch` changes every
+    /// time the server starts, so a client seeing a new value knows its
+    /// registration was lost.
+    HeartbeatAck {
+        epoch: u64,
+    },
+    /// Ask the server for its counters.
+    GetStats,
+    /// Reply to [`RendezvousMessage::GetStats`].
+    Stats {
+        stats: RendezvousStats,
+    },
+    /// Ask the server which address this datagram came from. Answered on
+    /// the secondary port too, see [`crate::server::ServerConfig`].
+    WhatIsMyAddr,
+    /// Reply to [`RendezvousMessage::WhatIsMyAddr`].
+    YourAddr {
+        /// Source address of the query as seen by the server.
+        addr: SocketAddr,
+    },
+    /// Reply to a [`RendezvousMessage::Register`] the server turned down
+    /// because it is full, suggesting other servers to register with.
+    Redirect {
+        addresses: Vec<SocketAddr>,
+    },
+    /// DHT request sent directly to a node, asking for the contacts it
+    /// knows closest to `target`.
+    FindNode {
+        nonce: u64,
+        target: [u8; 20],
+    },
+    /// Reply to [`RendezvousMessage::FindNode`], and to a
+    /// [`RendezvousMessage::FindValue`] for a key the node does not hold.
+    Nodes {
+        nonce: u64,
+        contacts: Vec<Contact>,
+    },
+    /// DHT request asking a node to keep `value` under `key` for `ttl`
+    /// seconds. A value with a lower `seq` than the one held is not
+    /// stored.
+    Store {
+        nonce: u64,
+        key: String,
+        value: Value,
+        seq: u64,
+        ttl: u64,
+    },
+    /// Reply to [`RendezvousMessage::Store`] once the value is stored.
+    Stored {
+        nonce: u64,
+    },
+    /// DHT request for the value stored under `key`.
+    FindValue {
+        nonce: u64,
+        key: String,
+    },
+    /// Reply to [`RendezvousMessage::FindValue`] from a node holding the
+    /// key, with the sequence number of its value.
+    Found {
+        nonce: u64,
+        value: Value,
+        seq: u64,
+    },
+    /// DHT request checking that a node is still alive.
+    Ping {
+        nonce: u64,
+    },
+    /// Reply to [`RendezvousMessage::Ping`] carrying the public key of
+    /// the node that answered and its signature of the nonce, proving it
+    /// owns the node id derived from the key.
+    Pong {
+        nonce: u64,
+        public_key: [u8; 32],
+        signature: Vec<u8>,
+    },
+    /// DHT request asking a node on the path of a lookup to keep a copy of
+    /// `value` for `ttl` seconds. Answered with
+    /// [`RendezvousMessage::Stored`].
+    Cache {
+        nonce: u64,
+        key: String,
+        value: Value,
+        ttl: u64,
+    },
+    /// Reply to a [`RendezvousMessage::Register`] or a DHT request sent
+    /// from another network, carrying the network of the receiver. The
+    /// request is otherwise ignored.
+    WrongNetwork {
+        network: NetworkId,
+    },
+    /// DHT notice from a node leaving the network, sent to its routing
+    /// table neighbours so they drop it at once. Signed like a
+    /// [`RendezvousMessage::Pong`] and never answered.
+    Leave {
+        nonce: u64,
+        public_key: [u8; 32],
+        signature: Vec<u8>,
+    },
+    /// DHT request for the hashes of the children of the Merkle tree
+    /// nodes `indexes` at `level`, built over the records held in `range`.
+    /// See [`crate::merkle`].
+    SyncDigest {
+        nonce: u64,
+        range: KeyRange,
+        level: u8,
+        indexes: Vec<u16>,
+    },
+    /// Reply to [`RendezvousMessage::SyncDigest`]: the children of every
+    /// requested node in turn.
+    Digests {
+        nonce: u64,
+        hashes: Vec<[u8; 20]>,
+    },
+    /// DHT request for the keys held in the Merkle tree `leaves` of
+    /// `range`.
+    SyncKeys {
+        nonce: u64,
+        range: KeyRange,
+        leaves: Vec<u16>,
+    },
+    /// Reply to [`RendezvousMessage::SyncKeys`], cut short once the keys
+    /// add up to [`crate::merkle::KEYS_BUDGET`] bytes.
+    Keys {
+        nonce: u64,
+        versions: Vec<KeyVersion>,
+    },
+    /// Reply to a message sent in protocol `version`, which the receiver
+    /// does not speak, or to a [`RendezvousMessage::Hello`] sharing no
+    /// version with it. Carries the versions the receiver speaks. Kept in
+    /// every version and never answered.
+    UnsupportedVersion {
+        version: u8,
+        min_version: u8,
+        max_version: u8,
+    },
+    /// Handshake opening a conversation, listing the protocol versions the
+    /// sender speaks. Answered with [`RendezvousMessage::HelloAck`] or
+    /// [`RendezvousMessage::UnsupportedVersion`].
+    Hello {
+        nonce: u64,
+        min_version: u8,
+        max_version: u8,
+    },
+    /// Reply to [`RendezvousMessage::Hello`] with the highest version both
+    /// ends speak, see [`negotiate`].
+    HelloAck {
+        nonce: u64,
+        version: u8,
+    },
+    /// Piece `index` of the `count` pieces a datagram larger than the path
+    /// carries was cut into, see [`crate::fragment`]. `data` is a slice of
+    /// the whole datagram, envelope included, and `id` tells the datagrams
+    /// of a sender apart.
+    Fragment {
+        id: u64,
+        index: u16,
+        count: u16,
+        data: Vec<u8>,
+    },
+}
+
+impl RendezvousMessage {
+    /// Nonce of requests with side effects, used by the server to detect
+    /// retried duplicates.
+    pub fn nonce(&self) -> Option<u64> {
+        match self {
+            RendezvousMessage::Register { nonce, .. }
+            | RendezvousMessage::InitiateConnection { nonce, .. } => {
+                Some(*nonce)
+            }
+            _ => None,
+        }
+    }
+
+    /// Nonce of DHT requests and replies, which a reply echoes from its
+    /// request.
+    pub fn rpc_nonce(&self) -> Option<u64> {
+        match self {
+            RendezvousMessage::FindNode { nonce, .. }
+            | RendezvousMessage::Nodes { nonce, .. }
+            | RendezvousMessage::Store { nonce, .. }
+            | RendezvousMessage::Stored { nonce }
+            | RendezvousMessage::FindValue { nonce, .. }
+            | RendezvousMessage::Found { nonce, .. }
+            | RendezvousMessage::Ping { nonce }
+            | RendezvousMessage::Pong { nonce, .. }
+            | RendezvousMessage::Cache { nonce, .. }
+            | RendezvousMessage::SyncDigest { nonce, .. }
+            | RendezvousMessage::Digests { nonce, .. }
+            | RendezvousMessage::SyncKeys { nonce, .. }
+            | RendezvousMessage::Keys { nonce, .. }
+            | RendezvousMessage::Hello { nonce, .. }
+            | RendezvousMessage::HelloAck { nonce, .. } => Some(*nonce),
+            _ => None,
+        }
+    }
+
+    /// Whether this is a DHT request another node expects an answer to.
+    pub fn is_rpc_request(&self) -> bool {
+        matches!(
+            self,
+            RendezvousMessage::FindNode { .. }
+                | RendezvousMessage::Store { .. }
+                | RendezvousMessage::FindValue { .. }
+                | RendezvousMessage::Ping { .. }
+                | RendezvousMessage::Cache { .. }
+                | RendezvousMessage::SyncDigest { .. }
+                | RendezvousMessage::SyncKeys { .. }
+                | RendezvousMessage::Hello { .. }
+        )
+    }
+}
+
+/// Encode a message of `network` into a datagram payload in
+/// [`PROTOCOL_VERSION`]: the envelope, then the message.
+///
+/// Fails when the encoded message is larger than [`MAX_MESSAGE_SIZE`].
+pub fn encode(
+    network: NetworkId,
+    msg: &RendezvousMessage,
+) -> Result<Vec<u8>, EncodeError> {
+    encode_version(PROTOCOL_VERSION, network, msg)
+}
+
+/// Like [`encode`], but append the payload to `buf`, so its allocation can
+/// be reused. `buf` is left as it was on error.
+pub fn encode_into(
+    network: NetworkId,
+    msg: &RendezvousMessage,
+    buf: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    encode_version_into(PROTOCOL_VERSION, network, msg, buf)
+}
+
+/// Encode a message of `network` into a datagram payload in protocol
+/// `version`, as negotiated with [`RendezvousMessage::Hello`].
+pub fn encode_version(
+    version: u8,
+    network: NetworkId,
+    msg: &RendezvousMessage,
+) -> Result<Vec<u8>, EncodeError> {
+    let mut buf = Vec::new();
+    encode_version_into(version, network, msg, &mut buf)?;
+    Ok(buf)
+}
+
+/// Like [`encode_version`], but append the payload to `buf`. `buf` is left
+/// as it was on error.
+pub fn encode_version_into(
+    version: u8,
+    network: NetworkId,
+    msg: &RendezvousMessage,
+    buf: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    let start = buf.len();
+    let result = write_message(version, network, msg, buf);
+    if result.is_err() {
+        buf.truncate(start);
+    }
+    result
+}
+
+/// Append the envelope, then the message, to `buf`.
+fn write_message(
+    version: u8,
+    network: NetworkId,
+    msg: &RendezvousMessage,
+    buf: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    let start = buf.len();
+    buf.extend_from_slice(&MAGIC);
+    // The message type is filled in once the message is encoded.
+    buf.extend_from_slice(&[version, 0]);
+    buf.extend_from_slice(&network.0);
+    bincode::encode_into_std_write(msg, &mut *buf, CODEC)?;
+
+    let kind = message_type(&buf[start + ENVELOPE_SIZE..])
+        .ok()
+        .and_then(|kind| u8::try_from(kind).ok())
+        .ok_or(EncodeError::Other("message type does not fit the envelope"))?;
+    buf[start + MAGIC.len() + 1] = kind;
+
+    let len = buf.len() - start;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(EncodeError::OtherString(format!(
+            "message of {len} bytes exceeds maximum of {MAX_MESSAGE_SIZE}"
+        )));
+    }
+    Ok(())
+}
+
+/// Variant index bincode wrote at the start of an encoded message.
+fn message_type(body: &[u8]) -> Result<u32, DecodeError> {
+    bincode::decode_from_slice(body, CODEC).map(|(kind, _)| kind)
+}
+
+/// Why a datagram payload could not be decoded, see [`decode`].
+#[derive(Debug)]
+pub enum WireError {
+    /// The payload does not start with [`MAGIC`]: not a message at all.
+    BadMagic,
+    /// The message is in a protocol version this build does not speak.
+    UnsupportedVersion { version: u8, kind: u8, network: NetworkId },
+    /// The message type is not one of this version.
+    UnknownType { kind: u8 },
+    /// The message is malformed.
+    Decode(DecodeError),
+}
+
+impl WireError {
+    /// The [`RendezvousMessage::UnsupportedVersion`] owed to the sender of
+    /// a message of `network` this build does not speak, if any.
+    pub fn version_reply(
+        &self,
+        network: NetworkId,
+    ) -> Option<RendezvousMessage> {
+        match *self {
+            WireError::UnsupportedVersion { version, kind, network: from }
+                if from == network && kind != UNSUPPORTED_VERSION_TYPE =>
+            {
+                Some(RendezvousMessage::UnsupportedVersion {
+                    version,
+                    min_version: MIN_PROTOCOL_VERSION,
+                    max_version: PROTOCOL_VERSION,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::BadMagic => write!(f, "not a rendezvous message"),
+            WireError::UnsupportedVersion { version, .. } => write!(
+                f,
+                "protocol version {version} is not supported, only \
+                 {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}"
+            ),
+            WireError::UnknownType { kind } => {
+                write!(f, "unknown message type {kind}")
+            }
+            WireError::Decode(e) => write!(f, "malformed message: {e}"),
+        }
+    }
+}
+
+impl error::Error for WireError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            WireError::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecodeError> for WireError {
+    fn from(e: DecodeError) -> Self {
+        WireError::Decode(e)
+    }
+}
+
+/// Decode a message and the network it was sent from out of a datagram
+/// payload.
+///
+/// Payloads larger than [`MAX_MESSAGE_SIZE`] are rejected without being
+/// decoded. A message of an unsupported version is reported with the
+/// fields of its envelope, so it can be answered, see
+/// [`WireError::version_reply`].
+pub fn decode(
+    buf: &[u8],
+) -> Result<(NetworkId, RendezvousMessage), WireError> {
+    if buf.len() > MAX_MESSAGE_SIZE {
+        return Err(DecodeError::LimitExceeded.into());
+    }
+    let Some((envelope, body)) = buf.split_first_chunk::<ENVELOPE_SIZE>()
+    else {
+        return Err(if buf.starts_with(&MAGIC) {
+            DecodeError::UnexpectedEnd {
+                additional: ENVELOPE_SIZE - buf.len(),
+            }
+            .into()
+        } else {
+            WireError::BadMagic
+        });
+    };
+    let [m0, m1, version, kind, n0, n1, n2, n3] = *envelope;
+    if [m0, m1] != MAGIC {
+        return Err(WireError::BadMagic);
+    }
+    let network = NetworkId([n0, n1, n2, n3]);
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        return Err(WireError::UnsupportedVersion { version, kind, network });
+    }
+
+    let msg = match bincode::decode_from_slice(
+        body,
+        CODEC.with_limit::<MAX_MESSAGE_SIZE>(),
+    ) {
+        Ok((msg, _)) => msg,
+        Err(DecodeError::UnexpectedVariant { .. }) => {
+            return Err(WireError::UnknownType { kind });
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if message_type(body)? != u32::from(kind) {
+        return Err(DecodeError::OtherString(format!(
+            "envelope type {kind} does not match the message"
+        ))
+        .into());
+    }
+    Ok((network, msg))
+}
+
+/// Error returned by the framing helpers.
+#[derive(Debug)]
+pub enum FrameError {
+    /// The frame length exceeds the allowed maximum.
+    TooLarge { len: usize, max: usize },
+    /// The stream ended in the middle of a frame.
+    Truncated,
+    /// The underlying stream failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::TooLarge { len, max } => {
+                write!(f, "frame of {len} bytes exceeds maximum of {max}")
+            }
+            FrameError::Truncated => write!(f, "stream ended mid-frame"),
+            FrameError::Io(e) => write!(f, "I/O failure: {e}"),
+        }
+    }
+}
+
+impl error::Error for FrameError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            FrameError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ReadError> for FrameError {
+    fn from(e: ReadError) -> Self {
+        match e {
+            ReadError::TooShort { .. } => FrameError::Truncated,
+            ReadError::Io(e) => FrameError::Io(e),
+            e @ ReadError::Malformed(_) => FrameError::Io(e.into()),
+        }
+    }
+}
+
+/// Write `payload` as a single frame: a big-endian `u32` length followed by
+/// the payload bytes.
+pub fn write_frame<W: io::Write>(
+    writer: &mut W,
+    payload: &[u8],
+    max_len: usize,
+) -> Result<(), FrameError> {
+    if payload.len() > max_len || payload.len() > u32::MAX as usize {
+        return Err(FrameError::TooLarge { len: payload.len(), max: max_len });
+    }
+
+    let header = (payload.len() as u32).to_be_bytes();
+    writer.write_all(&header).map_err(FrameError::Io)?;
+    writer.write_all(payload).map_err(FrameError::Io)?;
+    Ok(())
+}
+
+/// Read a single frame written by [`write_frame`].
+///
+/// Returns `Ok(None)` when the stream ends cleanly on a frame boundary.
+/// The length is checked against `max_len` before the body is allocated,
+/// so a bogus header can not make us reserve arbitrary amounts of memory.
+pub fn read_frame<R: io::Read>(
+    reader: &mut R,
+    max_len: usize,
+) -> Result<Option<Vec<u8>>, FrameError> {
+    let mut header = [0u8; 4];
+    match read_full(reader, &mut header) {
+        Ok(()) => {}
+        Err(ReadError::TooShort { got: 0, .. }) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let len = u32::from_be_bytes(header) as usize;
+    if len > max_len {
+        return Err(FrameError::TooLarge { len, max: max_len });
+    }
+
+    let mut payload = vec![0u8; len];
+    read_full(reader, &mut payload)?;
+    Ok(Some(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    #[test]
+    fn negotiate_highest_common_version() {
+        assert_eq!(negotiate(1, 9), Some(PROTOCOL_VERSION));
+        assert_eq!(
+            negotiate(MIN_PROTOCOL_VERSION, MIN_PROTOCOL_VERSION),
+            Some(MIN_PROTOCOL_VERSION)
+        );
+        assert_eq!(negotiate(PROTOCOL_VERSION + 1, 9), None);
+        assert_eq!(negotiate(0, MIN_PROTOCOL_VERSION - 1), None);
+    }
+
+    #[test]
+    fn unknown_version_is_reported() {
+        let mut data =
+            encode(NetworkId::MAIN, &RendezvousMessage::Ping { nonce: 1 })
+                .unwrap();
+        data[2] = 9;
+
+        let error = decode(&data).unwrap_err();
+        assert!(matches!(
+            error.version_reply(NetworkId::MAIN),
+            Some(RendezvousMessage::UnsupportedVersion { version: 9, .. })
+        ));
+        // Messages of other networks are not answered.
+        assert!(error.version_reply(NetworkId::from_name("test")).is_none());
+    }
+
+    #[test]
+    fn oversize_message_does_not_encode() {
+        let relay = RendezvousMessage::Relay {
+            to_peer_id: "bob".to_string(),
+            payload: vec![0; MAX_MESSAGE_SIZE],
+            hops: DEFAULT_HOPS,
+        };
+        assert!(encode(NetworkId::MAIN, &relay).is_err());
+    }
+
+    /// A stream handing out `chunks` one read at a time.
+    struct Chunked {
+        chunks: VecDeque<Vec<u8>>,
+    }
+
+    impl Chunked {
+        /// `data` cut at `cuts`.
+        fn new(data: &[u8], cuts: &[usize]) -> Self {
+            let mut chunks = VecDeque::new();
+            let mut start = 0;
+            for &end in cuts.iter().chain([&data.len()]) {
+                chunks.push_back(data[start..end].to_vec());
+                start = end;
+            }
+            Chunked { chunks }
+        }
+    }
+
+    impl io::Read for Chunked {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some(mut chunk) = self.chunks.pop_front() else {
+                return Ok(0);
+            };
+            let len = chunk.len().min(buf.len());
+            buf[..len].copy_from_slice(&chunk[..len]);
+            if len < chunk.len() {
+                self.chunks.push_front(chunk.split_off(len));
+            }
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn frame_arrives_in_separate_chunks() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, b"hello", 16).unwrap();
+        write_frame(&mut stream, b"", 16).unwrap();
+        assert_eq!(stream[..4], [0, 0, 0, 5]);
+
+        // Length split in two, then the body in two.
+        let mut reader = Chunked::new(&stream, &[2, 4, 6]);
+        assert_eq!(read_frame(&mut reader, 16).unwrap().unwrap(), b"hello");
+        assert_eq!(read_frame(&mut reader, 16).unwrap().unwrap(), b"");
+        assert!(read_frame(&mut reader, 16).unwrap().is_none());
+    }
+
+    #[test]
+    fn oversize_frame_is_refused() {
+        let error = write_frame(&mut Vec::new(), &[0; 17], 16).unwrap_err();
+        assert!(matches!(error, FrameError::TooLarge { len: 17, max: 16 }));
+
+        // The body is never read, only the header is.
+        let mut reader = Chunked::new(&[0, 0, 1, 0], &[]);
+        let error = read_frame(&mut reader, 16).unwrap_err();
+        assert!(matches!(error, FrameError::TooLarge { len: 256, max: 16 }));
+    }
+
+    #[test]
+    fn frame_cut_short_is_truncated() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, b"hello", 16).unwrap();
+        for end in [2, 7] {
+            let mut reader = Chunked::new(&stream[..end], &[1]);
+            let error = read_frame(&mut reader, 16).unwrap_err();
+            assert!(matches!(error, FrameError::Truncated));
+        }
+    }
+}
@@ -0,0 +1,606 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! QUIC transport.
+//!
+//! [`QuicTransport`] carries the same datagrams as UDP over QUIC
+//! connections, each one on a stream of its own. Streams are delivered
+//! reliably and share the congestion control of their connection, so a
+//! lossy link retransmits instead of dropping requests, and a large
+//! message does not hold back the small ones behind it.
+//!
+//! Connections are encrypted with a certificate made up for each
+//! transport, which peers accept without checking: a peer proves who it
+//! is by signing its messages, see [`crate::pins`].
+//!
+//! QUIC runs on a UDP port of its own, advertised to other peers with a
+//! capability tag, see [`capability`]. [`crate::tcp::FallbackTransport`]
+//! reaches the peers advertising one over QUIC.
+//!
+//! The connections are driven by a thread running a tokio runtime of
+//! their own, so the transport can be used from anywhere.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{Arc, Condvar, Mutex, MutexGuard, Weak},
+    thread,
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use quinn::{
+    ClientConfig, Connection, Endpoint, EndpointConfig, ServerConfig,
+    TokioRuntime, TransportConfig,
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+};
+use rustls::{
+    DigitallySignedStruct, SignatureScheme,
+    client::danger::{
+        HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+    },
+    crypto::{CryptoProvider, ring},
+    pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+};
+use tokio::{runtime::Handle, sync::mpsc, sync::oneshot};
+
+use crate::{
+    protocol,
+    transport::{Transport, canonical},
+};
+
+/// Protocol named in the TLS handshake, so only tesseras peers connect.
+const ALPN: &[u8] = b"tesseras";
+
+/// Server name given when connecting. Certificates are not checked, see
+/// the module docs.
+const SERVER_NAME: &str = "tesseras";
+
+/// Prefix of the capability tag advertising the QUIC port of a peer.
+const CAPABILITY_PREFIX: &str = "quic=";
+
+/// How long to wait for an outgoing connection to be established.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Time between keep-alives on an idle connection, well under the idle
+/// timeout and the lifetime of most NAT mappings.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Maximum number of open connections. Further ones are refused.
+pub const MAX_CONNECTIONS: usize = 256;
+
+/// Maximum number of received messages waiting for `recv_from`. Messages
+/// beyond this are dropped, like datagrams on a full socket buffer.
+const MAX_QUEUED_MESSAGES: usize = 1024;
+
+/// Capability tag advertising a QUIC endpoint on `port`.
+pub fn capability(port: u16) -> String {
+    format!("{CAPABILITY_PREFIX}{port}")
+}
+
+/// QUIC port advertised among `capabilities`, if any, see [`capability`].
+pub fn advertised_port(capabilities: &[String]) -> Option<u16> {
+    capabilities
+        .iter()
+        .find_map(|tag| tag.strip_prefix(CAPABILITY_PREFIX)?.parse().ok())
+}
+
+/// State shared by the handles of a [`QuicTransport`] and its tasks.
+#[derive(Debug)]
+struct Shared {
+    local_addr: SocketAddr,
+    endpoint: Endpoint,
+    /// Runtime driving the endpoint, see [`QuicTransport::bind`].
+    runtime: Handle,
+    /// Queue of the task writing to each peer, by peer address.
+    writers: Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<Vec<u8>>>>,
+    /// Peers with an open connection.
+    connected: Mutex<HashSet<SocketAddr>>,
+    /// Received messages, oldest first.
+    inbox: Mutex<VecDeque<(Vec<u8>, SocketAddr)>>,
+    arrived: Condvar,
+    /// Stops the runtime thread once dropped.
+    _stop: oneshot::Sender<()>,
+}
+
+impl Shared {
+    fn writers(
+        &self,
+    ) -> MutexGuard<'_, HashMap<SocketAddr, mpsc::UnboundedSender<Vec<u8>>>>
+    {
+        // A panic while holding the lock leaves the map intact.
+        self.writers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn connected(&self) -> MutexGuard<'_, HashSet<SocketAddr>> {
+        self.connected.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn inbox(&self) -> MutexGuard<'_, VecDeque<(Vec<u8>, SocketAddr)>> {
+        self.inbox.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn deliver(&self, data: Vec<u8>, from: SocketAddr) {
+        let mut inbox = self.inbox();
+        if inbox.len() >= MAX_QUEUED_MESSAGES {
+            debug!("Dropping a QUIC message from {from}: receive queue full");
+            return;
+        }
+        inbox.push_back((data, from));
+        self.arrived.notify_one();
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        self.endpoint.close(0u32.into(), b"closed");
+    }
+}
+
+/// Transport carrying datagrams as streams over QUIC connections.
+///
+/// Connections are opened on the first datagram sent to an address and
+/// accepted from any peer; a datagram to a peer goes over the connection
+/// it has with us, whoever opened it. Sending only queues the datagram,
+/// a peer that can not be reached shows as requests timing out. Handles
+/// made with [`Clone`] share the connections, each has its own blocking
+/// mode. New transports are nonblocking.
+#[derive(Debug, Clone)]
+pub struct QuicTransport {
+    shared: Arc<Shared>,
+    nonblocking: bool,
+}
+
+impl QuicTransport {
+    /// Listen on `addr` and start accepting connections.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        let local_addr = socket.local_addr()?;
+        let provider = Arc::new(ring::default_provider());
+        let server = server_config(&provider)?;
+        let client = client_config(&provider)?;
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let endpoint = {
+            let _guard = runtime.enter();
+            let mut endpoint = Endpoint::new(
+                EndpointConfig::default(),
+                Some(server),
+                socket,
+                Arc::new(TokioRuntime),
+            )?;
+            endpoint.set_default_client_config(client);
+            endpoint
+        };
+
+        let (stop, stopped) = oneshot::channel::<()>();
+        let shared = Arc::new(Shared {
+            local_addr,
+            endpoint: endpoint.clone(),
+            runtime: runtime.handle().clone(),
+            writers: Mutex::new(HashMap::new()),
+            connected: Mutex::new(HashSet::new()),
+            inbox: Mutex::new(VecDeque::new()),
+            arrived: Condvar::new(),
+            _stop: stop,
+        });
+        runtime.spawn(accept(endpoint, Arc::downgrade(&shared)));
+        thread::Builder::new().name("quic".to_string()).spawn(move || {
+            let _ = runtime.block_on(stopped);
+        })?;
+
+        info!("Listening on QUIC at {local_addr}");
+        Ok(QuicTransport { shared, nonblocking: true })
+    }
+
+    /// Make `recv_from` wait for a message instead of failing with
+    /// [`io::ErrorKind::WouldBlock`].
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    /// Whether a connection with `addr` is open.
+    pub fn is_connected(&self, addr: SocketAddr) -> bool {
+        self.shared.connected().contains(&addr)
+    }
+
+    /// Number of open connections.
+    pub fn connections(&self) -> usize {
+        self.shared.connected().len()
+    }
+}
+
+impl Transport for QuicTransport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if buf.len() > protocol::MAX_MESSAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} bytes is too large to send", buf.len()),
+            ));
+        }
+        let mut writers = self.shared.writers();
+
+        if writers.get(&addr).is_none_or(|queue| queue.is_closed()) {
+            if writers.len() >= MAX_CONNECTIONS && !writers.contains_key(&addr)
+            {
+                return Err(io::Error::other("too many QUIC connections"));
+            }
+            let queue = spawn_writer(&self.shared, addr, None);
+            writers.insert(addr, queue);
+        }
+
+        let Some(queue) = writers.get(&addr) else {
+            return Err(io::ErrorKind::NotConnected.into());
+        };
+        queue
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::NotConnected))?;
+        Ok(buf.len())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut inbox = self.shared.inbox();
+        loop {
+            if let Some((data, from)) = inbox.pop_front() {
+                // Same truncation semantics as a UDP socket.
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                return Ok((len, from));
+            }
+            if self.nonblocking {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            inbox = self
+                .shared
+                .arrived
+                .wait(inbox)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.shared.local_addr)
+    }
+}
+
+/// Accept connections until the endpoint is closed.
+async fn accept(endpoint: Endpoint, shared: Weak<Shared>) {
+    while let Some(incoming) = endpoint.accept().await {
+        let peer = canonical(incoming.remote_address());
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+        if shared.connected().len() >= MAX_CONNECTIONS {
+            warn!("Refusing a QUIC connection from {peer}: too many");
+            incoming.refuse();
+            continue;
+        }
+        let weak = Arc::downgrade(&shared);
+        tokio::spawn(async move {
+            match incoming.await {
+                Ok(connection) => adopt(&weak, connection),
+                Err(e) => debug!("QUIC connection from {peer} failed: {e}"),
+            }
+        });
+    }
+}
+
+/// Keep `connection`, accepted from a peer, and start reading it. Replies
+/// go over it unless a connection with the peer was already made.
+fn adopt(shared: &Weak<Shared>, connection: Connection) {
+    let Some(shared) = shared.upgrade() else {
+        return;
+    };
+    let peer = canonical(connection.remote_address());
+    debug!("Accepted a QUIC connection from {peer}");
+    tokio::spawn(read(connection.clone(), Arc::downgrade(&shared)));
+
+    let mut writers = shared.writers();
+    if writers.get(&peer).is_none_or(|queue| queue.is_closed()) {
+        let queue = spawn_writer(&shared, peer, Some(connection));
+        writers.insert(peer, queue);
+    }
+}
+
+/// Start the task writing the datagrams queued for `peer`, over
+/// `connection` or one it opens, and return its queue.
+fn spawn_writer(
+    shared: &Arc<Shared>,
+    peer: SocketAddr,
+    connection: Option<Connection>,
+) -> mpsc::UnboundedSender<Vec<u8>> {
+    let (queue, datagrams) = mpsc::unbounded_channel();
+    shared.runtime.spawn(write(
+        Arc::downgrade(shared),
+        peer,
+        connection,
+        datagrams,
+    ));
+    queue
+}
+
+/// Write each datagram of `datagrams` to `peer` on a stream of its own,
+/// connecting again whenever the connection is lost, until the transport
+/// is dropped. A datagram that can not be written is dropped.
+async fn write(
+    shared: Weak<Shared>,
+    peer: SocketAddr,
+    mut connection: Option<Connection>,
+    mut datagrams: mpsc::UnboundedReceiver<Vec<u8>>,
+) {
+    while let Some(datagram) = datagrams.recv().await {
+        let open = connection
+            .take()
+            .filter(|connection| connection.close_reason().is_none());
+        let open = match open {
+            Some(connection) => connection,
+            None => match connect(&shared, peer).await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    debug!("Could not connect to {peer} over QUIC: {e}");
+                    continue;
+                }
+            },
+        };
+
+        let sent = async {
+            let mut stream = open.open_uni().await?;
+            stream.write_all(&datagram).await?;
+            stream.finish()?;
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+        };
+        match sent.await {
+            Ok(()) => connection = Some(open),
+            Err(e) => debug!("Could not send to {peer} over QUIC: {e}"),
+        }
+    }
+}
+
+/// Open a connection to `peer` and start reading it.
+async fn connect(
+    shared: &Weak<Shared>,
+    peer: SocketAddr,
+) -> Result<Connection, Box<dyn std::error::Error + Send + Sync>> {
+    let endpoint = match shared.upgrade() {
+        Some(shared) => shared.endpoint.clone(),
+        None => return Err("transport dropped".into()),
+    };
+    let connecting = endpoint.connect(peer, SERVER_NAME)?;
+    let connection =
+        tokio::time::timeout(CONNECT_TIMEOUT, connecting).await??;
+    debug!("Connected to {peer} over QUIC");
+    tokio::spawn(read(connection.clone(), shared.clone()));
+    Ok(connection)
+}
+
+/// Deliver the datagrams of the streams `connection` opens until it
+/// closes.
+async fn read(connection: Connection, shared: Weak<Shared>) {
+    let peer = canonical(connection.remote_address());
+    match shared.upgrade() {
+        Some(shared) => shared.connected().insert(peer),
+        None => return,
+    };
+
+    loop {
+        let mut stream = match connection.accept_uni().await {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!("QUIC connection with {peer} closed: {e}");
+                break;
+            }
+        };
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            match stream.read_to_end(protocol::MAX_MESSAGE_SIZE).await {
+                Ok(data) => {
+                    if let Some(shared) = shared.upgrade() {
+                        shared.deliver(data, peer);
+                    }
+                }
+                Err(e) => debug!("Dropping a QUIC stream from {peer}: {e}"),
+            }
+        });
+    }
+
+    if let Some(shared) = shared.upgrade() {
+        shared.connected().remove(&peer);
+    }
+}
+
+/// Settings shared by incoming and outgoing connections.
+fn transport_config() -> Arc<TransportConfig> {
+    let mut config = TransportConfig::default();
+    config.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+    Arc::new(config)
+}
+
+/// Accept connections with a certificate made up on the spot.
+fn server_config(provider: &Arc<CryptoProvider>) -> io::Result<ServerConfig> {
+    let certified = rcgen::generate_simple_self_signed([SERVER_NAME.into()])
+        .map_err(io::Error::other)?;
+    let key = PrivatePkcs8KeyDer::from(certified.signing_key.serialize_der());
+
+    let mut tls =
+        rustls::ServerConfig::builder_with_provider(Arc::clone(provider))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(io::Error::other)?
+            .with_no_client_auth()
+            .with_single_cert(vec![certified.cert.der().clone()], key.into())
+            .map_err(io::Error::other)?;
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = QuicServerConfig::try_from(tls).map_err(io::Error::other)?;
+    let mut config = ServerConfig::with_crypto(Arc::new(crypto));
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+/// Connect accepting any certificate, see [`AnyCertificate`].
+fn client_config(provider: &Arc<CryptoProvider>) -> io::Result<ClientConfig> {
+    let mut tls =
+        rustls::ClientConfig::builder_with_provider(Arc::clone(provider))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(io::Error::other)?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AnyCertificate(
+                Arc::clone(provider),
+            )))
+            .with_no_client_auth();
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = QuicClientConfig::try_from(tls).map_err(io::Error::other)?;
+    let mut config = ClientConfig::new(Arc::new(crypto));
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+/// Certificate verifier trusting any certificate, still checking that the
+/// peer holds its key.
+///
+/// Peers make up their certificates, so there is nothing to check them
+/// against: the connection is only encrypted, and peers prove who they
+/// are by signing their messages.
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{tcp::FallbackTransport, transport::UdpTransport};
+
+    /// Receive from `transport`, polling for up to five seconds.
+    fn recv(transport: &impl Transport) -> (Vec<u8>, SocketAddr) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut buf = [0u8; 64];
+        loop {
+            match transport.recv_from(&mut buf) {
+                Ok((len, from)) => return (buf[..len].to_vec(), from),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    assert!(Instant::now() < deadline, "nothing received");
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => panic!("recv failed: {e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn advertised_port_is_parsed() {
+        let tags = ["relay".to_string(), capability(4433)];
+        assert_eq!(advertised_port(&tags), Some(4433));
+        assert_eq!(advertised_port(&["quic=x".to_string()]), None);
+        assert_eq!(advertised_port(&[]), None);
+    }
+
+    #[test]
+    fn datagrams_cross_and_are_answered() {
+        let a = QuicTransport::bind("127.0.0.1:0").unwrap();
+        let b = QuicTransport::bind("127.0.0.1:0").unwrap();
+        let b_addr = b.local_addr().unwrap();
+
+        a.send_to(b"ping", b_addr).unwrap();
+        let (data, from) = recv(&b);
+        assert_eq!(data, b"ping");
+        assert_eq!(from, a.local_addr().unwrap());
+        assert!(b.is_connected(from));
+
+        // The reply goes over the connection `a` opened.
+        b.send_to(b"pong", from).unwrap();
+        assert_eq!(recv(&a), (b"pong".to_vec(), b_addr));
+        assert_eq!(a.connections(), 1);
+    }
+
+    #[test]
+    fn fallback_reaches_advertised_quic_ports() {
+        let fallback = |quic: QuicTransport| {
+            let udp = UdpTransport::bind("127.0.0.1:0").unwrap();
+            udp.socket().set_nonblocking(true).unwrap();
+            FallbackTransport::new(udp, None)
+                .with_fallback_after(2)
+                .with_quic(quic)
+        };
+        let a = fallback(QuicTransport::bind("127.0.0.1:0").unwrap());
+        let b = fallback(QuicTransport::bind("127.0.0.1:0").unwrap());
+        let a_addr = a.local_addr().unwrap();
+        let b_addr = b.local_addr().unwrap();
+        a.learn_capabilities(b_addr, &b.capabilities());
+        b.learn_capabilities(a_addr, &a.capabilities());
+
+        a.send_to(b"ping", b_addr).unwrap();
+        // Received over QUIC, but seen from the UDP address of `a`.
+        assert_eq!(recv(&b), (b"ping".to_vec(), a_addr));
+        assert_eq!(b.quic().unwrap().connections(), 1);
+
+        // Timeouts move the destination back to UDP for good.
+        a.timed_out(b_addr);
+        a.timed_out(b_addr);
+        assert!(a.over_quic().is_empty());
+        a.learn_capabilities(b_addr, &b.capabilities());
+        assert!(a.over_quic().is_empty());
+        a.send_to(b"udp", b_addr).unwrap();
+        assert_eq!(recv(b.udp()), (b"udp".to_vec(), a_addr));
+    }
+}
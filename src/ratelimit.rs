@@ -0,0 +1,247 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Outbound rate limiting.
+//!
+//! [`RateLimitedTransport`] holds outbound datagrams to a token bucket
+//! shared by every destination and to one bucket per destination, so a
+//! node doing aggressive lookups or republishing cannot saturate a home
+//! uplink. Datagrams over the limit are dropped, as a congested link would,
+//! and left to the retransmission of requests, see [`crate::rpc`].
+
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    str::FromStr,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use crate::transport::Transport;
+
+/// Number of destinations whose bucket is kept before idle ones are
+/// forgotten. A forgotten bucket was full, so forgetting it changes
+/// nothing.
+const MAX_PEER_BUCKETS: usize = 4096;
+
+/// Sustained rate and burst of a token bucket, in datagrams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    /// Datagrams allowed per second.
+    pub per_second: u32,
+    /// Datagrams allowed at once after an idle period.
+    pub burst: u32,
+}
+
+impl Rate {
+    /// `per_second` datagrams per second, up to one second worth at once.
+    pub fn per_second(per_second: u32) -> Self {
+        Rate { per_second, burst: per_second }
+    }
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    /// Parse `<per second>` or `<per second>/<burst>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |n: &str| {
+            n.parse::<u32>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| format!("invalid rate '{s}'"))
+        };
+        match s.split_once('/') {
+            Some((per_second, burst)) => Ok(Rate {
+                per_second: parse(per_second)?,
+                burst: parse(burst)?,
+            }),
+            None => Ok(Rate::per_second(parse(s)?)),
+        }
+    }
+}
+
+/// Outbound limits, none by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Limit of all the datagrams sent.
+    pub global: Option<Rate>,
+    /// Limit of the datagrams sent to each destination.
+    pub per_peer: Option<Rate>,
+}
+
+/// Token bucket holding `burst` tokens, refilled at `per_second` tokens
+/// per second. Sending a datagram takes one.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: Rate,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket.
+    pub fn new(rate: Rate, now: Instant) -> Self {
+        TokenBucket { rate, tokens: rate.burst as f64, refilled: now }
+    }
+
+    /// Add the tokens earned since the last refill.
+    pub fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled);
+        self.tokens = (self.tokens
+            + elapsed.as_secs_f64() * self.rate.per_second as f64)
+            .min(self.rate.burst as f64);
+        self.refilled = now;
+    }
+
+    /// Whether a token is left.
+    pub fn has_token(&self) -> bool {
+        self.tokens >= 1.0
+    }
+
+    /// Take a token, see [`Self::has_token`].
+    pub fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.tokens >= self.rate.burst as f64
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    global: Option<TokenBucket>,
+    peers: HashMap<SocketAddr, TokenBucket>,
+}
+
+/// Transport dropping outbound datagrams over a [`RateLimit`].
+#[derive(Debug)]
+pub struct RateLimitedTransport<T: Transport> {
+    inner: T,
+    limit: RateLimit,
+    buckets: Mutex<Buckets>,
+    dropped: AtomicU64,
+}
+
+impl<T: Transport> RateLimitedTransport<T> {
+    pub fn new(inner: T, limit: RateLimit) -> Self {
+        let now = Instant::now();
+        RateLimitedTransport {
+            inner,
+            limit,
+            buckets: Mutex::new(Buckets {
+                global: limit.global.map(|rate| TokenBucket::new(rate, now)),
+                peers: HashMap::new(),
+            }),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Datagrams dropped for being over the limit.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Take a token from both the global bucket and the bucket of `addr`,
+    /// or none if either is empty.
+    fn admit(&self, addr: SocketAddr) -> bool {
+        let now = Instant::now();
+        let mut buckets =
+            self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let Buckets { global, peers } = &mut *buckets;
+
+        if let Some(global) = global.as_mut() {
+            global.refill(now);
+            if !global.has_token() {
+                return false;
+            }
+        }
+        if let Some(rate) = self.limit.per_peer {
+            if peers.len() >= MAX_PEER_BUCKETS && !peers.contains_key(&addr) {
+                peers.retain(|_, bucket| {
+                    bucket.refill(now);
+                    !bucket.is_full()
+                });
+            }
+            let bucket = peers
+                .entry(addr)
+                .or_insert_with(|| TokenBucket::new(rate, now));
+            bucket.refill(now);
+            if !bucket.has_token() {
+                return false;
+            }
+            bucket.take();
+        }
+        if let Some(global) = global.as_mut() {
+            global.take();
+        }
+        true
+    }
+}
+
+impl<T: Transport> Transport for RateLimitedTransport<T> {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if !self.admit(addr) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return Ok(buf.len());
+        }
+        self.inner.send_to(buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.inner.recv_from(buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn timed_out(&self, addr: SocketAddr) {
+        self.inner.timed_out(addr);
+    }
+
+    fn capabilities(&self) -> Vec<String> {
+        self.inner.capabilities()
+    }
+
+    fn learn_capabilities(&self, addr: SocketAddr, capabilities: &[String]) {
+        self.inner.learn_capabilities(addr, capabilities);
+    }
+
+    fn negotiate(&self, peer_id: &str, addr: SocketAddr) -> bool {
+        self.inner.negotiate(peer_id, addr)
+    }
+
+    fn take_signals(&self) -> Vec<(String, Vec<u8>)> {
+        self.inner.take_signals()
+    }
+
+    fn on_signal(&self, peer_id: &str, payload: &[u8]) -> bool {
+        self.inner.on_signal(peer_id, payload)
+    }
+}
@@ -0,0 +1,151 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Periodic bucket refresh.
+//!
+//! A bucket that has not seen a node or a lookup within the refresh
+//! interval is refreshed by looking up a random id in its range, which
+//! discovers live nodes and lets dead ones be evicted.
+
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+
+use crate::routing::{self, RoutingTable};
+
+/// Default time a bucket may go untouched before it is refreshed.
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Upper bound on how long the refresh thread sleeps between checks.
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Pick a lookup target for every bucket untouched for `interval` and mark
+/// those buckets as touched. The caller runs the lookups.
+pub fn stale_targets(
+    table: &mut RoutingTable,
+    now: Instant,
+    interval: Duration,
+) -> io::Result<Vec<[u8; 20]>> {
+    let mut targets = Vec::new();
+
+    for index in 0..table.bucket_count() {
+        if now.duration_since(table.last_touched(index)) < interval {
+            continue;
+        }
+        targets.push(table.id_in_bucket(index, routing::random_id()?));
+        table.touch(index, now);
+    }
+
+    Ok(targets)
+}
+
+/// Spawn a thread refreshing the stale buckets of `table` every
+/// `interval`, calling `lookup` for each target id.
+///
+/// The table lock is not held while `lookup` runs. The thread exits once
+/// every other handle to `table` has been dropped.
+pub fn spawn<F>(
+    table: &Arc<Mutex<RoutingTable>>,
+    interval: Duration,
+    mut lookup: F,
+) -> JoinHandle<()>
+where
+    F: FnMut([u8; 20]) + Send + 'static,
+{
+    let table = Arc::downgrade(table);
+    let tick = interval.min(MAX_CHECK_INTERVAL);
+
+    thread::spawn(move || {
+        loop {
+            thread::sleep(tick);
+
+            let Some(table) = table.upgrade() else {
+                break;
+            };
+            let targets = {
+                let mut table = table.lock().unwrap();
+                stale_targets(&mut table, Instant::now(), interval)
+            };
+            drop(table);
+
+            match targets {
+                Ok(targets) => {
+                    if !targets.is_empty() {
+                        debug!("Refreshing {} stale buckets", targets.len());
+                    }
+                    targets.into_iter().for_each(&mut lookup);
+                }
+                Err(e) => warn!("Bucket refresh failed: {e}"),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::routing::Layout;
+
+    const INTERVAL: Duration = Duration::from_millis(10);
+
+    /// A table where every bucket but `stale` was touched at `at`.
+    fn table_with_stale(stale: usize, at: Instant) -> RoutingTable {
+        let mut table = RoutingTable::with_layout([0x5a; 20], Layout::Flat);
+        for index in 0..table.bucket_count() {
+            if index != stale {
+                table.touch(index, at);
+            }
+        }
+        table
+    }
+
+    #[test]
+    fn only_stale_buckets_are_targeted() {
+        let now = Instant::now() + Duration::from_secs(3600);
+        let mut table = table_with_stale(5, now);
+
+        let targets = stale_targets(&mut table, now, INTERVAL).unwrap();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(table.bucket_index(&targets[0]), Some(5));
+
+        // The target's bucket counts as looked up.
+        assert_eq!(table.last_touched(5), now);
+        assert!(stale_targets(&mut table, now, INTERVAL).unwrap().is_empty());
+    }
+
+    #[test]
+    fn refresh_thread_looks_up_the_stale_bucket() {
+        // Every other bucket stays fresh for the whole test.
+        let later = Instant::now() + Duration::from_secs(3600);
+        let table = Arc::new(Mutex::new(table_with_stale(17, later)));
+        let (tx, rx) = mpsc::channel();
+        let _refresh = spawn(&table, INTERVAL, move |target| {
+            let _ = tx.send(target);
+        });
+
+        for _ in 0..2 {
+            let target = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+            assert_eq!(table.lock().unwrap().bucket_index(&target), Some(17));
+        }
+    }
+}
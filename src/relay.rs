@@ -0,0 +1,340 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Relay queues.
+//!
+//! Relayed packets are queued per destination and flushed round-robin, so
+//! a destination the kernel can not keep up with only delays its own
+//! traffic. Each destination may hold at most a fixed number of queued
+//! bytes; packets beyond that are dropped and counted instead of growing
+//! the server's memory without bound.
+//!
+//! On top of that every session, a sender and receiver pair, may only have
+//! a smaller number of bytes in flight. A fast sender filling its session
+//! is throttled by having its packets dropped, leaving room in the
+//! destination's queue for other senders.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use log::error;
+
+use crate::{
+    pool::BufferPool,
+    transport::{MeteredTransport, Transport},
+};
+
+/// How long a session with nothing queued keeps its stats.
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Default)]
+struct Queue {
+    /// Packets with the address of their sender.
+    packets: VecDeque<(SocketAddr, Vec<u8>)>,
+    bytes: usize,
+}
+
+/// Counters of one relay session, from a sender to a receiver.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    /// Bytes queued and not yet sent.
+    pub in_flight: usize,
+    /// Packets sent to the receiver.
+    pub forwarded: u64,
+    /// Bytes sent to the receiver.
+    pub forwarded_bytes: u64,
+    /// Packets dropped because the session or the destination was full.
+    pub dropped: u64,
+}
+
+#[derive(Debug)]
+struct Session {
+    stats: SessionStats,
+    last_active: Instant,
+}
+
+/// Per-destination queues of relayed packets.
+#[derive(Debug)]
+pub struct RelayQueues {
+    limit: usize,
+    session_limit: usize,
+    queues: HashMap<SocketAddr, Queue>,
+    /// Destinations with queued packets, in round-robin order.
+    order: VecDeque<SocketAddr>,
+    sessions: HashMap<(SocketAddr, SocketAddr), Session>,
+    dropped: u64,
+    /// Where packets go once sent or dropped.
+    pool: BufferPool,
+}
+
+impl RelayQueues {
+    /// Create queues holding at most `limit` bytes per destination and
+    /// `session_limit` bytes per session.
+    pub fn new(limit: usize, session_limit: usize) -> Self {
+        RelayQueues {
+            limit,
+            session_limit,
+            queues: HashMap::new(),
+            order: VecDeque::new(),
+            sessions: HashMap::new(),
+            dropped: 0,
+            pool: BufferPool::default(),
+        }
+    }
+
+    /// Give the buffers of packets sent or dropped back to `pool`.
+    pub fn with_pool(mut self, pool: BufferPool) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Queue `packet` from `from` for `to`. Returns false, and counts the
+    /// packet as dropped, when the session or the destination is over its
+    /// limit.
+    pub fn push(
+        &mut self,
+        from: SocketAddr,
+        to: SocketAddr,
+        packet: Vec<u8>,
+    ) -> bool {
+        let session = self.sessions.entry((from, to)).or_insert(Session {
+            stats: SessionStats::default(),
+            last_active: Instant::now(),
+        });
+        session.last_active = Instant::now();
+
+        let queue = self.queues.entry(to).or_default();
+        if queue.bytes + packet.len() > self.limit
+            || session.stats.in_flight + packet.len() > self.session_limit
+        {
+            session.stats.dropped += 1;
+            self.dropped += 1;
+            self.pool.put(packet);
+            return false;
+        }
+
+        if queue.packets.is_empty() {
+            self.order.push_back(to);
+        }
+        queue.bytes += packet.len();
+        session.stats.in_flight += packet.len();
+        queue.packets.push_back((from, packet));
+        true
+    }
+
+    /// Send queued packets, one per destination in turn, until every queue
+    /// is empty or the transport would block. Sent bytes are counted as
+    /// relayed in the transport's counters. Returns how many packets were
+    /// sent.
+    pub fn flush<T: Transport>(
+        &mut self,
+        transport: &MeteredTransport<T>,
+    ) -> usize {
+        let mut sent = 0;
+
+        while let Some(to) = self.order.pop_front() {
+            let Some(queue) = self.queues.get_mut(&to) else {
+                continue;
+            };
+            let Some((from, packet)) = queue.packets.pop_front() else {
+                self.queues.remove(&to);
+                continue;
+            };
+            let session = self.sessions.get_mut(&(from, to));
+
+            match transport.send_to(&packet, to) {
+                Ok(len) => {
+                    sent += 1;
+                    transport.counters().add_relayed(len);
+                    if let Some(session) = session {
+                        session.stats.forwarded += 1;
+                        session.stats.forwarded_bytes += len as u64;
+                        session.stats.in_flight -= packet.len();
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    queue.packets.push_front((from, packet));
+                    self.order.push_front(to);
+                    return sent;
+                }
+                Err(e) => {
+                    error!("Failed to relay packet to {}: {}", to, e);
+                    self.dropped += 1;
+                    if let Some(session) = session {
+                        session.stats.dropped += 1;
+                        session.stats.in_flight -= packet.len();
+                    }
+                }
+            }
+
+            queue.bytes -= packet.len();
+            self.pool.put(packet);
+            if queue.packets.is_empty() {
+                self.queues.remove(&to);
+            } else {
+                self.order.push_back(to);
+            }
+        }
+
+        sent
+    }
+
+    /// Bytes currently queued for `to`.
+    pub fn queued_bytes(&self, to: SocketAddr) -> usize {
+        self.queues.get(&to).map_or(0, |q| q.bytes)
+    }
+
+    /// Counters of the session from `from` to `to`, if it is known.
+    pub fn session(
+        &self,
+        from: SocketAddr,
+        to: SocketAddr,
+    ) -> Option<SessionStats> {
+        self.sessions.get(&(from, to)).map(|s| s.stats)
+    }
+
+    /// Every known session as `(from, to, stats)`.
+    pub fn sessions(
+        &self,
+    ) -> impl Iterator<Item = (SocketAddr, SocketAddr, SessionStats)> + '_
+    {
+        self.sessions.iter().map(|(&(from, to), s)| (from, to, s.stats))
+    }
+
+    /// Forget sessions with nothing in flight that have been idle for a
+    /// while. Returns how many were removed.
+    pub fn expire_sessions(&mut self, now: Instant) -> usize {
+        let before = self.sessions.len();
+        self.sessions.retain(|_, s| {
+            s.stats.in_flight > 0
+                || now.duration_since(s.last_active) < SESSION_IDLE_TIMEOUT
+        });
+        before - self.sessions.len()
+    }
+
+    /// Number of packets dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::transport::{MockNetwork, TrafficCounters};
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn sender_over_its_session_limit_is_throttled() {
+        let mut queues = RelayQueues::new(1000, 100);
+        let (fast, other, to) = (
+            addr("10.0.0.1:4000"),
+            addr("10.0.0.2:4000"),
+            addr("10.0.0.3:4000"),
+        );
+
+        let accepted =
+            (0..50).filter(|_| queues.push(fast, to, vec![0; 30])).count();
+
+        assert_eq!(accepted, 3);
+        let stats = queues.session(fast, to).unwrap();
+        assert_eq!((stats.in_flight, stats.dropped), (90, 47));
+        assert_eq!(queues.queued_bytes(to), 90);
+        assert_eq!(queues.dropped(), 47);
+
+        // The destination still has room for other senders.
+        assert!(queues.push(other, to, vec![0; 30]));
+        assert_eq!(queues.session(other, to).unwrap().dropped, 0);
+    }
+
+    #[test]
+    fn destination_limit_caps_every_sender() {
+        let mut queues = RelayQueues::new(100, 100);
+        let to = addr("10.0.0.9:4000");
+        for port in 1..=10 {
+            queues.push(addr(&format!("10.0.0.1:{port}")), to, vec![0; 30]);
+        }
+        assert_eq!(queues.queued_bytes(to), 90);
+        assert_eq!(queues.dropped(), 7);
+    }
+
+    #[test]
+    fn flushing_frees_the_session_and_counts_relayed_bytes() {
+        let network = MockNetwork::new();
+        let counters = Arc::new(TrafficCounters::default());
+        let server = MeteredTransport::new(
+            network.bind(addr("10.0.0.254:7000")).unwrap(),
+            Arc::clone(&counters),
+        );
+        let to = network.bind(addr("10.0.0.3:4000")).unwrap();
+        let to_addr = to.local_addr().unwrap();
+        let from = addr("10.0.0.1:4000");
+        let mut queues = RelayQueues::new(1000, 100);
+        for _ in 0..4 {
+            queues.push(from, to_addr, vec![1; 30]);
+        }
+
+        assert_eq!(queues.flush(&server), 3);
+
+        let stats = queues.session(from, to_addr).unwrap();
+        assert_eq!(
+            stats,
+            SessionStats {
+                in_flight: 0,
+                forwarded: 3,
+                forwarded_bytes: 90,
+                dropped: 1
+            }
+        );
+        assert_eq!(queues.queued_bytes(to_addr), 0);
+        assert_eq!(counters.bytes_relayed(), 90);
+        let mut buf = [0u8; 64];
+        assert_eq!(
+            to.recv_from(&mut buf).unwrap(),
+            (30, addr("10.0.0.254:7000"))
+        );
+
+        // Once drained the session accepts packets again.
+        assert!(queues.push(from, to_addr, vec![1; 30]));
+    }
+
+    #[test]
+    fn idle_sessions_expire() {
+        let mut queues = RelayQueues::new(1000, 100);
+        let (from, to) = (addr("10.0.0.1:4000"), addr("10.0.0.3:4000"));
+        queues.push(from, to, vec![0; 30]);
+        let later = Instant::now() + SESSION_IDLE_TIMEOUT * 2;
+
+        // Still in flight, kept.
+        assert_eq!(queues.expire_sessions(later), 0);
+
+        queues.flush(&MeteredTransport::new(
+            MockNetwork::new().bind(addr("10.0.0.254:7000")).unwrap(),
+            Arc::new(TrafficCounters::default()),
+        ));
+        assert_eq!(queues.expire_sessions(later), 1);
+        assert!(queues.session(from, to).is_none());
+    }
+}
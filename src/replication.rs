@@ -0,0 +1,259 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Replica target selection and read repair.
+//!
+//! A put is replicated to the N peers closest to the key. Pure XOR distance
+//! ignores network proximity, so [`ReplicaSelection::Latency`] lets callers
+//! trade a little id-space closeness for lower round-trip times.
+//!
+//! A get that finds replicas missing the value or holding an older
+//! version plans a read repair, see [`plan_read_repair`], and queues it in
+//! [`Repairs`] to be sent once the get has answered.
+
+use std::{collections::VecDeque, time::Duration};
+
+use crate::{
+    protocol::Contact,
+    routing::{K, distance},
+    store::Value,
+};
+
+/// Default number of nodes a put is replicated to, Kademlia's k.
+pub const DEFAULT_REPLICATION_FACTOR: usize = K;
+
+/// Most replicas a single get plans to repair.
+pub const MAX_REPAIRS_PER_GET: usize = 4;
+
+/// Most repairs waiting to be sent, the oldest are dropped past it.
+pub const MAX_PENDING_REPAIRS: usize = 64;
+
+/// A peer that may receive a replica.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub id: [u8; 20],
+    pub rtt: Option<Duration>,
+}
+
+/// Strategy used to pick replica targets among candidates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplicaSelection {
+    /// Pick the N closest candidates by XOR distance.
+    #[default]
+    Distance,
+    /// Take the `band` closest candidates by XOR distance and pick the N
+    /// with the lowest measured RTT among them. Candidates without a
+    /// measured RTT are only picked after every measured one.
+    Latency { band: usize },
+}
+
+/// Select up to `n` replica targets for `target` out of `candidates`.
+///
+/// The returned list is ordered by preference.
+pub fn select_replicas(
+    target: &[u8; 20],
+    candidates: &[Candidate],
+    n: usize,
+    strategy: ReplicaSelection,
+) -> Vec<Candidate> {
+    let mut by_distance: Vec<&Candidate> = candidates.iter().collect();
+    by_distance.sort_by_key(|c| distance(&c.id, target));
+
+    match strategy {
+        ReplicaSelection::Distance => {
+            by_distance.into_iter().take(n).cloned().collect()
+        }
+        ReplicaSelection::Latency { band } => {
+            // The band can never be narrower than the replica count,
+            // otherwise we would return fewer replicas than requested.
+            by_distance.truncate(band.max(n));
+            // Stable sort keeps XOR order among equal (or unknown) RTTs.
+            by_distance.sort_by_key(|c| (c.rtt.is_none(), c.rtt));
+            by_distance.into_iter().take(n).cloned().collect()
+        }
+    }
+}
+
+/// A replica's answer to a get.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicaAnswer {
+    pub replica: [u8; 20],
+    /// Version and value held by the replica, `None` when it has no value.
+    pub record: Option<(u64, Value)>,
+}
+
+/// Repairs to issue after a get, see [`plan_read_repair`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepairPlan {
+    pub version: u64,
+    pub value: Value,
+    /// Replicas missing the value or holding an older version.
+    pub targets: Vec<[u8; 20]>,
+}
+
+/// Work out which replicas need the newest value pushed back to them.
+///
+/// The authoritative record is the one with the highest version. At most
+/// `max_repairs` targets are returned to bound the repair traffic a single
+/// get can generate. Returns `None` when no replica had a value or every
+/// replica is already up to date.
+pub fn plan_read_repair(
+    answers: &[ReplicaAnswer],
+    max_repairs: usize,
+) -> Option<RepairPlan> {
+    let (version, value) = answers
+        .iter()
+        .filter_map(|a| a.record.as_ref())
+        .max_by_key(|(version, _)| *version)?;
+
+    let targets: Vec<[u8; 20]> = answers
+        .iter()
+        .filter(|a| match &a.record {
+            Some((v, _)) => v < version,
+            None => true,
+        })
+        .map(|a| a.replica)
+        .take(max_repairs)
+        .collect();
+
+    if targets.is_empty() {
+        return None;
+    }
+
+    Some(RepairPlan { version: *version, value: value.clone(), targets })
+}
+
+/// The newest record of a key, owed to a stale replica.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repair {
+    pub contact: Contact,
+    pub key: String,
+    pub version: u64,
+    pub value: Value,
+}
+
+/// Repairs
+///
+/// Read repairs planned by gets and not sent yet, oldest first.
+#[derive(Debug, Default)]
+pub struct Repairs {
+    pending: VecDeque<Repair>,
+}
+
+impl Repairs {
+    /// Queue the repairs of `plan` for `key`, to the targets found among
+    /// `contacts`. A target already owed `key` is owed the newer record.
+    pub fn add(&mut self, key: &str, plan: &RepairPlan, contacts: &[Contact]) {
+        let targets = contacts
+            .iter()
+            .filter(|contact| plan.targets.contains(&contact.node_id));
+        for contact in targets {
+            let queued = self.pending.iter_mut().find(|repair| {
+                repair.contact.node_id == contact.node_id && repair.key == key
+            });
+            if let Some(repair) = queued {
+                if repair.version < plan.version {
+                    repair.version = plan.version;
+                    repair.value = plan.value.clone();
+                }
+                continue;
+            }
+            if self.pending.len() >= MAX_PENDING_REPAIRS {
+                self.pending.pop_front();
+            }
+            self.pending.push_back(Repair {
+                contact: contact.clone(),
+                key: key.to_string(),
+                version: plan.version,
+                value: plan.value.clone(),
+            });
+        }
+    }
+
+    /// Take up to `max` repairs to send, oldest first.
+    pub fn take(&mut self, max: usize) -> Vec<Repair> {
+        let count = max.min(self.pending.len());
+        self.pending.drain(..count).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A candidate at distance `n` from the zero id.
+    fn candidate(n: u8, rtt_ms: Option<u64>) -> Candidate {
+        let mut id = [0; 20];
+        id[19] = n;
+        Candidate { id, rtt: rtt_ms.map(Duration::from_millis) }
+    }
+
+    /// Distances from the zero id of `selected`.
+    fn distances(selected: &[Candidate]) -> Vec<u8> {
+        selected.iter().map(|c| c.id[19]).collect()
+    }
+
+    fn candidates() -> Vec<Candidate> {
+        vec![
+            candidate(4, Some(1)),
+            candidate(1, Some(80)),
+            candidate(5, None),
+            candidate(3, Some(30)),
+            candidate(2, Some(10)),
+        ]
+    }
+
+    #[test]
+    fn distance_ignores_latency() {
+        let selected = select_replicas(
+            &[0; 20],
+            &candidates(),
+            2,
+            ReplicaSelection::default(),
+        );
+        assert_eq!(distances(&selected), [1, 2]);
+    }
+
+    #[test]
+    fn latency_prefers_fast_peers_within_the_band() {
+        let strategy = ReplicaSelection::Latency { band: 3 };
+        let selected = select_replicas(&[0; 20], &candidates(), 2, strategy);
+        // 4 answers fastest but is outside the 3 closest.
+        assert_eq!(distances(&selected), [2, 3]);
+    }
+
+    #[test]
+    fn latency_picks_unmeasured_peers_last() {
+        let strategy = ReplicaSelection::Latency { band: 5 };
+        let selected = select_replicas(&[0; 20], &candidates(), 5, strategy);
+        assert_eq!(distances(&selected), [4, 2, 3, 1, 5]);
+    }
+
+    #[test]
+    fn latency_band_is_never_narrower_than_the_replica_count() {
+        let strategy = ReplicaSelection::Latency { band: 1 };
+        let selected = select_replicas(&[0; 20], &candidates(), 3, strategy);
+        assert_eq!(distances(&selected), [2, 3, 1]);
+    }
+}
@@ -0,0 +1,156 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Record republishing.
+//!
+//! Nodes come and go, so the set of nodes closest to a key drifts away
+//! from the one a record was first stored on. Records this node put
+//! ("originated") are pushed again to the current closest nodes every
+//! origin interval, and records it merely holds for others are
+//! re-replicated every replicate interval, so records survive churn.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Default time between two republishes of an originated record.
+pub const DEFAULT_ORIGIN_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Default time between two re-replications of a record held for others.
+pub const DEFAULT_REPLICATE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Where a record came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// Put by this node.
+    Local,
+    /// Stored on behalf of another node.
+    Remote,
+}
+
+#[derive(Debug)]
+struct Record {
+    origin: Origin,
+    last_published: Instant,
+}
+
+/// Republisher
+///
+/// Tracks the origin of every record and when it was last published, and
+/// tells which records are due again.
+#[derive(Debug)]
+pub struct Republisher {
+    records: HashMap<String, Record>,
+    origin_interval: Duration,
+    replicate_interval: Duration,
+}
+
+impl Default for Republisher {
+    fn default() -> Self {
+        Republisher::new(DEFAULT_ORIGIN_INTERVAL, DEFAULT_REPLICATE_INTERVAL)
+    }
+}
+
+impl Republisher {
+    pub fn new(
+        origin_interval: Duration,
+        replicate_interval: Duration,
+    ) -> Self {
+        Republisher {
+            records: HashMap::new(),
+            origin_interval,
+            replicate_interval,
+        }
+    }
+
+    pub fn origin_interval(&self) -> Duration {
+        self.origin_interval
+    }
+
+    pub fn set_origin_interval(&mut self, interval: Duration) {
+        self.origin_interval = interval;
+    }
+
+    pub fn replicate_interval(&self) -> Duration {
+        self.replicate_interval
+    }
+
+    /// Record that this node put `key` and published it at `now`.
+    pub fn originated(&mut self, key: &str, now: Instant) {
+        self.records.insert(
+            key.to_string(),
+            Record { origin: Origin::Local, last_published: now },
+        );
+    }
+
+    /// Record that `key` was stored for another node at `now`.
+    ///
+    /// Its holders presumably all got it at the same time, so the next
+    /// re-replication is postponed. A record this node originated stays
+    /// local.
+    pub fn stored(&mut self, key: &str, now: Instant) {
+        let record = self
+            .records
+            .entry(key.to_string())
+            .or_insert(Record { origin: Origin::Remote, last_published: now });
+        record.last_published = record.last_published.max(now);
+    }
+
+    /// Start tracking `key` as held for another node, unless it is
+    /// already tracked.
+    pub fn track(&mut self, key: &str, now: Instant) {
+        if !self.records.contains_key(key) {
+            self.stored(key, now);
+        }
+    }
+
+    /// Stop tracking `key`, e.g. once it left the store.
+    pub fn forget(&mut self, key: &str) {
+        self.records.remove(key);
+    }
+
+    pub fn origin(&self, key: &str) -> Option<Origin> {
+        self.records.get(key).map(|r| r.origin)
+    }
+
+    /// Number of tracked records with the given origin.
+    pub fn count(&self, origin: Origin) -> usize {
+        self.records.values().filter(|r| r.origin == origin).count()
+    }
+
+    /// Keys due for publishing as of `now`, originated ones first, each
+    /// marked as published at `now`.
+    pub fn due(&mut self, now: Instant) -> Vec<(String, Origin)> {
+        let mut due: Vec<(String, Origin)> = self
+            .records
+            .iter_mut()
+            .filter(|(_, record)| {
+                let interval = match record.origin {
+                    Origin::Local => self.origin_interval,
+                    Origin::Remote => self.replicate_interval,
+                };
+                now.duration_since(record.last_published) >= interval
+            })
+            .map(|(key, record)| {
+                record.last_published = now;
+                (key.clone(), record.origin)
+            })
+            .collect();
+
+        due.sort_by_key(|(_, origin)| *origin != Origin::Local);
+        due
+    }
+}
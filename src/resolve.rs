@@ -0,0 +1,163 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Tiered value resolution.
+//!
+//! A networked get tries the cheapest source first: the local store, then
+//! the replicas already known from the routing table, then an iterative
+//! lookup. Each tier gets its own time budget, and the first tier that
+//! finds the value wins.
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::store::Value;
+
+/// A source a value can be resolved from, cheapest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tier {
+    /// The node's own store.
+    Local,
+    /// Nodes known to hold replicas of the key.
+    Replicas,
+    /// An iterative lookup through the network.
+    Lookup,
+}
+
+impl fmt::Display for Tier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tier::Local => write!(f, "local"),
+            Tier::Replicas => write!(f, "replicas"),
+            Tier::Lookup => write!(f, "lookup"),
+        }
+    }
+}
+
+/// Fetch a key from one tier within the given time budget. `Ok(None)`
+/// means the tier does not have the value.
+pub type Fetch<'a> = Box<
+    dyn FnMut(
+            &str,
+            Duration,
+        ) -> Result<Option<Value>, Box<dyn std::error::Error>>
+        + 'a,
+>;
+
+/// What happened when a tier was tried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Found,
+    Missing,
+    /// The tier used up its budget without finding the value.
+    TimedOut,
+    Failed(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Found => write!(f, "found"),
+            Outcome::Missing => write!(f, "missing"),
+            Outcome::TimedOut => write!(f, "timed out"),
+            Outcome::Failed(e) => write!(f, "failed: {e}"),
+        }
+    }
+}
+
+/// One tier tried while resolving a key.
+#[derive(Debug, Clone)]
+pub struct Attempt {
+    pub tier: Tier,
+    pub elapsed: Duration,
+    pub outcome: Outcome,
+}
+
+/// Result of [`Ladder::resolve`].
+#[derive(Debug, Default)]
+pub struct Resolution {
+    /// The value and the tier that produced it.
+    pub value: Option<(Tier, Value)>,
+    /// Every tier tried, in order.
+    pub attempts: Vec<Attempt>,
+}
+
+struct Rung<'a> {
+    tier: Tier,
+    timeout: Duration,
+    fetch: Fetch<'a>,
+}
+
+/// Ladder
+///
+/// Ordered tiers to resolve a key from.
+#[derive(Default)]
+pub struct Ladder<'a> {
+    rungs: Vec<Rung<'a>>,
+}
+
+impl<'a> Ladder<'a> {
+    pub fn new() -> Self {
+        Ladder { rungs: Vec::new() }
+    }
+
+    /// Add a tier, tried after the ones already added, with `timeout` as
+    /// its time budget.
+    pub fn tier(
+        mut self,
+        tier: Tier,
+        timeout: Duration,
+        fetch: Fetch<'a>,
+    ) -> Self {
+        self.rungs.push(Rung { tier, timeout, fetch });
+        self
+    }
+
+    /// Try each tier in order until one finds `key`. A failing tier does
+    /// not stop the fall through to the next one.
+    pub fn resolve(&mut self, key: &str) -> Resolution {
+        let mut resolution = Resolution::default();
+
+        for rung in &mut self.rungs {
+            let start = Instant::now();
+            let result = (rung.fetch)(key, rung.timeout);
+            let elapsed = start.elapsed();
+
+            let outcome = match result {
+                Ok(Some(value)) => {
+                    resolution.value = Some((rung.tier, value));
+                    Outcome::Found
+                }
+                Ok(None) if elapsed >= rung.timeout => Outcome::TimedOut,
+                Ok(None) => Outcome::Missing,
+                Err(e) => Outcome::Failed(e.to_string()),
+            };
+
+            resolution.attempts.push(Attempt {
+                tier: rung.tier,
+                elapsed,
+                outcome,
+            });
+            if resolution.value.is_some() {
+                break;
+            }
+        }
+
+        resolution
+    }
+}
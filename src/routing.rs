@@ -0,0 +1,443 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Kademlia routing table.
+//!
+//! Node ids are 160 bit. Every bucket covers the XOR distances to the
+//! local id that start with a given prefix, and buckets are indexed
+//! closest range first.
+//!
+//! In the [`Layout::Flat`] layout there is one bucket per distance bit:
+//! bucket `i` holds the nodes whose distance has its highest set bit at
+//! position `i`, i.e. distances in `[2^i, 2^(i+1))`. In the
+//! [`Layout::Split`] layout the table starts with a single bucket covering
+//! every distance and splits full buckets in two as nodes come in: always
+//! the one covering the local id, and any other as long as fewer than [`K`]
+//! nodes are known closer to the local id than its range. The buckets then
+//! follow the shape of the network, and the table keeps more contacts
+//! around the local id, which shortens lookups on large networks.
+//!
+//! A full bucket keeps its nodes and puts newcomers in its replacement
+//! cache instead. Nodes only leave when removed or when they fail enough
+//! liveness probes in a row, see [`RoutingTable::record_failure`], and the
+//! most recently seen replacement then takes the free slot. Long-lived
+//! responsive nodes are thus never pushed out by new ones; to make room,
+//! ping the [`RoutingTable::eviction_candidates`] and remove those that do
+//! not answer.
+
+use std::{collections::HashMap, fs::File, io, time::Instant};
+
+use crate::{io::read_full, node_id::NodeId};
+
+/// Number of bits in a node id, and so the number of buckets of the flat
+/// layout.
+pub const ID_BITS: usize = NodeId::BITS;
+
+/// Maximum number of nodes kept per bucket.
+pub const K: usize = 20;
+
+/// Maximum number of replacements cached per bucket.
+pub const REPLACEMENT_CACHE_SIZE: usize = K;
+
+/// Read a random node id from `/dev/urandom`.
+pub fn random_id() -> io::Result<[u8; 20]> {
+    let mut file = File::open("/dev/urandom")?;
+    let mut buf = [0u8; 20];
+    read_full(&mut file, &mut buf)?;
+    Ok(buf)
+}
+
+/// XOR distance between two ids, see [`NodeId::distance`].
+pub fn distance(a: &[u8; 20], b: &[u8; 20]) -> [u8; 20] {
+    NodeId::new(*a).distance(&NodeId::new(*b)).into()
+}
+
+/// Number of leading zero bits in `distance`, [`ID_BITS`] when it is zero.
+pub fn leading_zeros(distance: &[u8; 20]) -> usize {
+    NodeId::new(*distance).leading_zeros()
+}
+
+/// How a routing table divides the distances into buckets, see the
+/// module documentation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// [`ID_BITS`] fixed buckets, one per distance bit.
+    Flat,
+    /// Buckets split as they fill up.
+    #[default]
+    Split,
+}
+
+/// Whether bit `index` of `id`, counting from the most significant one, is
+/// set.
+fn bit(id: &[u8; 20], index: usize) -> bool {
+    id[index / 8] & (0x80 >> (index % 8)) != 0
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Distances covered: those whose first `depth` bits are the ones of
+    /// `prefix`. The other bits of `prefix` are zero.
+    prefix: [u8; 20],
+    depth: usize,
+    /// Least recently seen first.
+    nodes: Vec<[u8; 20]>,
+    /// Nodes turned away while the bucket was full, least recently seen
+    /// first.
+    replacements: Vec<[u8; 20]>,
+    /// Last time a node in this bucket was seen or a lookup targeted its
+    /// range.
+    last_touched: Instant,
+}
+
+impl Bucket {
+    fn new(prefix: [u8; 20], depth: usize, now: Instant) -> Self {
+        Bucket {
+            prefix,
+            depth,
+            nodes: Vec::new(),
+            replacements: Vec::new(),
+            last_touched: now,
+        }
+    }
+
+    fn covers(&self, distance: &[u8; 20]) -> bool {
+        let (bytes, bits) = (self.depth / 8, self.depth % 8);
+        distance[..bytes] == self.prefix[..bytes]
+            && (bits == 0
+                || (distance[bytes] ^ self.prefix[bytes])
+                    & (0xff << (8 - bits))
+                    == 0)
+    }
+}
+
+/// RoutingTable
+///
+/// Known nodes grouped in k-buckets by their distance to the local id.
+#[derive(Debug)]
+pub struct RoutingTable {
+    local_id: [u8; 20],
+    layout: Layout,
+    /// Closest range first.
+    buckets: Vec<Bucket>,
+    /// Consecutive failed probes of nodes that failed at least once.
+    failures: HashMap<[u8; 20], u32>,
+    /// Last time each node or replacement was seen.
+    last_seen: HashMap<[u8; 20], Instant>,
+}
+
+impl RoutingTable {
+    /// A table with the default [`Layout`].
+    pub fn new(local_id: [u8; 20]) -> Self {
+        Self::with_layout(local_id, Layout::default())
+    }
+
+    pub fn with_layout(local_id: [u8; 20], layout: Layout) -> Self {
+        let now = Instant::now();
+        let buckets = match layout {
+            Layout::Flat => (0..ID_BITS)
+                .map(|i| {
+                    let depth = ID_BITS - i;
+                    let mut prefix = [0u8; 20];
+                    prefix[(depth - 1) / 8] = 0x80 >> ((depth - 1) % 8);
+                    Bucket::new(prefix, depth, now)
+                })
+                .collect(),
+            Layout::Split => vec![Bucket::new([0; 20], 0, now)],
+        };
+
+        RoutingTable {
+            local_id,
+            layout,
+            buckets,
+            failures: HashMap::new(),
+            last_seen: HashMap::new(),
+        }
+    }
+
+    pub fn local_id(&self) -> &[u8; 20] {
+        &self.local_id
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Number of buckets, which only grows in the split layout.
+    pub fn bucket_count(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Index of the bucket covering `id`, or `None` for the local id.
+    ///
+    /// Indexes shift when a bucket splits, so they are only valid until
+    /// the next insertion.
+    pub fn bucket_index(&self, id: &[u8; 20]) -> Option<usize> {
+        if *id == self.local_id {
+            return None;
+        }
+        let distance = distance(&self.local_id, id);
+        self.buckets.iter().position(|b| b.covers(&distance))
+    }
+
+    /// Record that `id` was seen, moving it to the tail of its bucket.
+    ///
+    /// Returns `false` if the bucket is full, can not split and `id` was
+    /// not added; it is kept in the bucket's replacement cache instead.
+    pub fn insert(&mut self, id: [u8; 20], now: Instant) -> bool {
+        let Some(mut index) = self.bucket_index(&id) else {
+            return false;
+        };
+        while self.buckets[index].nodes.len() >= K
+            && !self.buckets[index].nodes.contains(&id)
+            && self.can_split(index)
+        {
+            self.split(index);
+            index = self.bucket_index(&id).unwrap_or(index);
+        }
+        let bucket = &mut self.buckets[index];
+        bucket.last_touched = now;
+        self.last_seen.insert(id, now);
+
+        if let Some(pos) = bucket.nodes.iter().position(|n| *n == id) {
+            bucket.nodes.remove(pos);
+        } else if bucket.nodes.len() >= K {
+            bucket.replacements.retain(|n| *n != id);
+            if bucket.replacements.len() >= REPLACEMENT_CACHE_SIZE {
+                let dropped = bucket.replacements.remove(0);
+                self.last_seen.remove(&dropped);
+            }
+            bucket.replacements.push(id);
+            return false;
+        }
+        bucket.nodes.push(id);
+        self.failures.remove(&id);
+        true
+    }
+
+    /// Whether full bucket `index` may split rather than turn nodes away.
+    fn can_split(&self, index: usize) -> bool {
+        let bucket = &self.buckets[index];
+        if self.layout == Layout::Flat || bucket.depth >= ID_BITS {
+            return false;
+        }
+        // The bucket covering the local id always splits; any other only
+        // while the K closest nodes are not known yet.
+        bucket.covers(&[0; 20])
+            || self.buckets[..index]
+                .iter()
+                .map(|b| b.nodes.len())
+                .sum::<usize>()
+                < K
+    }
+
+    /// Split bucket `index` in two on its next distance bit, the closer
+    /// half keeping the index. Replacements fill any half left short.
+    fn split(&mut self, index: usize) {
+        let local_id = self.local_id;
+        let bucket = &mut self.buckets[index];
+        let depth = bucket.depth;
+        let far = |id: &[u8; 20]| bit(&distance(&local_id, id), depth);
+
+        let mut prefix = bucket.prefix;
+        prefix[depth / 8] |= 0x80 >> (depth % 8);
+        let mut high = Bucket::new(prefix, depth + 1, bucket.last_touched);
+        bucket.depth += 1;
+
+        let nodes = std::mem::take(&mut bucket.nodes);
+        let replacements = std::mem::take(&mut bucket.replacements);
+        (high.nodes, bucket.nodes) = nodes.into_iter().partition(far);
+        (high.replacements, bucket.replacements) =
+            replacements.into_iter().partition(far);
+
+        for half in [&mut *bucket, &mut high] {
+            while half.nodes.len() < K
+                && let Some(replacement) = half.replacements.pop()
+            {
+                half.nodes.push(replacement);
+            }
+        }
+        self.buckets.insert(index + 1, high);
+    }
+
+    /// Remove `id`, returning whether it was present. The most recently
+    /// seen replacement, if any, takes its slot.
+    pub fn remove(&mut self, id: &[u8; 20]) -> bool {
+        let Some(index) = self.bucket_index(id) else {
+            return false;
+        };
+        self.failures.remove(id);
+        self.last_seen.remove(id);
+        let bucket = &mut self.buckets[index];
+        bucket.replacements.retain(|n| n != id);
+        let before = bucket.nodes.len();
+        bucket.nodes.retain(|n| n != id);
+        if bucket.nodes.len() == before {
+            return false;
+        }
+
+        if let Some(replacement) = bucket.replacements.pop() {
+            bucket.nodes.push(replacement);
+        }
+        true
+    }
+
+    pub fn contains(&self, id: &[u8; 20]) -> bool {
+        self.bucket_index(id)
+            .is_some_and(|index| self.buckets[index].nodes.contains(id))
+    }
+
+    /// Whether `id` is in the table or waiting in a replacement cache.
+    pub fn knows(&self, id: &[u8; 20]) -> bool {
+        self.bucket_index(id).is_some_and(|index| {
+            let bucket = &self.buckets[index];
+            bucket.nodes.contains(id) || bucket.replacements.contains(id)
+        })
+    }
+
+    /// Replacements cached for bucket `index`, least recently seen first.
+    pub fn replacements(&self, index: usize) -> &[[u8; 20]] {
+        &self.buckets[index].replacements
+    }
+
+    /// The least recently seen node of every full bucket with replacements
+    /// waiting. Each should be pinged, then either confirmed with
+    /// [`Self::record_success`] or removed to let a replacement in.
+    pub fn eviction_candidates(&self) -> Vec<[u8; 20]> {
+        self.buckets
+            .iter()
+            .filter(|b| b.nodes.len() >= K && !b.replacements.is_empty())
+            .filter_map(|b| b.nodes.first().copied())
+            .collect()
+    }
+
+    /// Every node in the table, closest buckets first.
+    pub fn nodes(&self) -> impl Iterator<Item = &[u8; 20]> {
+        self.buckets.iter().flat_map(|b| &b.nodes)
+    }
+
+    /// Up to `count` nodes closest to `target` by XOR distance, closest
+    /// first.
+    pub fn closest(&self, target: &[u8; 20], count: usize) -> Vec<[u8; 20]> {
+        let mut nodes: Vec<[u8; 20]> = self.nodes().copied().collect();
+        nodes.sort_by_key(|id| distance(id, target));
+        nodes.truncate(count);
+        nodes
+    }
+
+    /// Record that `id` answered a probe, clearing its failures and moving
+    /// it to the tail of its bucket. Returns `false` if it is unknown.
+    pub fn record_success(&mut self, id: &[u8; 20], now: Instant) -> bool {
+        self.contains(id) && self.insert(*id, now)
+    }
+
+    /// Record that `id` failed a probe, evicting it once it has failed
+    /// `threshold` probes in a row. Returns whether it was evicted.
+    pub fn record_failure(&mut self, id: &[u8; 20], threshold: u32) -> bool {
+        if !self.contains(id) {
+            return false;
+        }
+
+        let failures = self.failures.entry(*id).or_default();
+        *failures += 1;
+        if *failures < threshold.max(1) {
+            return false;
+        }
+        self.remove(id)
+    }
+
+    /// Last time `id` was seen, if it is in the table or a replacement
+    /// cache.
+    pub fn last_seen(&self, id: &[u8; 20]) -> Option<Instant> {
+        self.last_seen.get(id).copied()
+    }
+
+    /// Consecutive failed probes of `id`.
+    pub fn failures(&self, id: &[u8; 20]) -> u32 {
+        self.failures.get(id).copied().unwrap_or_default()
+    }
+
+    /// Mark bucket `index` as recently looked up.
+    pub fn touch(&mut self, index: usize, now: Instant) {
+        self.buckets[index].last_touched = now;
+    }
+
+    /// Last time bucket `index` was touched.
+    pub fn last_touched(&self, index: usize) -> Instant {
+        self.buckets[index].last_touched
+    }
+
+    /// Total number of nodes in the table.
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(|b| b.nodes.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Estimate the number of nodes in the whole network from how densely
+    /// the [`K`] nodes closest to the local id fill the id space around
+    /// it, `None` while the table is empty.
+    ///
+    /// Ids are uniformly distributed, so the `i`-th closest node of a
+    /// network of `n` nodes is expected at a distance of `i / n` of the id
+    /// space. `n` is fitted to the observed distances by least squares,
+    /// then the local node is counted in. The estimate is only as good as
+    /// the closest buckets are complete, so it is best after a lookup of
+    /// the local id.
+    pub fn estimated_size(&self) -> Option<u64> {
+        // Fraction of the id space below `distance`.
+        fn fraction(distance: &[u8; 20]) -> f64 {
+            distance.iter().rev().fold(0.0, |sum, &b| (sum + b as f64) / 256.0)
+        }
+
+        let closest = self.closest(&self.local_id, K);
+        if closest.is_empty() {
+            return None;
+        }
+        let (mut squares, mut weighted) = (0.0, 0.0);
+        for (i, id) in closest.iter().enumerate() {
+            let rank = (i + 1) as f64;
+            squares += rank * rank;
+            weighted += rank * fraction(&distance(id, &self.local_id));
+        }
+        Some((squares / weighted).round() as u64 + 1)
+    }
+
+    /// An id inside bucket `index`'s range, with the free bits taken from
+    /// `random`.
+    pub fn id_in_bucket(&self, index: usize, random: [u8; 20]) -> [u8; 20] {
+        // Build a distance starting with the bucket's prefix.
+        let bucket = &self.buckets[index];
+        let mut distance = random;
+        for (i, byte) in distance.iter_mut().enumerate() {
+            let first = i * 8;
+            if first + 8 <= bucket.depth {
+                *byte = bucket.prefix[i];
+            } else if first < bucket.depth {
+                let mask = 0xff >> (bucket.depth - first);
+                *byte = (*byte & mask) | bucket.prefix[i];
+            }
+        }
+
+        let mut id = self.local_id;
+        for (b, d) in id.iter_mut().zip(distance) {
+            *b ^= d;
+        }
+        id
+    }
+}
@@ -0,0 +1,234 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Retransmission of requests sent over UDP.
+//!
+//! Every request carries a nonce, its transaction id, which the reply
+//! echoes so the two can be matched. A request left unanswered is sent
+//! again with the same nonce after a delay that doubles every time, see
+//! [`Backoff`]. A destination that leaves [`DEFAULT_DEAD_AFTER`]
+//! transactions in a row unanswered is declared dead, see [`Failures`].
+//!
+//! The first delay is not fixed but follows the round trip times measured
+//! to the destination, Jacobson style, see [`RttEstimates`]: fast peers
+//! are given up on quickly and slow ones are not retried needlessly. Only
+//! replies to requests sent once are measured, since the reply to a
+//! retransmitted one cannot tell which send it answers (Karn).
+//!
+//! Receivers must treat a repeated request as the same one: DHT requests
+//! are idempotent and the rendezvous server replays its replies to
+//! duplicates, see [`crate::dedup`].
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// Default delay before the first retransmission.
+pub const DEFAULT_INITIAL_DELAY: Duration = Duration::from_millis(250);
+
+/// Default number of times a request is sent, the first one included.
+pub const DEFAULT_MAX_SENDS: u32 = 3;
+
+/// Default number of failed transactions in a row after which a
+/// destination is declared dead.
+pub const DEFAULT_DEAD_AFTER: u32 = 3;
+
+/// Shortest retransmission delay, however fast the destination answers.
+pub const MIN_RETRANSMIT_DELAY: Duration = Duration::from_millis(50);
+
+/// Longest retransmission delay, however slow the destination answers.
+pub const MAX_RETRANSMIT_DELAY: Duration = Duration::from_secs(2);
+
+/// Retransmission schedule: a request is sent up to `max_sends` times,
+/// `initial`, then twice as long, and so on, after the previous send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max_sends: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: DEFAULT_INITIAL_DELAY,
+            max_sends: DEFAULT_MAX_SENDS,
+        }
+    }
+}
+
+impl Backoff {
+    /// This schedule starting with `initial` instead.
+    pub fn starting_at(self, initial: Duration) -> Self {
+        Backoff { initial, ..self }
+    }
+
+    /// How long the whole schedule lasts, from the first send until the
+    /// last one is given up on.
+    pub fn timeout(&self) -> Duration {
+        (1..=self.max_sends.max(1))
+            .map(|sends| self.initial.saturating_mul(1 << (sends - 1).min(16)))
+            .fold(Duration::ZERO, Duration::saturating_add)
+    }
+
+    /// How long to wait after the `sends`-th send before sending again,
+    /// `None` once every send is spent.
+    pub fn delay(&self, sends: u32) -> Option<Duration> {
+        (sends < self.max_sends).then(|| {
+            self.initial.saturating_mul(1 << sends.saturating_sub(1).min(16))
+        })
+    }
+}
+
+/// A request awaiting its reply.
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    pub to: SocketAddr,
+    pub nonce: u64,
+    /// Encoded request, sent again as is.
+    pub payload: Vec<u8>,
+    /// Number of times it was sent.
+    pub sends: u32,
+    pub last_sent: Instant,
+}
+
+impl Transaction {
+    /// A transaction for `payload`, sent for the first time at `now`.
+    pub fn new(
+        to: SocketAddr,
+        nonce: u64,
+        payload: Vec<u8>,
+        now: Instant,
+    ) -> Self {
+        Transaction { to, nonce, payload, sends: 1, last_sent: now }
+    }
+
+    /// Whether the request should be sent again at `now`.
+    pub fn is_due(&self, backoff: &Backoff, now: Instant) -> bool {
+        backoff
+            .delay(self.sends)
+            .is_some_and(|delay| now >= self.last_sent + delay)
+    }
+
+    /// Record a retransmission at `now`.
+    pub fn resent(&mut self, now: Instant) {
+        self.sends += 1;
+        self.last_sent = now;
+    }
+}
+
+/// Smoothed round trip time to a destination and its variation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RttEstimate {
+    pub srtt: Duration,
+    pub rttvar: Duration,
+}
+
+impl RttEstimate {
+    /// Retransmission delay, `srtt + 4 * rttvar` within
+    /// [`MIN_RETRANSMIT_DELAY`] and [`MAX_RETRANSMIT_DELAY`].
+    pub fn delay(&self) -> Duration {
+        (self.srtt + self.rttvar.saturating_mul(4))
+            .clamp(MIN_RETRANSMIT_DELAY, MAX_RETRANSMIT_DELAY)
+    }
+}
+
+/// Round trip time estimates per destination (RFC 6298).
+#[derive(Debug, Clone, Default)]
+pub struct RttEstimates {
+    estimates: HashMap<SocketAddr, RttEstimate>,
+}
+
+impl RttEstimates {
+    /// Fold a round trip time measured to `addr` into its estimate.
+    pub fn sample(&mut self, addr: SocketAddr, rtt: Duration) {
+        let Some(estimate) = self.estimates.get_mut(&addr) else {
+            self.estimates
+                .insert(addr, RttEstimate { srtt: rtt, rttvar: rtt / 2 });
+            return;
+        };
+        let deviation = estimate.srtt.abs_diff(rtt);
+        estimate.rttvar = (estimate.rttvar * 3 + deviation) / 4;
+        estimate.srtt = (estimate.srtt * 7 + rtt) / 8;
+    }
+
+    /// Estimate for `addr`, if any round trip to it was measured.
+    pub fn get(&self, addr: SocketAddr) -> Option<RttEstimate> {
+        self.estimates.get(&addr).copied()
+    }
+
+    /// Retransmission delay to `addr`, if any round trip to it was
+    /// measured.
+    pub fn delay(&self, addr: SocketAddr) -> Option<Duration> {
+        self.get(addr).map(|estimate| estimate.delay())
+    }
+
+    /// `base` starting with the retransmission delay to `addr`, or as is
+    /// if no round trip to it was measured.
+    pub fn backoff(&self, base: Backoff, addr: SocketAddr) -> Backoff {
+        match self.delay(addr) {
+            Some(delay) => base.starting_at(delay),
+            None => base,
+        }
+    }
+
+    /// Forget the estimate for `addr`.
+    pub fn remove(&mut self, addr: SocketAddr) {
+        self.estimates.remove(&addr);
+    }
+}
+
+/// Consecutive failed transactions per destination.
+#[derive(Debug, Clone)]
+pub struct Failures {
+    threshold: u32,
+    counts: HashMap<SocketAddr, u32>,
+}
+
+impl Default for Failures {
+    fn default() -> Self {
+        Failures::new(DEFAULT_DEAD_AFTER)
+    }
+}
+
+impl Failures {
+    /// Declare a destination dead after `threshold` failures in a row.
+    pub fn new(threshold: u32) -> Self {
+        Failures { threshold: threshold.max(1), counts: HashMap::new() }
+    }
+
+    /// Count a failed transaction with `addr`. Returns whether this one
+    /// makes it dead; later failures do not declare it again.
+    pub fn record_failure(&mut self, addr: SocketAddr) -> bool {
+        let count = self.counts.entry(addr).or_default();
+        *count += 1;
+        *count == self.threshold
+    }
+
+    /// `addr` answered, clear its failures.
+    pub fn record_success(&mut self, addr: SocketAddr) {
+        self.counts.remove(&addr);
+    }
+
+    /// Failures in a row of `addr`.
+    pub fn count(&self, addr: SocketAddr) -> u32 {
+        self.counts.get(&addr).copied().unwrap_or(0)
+    }
+
+    pub fn threshold(&self) -> u32 {
+        self.threshold
+    }
+}
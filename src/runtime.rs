@@ -0,0 +1,295 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Async runtime shared by the server and the CLI.
+//!
+//! Both run on a single-threaded tokio runtime: datagrams, timers and, in
+//! the CLI, lines typed at the prompt arrive as events of one loop, see
+//! [`next_event`], so maintenance keeps running while the REPL waits for
+//! input and the server sleeps until a datagram or a timer is due instead
+//! of polling.
+//!
+//! UDP sockets are read by tasks of the runtime waiting on a
+//! [`tokio::net::UdpSocket`], and stdin by a task reading
+//! [`tokio::io::stdin`], see [`spawn_udp_receiver`] and [`read_lines`].
+//! Stream transports multiplex their connections behind a blocking
+//! [`Transport`], so they keep a dedicated thread, see [`spawn_receiver`].
+//! Either way what is read reaches the loop over a channel.
+
+use std::{
+    future, io,
+    net::SocketAddr,
+    task::Poll,
+    thread::{self, JoinHandle},
+};
+
+use log::{error, warn};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, Interest},
+    net::UdpSocket,
+    runtime::{Builder, Runtime},
+    sync::mpsc,
+    task,
+    time::{Interval, MissedTickBehavior},
+};
+
+use crate::{
+    batch::RecvBatch, pool::BufferPool, protocol, transport::Transport,
+};
+
+/// Datagrams or lines queued between a reader thread and the runtime
+/// before the reader waits.
+pub const QUEUE_CAPACITY: usize = 1024;
+
+/// Build the runtime the server and the CLI run on.
+pub fn build() -> io::Result<Runtime> {
+    Builder::new_current_thread().enable_all().build()
+}
+
+/// An interval whose missed ticks are delayed rather than burst, so a
+/// handler that ran long is not called again right away.
+pub fn interval(period: std::time::Duration) -> Interval {
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval
+}
+
+/// A datagram received by [`spawn_receiver`].
+#[derive(Debug)]
+pub struct Datagram {
+    pub data: Vec<u8>,
+    pub from: SocketAddr,
+    /// Which receiver got it, as given to [`spawn_receiver`].
+    pub source: usize,
+}
+
+/// Spawn a task receiving datagrams from `transport`, up to `batch` at a
+/// time, whenever `socket` is readable, and sending them to `queue` tagged
+/// with `source`. `transport` must read the same nonblocking socket as
+/// `socket`, which is only used to wait; see [`Transport::recv_batch`].
+///
+/// Datagrams are handled like in [`spawn_receiver`]. The task ends once
+/// `queue` is closed or the transport fails. Must be called from within
+/// the runtime.
+pub fn spawn_udp_receiver<T>(
+    socket: UdpSocket,
+    transport: T,
+    source: usize,
+    batch: usize,
+    pool: BufferPool,
+    queue: mpsc::Sender<Datagram>,
+) -> task::JoinHandle<()>
+where
+    T: Transport + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut batch = RecvBatch::new(batch);
+        loop {
+            let received = match socket.readable().await {
+                Ok(()) => socket.try_io(Interest::READABLE, || {
+                    transport.recv_batch(&mut batch)
+                }),
+                Err(e) => Err(e),
+            };
+            match received {
+                Ok(_) => {}
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue;
+                }
+                Err(e) => {
+                    error!("Receiver {source} stopped: {e}");
+                    return;
+                }
+            }
+            for datagram in datagrams(&batch, source, &pool) {
+                if queue.send(datagram).await.is_err() {
+                    return;
+                }
+            }
+        }
+    })
+}
+
+/// Spawn a thread receiving datagrams from `transport`, which must be
+/// blocking, up to `batch` at a time, and sending them to `queue` tagged
+/// with `source`. See [`Transport::recv_batch`].
+///
+/// Datagrams are copied into buffers taken from `pool`, which the consumer
+/// should give back once done with them.
+///
+/// Datagrams longer than [`protocol::MAX_MESSAGE_SIZE`] are dropped. The
+/// thread exits once `queue` is closed or the transport fails.
+pub fn spawn_receiver<T>(
+    transport: T,
+    source: usize,
+    batch: usize,
+    pool: BufferPool,
+    queue: mpsc::Sender<Datagram>,
+) -> io::Result<JoinHandle<()>>
+where
+    T: Transport + Send + 'static,
+{
+    thread::Builder::new().name(format!("receiver-{source}")).spawn(
+        move || {
+            let mut batch = RecvBatch::new(batch);
+            loop {
+                match transport.recv_batch(&mut batch) {
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("Receiver {source} stopped: {e}");
+                        return;
+                    }
+                }
+                for datagram in datagrams(&batch, source, &pool) {
+                    if queue.blocking_send(datagram).is_err() {
+                        return;
+                    }
+                }
+            }
+        },
+    )
+}
+
+/// The datagrams of `batch` tagged with `source`, copied into buffers of
+/// `pool`, dropping those longer than [`protocol::MAX_MESSAGE_SIZE`].
+fn datagrams<'a>(
+    batch: &'a RecvBatch,
+    source: usize,
+    pool: &'a BufferPool,
+) -> impl Iterator<Item = Datagram> + 'a {
+    batch.iter().filter_map(move |(data, from)| {
+        if data.len() > protocol::MAX_MESSAGE_SIZE {
+            warn!(
+                "Dropping overlong datagram from {from} ({}+ bytes)",
+                data.len()
+            );
+            return None;
+        }
+        Some(Datagram { data: pool.copy(data), from, source })
+    })
+}
+
+/// Spawn a task reading lines from `reader` and sending them, newline
+/// included, to the returned queue, which closes at the end of input.
+/// Must be called from within the runtime.
+///
+/// [`tokio::io::stdin`] is read by a blocking thread that can not be
+/// cancelled, so a runtime reading it should be shut down with
+/// [`Runtime::shutdown_background`].
+pub fn read_lines<R>(reader: R) -> mpsc::Receiver<String>
+where
+    R: AsyncBufRead + Unpin + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+    tokio::spawn(async move {
+        let mut lines = reader.lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if tx.send(line + "\n").await.is_err() {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+/// What [`next_event`] waited for.
+#[derive(Debug)]
+pub enum Event<T> {
+    /// An item from the queue, `None` once it is closed and empty.
+    Received(Option<T>),
+    /// The interval ticked.
+    Tick,
+}
+
+/// Wait for the next item of `queue` or the next tick of `interval`,
+/// whichever comes first. A due tick wins, so a busy queue can not starve
+/// the timer.
+pub async fn next_event<T>(
+    queue: &mut mpsc::Receiver<T>,
+    interval: &mut Interval,
+) -> Event<T> {
+    future::poll_fn(|cx| {
+        if interval.poll_tick(cx).is_ready() {
+            return Poll::Ready(Event::Tick);
+        }
+        queue.poll_recv(cx).map(Event::Received)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::transport::UdpTransport;
+
+    #[test]
+    fn udp_receiver_forwards_datagrams() {
+        build().unwrap().block_on(async {
+            let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket.set_nonblocking(true).unwrap();
+            let addr = socket.local_addr().unwrap();
+            let transport =
+                UdpTransport::from_socket(socket.try_clone().unwrap());
+            let socket = UdpSocket::from_std(socket).unwrap();
+            let (queue, mut datagrams) = mpsc::channel(QUEUE_CAPACITY);
+            spawn_udp_receiver(
+                socket,
+                transport,
+                7,
+                4,
+                BufferPool::new(4),
+                queue,
+            );
+
+            let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            for data in [b"one", b"two"] {
+                sender.send_to(data, addr).unwrap();
+            }
+
+            for expected in [&b"one"[..], b"two"] {
+                let datagram = tokio::time::timeout(
+                    Duration::from_secs(2),
+                    datagrams.recv(),
+                )
+                .await
+                .unwrap()
+                .unwrap();
+                assert_eq!(datagram.data, expected);
+                assert_eq!(datagram.from, sender.local_addr().unwrap());
+                assert_eq!(datagram.source, 7);
+            }
+        });
+    }
+
+    #[test]
+    fn lines_are_read_until_the_end_of_input() {
+        build().unwrap().block_on(async {
+            let mut lines = read_lines(&b"/put a 1\n/get a"[..]);
+            assert_eq!(lines.recv().await.as_deref(), Some("/put a 1\n"));
+            assert_eq!(lines.recv().await.as_deref(), Some("/get a\n"));
+            assert_eq!(lines.recv().await, None);
+        });
+    }
+}
@@ -0,0 +1,2251 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Rendezvous server.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{self, Arguments},
+    fs, io,
+    net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bincode::error::EncodeError;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::{
+    access::AccessList,
+    batch::{DEFAULT_BATCH_SIZE, Outbox},
+    dedup::DedupCache,
+    fingerprint::peer_fingerprint,
+    fragment::{DEFAULT_MAX_PAYLOAD, FragmentingTransport},
+    peers::{PeerObserver, PeerTable},
+    pool::BufferPool,
+    protocol::{
+        self, NetworkId, PeerInfo, RendezvousMessage, RendezvousStats,
+    },
+    relay::{RelayQueues, SessionStats},
+    runtime::{self, Datagram, Event},
+    tcp::{FallbackTransport, TcpTransport},
+    transport::{
+        MeteredTransport, SocketOptions, TrafficCounters, Transport,
+        UdpTransport,
+    },
+    watchdog::Watchdog,
+    websocket::WebSocketTransport,
+};
+
+/// How often expired peers are pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the access list file is checked for changes.
+const ACCESS_LIST_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often [`RendezvousServer::serve`] runs housekeeping without being
+/// woken by a datagram.
+pub const HOUSEKEEPING_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Receiver tags of the main and the secondary port.
+const PRIMARY: usize = 0;
+const SECONDARY: usize = 1;
+
+/// Default listening address, serving IPv4 and IPv6 where sockets are
+/// dual-stack, see [`SocketOptions::dual_stack`].
+pub const DEFAULT_BIND_ADDR: &str = "[::]:8000";
+
+/// Listening address used instead of [`DEFAULT_BIND_ADDR`] on hosts
+/// without IPv6.
+const IPV4_BIND_ADDR: &str = "0.0.0.0:8000";
+
+/// Format used for the per-request access log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Free-form debug messages through the logger.
+    Human,
+    /// One JSON object per request on stdout.
+    Json,
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Human => write!(f, "human"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format: {s}")),
+        }
+    }
+}
+
+/// A single access log entry.
+///
+/// `ts` is the number of milliseconds since the UNIX epoch.
+#[derive(Debug, Serialize)]
+struct AccessRecord<'a> {
+    ts: u64,
+    src: SocketAddr,
+    #[serde(rename = "type")]
+    kind: &'a str,
+    peer_id: &'a str,
+    result: &'a str,
+}
+
+impl<'a> AccessRecord<'a> {
+    fn new(
+        src: SocketAddr,
+        kind: &'a str,
+        peer_id: &'a str,
+        result: &'a str,
+    ) -> Self {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+
+        AccessRecord { ts, src, kind, peer_id, result }
+    }
+}
+
+/// Server configuration.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Address to listen on, [`DEFAULT_BIND_ADDR`] by default.
+    pub bind_addr: String,
+    /// Port listened on instead of the one of `bind_addr`.
+    pub port: Option<u16>,
+    /// Second address answering [`RendezvousMessage::WhatIsMyAddr`], so
+    /// clients can compare the mappings their NAT creates towards two
+    /// ports. `None` binds only `bind_addr`.
+    pub secondary_bind_addr: Option<String>,
+    /// Also accept TCP connections on the port of `bind_addr`, for peers
+    /// whose network blocks UDP, see [`crate::tcp`].
+    pub tcp: bool,
+    /// Address accepting WebSocket connections, for browsers and peers
+    /// behind proxies letting only HTTP through, see [`crate::websocket`].
+    /// `None` accepts none.
+    pub websocket: Option<String>,
+    pub socket: SocketOptions,
+    /// Largest datagram sent in one piece. Larger replies are cut into
+    /// fragments, see [`crate::fragment`].
+    pub max_payload: usize,
+    /// Datagrams received or sent per system call where batching is
+    /// supported, see [`crate::batch`]. One disables batching.
+    pub batch_size: usize,
+    /// Number of unknown target ids remembered by the negative cache.
+    /// Zero disables the cache.
+    pub negative_cache_capacity: usize,
+    /// How long a negative cache entry stays valid.
+    pub negative_cache_ttl: Duration,
+    pub log_format: LogFormat,
+    /// Forward [`RendezvousMessage::Relay`] payloads between peers.
+    pub relay: bool,
+    /// Maximum bytes queued for a single relay destination. Packets beyond
+    /// this are dropped.
+    pub relay_queue_limit: usize,
+    /// Maximum bytes queued for a single sender and receiver pair, so one
+    /// fast sender can not fill a destination's queue on its own.
+    pub relay_session_limit: usize,
+    /// Drop peers not seen for this long. `None` keeps them forever.
+    pub peer_ttl: Option<Duration>,
+    /// Maximum number of registered peers. When full, the least recently
+    /// seen peer is evicted. `None` means unbounded.
+    pub max_peers: Option<usize>,
+    /// Sibling servers a new peer is redirected to when `max_peers` is
+    /// reached. When empty the least recently seen peer is evicted
+    /// instead.
+    pub peer_servers: Vec<SocketAddr>,
+    /// Number of recent requests remembered for deduplication. Zero
+    /// disables deduplication.
+    pub dedup_capacity: usize,
+    /// How long a request is remembered for deduplication.
+    pub dedup_window: Duration,
+    /// Log an error when the receive loop makes no progress for this
+    /// long. `None` disables the watchdog.
+    pub watchdog: Option<Duration>,
+    /// File holding the peer allowlist and blacklist, see
+    /// [`crate::access`]. Reloaded whenever it changes.
+    pub access_list: Option<PathBuf>,
+    /// Network served. Peers registering from another one are refused.
+    pub network: NetworkId,
+}
+
+impl ServerConfig {
+    /// Names of the settings accepted by [`Self::set`].
+    pub const KEYS: &[&str] = &[
+        "bind",
+        "port",
+        "secondary_bind",
+        "tcp",
+        "websocket",
+        "recv_buffer",
+        "send_buffer",
+        "reuse_address",
+        "reuse_port",
+        "ttl",
+        "max_payload",
+        "batch_size",
+        "negative_cache",
+        "negative_cache_ttl",
+        "log_format",
+        "peer_ttl",
+        "max_peers",
+        "peer_servers",
+        "relay",
+        "relay_queue_limit",
+        "relay_session_limit",
+        "dedup_capacity",
+        "dedup_window",
+        "watchdog",
+        "access_list",
+        "network",
+    ];
+
+    /// Settings that act as switches on the command line.
+    pub const SWITCHES: &[&str] = &["tcp", "relay", "reuse_port"];
+
+    /// Set a setting from its textual value. Durations are in seconds and
+    /// optional settings accept `none`.
+    pub fn set(
+        &mut self,
+        key: &str,
+        value: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        fn secs(value: &str) -> Result<Duration, std::num::ParseIntError> {
+            value.parse().map(Duration::from_secs)
+        }
+
+        fn optional<T, E>(
+            value: &str,
+            parse: impl Fn(&str) -> Result<T, E>,
+        ) -> Result<Option<T>, E> {
+            if value == "none" { Ok(None) } else { parse(value).map(Some) }
+        }
+
+        match key {
+            "bind" => self.bind_addr = value.to_string(),
+            "port" => self.port = optional(value, str::parse)?,
+            "secondary_bind" => {
+                self.secondary_bind_addr =
+                    optional(value, |v| Ok::<_, String>(v.to_string()))?;
+            }
+            "tcp" => self.tcp = value.parse()?,
+            "websocket" => {
+                self.websocket =
+                    optional(value, |v| Ok::<_, String>(v.to_string()))?;
+            }
+            "recv_buffer" => {
+                self.socket.recv_buffer_size = optional(value, str::parse)?;
+            }
+            "send_buffer" => {
+                self.socket.send_buffer_size = optional(value, str::parse)?;
+            }
+            "reuse_address" => self.socket.reuse_address = value.parse()?,
+            "reuse_port" => self.socket.reuse_port = value.parse()?,
+            "ttl" => self.socket.ttl = optional(value, str::parse)?,
+            "max_payload" => self.max_payload = value.parse()?,
+            "batch_size" => self.batch_size = value.parse()?,
+            "negative_cache" => {
+                self.negative_cache_capacity = value.parse()?
+            }
+            "negative_cache_ttl" => self.negative_cache_ttl = secs(value)?,
+            "log_format" => self.log_format = value.parse()?,
+            "peer_ttl" => self.peer_ttl = optional(value, secs)?,
+            "max_peers" => self.max_peers = optional(value, str::parse)?,
+            "peer_servers" => {
+                self.peer_servers = optional(value, |v| {
+                    v.split(',').map(|a| a.trim().parse()).collect()
+                })?
+                .unwrap_or_default();
+            }
+            "relay" => self.relay = value.parse()?,
+            "relay_queue_limit" => self.relay_queue_limit = value.parse()?,
+            "relay_session_limit" => {
+                self.relay_session_limit = value.parse()?
+            }
+            "dedup_capacity" => self.dedup_capacity = value.parse()?,
+            "dedup_window" => self.dedup_window = secs(value)?,
+            "watchdog" => self.watchdog = optional(value, secs)?,
+            "access_list" => {
+                self.access_list =
+                    optional(value, |v| Ok::<_, String>(PathBuf::from(v)))?;
+            }
+            "network" => self.network = value.parse()?,
+            _ => return Err(format!("unknown setting '{key}'").into()),
+        }
+
+        Ok(())
+    }
+
+    /// Textual value of a setting, in the format accepted by [`Self::set`].
+    pub fn get(&self, key: &str) -> Option<String> {
+        fn optional<T: ToString>(value: Option<T>) -> String {
+            value.map_or_else(|| "none".to_string(), |v| v.to_string())
+        }
+
+        let value = match key {
+            "bind" => self.bind_addr.clone(),
+            "port" => optional(self.port),
+            "secondary_bind" => optional(self.secondary_bind_addr.as_ref()),
+            "tcp" => self.tcp.to_string(),
+            "websocket" => optional(self.websocket.as_ref()),
+            "recv_buffer" => optional(self.socket.recv_buffer_size),
+            "send_buffer" => optional(self.socket.send_buffer_size),
+            "reuse_address" => self.socket.reuse_address.to_string(),
+            "reuse_port" => self.socket.reuse_port.to_string(),
+            "ttl" => optional(self.socket.ttl),
+            "max_payload" => self.max_payload.to_string(),
+            "batch_size" => self.batch_size.to_string(),
+            "negative_cache" => self.negative_cache_capacity.to_string(),
+            "negative_cache_ttl" => {
+                self.negative_cache_ttl.as_secs().to_string()
+            }
+            "log_format" => self.log_format.to_string(),
+            "peer_ttl" => optional(self.peer_ttl.map(|d| d.as_secs())),
+            "max_peers" => optional(self.max_peers),
+            "peer_servers" => {
+                optional((!self.peer_servers.is_empty()).then(|| {
+                    let addrs: Vec<String> = self
+                        .peer_servers
+                        .iter()
+                        .map(SocketAddr::to_string)
+                        .collect();
+                    addrs.join(",")
+                }))
+            }
+            "relay" => self.relay.to_string(),
+            "relay_queue_limit" => self.relay_queue_limit.to_string(),
+            "relay_session_limit" => self.relay_session_limit.to_string(),
+            "dedup_capacity" => self.dedup_capacity.to_string(),
+            "dedup_window" => self.dedup_window.as_secs().to_string(),
+            "watchdog" => optional(self.watchdog.map(|d| d.as_secs())),
+            "access_list" => {
+                optional(self.access_list.as_ref().map(|p| p.display()))
+            }
+            "network" => self.network.to_string(),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_addr: DEFAULT_BIND_ADDR.to_string(),
+            port: None,
+            secondary_bind_addr: None,
+            tcp: false,
+            websocket: None,
+            socket: SocketOptions::default(),
+            max_payload: DEFAULT_MAX_PAYLOAD,
+            batch_size: DEFAULT_BATCH_SIZE,
+            negative_cache_capacity: 0,
+            negative_cache_ttl: Duration::from_secs(5),
+            log_format: LogFormat::Human,
+            relay: false,
+            relay_queue_limit: 256 * 1024,
+            relay_session_limit: 64 * 1024,
+            peer_ttl: None,
+            max_peers: None,
+            peer_servers: Vec::new(),
+            dedup_capacity: 4096,
+            dedup_window: Duration::from_secs(10),
+            watchdog: None,
+            access_list: None,
+            network: NetworkId::MAIN,
+        }
+    }
+}
+
+/// NegativeCache
+///
+/// Remembers target ids that were recently queried but not registered, so
+/// repeated queries for a nonexistent peer are answered without touching the
+/// peer map. Entries expire after a fixed TTL and the oldest entry is evicted
+/// when the cache is full.
+pub struct NegativeCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<String, Instant>,
+    order: VecDeque<String>,
+    hits: u64,
+    misses: u64,
+}
+
+impl NegativeCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        NegativeCache {
+            capacity,
+            ttl,
+            entries: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns true when `peer_id` is known to be absent.
+    pub fn contains(&mut self, peer_id: &str) -> bool {
+        let fresh = match self.entries.get(peer_id) {
+            Some(inserted) => inserted.elapsed() < self.ttl,
+            None => false,
+        };
+
+        if fresh {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+
+        fresh
+    }
+
+    /// Remember that `peer_id` was not found.
+    pub fn insert(&mut self, peer_id: String) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.insert(peer_id.clone(), Instant::now()).is_some() {
+            // Refreshed entry, move it to the back of the eviction order.
+            self.order.retain(|id| id != &peer_id);
+        }
+        self.order.push_back(peer_id);
+
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    /// Forget `peer_id`, e.g. because it has just registered.
+    pub fn remove(&mut self, peer_id: &str) {
+        if self.entries.remove(peer_id).is_some() {
+            self.order.retain(|id| id != peer_id);
+        }
+    }
+
+    /// Lookups answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Lookups made, answered from the cache or not.
+    pub fn lookups(&self) -> u64 {
+        self.hits + self.misses
+    }
+
+    /// Fraction of lookups answered from the cache.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 { 0.0 } else { self.hits as f64 / total as f64 }
+    }
+}
+
+/// RendezvousServer
+///
+/// A rendezvous protocol is a computer network protocol that enables resources
+/// or P2P network peers to find each other. A rendezvous protocol uses a
+/// handshaking model, unlike an eager protocol which directly copies the data
+pub struct RendezvousServer<T: Transport = FallbackTransport> {
+    transport: MeteredTransport<FragmentingTransport<T>>,
+    /// Replies waiting to be sent in one batch.
+    outbox: Outbox,
+    /// Buffers of the datagrams received and sent.
+    pool: BufferPool,
+    batch_size: usize,
+    /// Second port, only answering [`RendezvousMessage::WhatIsMyAddr`].
+    secondary: Option<MeteredTransport<T>>,
+    traffic: Arc<TrafficCounters>,
+    /// Byte totals at the start of the current one second window.
+    window: (Instant, u64, u64),
+    /// Bytes received and sent during the last full window.
+    rate: (u64, u64),
+    peers: PeerTable,
+    /// Where new peers are sent once the peer table is full.
+    peer_servers: Vec<SocketAddr>,
+    last_prune: Instant,
+    negative_cache: Option<NegativeCache>,
+    log_format: LogFormat,
+    relay: Option<RelayQueues>,
+    /// Relay requests dropped because their hop budget was spent.
+    hops_exhausted: u64,
+    dedup: DedupCache,
+    watchdog: Option<Duration>,
+    /// Generation of this server instance, see [`Self::epoch`].
+    epoch: u64,
+    access: AccessList,
+    access_file: Option<AccessFile>,
+    network: NetworkId,
+}
+
+/// The file an [`AccessList`] is loaded from.
+struct AccessFile {
+    path: PathBuf,
+    /// Modification time of the loaded version.
+    modified: Option<SystemTime>,
+    /// When the file was last checked for changes, `None` before the first
+    /// load.
+    checked_at: Option<Instant>,
+}
+
+impl RendezvousServer<FallbackTransport> {
+    pub fn new(bind_addr: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_config(ServerConfig {
+            bind_addr: bind_addr.to_string(),
+            ..ServerConfig::default()
+        })
+    }
+
+    pub fn with_config(
+        config: ServerConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let bind = |addr: &str| {
+            UdpTransport::bind_with(
+                with_port(addr, config.port)?,
+                &config.socket,
+            )
+        };
+        let transport = match bind(&config.bind_addr) {
+            Err(e) if config.bind_addr == DEFAULT_BIND_ADDR => {
+                warn!("No IPv6 ({e}), listening on IPv4 only");
+                bind(IPV4_BIND_ADDR)?
+            }
+            result => result?,
+        };
+        transport.socket().set_nonblocking(true)?;
+
+        info!("Server Rendezvous Listening on {}", transport.local_addr()?);
+
+        // The OS may clamp the requested sizes, so report what we got.
+        let (recv_buffer, send_buffer) = transport.buffer_sizes()?;
+        info!(
+            "Socket buffers: receive {} bytes (requested {}), send {} bytes \
+             (requested {})",
+            recv_buffer,
+            requested(config.socket.recv_buffer_size),
+            send_buffer,
+            requested(config.socket.send_buffer_size)
+        );
+
+        // Same port as UDP, even when the OS picked it.
+        let tcp = if config.tcp {
+            let tcp = TcpTransport::bind(transport.local_addr()?)?;
+            info!("Accepting TCP connections on {}", tcp.local_addr()?);
+            Some(tcp)
+        } else {
+            None
+        };
+        let mut transport = FallbackTransport::new(transport, tcp);
+        if let Some(addr) = &config.websocket {
+            let websocket = WebSocketTransport::bind(addr)?;
+            info!(
+                "Accepting WebSocket connections on {}",
+                websocket.local_addr()?
+            );
+            transport = transport.with_websocket(websocket);
+        }
+
+        let secondary = match &config.secondary_bind_addr {
+            Some(addr) => {
+                let secondary = UdpTransport::bind_with(addr, &config.socket)?;
+                secondary.socket().set_nonblocking(true)?;
+                info!("Answering address queries on {addr} too");
+                Some(FallbackTransport::new(secondary, None))
+            }
+            None => None,
+        };
+
+        let mut server = Self::with_transport(transport, config);
+        // Refuse to start with a broken access list rather than running
+        // without one.
+        server.reload_access_list()?;
+        Ok(match secondary {
+            Some(secondary) => server.with_secondary(secondary),
+            None => server,
+        })
+    }
+}
+
+impl RendezvousServer<FallbackTransport> {
+    /// Serve on a new [`runtime`] until an error stops the server.
+    pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        runtime::build()?.block_on(self.serve())
+    }
+
+    /// Serve on the current runtime until an error stops the server.
+    ///
+    /// Receiver tasks wait on both ports and receiver threads read the TCP
+    /// and WebSocket connections, so the server sleeps until a datagram
+    /// arrives or housekeeping is due every [`HOUSEKEEPING_INTERVAL`]. The
+    /// connections are switched to blocking mode,
+    /// [`RendezvousServer::poll`] must not be used afterwards.
+    ///
+    /// Datagrams received and replies sent live in buffers of a shared
+    /// [`BufferPool`], so once warmed up they are not allocated again.
+    pub async fn serve(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let (queue, mut datagrams) = mpsc::channel(runtime::QUEUE_CAPACITY);
+        let (socket, receiver) =
+            self.receiver(self.transport.inner().inner())?;
+        runtime::spawn_udp_receiver(
+            socket,
+            receiver,
+            PRIMARY,
+            self.batch_size,
+            self.pool.clone(),
+            queue.clone(),
+        );
+        if let Some(secondary) = &self.secondary {
+            let (socket, receiver) = self.receiver(secondary.inner())?;
+            runtime::spawn_udp_receiver(
+                socket,
+                receiver,
+                SECONDARY,
+                self.batch_size,
+                self.pool.clone(),
+                queue.clone(),
+            );
+        }
+        if let Some(tcp) = self.transport.inner().inner().tcp() {
+            // Answered like datagrams on the main port, replies go back
+            // over the connection they came from.
+            let mut tcp = tcp.clone();
+            tcp.set_nonblocking(false);
+            runtime::spawn_receiver(
+                MeteredTransport::new(tcp, Arc::clone(&self.traffic)),
+                PRIMARY,
+                1,
+                self.pool.clone(),
+                queue.clone(),
+            )?;
+        }
+        if let Some(websocket) = self.transport.inner().inner().websocket() {
+            let mut websocket = websocket.clone();
+            websocket.set_nonblocking(false);
+            runtime::spawn_receiver(
+                MeteredTransport::new(websocket, Arc::clone(&self.traffic)),
+                PRIMARY,
+                1,
+                self.pool.clone(),
+                queue.clone(),
+            )?;
+        }
+        drop(queue);
+
+        let watchdog = self.watchdog.map(|threshold| {
+            info!("Watchdog enabled: threshold {threshold:?}");
+            Watchdog::spawn("Receive loop", threshold)
+        });
+        let mut housekeeping = runtime::interval(HOUSEKEEPING_INTERVAL);
+
+        loop {
+            if let Some(watchdog) = &watchdog {
+                watchdog.beat();
+            }
+            match runtime::next_event(&mut datagrams, &mut housekeeping).await
+            {
+                Event::Received(Some(datagram)) => {
+                    self.handle_received(datagram);
+                    // Answer what is already queued before sending the
+                    // replies in one batch.
+                    while self.outbox.len() < self.batch_size
+                        && let Ok(datagram) = datagrams.try_recv()
+                    {
+                        self.handle_received(datagram);
+                    }
+                    self.outbox.flush(&self.transport, self.batch_size);
+                    if let Some(relay) = self.relay.as_mut() {
+                        relay.flush(&self.transport);
+                    }
+                }
+                Event::Received(None) => {
+                    return Err("every receiver stopped".into());
+                }
+                Event::Tick => self.housekeeping(),
+            }
+        }
+    }
+
+    /// Handle a datagram from a receiver thread and give its buffer back.
+    /// A datagram that can not be answered is logged and dropped.
+    fn handle_received(&mut self, datagram: Datagram) {
+        let result = if datagram.source == SECONDARY {
+            self.handle_secondary(&datagram.data, datagram.from)
+        } else {
+            self.handle_datagram(&datagram.data, datagram.from)
+        };
+        if let Err(e) = result {
+            warn!("Dropping datagram from {}: {}", datagram.from, e);
+        }
+        self.pool.put(datagram.data);
+    }
+
+    /// Handles on the UDP socket of `transport` for a receiver task: one
+    /// registered with the runtime to wait on, and one to read with,
+    /// counting into the same traffic counters.
+    fn receiver(
+        &self,
+        transport: &FallbackTransport,
+    ) -> io::Result<(tokio::net::UdpSocket, MeteredTransport<UdpTransport>)>
+    {
+        let socket = transport.udp().socket();
+        socket.set_nonblocking(true)?;
+        Ok((
+            tokio::net::UdpSocket::from_std(socket.try_clone()?)?,
+            MeteredTransport::new(
+                UdpTransport::from_socket(socket.try_clone()?),
+                Arc::clone(&self.traffic),
+            ),
+        ))
+    }
+}
+
+/// `addr` with its port replaced by `port`, if any.
+fn with_port(addr: &str, port: Option<u16>) -> io::Result<SocketAddr> {
+    let mut addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "no address to bind")
+    })?;
+    if let Some(port) = port {
+        addr.set_port(port);
+    }
+    Ok(addr)
+}
+
+/// Requested socket buffer size for the startup log.
+fn requested(size: Option<usize>) -> String {
+    size.map_or_else(|| "default".to_string(), |size| size.to_string())
+}
+
+impl<T: Transport> RendezvousServer<T> {
+    /// Create a server on top of an already bound, nonblocking transport.
+    ///
+    /// `config.bind_addr` is ignored.
+    pub fn with_transport(transport: T, config: ServerConfig) -> Self {
+        let negative_cache = (config.negative_cache_capacity > 0).then(|| {
+            info!(
+                "Negative cache enabled: capacity={}, ttl={:?}",
+                config.negative_cache_capacity, config.negative_cache_ttl
+            );
+            NegativeCache::new(
+                config.negative_cache_capacity,
+                config.negative_cache_ttl,
+            )
+        });
+
+        let pool = BufferPool::default();
+        let relay = config.relay.then(|| {
+            info!(
+                "Relay enabled: queue limit {} bytes per destination, {} \
+                 bytes per session",
+                config.relay_queue_limit, config.relay_session_limit
+            );
+            RelayQueues::new(
+                config.relay_queue_limit,
+                config.relay_session_limit,
+            )
+            .with_pool(pool.clone())
+        });
+
+        let traffic = Arc::new(TrafficCounters::default());
+
+        RendezvousServer {
+            transport: MeteredTransport::new(
+                FragmentingTransport::new(transport, config.max_payload),
+                Arc::clone(&traffic),
+            ),
+            outbox: Outbox::with_pool(pool.clone()),
+            pool,
+            batch_size: config.batch_size.max(1),
+            secondary: None,
+            traffic,
+            window: (Instant::now(), 0, 0),
+            rate: (0, 0),
+            peers: PeerTable::new(config.peer_ttl, config.max_peers),
+            peer_servers: config.peer_servers,
+            last_prune: Instant::now(),
+            negative_cache,
+            log_format: config.log_format,
+            relay,
+            hops_exhausted: 0,
+            dedup: DedupCache::new(config.dedup_capacity, config.dedup_window),
+            watchdog: config.watchdog,
+            // Start time in milliseconds, so a restarted server always
+            // gets a new, larger epoch.
+            epoch: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+            access: AccessList::new(),
+            access_file: config.access_list.map(|path| AccessFile {
+                path,
+                modified: None,
+                checked_at: None,
+            }),
+            network: config.network,
+        }
+    }
+
+    /// Replace the access list. A configured access list file still
+    /// replaces it again the next time the file changes.
+    pub fn set_access_list(&mut self, access: AccessList) {
+        self.access = access;
+    }
+
+    /// Load the configured access list file if it changed since the last
+    /// load. Returns whether it was loaded; on error the current list is
+    /// kept.
+    pub fn reload_access_list(&mut self) -> io::Result<bool> {
+        let Some(file) = self.access_file.as_mut() else {
+            return Ok(false);
+        };
+        file.checked_at = Some(Instant::now());
+
+        let modified = fs::metadata(&file.path)?.modified().ok();
+        if modified.is_some() && modified == file.modified {
+            return Ok(false);
+        }
+
+        self.access = AccessList::load(&file.path)?;
+        file.modified = modified;
+        info!("Loaded access list from {}", file.path.display());
+        Ok(true)
+    }
+
+    /// Also answer [`RendezvousMessage::WhatIsMyAddr`] on `transport`,
+    /// an already bound, nonblocking transport on a second port. Every
+    /// other message arriving there is dropped.
+    pub fn with_secondary(mut self, transport: T) -> Self {
+        self.secondary =
+            Some(MeteredTransport::new(transport, Arc::clone(&self.traffic)));
+        self
+    }
+
+    /// Register a callback invoked whenever a peer is pruned or evicted.
+    pub fn add_peer_observer(&mut self, observer: PeerObserver) {
+        self.peers.add_observer(observer);
+    }
+
+    /// Address the main port is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.transport.local_addr()
+    }
+
+    /// Generation of this server instance, sent in every
+    /// [`RendezvousMessage::HeartbeatAck`]. Changes on every start.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Current counters, including the bandwidth used and the hits of the
+    /// negative cache.
+    pub fn stats(&self) -> RendezvousStats {
+        let cache = self.negative_cache.as_ref();
+        RendezvousStats {
+            peers: self.peers.len() as u64,
+            bytes_in: self.traffic.bytes_in(),
+            bytes_out: self.traffic.bytes_out(),
+            bytes_in_per_sec: self.rate.0,
+            bytes_out_per_sec: self.rate.1,
+            negative_cache_hits: cache.map_or(0, NegativeCache::hits),
+            negative_cache_lookups: cache.map_or(0, NegativeCache::lookups),
+        }
+    }
+
+    /// Close the bandwidth window once a second has passed, keeping its
+    /// byte counts as the current per second rate.
+    fn roll_window(&mut self) {
+        let (start, bytes_in, bytes_out) = self.window;
+        let elapsed = start.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return;
+        }
+
+        let (now_in, now_out) =
+            (self.traffic.bytes_in(), self.traffic.bytes_out());
+        let secs = elapsed.as_secs_f64();
+        self.rate = (
+            ((now_in - bytes_in) as f64 / secs) as u64,
+            ((now_out - bytes_out) as f64 / secs) as u64,
+        );
+        self.window = (Instant::now(), now_in, now_out);
+    }
+
+    /// Number of relayed packets dropped because their session or
+    /// destination was over its limit, or `None` when relaying is disabled.
+    pub fn relay_dropped(&self) -> Option<u64> {
+        self.relay.as_ref().map(RelayQueues::dropped)
+    }
+
+    /// Bytes forwarded on behalf of other peers since the server started.
+    pub fn bytes_relayed(&self) -> u64 {
+        self.traffic.bytes_relayed()
+    }
+
+    /// Counters of every relay session as `(from, to, stats)`, empty when
+    /// relaying is disabled.
+    pub fn relay_sessions(
+        &self,
+    ) -> Vec<(SocketAddr, SocketAddr, SessionStats)> {
+        self.relay
+            .as_ref()
+            .map(|relay| relay.sessions().collect())
+            .unwrap_or_default()
+    }
+
+    /// Number of relay requests dropped because their hop budget was
+    /// spent.
+    pub fn hops_exhausted(&self) -> u64 {
+        self.hops_exhausted
+    }
+
+    /// Handle every datagram currently queued on the transport, send the
+    /// replies, flush queued relay packets and return how many datagrams
+    /// were received.
+    ///
+    /// This is how a server over a nonblocking transport other than UDP is
+    /// driven; [`RendezvousServer::run`] waits for datagrams instead.
+    pub fn poll(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut buf = [0u8; protocol::RECV_BUFFER_SIZE];
+        let mut received = 0;
+
+        loop {
+            match self.transport.recv_from(&mut buf) {
+                Ok((len, peer_addr)) if len > protocol::MAX_MESSAGE_SIZE => {
+                    received += 1;
+                    warn!(
+                        "Dropping overlong datagram from {} ({}+ bytes)",
+                        peer_addr, len
+                    );
+                }
+                Ok((len, peer_addr)) => {
+                    received += 1;
+                    if let Err(e) =
+                        self.handle_datagram(&buf[..len], peer_addr)
+                    {
+                        warn!("Dropping datagram from {}: {}", peer_addr, e);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    error!("Erro: {}", e);
+                    break;
+                }
+            }
+        }
+
+        received += self.poll_secondary(&mut buf)?;
+        self.outbox.flush(&self.transport, self.batch_size);
+        self.housekeeping();
+        Ok(received)
+    }
+
+    /// Encode `msg` into a pooled buffer and queue it for `to`.
+    fn reply(
+        &mut self,
+        msg: &RendezvousMessage,
+        to: SocketAddr,
+    ) -> Result<(), EncodeError> {
+        let mut buf = self.outbox.buffer();
+        protocol::encode_into(self.network, msg, &mut buf)?;
+        self.outbox.push(buf, to);
+        Ok(())
+    }
+
+    /// Handle a datagram received on the main port.
+    fn handle_datagram(
+        &mut self,
+        data: &[u8],
+        from: SocketAddr,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Receivers hand over fragments as they arrive.
+        let Some(data) = self.transport.inner().reassemble(data, from) else {
+            return Ok(());
+        };
+        match protocol::decode(&data) {
+            Ok((network, msg)) if network == self.network => {
+                self.handle_message(msg, from)
+            }
+            Ok((network, msg)) => self.reject(network, msg, from),
+            Err(e) => {
+                let Some(reply) = e.version_reply(self.network) else {
+                    return Ok(());
+                };
+                self.reply(&reply, from)?;
+                self.log_access(
+                    AccessRecord::new(from, "message", "", "bad_version"),
+                    Some(format_args!("Answering {from}: {e}")),
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Flush queued relay packets and run the periodic upkeep: bandwidth
+    /// window, access list reload and peer pruning.
+    fn housekeeping(&mut self) {
+        if let Some(relay) = self.relay.as_mut() {
+            relay.flush(&self.transport);
+        }
+
+        self.roll_window();
+
+        let check_access = self.access_file.as_ref().is_some_and(|file| {
+            file.checked_at
+                .is_none_or(|at| at.elapsed() >= ACCESS_LIST_CHECK_INTERVAL)
+        });
+        if check_access && let Err(e) = self.reload_access_list() {
+            error!("Failed to reload access list, keeping the old one: {e}");
+        }
+
+        if self.last_prune.elapsed() >= PRUNE_INTERVAL {
+            self.last_prune = Instant::now();
+            let pruned = self.peers.prune(SystemTime::now());
+            if pruned > 0 {
+                debug!("Pruned {} expired peer(s)", pruned);
+            }
+            if let Some(relay) = self.relay.as_mut() {
+                relay.expire_sessions(Instant::now());
+            }
+        }
+    }
+
+    /// Answer the address queries queued on the secondary port and return
+    /// how many datagrams were received there.
+    fn poll_secondary(
+        &mut self,
+        buf: &mut [u8],
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut received = 0;
+
+        while let Some(secondary) = &self.secondary {
+            let (len, from) = match secondary.recv_from(buf) {
+                Ok(datagram) => datagram,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    error!("Erro: {}", e);
+                    break;
+                }
+            };
+            received += 1;
+            if let Err(e) = self.handle_secondary(&buf[..len], from) {
+                warn!("Dropping datagram from {}: {}", from, e);
+            }
+        }
+
+        Ok(received)
+    }
+
+    /// Answer a datagram received on the secondary port if it is an
+    /// address query.
+    fn handle_secondary(
+        &mut self,
+        data: &[u8],
+        from: SocketAddr,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(secondary) = &self.secondary else {
+            return Ok(());
+        };
+
+        let record = match protocol::decode(data) {
+            Ok((network, RendezvousMessage::WhatIsMyAddr))
+                if network == self.network =>
+            {
+                let reply = RendezvousMessage::YourAddr { addr: from };
+                let mut buf = self.pool.take();
+                protocol::encode_into(self.network, &reply, &mut buf)?;
+                let sent = secondary.send_to(&buf, from);
+                self.pool.put(buf);
+                sent?;
+                AccessRecord::new(
+                    from,
+                    "what_is_my_addr",
+                    "",
+                    "sent_secondary",
+                )
+            }
+            _ => AccessRecord::new(from, "secondary", "", "ignored"),
+        };
+        self.log_access(record, None);
+        Ok(())
+    }
+
+    /// Handle `msg` sent from the foreign `network`: answer registrations
+    /// with [`RendezvousMessage::WrongNetwork`] and drop everything else.
+    fn reject(
+        &mut self,
+        network: NetworkId,
+        msg: RendezvousMessage,
+        from: SocketAddr,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let RendezvousMessage::Register { peer_id, .. } = msg else {
+            self.log_access(
+                AccessRecord::new(from, "foreign", "", "ignored"),
+                Some(format_args!(
+                    "Dropping a message from {from} of network {network}"
+                )),
+            );
+            return Ok(());
+        };
+
+        let reply = RendezvousMessage::WrongNetwork { network: self.network };
+        self.reply(&reply, from)?;
+        self.log_access(
+            AccessRecord::new(from, "register", &peer_id, "wrong_network"),
+            Some(format_args!(
+                "Refusing peer {peer_id} from {from}: network {network}, not \
+                 {}",
+                self.network
+            )),
+        );
+        Ok(())
+    }
+
+    fn handle_message(
+        &mut self,
+        msg: RendezvousMessage,
+        from: SocketAddr,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let requester = match &msg {
+            RendezvousMessage::Register { peer_id, .. } => {
+                Some(("register", Some(peer_id.clone())))
+            }
+            RendezvousMessage::InitiateConnection { from_peer_id, .. } => {
+                Some(("initiate", Some(from_peer_id.clone())))
+            }
+            // Finding who sent a query means scanning every peer, so only
+            // do it when the list has peer entries to match.
+            RendezvousMessage::Query { .. } => Some((
+                "query",
+                self.access
+                    .names_peers()
+                    .then(|| {
+                        self.peers
+                            .values()
+                            .find(|peer| peer.public_addr == from)
+                            .map(|peer| peer.peer_id.clone())
+                    })
+                    .flatten(),
+            )),
+            _ => None,
+        };
+        if let Some((kind, peer_id)) = requester
+            && let Err(denial) =
+                self.access.check(from.ip(), peer_id.as_deref())
+        {
+            let peer_id = peer_id.unwrap_or_default();
+            self.log_access(
+                AccessRecord::new(from, kind, &peer_id, "denied"),
+                Some(format_args!(
+                    "Dropping {kind} from {from} (peer '{peer_id}'): {denial}"
+                )),
+            );
+            return Ok(());
+        }
+
+        if let Some(nonce) = msg.nonce()
+            && let Some(replies) = self.dedup.replies(from, nonce)
+        {
+            for reply in &replies {
+                self.outbox.push(reply.clone(), from);
+            }
+            self.log_access(
+                AccessRecord::new(from, "duplicate", "", "replayed"),
+                Some(format_args!(
+                    "Duplicate request from {} (nonce {}), {} reply(ies) re-sent",
+                    from,
+                    nonce,
+                    replies.len()
+                )),
+            );
+            return Ok(());
+        }
+
+        match msg {
+            RendezvousMessage::Register { nonce, peer_id, .. }
+                if self.peers.is_full()
+                    && !self.peer_servers.is_empty()
+                    && self.peers.get(&peer_id).is_none() =>
+            {
+                let reply = protocol::encode(
+                    self.network,
+                    &RendezvousMessage::Redirect {
+                        addresses: self.peer_servers.clone(),
+                    },
+                )?;
+                self.outbox.push(reply.clone(), from);
+
+                self.log_access(
+                    AccessRecord::new(
+                        from,
+                        "register",
+                        &peer_id,
+                        "redirected",
+                    ),
+                    Some(format_args!(
+                        "Server full, redirecting peer {} to {} sibling(s)",
+                        peer_id,
+                        self.peer_servers.len()
+                    )),
+                );
+
+                self.dedup.record(from, nonce, vec![reply]);
+            }
+
+            RendezvousMessage::Register {
+                nonce,
+                peer_id,
+                private_addr,
+                mut capabilities,
+                mut candidates,
+            } => {
+                self.log_access(
+                    AccessRecord::new(
+                        from,
+                        "register",
+                        &peer_id,
+                        "registered",
+                    ),
+                    Some(format_args!(
+                        "Peer {} ({}) registrado: público={}, privado={}",
+                        peer_id,
+                        peer_fingerprint(&peer_id),
+                        from,
+                        private_addr
+                    )),
+                );
+
+                if let Some(cache) = self.negative_cache.as_mut() {
+                    cache.remove(&peer_id);
+                }
+
+                candidates.retain(|addr| {
+                    *addr != from && !addr.ip().is_unspecified()
+                });
+                candidates.dedup();
+                candidates.truncate(protocol::MAX_CANDIDATES);
+                capabilities
+                    .retain(|tag| tag.len() <= protocol::MAX_CAPABILITY_LEN);
+                capabilities.dedup();
+                capabilities.truncate(protocol::MAX_CAPABILITIES);
+
+                self.peers.insert(PeerInfo {
+                    peer_id,
+                    public_addr: from, // Address stun
+                    private_addr: Some(private_addr),
+                    last_seen: SystemTime::now(),
+                    capabilities,
+                    candidates,
+                });
+
+                self.dedup.record(from, nonce, Vec::new());
+            }
+
+            RendezvousMessage::Query { target_peer_id } => {
+                let cached_hit_rate =
+                    self.negative_cache.as_mut().and_then(|cache| {
+                        cache
+                            .contains(&target_peer_id)
+                            .then(|| cache.hit_rate())
+                    });
+
+                if let Some(hit_rate) = cached_hit_rate {
+                    self.log_access(
+                        AccessRecord::new(
+                            from,
+                            "query",
+                            &target_peer_id,
+                            "not_found_cached",
+                        ),
+                        Some(format_args!(
+                            "Peer {} not found (cached, hit rate {:.2})",
+                            target_peer_id, hit_rate
+                        )),
+                    );
+                    return Ok(());
+                }
+
+                if let Some(peer_info) = self.peers.get(&target_peer_id) {
+                    let response = RendezvousMessage::PeerInfo {
+                        peer: peer_info.clone(),
+                    };
+
+                    self.reply(&response, from)?;
+
+                    self.log_access(
+                        AccessRecord::new(
+                            from,
+                            "query",
+                            &target_peer_id,
+                            "found",
+                        ),
+                        None,
+                    );
+                } else {
+                    self.log_access(
+                        AccessRecord::new(
+                            from,
+                            "query",
+                            &target_peer_id,
+                            "not_found",
+                        ),
+                        None,
+                    );
+
+                    if let Some(cache) = self.negative_cache.as_mut() {
+                        cache.insert(target_peer_id);
+                    }
+                }
+            }
+
+            RendezvousMessage::InitiateConnection {
+                nonce,
+                from_peer_id,
+                to_peer_id,
+            } => {
+                let mut replies = Vec::new();
+
+                // Notify peers
+                if let (Some(from_peer), Some(to_peer)) = (
+                    self.peers.get(&from_peer_id),
+                    self.peers.get(&to_peer_id),
+                ) {
+                    // Send info from B to A
+                    let msg_to_a =
+                        RendezvousMessage::PeerInfo { peer: to_peer.clone() };
+                    let reply = protocol::encode(self.network, &msg_to_a)?;
+                    self.outbox.push(reply.clone(), from_peer.public_addr);
+                    if from_peer.public_addr == from {
+                        replies.push(reply);
+                    }
+
+                    // Send info from A to B
+                    let msg_to_b = RendezvousMessage::PeerInfo {
+                        peer: from_peer.clone(),
+                    };
+                    let mut reply = self.outbox.buffer();
+                    protocol::encode_into(
+                        self.network,
+                        &msg_to_b,
+                        &mut reply,
+                    )?;
+                    self.outbox.push(reply, to_peer.public_addr);
+
+                    self.log_access(
+                        AccessRecord::new(
+                            from,
+                            "initiate_connection",
+                            &from_peer_id,
+                            "initiated",
+                        ),
+                        Some(format_args!(
+                            "Iniciando hole punching: {} <-> {}",
+                            from_peer_id, to_peer_id
+                        )),
+                    );
+
+                    self.dedup.record(from, nonce, replies);
+                } else {
+                    self.log_access(
+                        AccessRecord::new(
+                            from,
+                            "initiate_connection",
+                            &from_peer_id,
+                            "unknown_peer",
+                        ),
+                        None,
+                    );
+                }
+            }
+
+            RendezvousMessage::ListPeers { capabilities } => {
+                // Stop before the peer that would no longer fit, so the
+                // list can be decoded.
+                let mut budget = protocol::PEER_LIST_BUDGET;
+                let peers: Vec<PeerInfo> = self
+                    .peers
+                    .values()
+                    .filter(|p| p.has_capabilities(&capabilities))
+                    .take(protocol::MAX_PEER_LIST)
+                    .take_while(|p| {
+                        budget
+                            .checked_sub(p.decoded_size())
+                            .map(|left| budget = left)
+                            .is_some()
+                    })
+                    .cloned()
+                    .collect();
+
+                let response = RendezvousMessage::PeerList { peers };
+                self.reply(&response, from)?;
+
+                self.log_access(
+                    AccessRecord::new(
+                        from,
+                        "list_peers",
+                        &capabilities.join(","),
+                        "listed",
+                    ),
+                    None,
+                );
+            }
+
+            RendezvousMessage::PeerInfo { peer } => {
+                self.log_access(
+                    AccessRecord::new(
+                        from,
+                        "peer_info",
+                        &peer.peer_id,
+                        "ignored",
+                    ),
+                    None,
+                );
+            }
+
+            RendezvousMessage::Relay { to_peer_id, payload, hops } => {
+                let result = self.relay(from, &to_peer_id, payload, hops)?;
+                self.log_access(
+                    AccessRecord::new(from, "relay", &to_peer_id, result),
+                    None,
+                );
+            }
+
+            RendezvousMessage::PeerList { .. } => {
+                self.log_access(
+                    AccessRecord::new(from, "peer_list", "", "ignored"),
+                    None,
+                );
+            }
+
+            RendezvousMessage::Relayed { from_peer_id, .. } => {
+                self.log_access(
+                    AccessRecord::new(
+                        from,
+                        "relayed",
+                        &from_peer_id,
+                        "ignored",
+                    ),
+                    None,
+                );
+            }
+
+            RendezvousMessage::Heartbeat { peer_id } => {
+                // Only the address the peer registered from may keep its
+                // registration alive.
+                let known = self
+                    .peers
+                    .get(&peer_id)
+                    .is_some_and(|peer| peer.public_addr == from)
+                    && self.peers.touch(&peer_id, SystemTime::now());
+
+                let ack =
+                    RendezvousMessage::HeartbeatAck { epoch: self.epoch };
+                self.reply(&ack, from)?;
+
+                self.log_access(
+                    AccessRecord::new(
+                        from,
+                        "heartbeat",
+                        &peer_id,
+                        if known { "acked" } else { "unknown_peer" },
+                    ),
+                    None,
+                );
+            }
+
+            RendezvousMessage::GetStats => {
+                let reply = RendezvousMessage::Stats { stats: self.stats() };
+                self.reply(&reply, from)?;
+
+                self.log_access(
+                    AccessRecord::new(from, "get_stats", "", "sent"),
+                    None,
+                );
+            }
+
+            RendezvousMessage::WhatIsMyAddr => {
+                let reply = RendezvousMessage::YourAddr { addr: from };
+                self.reply(&reply, from)?;
+
+                self.log_access(
+                    AccessRecord::new(from, "what_is_my_addr", "", "sent"),
+                    None,
+                );
+            }
+
+            RendezvousMessage::YourAddr { .. } => {
+                self.log_access(
+                    AccessRecord::new(from, "your_addr", "", "ignored"),
+                    None,
+                );
+            }
+
+            RendezvousMessage::Redirect { .. } => {
+                self.log_access(
+                    AccessRecord::new(from, "redirect", "", "ignored"),
+                    None,
+                );
+            }
+
+            RendezvousMessage::Stats { .. } => {
+                self.log_access(
+                    AccessRecord::new(from, "stats", "", "ignored"),
+                    None,
+                );
+            }
+
+            RendezvousMessage::HeartbeatAck { .. } => {
+                self.log_access(
+                    AccessRecord::new(from, "heartbeat_ack", "", "ignored"),
+                    None,
+                );
+            }
+
+            RendezvousMessage::Probe { from_peer_id, .. } => {
+                self.log_access(
+                    AccessRecord::new(from, "probe", &from_peer_id, "ignored"),
+                    None,
+                );
+            }
+
+            RendezvousMessage::WrongNetwork { .. } => {
+                self.log_access(
+                    AccessRecord::new(from, "wrong_network", "", "ignored"),
+                    None,
+                );
+            }
+
+            RendezvousMessage::Hello { nonce, min_version, max_version } => {
+                let reply =
+                    protocol::answer_hello(nonce, min_version, max_version);
+                let result = match reply {
+                    RendezvousMessage::HelloAck { .. } => "acked",
+                    _ => "no_common_version",
+                };
+                self.reply(&reply, from)?;
+
+                self.log_access(
+                    AccessRecord::new(from, "hello", "", result),
+                    None,
+                );
+            }
+
+            RendezvousMessage::HelloAck { .. } => {
+                self.log_access(
+                    AccessRecord::new(from, "hello_ack", "", "ignored"),
+                    None,
+                );
+            }
+
+            RendezvousMessage::UnsupportedVersion { .. } => {
+                self.log_access(
+                    AccessRecord::new(
+                        from,
+                        "unsupported_version",
+                        "",
+                        "ignored",
+                    ),
+                    None,
+                );
+            }
+
+            // Fragments are reassembled once, not a fragment carried in
+            // fragments.
+            RendezvousMessage::Fragment { .. } => {
+                self.log_access(
+                    AccessRecord::new(from, "fragment", "", "ignored"),
+                    None,
+                );
+            }
+
+            // DHT traffic is exchanged between nodes, servers take no
+            // part in it.
+            RendezvousMessage::FindNode { .. }
+            | RendezvousMessage::Nodes { .. }
+            | RendezvousMessage::Store { .. }
+            | RendezvousMessage::Stored { .. }
+            | RendezvousMessage::FindValue { .. }
+            | RendezvousMessage::Found { .. }
+            | RendezvousMessage::Ping { .. }
+            | RendezvousMessage::Pong { .. }
+            | RendezvousMessage::Cache { .. }
+            | RendezvousMessage::Leave { .. }
+            | RendezvousMessage::SyncDigest { .. }
+            | RendezvousMessage::Digests { .. }
+            | RendezvousMessage::SyncKeys { .. }
+            | RendezvousMessage::Keys { .. } => {
+                self.log_access(
+                    AccessRecord::new(from, "dht", "", "ignored"),
+                    None,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Queue `payload` from the peer registered at `from` for delivery to
+    /// `to_peer_id`, spending one of its `hops`. Returns the access log
+    /// result.
+    fn relay(
+        &mut self,
+        from: SocketAddr,
+        to_peer_id: &str,
+        payload: Vec<u8>,
+        hops: u8,
+    ) -> Result<&'static str, Box<dyn std::error::Error>> {
+        let Some(relay) = self.relay.as_mut() else {
+            return Ok("relay_disabled");
+        };
+
+        let Some(hops) = hops.checked_sub(1) else {
+            self.hops_exhausted += 1;
+            warn!(
+                "Dropping relay from {} to {} with no hops left ({} dropped)",
+                from, to_peer_id, self.hops_exhausted
+            );
+            return Ok("hops_exhausted");
+        };
+
+        // Only registered peers may relay, and only to registered peers,
+        // so the server can not be pointed at arbitrary addresses.
+        let Some(sender) = self.peers.values().find(|p| p.public_addr == from)
+        else {
+            return Ok("unregistered");
+        };
+        let Some(receiver) = self.peers.get(to_peer_id) else {
+            return Ok("unknown_peer");
+        };
+
+        let relayed = RendezvousMessage::Relayed {
+            from_peer_id: sender.peer_id.clone(),
+            payload,
+            hops,
+        };
+        let mut packet = self.pool.take();
+        if protocol::encode_into(self.network, &relayed, &mut packet).is_err()
+        {
+            self.pool.put(packet);
+            return Ok("too_large");
+        }
+
+        if relay.push(from, receiver.public_addr, packet) {
+            Ok("queued")
+        } else {
+            warn!(
+                "Relay from {} to {} is over its limit, dropping packet \
+                 ({} dropped)",
+                from,
+                to_peer_id,
+                relay.dropped()
+            );
+            Ok("dropped")
+        }
+    }
+
+    /// Log a handled request using the configured access log format.
+    ///
+    /// In human format only the optional free-form `message` is logged, at
+    /// debug level. In JSON format every request is written to stdout as a
+    /// single JSON object per line.
+    fn log_access(&self, record: AccessRecord, message: Option<Arguments>) {
+        match self.log_format {
+            LogFormat::Human => {
+                if let Some(message) = message {
+                    debug!("{message}");
+                }
+            }
+            LogFormat::Json => match serde_json::to_string(&record) {
+                Ok(line) => println!("{line}"),
+                Err(e) => error!("Failed to encode access log: {}", e),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use super::*;
+    use crate::client::RendezvousClient;
+    use crate::transport::{MockNetwork, MockTransport};
+
+    fn server() -> RendezvousServer<FallbackTransport> {
+        RendezvousServer::new("127.0.0.1:0").unwrap()
+    }
+
+    fn client() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        socket
+    }
+
+    fn send(
+        socket: &UdpSocket,
+        server: &RendezvousServer<FallbackTransport>,
+        msg: &RendezvousMessage,
+    ) {
+        let data = protocol::encode(NetworkId::default(), msg).unwrap();
+        socket.send_to(&data, server.local_addr().unwrap()).unwrap();
+    }
+
+    /// Poll `server` until it received a datagram.
+    fn poll(server: &mut RendezvousServer<FallbackTransport>) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while server.poll().unwrap() == 0 {
+            assert!(Instant::now() < deadline, "no datagram received");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn register(
+        nonce: u64,
+        peer_id: &str,
+        capabilities: Vec<String>,
+    ) -> RendezvousMessage {
+        RendezvousMessage::Register {
+            nonce,
+            peer_id: peer_id.to_string(),
+            private_addr: "10.0.0.1:4000".parse().unwrap(),
+            capabilities,
+            candidates: Vec::new(),
+        }
+    }
+
+    /// A server on `network` at 10.0.0.254:7000.
+    fn mock_server(
+        network: &MockNetwork,
+        config: ServerConfig,
+    ) -> RendezvousServer<MockTransport> {
+        let addr = "10.0.0.254:7000".parse().unwrap();
+        RendezvousServer::with_transport(network.bind(addr).unwrap(), config)
+    }
+
+    /// Send `msg` from `transport` to `server`.
+    fn send_mock(
+        transport: &MockTransport,
+        server: &RendezvousServer<MockTransport>,
+        msg: &RendezvousMessage,
+    ) {
+        let data = protocol::encode(NetworkId::default(), msg).unwrap();
+        transport.send_to(&data, server.local_addr().unwrap()).unwrap();
+    }
+
+    /// Every message waiting at `transport`.
+    fn received(transport: &MockTransport) -> Vec<RendezvousMessage> {
+        let mut buf = [0u8; protocol::RECV_BUFFER_SIZE];
+        let mut messages = Vec::new();
+        while let Ok((len, _)) = transport.recv_from(&mut buf) {
+            messages.push(protocol::decode(&buf[..len]).unwrap().1);
+        }
+        messages
+    }
+
+    #[test]
+    fn duplicate_request_takes_effect_once() {
+        let network = MockNetwork::new();
+        let mut server = mock_server(&network, ServerConfig::default());
+        let bind = |addr: &str| network.bind(addr.parse().unwrap()).unwrap();
+        let (alice, bob) = (bind("10.0.0.1:4000"), bind("10.0.0.2:4000"));
+        send_mock(&alice, &server, &register(1, "alice", Vec::new()));
+        send_mock(&bob, &server, &register(1, "bob", Vec::new()));
+        server.poll().unwrap();
+        received(&alice);
+        received(&bob);
+
+        let initiate = RendezvousMessage::InitiateConnection {
+            nonce: 7,
+            from_peer_id: "alice".to_string(),
+            to_peer_id: "bob".to_string(),
+        };
+        send_mock(&alice, &server, &initiate);
+        send_mock(&alice, &server, &initiate);
+        assert_eq!(server.poll().unwrap(), 2);
+
+        // Bob is introduced once, alice gets her reply again.
+        let introduced = |messages: Vec<RendezvousMessage>, peer_id: &str| {
+            messages
+                .iter()
+                .filter(|msg| {
+                    matches!(msg, RendezvousMessage::PeerInfo { peer }
+                        if peer.peer_id == peer_id)
+                })
+                .count()
+        };
+        assert_eq!(introduced(received(&bob), "alice"), 1);
+        assert_eq!(introduced(received(&alice), "bob"), 2);
+
+        // A new nonce is a new request.
+        let again = RendezvousMessage::InitiateConnection {
+            nonce: 8,
+            from_peer_id: "alice".to_string(),
+            to_peer_id: "bob".to_string(),
+        };
+        send_mock(&alice, &server, &again);
+        server.poll().unwrap();
+        assert_eq!(introduced(received(&bob), "alice"), 1);
+    }
+
+    #[test]
+    fn peers_find_each_other_over_the_mock_transport() {
+        let network = MockNetwork::new();
+        let mut server = mock_server(&network, ServerConfig::default());
+        let server_addr = server.local_addr().unwrap();
+        let client = |addr: &str, peer_id: &str| {
+            let transport = network.bind(addr.parse().unwrap()).unwrap();
+            RendezvousClient::new(transport, server_addr, peer_id.to_string())
+        };
+        let mut alice = client("10.0.0.1:4000", "alice");
+        let mut bob = client("10.0.0.2:4000", "bob");
+
+        alice.register("192.168.1.1:4000".parse().unwrap()).unwrap();
+        bob.register("192.168.1.2:4000".parse().unwrap()).unwrap();
+        while server.poll().unwrap() > 0 {}
+        while alice.recv().unwrap().is_some() {}
+        while bob.recv().unwrap().is_some() {}
+
+        bob.query("alice").unwrap();
+        assert!(server.poll().unwrap() > 0);
+        let peer = loop {
+            match bob.recv().unwrap() {
+                Some(RendezvousMessage::PeerInfo { peer }) => break peer,
+                Some(_) => continue,
+                None => panic!("no answer to the query"),
+            }
+        };
+        assert_eq!(peer.peer_id, "alice");
+        assert_eq!(
+            peer.public_addr,
+            "10.0.0.1:4000".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            peer.private_addr,
+            Some("192.168.1.1:4000".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn sequential_servers_bind_the_same_port() {
+        let mut first = server();
+        let addr = first.local_addr().unwrap();
+        let socket = client();
+        send(&socket, &first, &RendezvousMessage::Ping { nonce: 1 });
+        poll(&mut first);
+        drop(first);
+
+        let second = RendezvousServer::new(&addr.to_string()).unwrap();
+        assert_eq!(second.local_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn overlong_datagram_is_rejected() {
+        let network = MockNetwork::new();
+        let mut server = mock_server(&network, ServerConfig::default());
+        let server_addr = server.local_addr().unwrap();
+        let client = network.bind("10.0.0.1:4000".parse().unwrap()).unwrap();
+
+        let overlong = vec![0; protocol::MAX_MESSAGE_SIZE + 1];
+        client.send_to(&overlong, server_addr).unwrap();
+        let hello = RendezvousMessage::Hello {
+            nonce: 1,
+            min_version: protocol::MIN_PROTOCOL_VERSION,
+            max_version: protocol::PROTOCOL_VERSION,
+        };
+        let data = protocol::encode(NetworkId::default(), &hello).unwrap();
+        client.send_to(&data, server_addr).unwrap();
+        assert_eq!(server.poll().unwrap(), 2);
+
+        // Only the hello is answered, and the server is still serving.
+        let mut buf = [0u8; protocol::RECV_BUFFER_SIZE];
+        let (len, _) = client.recv_from(&mut buf).unwrap();
+        let (_, reply) = protocol::decode(&buf[..len]).unwrap();
+        assert!(matches!(reply, RendezvousMessage::HelloAck { nonce: 1, .. }));
+        assert!(client.recv_from(&mut buf).is_err());
+    }
+
+    #[test]
+    fn negative_cache_counts_hits() {
+        let mut cache = NegativeCache::new(4, Duration::from_secs(60));
+        assert!(!cache.contains("ghost"));
+        cache.insert("ghost".to_string());
+        assert!(cache.contains("ghost"));
+        assert!(cache.contains("ghost"));
+        assert!(!cache.contains("other"));
+
+        assert_eq!(cache.hits(), 2);
+        assert_eq!(cache.lookups(), 4);
+        assert_eq!(cache.hit_rate(), 0.5);
+    }
+
+    #[test]
+    fn negative_cache_evicts_oldest() {
+        let mut cache = NegativeCache::new(2, Duration::from_secs(60));
+        cache.insert("a".to_string());
+        cache.insert("b".to_string());
+        cache.insert("a".to_string());
+        cache.insert("c".to_string());
+
+        assert!(cache.contains("a"));
+        assert!(!cache.contains("b"));
+        assert!(cache.contains("c"));
+    }
+
+    #[test]
+    fn negative_cache_expires_entries() {
+        let mut cache = NegativeCache::new(4, Duration::from_millis(20));
+        cache.insert("ghost".to_string());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!cache.contains("ghost"));
+    }
+
+    #[test]
+    fn cached_queries_are_reported_in_stats() {
+        let mut server = RendezvousServer::with_config(ServerConfig {
+            bind_addr: "127.0.0.1:0".to_string(),
+            negative_cache_capacity: 8,
+            negative_cache_ttl: Duration::from_secs(60),
+            ..ServerConfig::default()
+        })
+        .unwrap();
+        let socket = client();
+        let query =
+            RendezvousMessage::Query { target_peer_id: "ghost".to_string() };
+        for _ in 0..3 {
+            send(&socket, &server, &query);
+            poll(&mut server);
+        }
+
+        let stats = server.stats();
+        assert_eq!(stats.negative_cache_hits, 2);
+        assert_eq!(stats.negative_cache_lookups, 3);
+
+        // Registering forgets the cached miss.
+        send(&socket, &server, &register(1, "ghost", Vec::new()));
+        poll(&mut server);
+        send(&socket, &server, &query);
+        poll(&mut server);
+        let mut buf = [0u8; protocol::RECV_BUFFER_SIZE];
+        let (len, _) = socket.recv_from(&mut buf).unwrap();
+        assert!(matches!(
+            protocol::decode(&buf[..len]),
+            Ok((_, RendezvousMessage::PeerInfo { .. }))
+        ));
+        assert_eq!(server.stats().negative_cache_hits, 2);
+    }
+
+    #[test]
+    fn register_bounds_capabilities() {
+        let mut server = server();
+        let socket = client();
+        let mut capabilities: Vec<String> =
+            (0..100).map(|i| format!("tag{i}")).collect();
+        capabilities.insert(0, "x".repeat(protocol::MAX_CAPABILITY_LEN + 1));
+
+        send(&socket, &server, &register(1, "alice", capabilities));
+        poll(&mut server);
+
+        let peer = server.peers.get("alice").unwrap();
+        assert_eq!(peer.capabilities.len(), protocol::MAX_CAPABILITIES);
+        assert_eq!(peer.capabilities[0], "tag0");
+    }
+
+    #[test]
+    fn peer_list_fits_a_datagram() {
+        let mut server = server();
+        let socket = client();
+        let tag = |i: usize| {
+            format!("{i:0>width$}", width = protocol::MAX_CAPABILITY_LEN)
+        };
+        for n in 0..protocol::MAX_PEER_LIST {
+            let capabilities =
+                (0..protocol::MAX_CAPABILITIES).map(tag).collect();
+            send(
+                &socket,
+                &server,
+                &register(n as u64, &format!("peer{n}"), capabilities),
+            );
+            poll(&mut server);
+        }
+        assert_eq!(server.peers.len(), protocol::MAX_PEER_LIST);
+
+        send(
+            &socket,
+            &server,
+            &RendezvousMessage::ListPeers { capabilities: vec![tag(0)] },
+        );
+        poll(&mut server);
+
+        // Replies larger than a fragment arrive in pieces.
+        let socket = FragmentingTransport::new(
+            UdpTransport::from_socket(socket),
+            DEFAULT_MAX_PAYLOAD,
+        );
+        let mut buf = [0u8; protocol::RECV_BUFFER_SIZE];
+        let (len, _) = socket.recv_from(&mut buf).unwrap();
+        assert!(len <= protocol::MAX_MESSAGE_SIZE);
+        let Ok((_, RendezvousMessage::PeerList { peers })) =
+            protocol::decode(&buf[..len])
+        else {
+            panic!("expected a peer list");
+        };
+        assert!(!peers.is_empty());
+        assert!(peers.len() < protocol::MAX_PEER_LIST);
+    }
+
+    #[test]
+    fn both_ports_tell_the_client_its_source_address() {
+        let network = MockNetwork::new();
+        let secondary_addr: SocketAddr = "10.0.0.254:7001".parse().unwrap();
+        let mut server = mock_server(&network, ServerConfig::default())
+            .with_secondary(network.bind(secondary_addr).unwrap());
+        let main_addr = server.local_addr().unwrap();
+        let client = network.bind("10.0.0.1:4000".parse().unwrap()).unwrap();
+        let query = protocol::encode(
+            NetworkId::default(),
+            &RendezvousMessage::WhatIsMyAddr,
+        )
+        .unwrap();
+        client.send_to(&query, main_addr).unwrap();
+        client.send_to(&query, secondary_addr).unwrap();
+        server.poll().unwrap();
+
+        let mut buf = [0u8; protocol::RECV_BUFFER_SIZE];
+        let mut observed = Vec::new();
+        while let Ok((len, from)) = client.recv_from(&mut buf) {
+            let Ok((_, RendezvousMessage::YourAddr { addr })) =
+                protocol::decode(&buf[..len])
+            else {
+                panic!("unexpected reply from {from}");
+            };
+            observed.push((from, addr));
+        }
+        observed.sort();
+        let source = client.local_addr().unwrap();
+        assert_eq!(observed, [(main_addr, source), (secondary_addr, source)]);
+    }
+
+    #[test]
+    fn secondary_port_only_answers_address_queries() {
+        let network = MockNetwork::new();
+        let secondary_addr: SocketAddr = "10.0.0.254:7001".parse().unwrap();
+        let mut server = mock_server(&network, ServerConfig::default())
+            .with_secondary(network.bind(secondary_addr).unwrap());
+        let client = network.bind("10.0.0.1:4000".parse().unwrap()).unwrap();
+        let data = protocol::encode(
+            NetworkId::default(),
+            &register(1, "alice", Vec::new()),
+        )
+        .unwrap();
+        client.send_to(&data, secondary_addr).unwrap();
+        server.poll().unwrap();
+
+        assert!(received(&client).is_empty());
+        assert_eq!(server.stats().peers, 0);
+    }
+
+    /// Register `peer_id` from `addr` and return the number of peers
+    /// `server` knows afterwards.
+    fn register_from(
+        network: &MockNetwork,
+        server: &mut RendezvousServer<MockTransport>,
+        addr: &str,
+        peer_id: &str,
+    ) -> u64 {
+        let transport = network.bind(addr.parse().unwrap()).unwrap();
+        send_mock(&transport, server, &register(1, peer_id, Vec::new()));
+        server.poll().unwrap();
+        server.stats().peers
+    }
+
+    #[test]
+    fn blacklisted_source_is_dropped() {
+        let network = MockNetwork::new();
+        let mut server = mock_server(&network, ServerConfig::default());
+        server.set_access_list("deny ip 10.0.0.66".parse().unwrap());
+
+        let mut register = |addr, peer_id| {
+            register_from(&network, &mut server, addr, peer_id)
+        };
+        assert_eq!(register("10.0.0.66:4000", "mallory"), 0);
+        assert_eq!(register("10.0.0.1:4000", "alice"), 1);
+    }
+
+    #[test]
+    fn private_server_rejects_unknown_peers() {
+        let network = MockNetwork::new();
+        let mut server = mock_server(&network, ServerConfig::default());
+        server.set_access_list("allow peer alice".parse().unwrap());
+        assert_eq!(
+            register_from(&network, &mut server, "10.0.0.1:4000", "alice"),
+            1
+        );
+        assert_eq!(
+            register_from(&network, &mut server, "10.0.0.2:4000", "bob"),
+            1
+        );
+
+        // Unknown sources can not query the registered peers either.
+        let eve = network.bind("10.0.0.3:4000".parse().unwrap()).unwrap();
+        let query =
+            RendezvousMessage::Query { target_peer_id: "alice".to_string() };
+        send_mock(&eve, &server, &query);
+        server.poll().unwrap();
+        assert!(received(&eve).is_empty());
+
+        let alice = network.bind("10.0.0.1:4001".parse().unwrap()).unwrap();
+        send_mock(&alice, &server, &register(2, "alice", Vec::new()));
+        send_mock(&alice, &server, &query);
+        server.poll().unwrap();
+        assert!(matches!(
+            received(&alice).as_slice(),
+            [RendezvousMessage::PeerInfo { .. }]
+        ));
+    }
+
+    #[test]
+    fn access_list_file_is_reloaded() {
+        let path = std::env::temp_dir()
+            .join(format!("tesseras-access-{}", std::process::id()));
+        fs::write(&path, "deny peer bob\n").unwrap();
+        let network = MockNetwork::new();
+        let config = ServerConfig {
+            access_list: Some(path.clone()),
+            ..Default::default()
+        };
+        let mut server = mock_server(&network, config);
+
+        assert!(server.reload_access_list().unwrap());
+        assert!(!server.reload_access_list().unwrap());
+        assert_eq!(
+            register_from(&network, &mut server, "10.0.0.2:4000", "bob"),
+            0
+        );
+
+        // Forget the modification time, so the rewrite is noticed even
+        // within the file system's timestamp granularity.
+        fs::write(&path, "# nobody denied\n").unwrap();
+        server.access_file.as_mut().unwrap().modified = None;
+        assert!(server.reload_access_list().unwrap());
+        assert_eq!(
+            register_from(&network, &mut server, "10.0.0.2:4000", "bob"),
+            1
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn relay_with_one_hop_is_dropped_at_the_next_hop() {
+        let network = MockNetwork::new();
+        let config = ServerConfig { relay: true, ..Default::default() };
+        let mut server = mock_server(&network, config);
+        let bind = |addr: &str| network.bind(addr.parse().unwrap()).unwrap();
+        let (alice, bob, carol) = (
+            bind("10.0.0.1:4000"),
+            bind("10.0.0.2:4000"),
+            bind("10.0.0.3:4000"),
+        );
+        for (transport, peer_id) in
+            [(&alice, "alice"), (&bob, "bob"), (&carol, "carol")]
+        {
+            send_mock(transport, &server, &register(1, peer_id, Vec::new()));
+        }
+        server.poll().unwrap();
+
+        let relay = |to: &str, hops| RendezvousMessage::Relay {
+            to_peer_id: to.to_string(),
+            payload: b"hello".to_vec(),
+            hops,
+        };
+        send_mock(&alice, &server, &relay("bob", 1));
+        server.poll().unwrap();
+        let hops = match received(&bob).as_slice() {
+            [RendezvousMessage::Relayed { from_peer_id, payload, hops }] => {
+                assert_eq!(from_peer_id, "alice");
+                assert_eq!(payload, b"hello");
+                *hops
+            }
+            other => panic!("unexpected messages {other:?}"),
+        };
+        assert_eq!(hops, 0);
+        assert_eq!(server.hops_exhausted(), 0);
+
+        // Bob passes the message on with the hops it has left.
+        send_mock(&bob, &server, &relay("carol", hops));
+        server.poll().unwrap();
+        assert!(received(&carol).is_empty());
+        assert_eq!(server.hops_exhausted(), 1);
+    }
+
+    #[test]
+    fn fast_relay_sender_is_throttled() {
+        let network = MockNetwork::new();
+        let config = ServerConfig {
+            relay: true,
+            relay_session_limit: 4 * 1024,
+            ..Default::default()
+        };
+        let mut server = mock_server(&network, config);
+        let bind = |addr: &str| network.bind(addr.parse().unwrap()).unwrap();
+        let (alice, bob) = (bind("10.0.0.1:4000"), bind("10.0.0.2:4000"));
+        send_mock(&alice, &server, &register(1, "alice", Vec::new()));
+        send_mock(&bob, &server, &register(1, "bob", Vec::new()));
+        server.poll().unwrap();
+
+        // A burst far over the session limit arrives before the next
+        // flush.
+        let relay = RendezvousMessage::Relay {
+            to_peer_id: "bob".to_string(),
+            payload: vec![0; 1000],
+            hops: protocol::DEFAULT_HOPS,
+        };
+        for _ in 0..20 {
+            send_mock(&alice, &server, &relay);
+        }
+        server.poll().unwrap();
+
+        let delivered = received(&bob).len();
+        assert!(delivered > 0 && delivered < 20, "{delivered} delivered");
+        assert_eq!(server.relay_dropped(), Some(20 - delivered as u64));
+        let [(from, to, stats)] = server.relay_sessions()[..] else {
+            panic!("expected one session");
+        };
+        assert_eq!(
+            (from, to),
+            (alice.local_addr().unwrap(), bob.local_addr().unwrap())
+        );
+        assert_eq!(stats.in_flight, 0);
+        assert_eq!(stats.forwarded, delivered as u64);
+        assert!(server.bytes_relayed() <= 4 * 1024);
+    }
+}
@@ -0,0 +1,401 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! Node snapshots.
+//!
+//! A snapshot holds what a node needs to move to another host: its id, the
+//! nodes in its routing table and the live entries of its store. It is a
+//! single JSON document carrying a format version. Ids and binary values
+//! are hex encoded; tombstones are not kept.
+
+use std::{fmt, fs, io, path::Path, time::Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fingerprint::fingerprint,
+    node_id::NodeId,
+    routing::{Layout, RoutingTable},
+    store::{Store, Value},
+};
+
+/// Version of the snapshot format written by this code.
+pub const VERSION: u32 = 1;
+
+/// Entries read from the store per page while capturing.
+const PAGE_SIZE: usize = 256;
+
+/// Error returned when capturing, loading or restoring a snapshot.
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// The file is not a snapshot or is damaged.
+    Corrupt(String),
+    /// The snapshot was written in a format this code does not read.
+    Version(u32),
+    /// The node id does not match its fingerprint or the routing table.
+    NodeIdMismatch,
+    /// The store to restore into already holds entries.
+    NotEmpty,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{e}"),
+            SnapshotError::Corrupt(e) => write!(f, "corrupt snapshot: {e}"),
+            SnapshotError::Version(version) => write!(
+                f,
+                "unsupported snapshot version {version} (expected {VERSION})"
+            ),
+            SnapshotError::NodeIdMismatch => {
+                write!(f, "node id is inconsistent")
+            }
+            SnapshotError::NotEmpty => write!(f, "store is not empty"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+/// Snapshot
+///
+/// Identity, routing knowledge and data of a node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub node_id: [u8; 20],
+    /// Routing table nodes, closest buckets first.
+    pub routing: Vec<[u8; 20]>,
+    /// Store entries, sorted by key.
+    pub entries: Vec<(String, Value)>,
+}
+
+/// On-disk form of a [`Snapshot`].
+#[derive(Serialize, Deserialize)]
+struct Document {
+    version: u32,
+    node_id: String,
+    /// Fingerprint of `node_id`, guarding against edited ids.
+    fingerprint: String,
+    routing: Vec<String>,
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    utf8: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bytes: Option<String>,
+}
+
+impl Snapshot {
+    /// Take a snapshot of the node `node_id` with its routing `table`, if
+    /// it has one, and every live entry of `store`.
+    pub fn capture(
+        node_id: [u8; 20],
+        table: Option<&RoutingTable>,
+        store: &dyn Store,
+    ) -> Result<Self, SnapshotError> {
+        if table.is_some_and(|table| *table.local_id() != node_id) {
+            return Err(SnapshotError::NodeIdMismatch);
+        }
+
+        let mut entries = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = store.page(cursor.as_deref(), PAGE_SIZE);
+            entries.extend(page.entries);
+            match page.next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        Ok(Snapshot {
+            node_id,
+            routing: table
+                .map(|table| table.nodes().copied().collect())
+                .unwrap_or_default(),
+            entries,
+        })
+    }
+
+    /// Write the snapshot to `path`, replacing it atomically.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let document = Document {
+            version: VERSION,
+            node_id: encode_hex(&self.node_id),
+            fingerprint: fingerprint(&self.node_id),
+            routing: self.routing.iter().map(|id| encode_hex(id)).collect(),
+            entries: self
+                .entries
+                .iter()
+                .map(|(key, value)| {
+                    let (utf8, bytes) = match value {
+                        Value::Utf8(s) => (Some(s.clone()), None),
+                        Value::Bytes(b) => (None, Some(encode_hex(b))),
+                    };
+                    Entry { key: key.clone(), utf8, bytes }
+                })
+                .collect(),
+        };
+        let contents = serde_json::to_string_pretty(&document)
+            .map_err(io::Error::other)?;
+
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Read and validate the snapshot at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let contents = fs::read_to_string(path)?;
+        let document: Document = serde_json::from_str(&contents)
+            .map_err(|e| SnapshotError::Corrupt(e.to_string()))?;
+        if document.version != VERSION {
+            return Err(SnapshotError::Version(document.version));
+        }
+
+        let node_id = decode_id(&document.node_id)?;
+        if fingerprint(&node_id) != document.fingerprint {
+            return Err(SnapshotError::NodeIdMismatch);
+        }
+
+        let routing = document
+            .routing
+            .iter()
+            .map(|id| decode_id(id))
+            .collect::<Result<Vec<_>, _>>()?;
+        if routing.contains(&node_id) {
+            return Err(SnapshotError::NodeIdMismatch);
+        }
+
+        let entries = document
+            .entries
+            .into_iter()
+            .map(|entry| {
+                let value = match (entry.utf8, entry.bytes) {
+                    (Some(s), None) => Value::Utf8(s),
+                    (None, Some(hex)) => {
+                        Value::Bytes(decode_hex(&hex).ok_or_else(|| {
+                            SnapshotError::Corrupt(format!(
+                                "bad value for key '{}'",
+                                entry.key
+                            ))
+                        })?)
+                    }
+                    _ => {
+                        return Err(SnapshotError::Corrupt(format!(
+                            "key '{}' needs exactly one value",
+                            entry.key
+                        )));
+                    }
+                };
+                Ok((entry.key, value))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Snapshot { node_id, routing, entries })
+    }
+
+    /// A routing table for the snapshot's node holding its routing nodes,
+    /// laid out as `layout`.
+    pub fn routing_table(&self, layout: Layout) -> RoutingTable {
+        let now = Instant::now();
+        let mut table = RoutingTable::with_layout(self.node_id, layout);
+        for id in &self.routing {
+            table.insert(*id, now);
+        }
+        table
+    }
+
+    /// Put every entry into `store`, which must be empty.
+    pub fn restore(&self, store: &mut dyn Store) -> Result<(), SnapshotError> {
+        if !store.is_empty() {
+            return Err(SnapshotError::NotEmpty);
+        }
+
+        for (key, value) in &self.entries {
+            store.put(key.clone(), value.clone());
+        }
+        Ok(())
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn decode_id(s: &str) -> Result<[u8; 20], SnapshotError> {
+    s.parse::<NodeId>()
+        .map(NodeId::into)
+        .map_err(|_| SnapshotError::Corrupt(format!("bad node id: {s}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::store::MemoryStore;
+
+    const NODE: [u8; 20] = [0x11; 20];
+
+    /// A file in the temporary directory unique to this process.
+    fn temp(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("tesseras-{}-{name}.json", std::process::id()))
+    }
+
+    fn populated() -> (RoutingTable, MemoryStore) {
+        let mut table = RoutingTable::new(NODE);
+        for byte in [0x80, 0x40, 0x20, 0x12] {
+            table.insert([byte; 20], Instant::now());
+        }
+        let mut store = MemoryStore::new();
+        store.put("text".to_string(), Value::Utf8("hello".to_string()));
+        store.put("bytes".to_string(), Value::Bytes(vec![0, 1, 0xff]));
+        store.set_tombstone_mode(true);
+        store.put("gone".to_string(), Value::Utf8("x".to_string()));
+        store.delete("gone", std::time::SystemTime::now());
+        (table, store)
+    }
+
+    #[test]
+    fn snapshot_round_trips_into_an_empty_node() {
+        let (table, store) = populated();
+        let path = temp("round-trip");
+        let snapshot = Snapshot::capture(NODE, Some(&table), &store).unwrap();
+        snapshot.save(&path).unwrap();
+
+        let loaded = Snapshot::load(&path).unwrap();
+        assert_eq!(loaded, snapshot);
+
+        let mut restored = MemoryStore::new();
+        loaded.restore(&mut restored).unwrap();
+        let page = |store: &MemoryStore| store.page(None, 100).entries;
+        assert_eq!(page(&restored), page(&store));
+        // The tombstone is not carried over.
+        assert_eq!(loaded.entries.len(), 2);
+        assert_eq!(restored.tombstones(), 0);
+
+        let routing = loaded.routing_table(table.layout());
+        assert_eq!(routing.local_id(), &NODE);
+        let mut nodes: Vec<_> = routing.nodes().collect();
+        let mut expected: Vec<_> = table.nodes().collect();
+        nodes.sort();
+        expected.sort();
+        assert_eq!(nodes, expected);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn restore_needs_an_empty_store() {
+        let (table, store) = populated();
+        let snapshot = Snapshot::capture(NODE, Some(&table), &store).unwrap();
+        let mut store = MemoryStore::new();
+        store.put("k".to_string(), Value::Utf8("v".to_string()));
+
+        assert!(matches!(
+            snapshot.restore(&mut store),
+            Err(SnapshotError::NotEmpty)
+        ));
+    }
+
+    #[test]
+    fn capture_checks_the_table_owner() {
+        let (table, store) = populated();
+        assert!(matches!(
+            Snapshot::capture([0x22; 20], Some(&table), &store),
+            Err(SnapshotError::NodeIdMismatch)
+        ));
+    }
+
+    /// Save a snapshot, edit its document with `edit` and load it again.
+    fn load_edited(
+        name: &str,
+        edit: impl FnOnce(&mut serde_json::Value),
+    ) -> Result<Snapshot, SnapshotError> {
+        let (table, store) = populated();
+        let path = temp(name);
+        Snapshot::capture(NODE, Some(&table), &store)
+            .unwrap()
+            .save(&path)
+            .unwrap();
+        let mut document: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        edit(&mut document);
+        fs::write(&path, document.to_string()).unwrap();
+
+        let loaded = Snapshot::load(&path);
+        fs::remove_file(path).unwrap();
+        loaded
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let loaded = load_edited("version", |document| {
+            document["version"] = (VERSION + 1).into();
+        });
+        assert!(
+            matches!(loaded, Err(SnapshotError::Version(v)) if v == VERSION + 1)
+        );
+    }
+
+    #[test]
+    fn edited_node_id_is_refused() {
+        let loaded = load_edited("node-id", |document| {
+            document["node_id"] = encode_hex(&[0x22; 20]).into();
+        });
+        assert!(matches!(loaded, Err(SnapshotError::NodeIdMismatch)));
+
+        let loaded = load_edited("own-id", |document| {
+            document["routing"][0] = encode_hex(&NODE).into();
+        });
+        assert!(matches!(loaded, Err(SnapshotError::NodeIdMismatch)));
+    }
+
+    #[test]
+    fn damaged_snapshots_are_corrupt() {
+        let loaded = load_edited("value", |document| {
+            document["entries"][0]["bytes"] = "zz".into();
+        });
+        assert!(matches!(loaded, Err(SnapshotError::Corrupt(_))));
+        assert!(matches!(
+            Snapshot::load(temp("missing")),
+            Err(SnapshotError::Io(_))
+        ));
+    }
+}
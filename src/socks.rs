@@ -0,0 +1,269 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! SOCKS5 proxy client.
+//!
+//! Restricted networks often let nothing out but connections through a
+//! SOCKS5 proxy (RFC 1928). [`Socks5Proxy::connect`] opens a TCP
+//! connection through one, authenticating with a username and password
+//! (RFC 1929) when given, so the TCP and WebSocket transports can reach
+//! the rendezvous server and peers from such networks, see
+//! [`crate::tcp::TcpTransport::with_proxy`]. UDP is not proxied.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    time::Duration,
+};
+
+use log::debug;
+
+const VERSION: u8 = 5;
+const AUTH_VERSION: u8 = 1;
+const METHOD_NONE: u8 = 0;
+const METHOD_PASSWORD: u8 = 2;
+const METHOD_UNACCEPTABLE: u8 = 0xff;
+const COMMAND_CONNECT: u8 = 1;
+const ADDRESS_IPV4: u8 = 1;
+const ADDRESS_DOMAIN: u8 = 3;
+const ADDRESS_IPV6: u8 = 4;
+
+/// A SOCKS5 proxy, written `socks5://[user:password@]host:port`. The
+/// scheme may be left out.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Socks5Proxy {
+    /// Host and port, resolved on every connection.
+    addr: String,
+    credentials: Option<(String, String)>,
+}
+
+impl Socks5Proxy {
+    pub fn new(addr: impl Into<String>) -> Self {
+        Socks5Proxy { addr: addr.into(), credentials: None }
+    }
+
+    /// Authenticate with `username` and `password`, at most 255 bytes
+    /// each.
+    pub fn with_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Host and port of the proxy.
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// Open a connection to `target` through the proxy, each step given at
+    /// most `timeout`.
+    pub fn connect(
+        &self,
+        target: SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<TcpStream> {
+        let proxy = self.addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "proxy not found")
+        })?;
+        let mut stream = TcpStream::connect_timeout(&proxy, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        self.handshake(&mut stream, target)?;
+        stream.set_read_timeout(None)?;
+        stream.set_write_timeout(None)?;
+        debug!("Connected to {target} through proxy {self}");
+        Ok(stream)
+    }
+
+    /// Negotiate a method, authenticate and ask for `target`.
+    fn handshake<S: Read + Write>(
+        &self,
+        stream: &mut S,
+        target: SocketAddr,
+    ) -> io::Result<()> {
+        let method = if self.credentials.is_some() {
+            METHOD_PASSWORD
+        } else {
+            METHOD_NONE
+        };
+        stream.write_all(&[VERSION, 1, method])?;
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply)?;
+        if reply[0] != VERSION {
+            return Err(proxy_error("not a SOCKS5 proxy"));
+        }
+        match reply[1] {
+            METHOD_NONE => {}
+            METHOD_PASSWORD => self.authenticate(stream)?,
+            METHOD_UNACCEPTABLE if self.credentials.is_none() => {
+                return Err(proxy_error("proxy requires authentication"));
+            }
+            _ => return Err(proxy_error("no acceptable method")),
+        }
+
+        let mut request = vec![VERSION, COMMAND_CONNECT, 0];
+        match target {
+            SocketAddr::V4(addr) => {
+                request.push(ADDRESS_IPV4);
+                request.extend_from_slice(&addr.ip().octets());
+            }
+            SocketAddr::V6(addr) => {
+                request.push(ADDRESS_IPV6);
+                request.extend_from_slice(&addr.ip().octets());
+            }
+        }
+        request.extend_from_slice(&target.port().to_be_bytes());
+        stream.write_all(&request)?;
+
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply)?;
+        if reply[0] != VERSION {
+            return Err(proxy_error("not a SOCKS5 proxy"));
+        }
+        if reply[1] != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!(
+                    "proxy could not connect to {target}: {}",
+                    reason(reply[1])
+                ),
+            ));
+        }
+        // The address the proxy connected from, of no use here.
+        let len = match reply[3] {
+            ADDRESS_IPV4 => 4,
+            ADDRESS_IPV6 => 16,
+            ADDRESS_DOMAIN => {
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len)?;
+                usize::from(len[0])
+            }
+            _ => return Err(proxy_error("bad address type in reply")),
+        };
+        let mut bound = vec![0u8; len + 2];
+        stream.read_exact(&mut bound)?;
+        Ok(())
+    }
+
+    /// Send the username and password, see RFC 1929.
+    fn authenticate<S: Read + Write>(&self, stream: &mut S) -> io::Result<()> {
+        let Some((username, password)) = &self.credentials else {
+            return Err(proxy_error("proxy asked for a password"));
+        };
+        let (Ok(username_len), Ok(password_len)) =
+            (u8::try_from(username.len()), u8::try_from(password.len()))
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "proxy username or password too long",
+            ));
+        };
+        let mut request = vec![AUTH_VERSION, username_len];
+        request.extend_from_slice(username.as_bytes());
+        request.push(password_len);
+        request.extend_from_slice(password.as_bytes());
+        stream.write_all(&request)?;
+
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply)?;
+        if reply[1] != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "proxy refused the username or password",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Why a proxy failed a request, from the reply code.
+fn reason(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn proxy_error(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+impl FromStr for Socks5Proxy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = match s.split_once("://") {
+            Some(("socks5" | "socks5h", rest)) => rest,
+            Some((scheme, _)) => {
+                return Err(format!("unsupported proxy scheme '{scheme}'"));
+            }
+            None => s,
+        };
+        let rest = rest.trim_end_matches('/');
+        let (credentials, addr) = match rest.rsplit_once('@') {
+            Some((credentials, addr)) => {
+                let (username, password) =
+                    credentials.split_once(':').unwrap_or((credentials, ""));
+                (Some((username.to_string(), password.to_string())), addr)
+            }
+            None => (None, rest),
+        };
+        let port = addr
+            .rsplit_once(':')
+            .and_then(|(host, port)| (!host.is_empty()).then_some(port))
+            .ok_or_else(|| format!("proxy address '{addr}' has no port"))?;
+        port.parse::<u16>()
+            .map_err(|e| format!("bad proxy port '{port}': {e}"))?;
+
+        let proxy = Socks5Proxy::new(addr);
+        Ok(match credentials {
+            Some((username, password)) => {
+                proxy.with_credentials(username, password)
+            }
+            None => proxy,
+        })
+    }
+}
+
+/// Leaves the password out.
+impl fmt::Display for Socks5Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.credentials {
+            Some((username, _)) => {
+                write!(f, "socks5://{username}@{}", self.addr)
+            }
+            None => write!(f, "socks5://{}", self.addr),
+        }
+    }
+}
+
+/// Leaves the password out.
+impl fmt::Debug for Socks5Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Socks5Proxy({self})")
+    }
+}
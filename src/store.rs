@@ -0,0 +1,557 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Key/value storage backends.
+//!
+//! A plain delete forgets the key, so a replica that still holds it would
+//! bring it back on the next sync. Stores in tombstone mode instead keep a
+//! timestamped marker for deleted keys, which reads treat as absent, until
+//! [`Store::compact`] purges markers older than a grace period.
+//!
+//! Values replicated through the DHT carry a sequence number, set by the
+//! node that wrote them, so replicas can tell which of two values is the
+//! fresher. Any local write resets it.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    ops::Bound,
+    time::{Duration, SystemTime},
+};
+
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+/// Number of bytes shown when displaying a binary value.
+const DISPLAY_BYTES: usize = 16;
+
+/// Default age after which tombstones are purged by a compaction.
+pub const DEFAULT_TOMBSTONE_GRACE: Duration = Duration::from_secs(24 * 3600);
+
+/// A stored value.
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encode, Decode,
+)]
+pub enum Value {
+    Utf8(String),
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    /// Raw bytes of the value; text is its UTF-8 encoding.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Value::Utf8(s) => s.as_bytes(),
+            Value::Bytes(b) => b,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Text is shown quoted, bytes as their size and a truncated hex dump.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Utf8(s) => write!(f, "'{s}'"),
+            Value::Bytes(b) => {
+                write!(f, "<{} bytes> ", b.len())?;
+                for byte in b.iter().take(DISPLAY_BYTES) {
+                    write!(f, "{byte:02x}")?;
+                }
+                if b.len() > DISPLAY_BYTES {
+                    write!(f, "...")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Result of [`Store::scan`].
+#[derive(Debug, Default)]
+pub struct Scan {
+    pub entries: Vec<(String, Value)>,
+    /// More entries matched than the limit allowed.
+    pub truncated: bool,
+}
+
+/// Result of [`Store::page`].
+#[derive(Debug, Default)]
+pub struct Page {
+    pub entries: Vec<(String, Value)>,
+    /// Cursor of the next page, `None` once every entry was returned.
+    pub next: Option<String>,
+}
+
+/// A key/value store backend.
+pub trait Store {
+    /// Insert or replace the value stored under `key`.
+    fn put(&mut self, key: String, value: Value);
+
+    /// Return the value stored under `key`.
+    fn get(&self, key: &str) -> Option<Value>;
+
+    /// Store `value` under `key` until `expires`, after which reads treat
+    /// it as absent. Stores without expiry support keep it for good.
+    fn put_expiring(
+        &mut self,
+        key: String,
+        value: Value,
+        _expires: SystemTime,
+    ) {
+        self.put(key, value);
+    }
+
+    /// When the value stored under `key` expires, `None` if it does not.
+    fn expires(&self, _key: &str) -> Option<SystemTime> {
+        None
+    }
+
+    /// Sequence number of the value stored under `key`, zero when none was
+    /// set since it was written.
+    fn seq(&self, _key: &str) -> u64 {
+        0
+    }
+
+    /// Set the sequence number of the value stored under `key`. Stores
+    /// without sequence support ignore it.
+    fn set_seq(&mut self, _key: &str, _seq: u64) {}
+
+    /// Store `new` under `key` only if the current value equals
+    /// `expected`, or the key is absent when `expected` is `None`.
+    ///
+    /// On a mismatch nothing is written and the current value is returned.
+    /// Backends must check and write atomically.
+    fn compare_and_swap(
+        &mut self,
+        key: &str,
+        expected: Option<&Value>,
+        new: Value,
+    ) -> Result<(), Option<Value>>;
+
+    /// Remove `key`, returning its value if it was present.
+    ///
+    /// This always forgets the key, even in tombstone mode; use
+    /// [`Store::delete`] for deletions that must survive a sync.
+    fn remove(&mut self, key: &str) -> Option<Value>;
+
+    /// Delete `key` at `now`, returning whether a value was present.
+    ///
+    /// Stores without tombstone support simply remove the key.
+    fn delete(&mut self, key: &str, _now: SystemTime) -> bool {
+        self.remove(key).is_some()
+    }
+
+    /// When `key` was deleted, if it is held as a tombstone.
+    fn tombstone(&self, _key: &str) -> Option<SystemTime> {
+        None
+    }
+
+    /// Purge tombstones older than `grace` as of `now` and return how many
+    /// were purged.
+    fn compact(&mut self, _now: SystemTime, _grace: Duration) -> usize {
+        0
+    }
+
+    /// Drop the values expired as of `now` and return how many were
+    /// dropped.
+    fn expire(&mut self, _now: SystemTime) -> usize {
+        0
+    }
+
+    /// Return up to `limit` entries whose key starts with `prefix`, sorted
+    /// by key.
+    fn scan(&self, prefix: &str, limit: usize) -> Scan;
+
+    /// Return up to `limit` entries following `cursor`, sorted by key.
+    ///
+    /// Pass `None` for the first page and [`Page::next`] for the following
+    /// ones. The cursor is the last key returned, so it stays valid when
+    /// the store changes between calls: every key present for the whole
+    /// enumeration is returned exactly once. A `limit` of zero is treated
+    /// as one, so an enumeration always makes progress.
+    fn page(&self, cursor: Option<&str>, limit: usize) -> Page;
+
+    /// Number of stored keys.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A slot in the [`MemoryStore`].
+#[derive(Debug, Clone)]
+enum Entry {
+    Live(Value),
+    /// Live until the given time.
+    Expiring(Value, SystemTime),
+    /// Deleted at the given time.
+    Tombstone(SystemTime),
+}
+
+impl Entry {
+    fn value(&self) -> Option<&Value> {
+        match self {
+            Entry::Live(value) => Some(value),
+            Entry::Expiring(value, at) => {
+                (SystemTime::now() < *at).then_some(value)
+            }
+            Entry::Tombstone(_) => None,
+        }
+    }
+}
+
+/// In-memory store backed by a `BTreeMap`, so scans and pages walk the
+/// keys in order.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    entries: BTreeMap<String, Entry>,
+    /// Sequence numbers of the live entries that have one.
+    seqs: BTreeMap<String, u64>,
+    /// Number of entries that are tombstones.
+    tombstones: usize,
+    tombstone_mode: bool,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep tombstones for deleted keys instead of removing them. Turning
+    /// the mode off leaves existing tombstones until they are compacted.
+    pub fn set_tombstone_mode(&mut self, enabled: bool) {
+        self.tombstone_mode = enabled;
+    }
+
+    pub fn tombstone_mode(&self) -> bool {
+        self.tombstone_mode
+    }
+
+    /// Number of tombstones held.
+    pub fn tombstones(&self) -> usize {
+        self.tombstones
+    }
+
+    fn insert(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        if matches!(entry, Entry::Tombstone(_)) {
+            self.tombstones += 1;
+        }
+        self.seqs.remove(&key);
+        let previous = self.entries.insert(key, entry);
+        if matches!(previous, Some(Entry::Tombstone(_))) {
+            self.tombstones -= 1;
+        }
+        previous
+    }
+}
+
+impl Store for MemoryStore {
+    fn put(&mut self, key: String, value: Value) {
+        self.insert(key, Entry::Live(value));
+    }
+
+    fn get(&self, key: &str) -> Option<Value> {
+        self.entries.get(key).and_then(Entry::value).cloned()
+    }
+
+    fn put_expiring(
+        &mut self,
+        key: String,
+        value: Value,
+        expires: SystemTime,
+    ) {
+        self.insert(key, Entry::Expiring(value, expires));
+    }
+
+    fn expires(&self, key: &str) -> Option<SystemTime> {
+        match self.entries.get(key)? {
+            Entry::Expiring(_, at) => Some(*at),
+            _ => None,
+        }
+    }
+
+    fn seq(&self, key: &str) -> u64 {
+        self.seqs.get(key).copied().unwrap_or_default()
+    }
+
+    fn set_seq(&mut self, key: &str, seq: u64) {
+        if self.entries.get(key).and_then(Entry::value).is_some() {
+            self.seqs.insert(key.to_string(), seq);
+        }
+    }
+
+    fn compare_and_swap(
+        &mut self,
+        key: &str,
+        expected: Option<&Value>,
+        new: Value,
+    ) -> Result<(), Option<Value>> {
+        let current = self.entries.get(key).and_then(Entry::value);
+        if current != expected {
+            return Err(current.cloned());
+        }
+
+        self.insert(key.to_string(), Entry::Live(new));
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Value> {
+        self.seqs.remove(key);
+        match self.entries.remove(key)? {
+            Entry::Live(value) => Some(value),
+            Entry::Expiring(value, at) => {
+                (SystemTime::now() < at).then_some(value)
+            }
+            Entry::Tombstone(_) => {
+                self.tombstones -= 1;
+                None
+            }
+        }
+    }
+
+    fn delete(&mut self, key: &str, now: SystemTime) -> bool {
+        if !self.tombstone_mode {
+            return self.remove(key).is_some();
+        }
+
+        // Deleting an absent key still records the tombstone, a replica
+        // may hold a value we never saw.
+        let previous = self.insert(key.to_string(), Entry::Tombstone(now));
+        previous.as_ref().and_then(Entry::value).is_some()
+    }
+
+    fn tombstone(&self, key: &str) -> Option<SystemTime> {
+        match self.entries.get(key)? {
+            Entry::Live(_) | Entry::Expiring(..) => None,
+            Entry::Tombstone(at) => Some(*at),
+        }
+    }
+
+    fn compact(&mut self, now: SystemTime, grace: Duration) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| match entry {
+            Entry::Live(_) | Entry::Expiring(..) => true,
+            Entry::Tombstone(at) => {
+                now.duration_since(*at).is_ok_and(|age| age <= grace)
+            }
+        });
+
+        let purged = before - self.entries.len();
+        self.tombstones -= purged;
+        purged
+    }
+
+    fn expire(&mut self, now: SystemTime) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| match entry {
+            Entry::Expiring(_, at) => now < *at,
+            Entry::Live(_) | Entry::Tombstone(_) => true,
+        });
+        let entries = &self.entries;
+        self.seqs.retain(|key, _| entries.contains_key(key));
+        before - self.entries.len()
+    }
+
+    fn scan(&self, prefix: &str, limit: usize) -> Scan {
+        let mut matches = self
+            .entries
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter_map(|(key, entry)| Some((key, entry.value()?)));
+
+        let entries: Vec<_> = matches
+            .by_ref()
+            .take(limit)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        Scan { truncated: matches.next().is_some(), entries }
+    }
+
+    fn page(&self, cursor: Option<&str>, limit: usize) -> Page {
+        let start = cursor.map_or(Bound::Unbounded, Bound::Excluded);
+        let mut live = self
+            .entries
+            .range::<str, _>((start, Bound::Unbounded))
+            .filter_map(|(key, entry)| Some((key, entry.value()?)));
+
+        let entries: Vec<_> = live
+            .by_ref()
+            .take(limit.max(1))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        let next = match live.next() {
+            Some(_) => entries.last().map(|(key, _)| key.clone()),
+            None => None,
+        };
+
+        Page { entries, next }
+    }
+
+    /// Tombstones are not counted, expired values are until the next
+    /// [`Store::expire`].
+    fn len(&self) -> usize {
+        self.entries.len() - self.tombstones
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store holding `a` to `e`.
+    fn five() -> MemoryStore {
+        let mut store = MemoryStore::new();
+        for key in ["c", "a", "e", "b", "d"] {
+            store.put(key.to_string(), Value::Utf8(key.to_uppercase()));
+        }
+        store
+    }
+
+    /// Every page of `limit` entries, walking the cursors from the start.
+    fn pages(store: &dyn Store, limit: usize) -> Vec<Page> {
+        let mut pages = vec![store.page(None, limit)];
+        while let Some(next) = pages.last().unwrap().next.clone() {
+            pages.push(store.page(Some(&next), limit));
+        }
+        pages
+    }
+
+    fn keys(page: &Page) -> Vec<&str> {
+        page.entries.iter().map(|(key, _)| key.as_str()).collect()
+    }
+
+    #[test]
+    fn pages_of_two_return_every_entry_once() {
+        let store = five();
+        let pages = pages(&store, 2);
+
+        let keys: Vec<_> = pages.iter().map(keys).collect();
+        assert_eq!(keys, [vec!["a", "b"], vec!["c", "d"], vec!["e"]]);
+        assert_eq!(pages[2].next, None);
+        assert_eq!(pages[2].entries[0].1, Value::Utf8("E".to_string()));
+    }
+
+    #[test]
+    fn exact_last_page_terminates() {
+        let mut store = five();
+        store.delete("e", SystemTime::now());
+
+        let pages = pages(&store, 2);
+        assert_eq!(pages.len(), 2);
+        assert_eq!(keys(&pages[1]), ["c", "d"]);
+        assert_eq!(pages[1].next, None);
+    }
+
+    #[test]
+    fn cursor_survives_changes_between_pages() {
+        let mut store = five();
+        let first = store.page(None, 2);
+        assert_eq!(first.next.as_deref(), Some("b"));
+
+        // Removing the cursor key itself and adding keys on both sides
+        // neither repeats nor skips the keys present throughout.
+        store.delete("b", SystemTime::now());
+        store.put("aa".to_string(), Value::Utf8("AA".to_string()));
+        store.put("ca".to_string(), Value::Utf8("CA".to_string()));
+
+        let second = store.page(first.next.as_deref(), 2);
+        assert_eq!(keys(&second), ["c", "ca"]);
+        let third = store.page(second.next.as_deref(), 2);
+        assert_eq!(keys(&third), ["d", "e"]);
+        assert_eq!(third.next, None);
+    }
+
+    #[test]
+    fn tombstones_are_not_paged() {
+        let mut store = five();
+        store.set_tombstone_mode(true);
+        store.delete("b", SystemTime::now());
+        store.delete("d", SystemTime::now());
+
+        let pages = pages(&store, 2);
+        let keys: Vec<_> = pages.iter().flat_map(keys).collect();
+        assert_eq!(keys, ["a", "c", "e"]);
+    }
+
+    #[test]
+    fn zero_limit_still_progresses() {
+        let store = five();
+        assert_eq!(pages(&store, 0).len(), 5);
+    }
+
+    fn utf8(s: &str) -> Value {
+        Value::Utf8(s.to_string())
+    }
+
+    #[test]
+    fn cas_swaps_on_a_match() {
+        let mut store = five();
+        assert_eq!(
+            store.compare_and_swap("a", Some(&utf8("A")), utf8("Z")),
+            Ok(())
+        );
+        assert_eq!(store.get("a"), Some(utf8("Z")));
+    }
+
+    #[test]
+    fn cas_keeps_the_value_on_a_mismatch() {
+        let mut store = five();
+        assert_eq!(
+            store.compare_and_swap("a", Some(&utf8("B")), utf8("Z")),
+            Err(Some(utf8("A")))
+        );
+        assert_eq!(
+            store.compare_and_swap("a", None, utf8("Z")),
+            Err(Some(utf8("A")))
+        );
+        assert_eq!(store.get("a"), Some(utf8("A")));
+    }
+
+    #[test]
+    fn cas_on_an_absent_key() {
+        let mut store = five();
+        assert_eq!(
+            store.compare_and_swap("x", Some(&utf8("X")), utf8("Z")),
+            Err(None)
+        );
+        assert_eq!(store.get("x"), None);
+
+        assert_eq!(store.compare_and_swap("x", None, utf8("Z")), Ok(()));
+        assert_eq!(store.get("x"), Some(utf8("Z")));
+    }
+
+    #[test]
+    fn cas_treats_a_tombstone_as_absent() {
+        let mut store = five();
+        store.set_tombstone_mode(true);
+        store.delete("a", SystemTime::now());
+
+        assert_eq!(store.compare_and_swap("a", None, utf8("Z")), Ok(()));
+        assert_eq!(store.get("a"), Some(utf8("Z")));
+        assert_eq!(store.tombstones(), 0);
+    }
+}
@@ -0,0 +1,708 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! TCP transport.
+//!
+//! Some networks block or throttle UDP. [`TcpTransport`] carries the same
+//! datagrams over TCP connections instead, each one in a frame written by
+//! [`protocol::write_frame`].
+//! [`FallbackTransport`] talks UDP and moves a destination to TCP once
+//! requests to it keep timing out. It also answers the peers connected
+//! over WebSocket, see [`crate::websocket`], and reaches the peers
+//! advertising a QUIC port over QUIC, see [`crate::quic`], and those the
+//! hole punch did not reach over WebRTC, see [`crate::webrtc`].
+//!
+//! Outgoing connections are made from the listening port, so the address
+//! a peer sees is the one it can connect back to, and the UDP and TCP
+//! ports of a node share a number.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{self, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Condvar, Mutex, MutexGuard, Weak},
+    thread,
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::{
+    batch::send_each,
+    protocol::{self, FrameError},
+    quic::{self, QuicTransport},
+    socks::Socks5Proxy,
+    transport::{Transport, UdpTransport, canonical, to_ipv6},
+    webrtc::{self, WebRtcTransport},
+    websocket::WebSocketTransport,
+};
+
+/// How long to wait for an outgoing connection to be accepted.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a write may block before the connection is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often the accept thread checks whether the transport is gone.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Maximum number of open connections. Further ones are refused.
+pub const MAX_CONNECTIONS: usize = 256;
+
+/// Maximum number of received frames waiting for `recv_from`. Frames
+/// beyond this are dropped, like datagrams on a full socket buffer.
+const MAX_QUEUED_FRAMES: usize = 1024;
+
+/// Default number of consecutive timeouts after which
+/// [`FallbackTransport`] switches a destination to TCP.
+pub const DEFAULT_FALLBACK_AFTER: u32 = 3;
+
+/// State shared by the handles of a [`TcpTransport`] and its threads.
+#[derive(Debug)]
+struct Shared {
+    local_addr: SocketAddr,
+    /// Open connections by peer address, used for writing.
+    streams: Mutex<HashMap<SocketAddr, TcpStream>>,
+    /// Received frames, oldest first.
+    inbox: Mutex<VecDeque<(Vec<u8>, SocketAddr)>>,
+    arrived: Condvar,
+}
+
+impl Shared {
+    fn streams(&self) -> MutexGuard<'_, HashMap<SocketAddr, TcpStream>> {
+        // A panic while holding the lock leaves the map intact.
+        self.streams.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn inbox(&self) -> MutexGuard<'_, VecDeque<(Vec<u8>, SocketAddr)>> {
+        self.inbox.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn deliver(&self, data: Vec<u8>, from: SocketAddr) {
+        let mut inbox = self.inbox();
+        if inbox.len() >= MAX_QUEUED_FRAMES {
+            debug!("Dropping a frame from {from}: receive queue full");
+            return;
+        }
+        inbox.push_back((data, from));
+        self.arrived.notify_one();
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        // Wakes up the reader threads, which hold clones of the streams.
+        for stream in self.streams().values() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Transport carrying datagrams as frames over TCP connections.
+///
+/// Connections are opened on the first datagram sent to an address and
+/// accepted from any peer; a datagram to a peer goes over the connection
+/// it has with us, whoever opened it. Each connection is read by its own
+/// thread. Handles made with [`Clone`] share the connections, each has
+/// its own blocking mode. New transports are nonblocking.
+#[derive(Debug, Clone)]
+pub struct TcpTransport {
+    shared: Arc<Shared>,
+    nonblocking: bool,
+    /// Proxy outgoing connections go through.
+    proxy: Option<Arc<Socks5Proxy>>,
+}
+
+impl TcpTransport {
+    /// Listen on `addr` and start accepting connections.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no address to bind")
+        })?;
+
+        let socket = stream_socket(addr)?;
+        if addr.is_ipv6()
+            && let Err(e) = socket.set_only_v6(false)
+        {
+            warn!("{addr} does not carry IPv4: {e}");
+        }
+        socket.bind(&addr.into())?;
+        socket.listen(128)?;
+        let listener: TcpListener = socket.into();
+        listener.set_nonblocking(true)?;
+
+        let shared = Arc::new(Shared {
+            local_addr: listener.local_addr()?,
+            streams: Mutex::new(HashMap::new()),
+            inbox: Mutex::new(VecDeque::new()),
+            arrived: Condvar::new(),
+        });
+        let weak = Arc::downgrade(&shared);
+        thread::Builder::new()
+            .name("tcp-accept".to_string())
+            .spawn(move || accept(&listener, &weak))?;
+
+        Ok(TcpTransport { shared, nonblocking: true, proxy: None })
+    }
+
+    /// Open outgoing connections through `proxy`. Peers then see the
+    /// proxy's address rather than ours, and can only answer over the
+    /// connection.
+    pub fn with_proxy(mut self, proxy: Socks5Proxy) -> Self {
+        self.proxy = Some(Arc::new(proxy));
+        self
+    }
+
+    /// Make `recv_from` wait for a frame instead of failing with
+    /// [`io::ErrorKind::WouldBlock`].
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    /// Whether a connection with `addr` is open.
+    pub fn is_connected(&self, addr: SocketAddr) -> bool {
+        self.shared.streams().contains_key(&addr)
+    }
+
+    /// Number of open connections.
+    pub fn connections(&self) -> usize {
+        self.shared.streams().len()
+    }
+
+    /// Open a connection to `addr` from the listening port, or through
+    /// the proxy.
+    fn connect(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        if let Some(proxy) = &self.proxy {
+            return proxy.connect(addr, CONNECT_TIMEOUT);
+        }
+        let local = self.shared.local_addr;
+        let target = if local.is_ipv6() { to_ipv6(addr) } else { addr };
+        let socket = stream_socket(target)?;
+        if local.is_ipv6() {
+            // Fails on systems never carrying IPv4 on an IPv6 socket, where
+            // the connection below fails for IPv4 destinations anyway.
+            let _ = socket.set_only_v6(false);
+        }
+        if local.is_ipv4() == target.is_ipv4()
+            && let Err(e) = socket.bind(&local.into())
+        {
+            // Still reachable, only not at the address we listen on.
+            debug!("Connecting to {addr} from an ephemeral port: {e}");
+        }
+        socket.connect_timeout(&target.into(), CONNECT_TIMEOUT)?;
+        debug!("Connected to {addr} over TCP");
+        Ok(socket.into())
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        // Written at once, so frames never interleave on the stream.
+        let mut frame = Vec::new();
+        protocol::write_frame(&mut frame, buf, protocol::MAX_MESSAGE_SIZE)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut streams = self.shared.streams();
+
+        if !streams.contains_key(&addr) {
+            if streams.len() >= MAX_CONNECTIONS {
+                return Err(io::Error::other("too many TCP connections"));
+            }
+            let stream = self.connect(addr)?;
+            register(&self.shared, &mut streams, stream, addr)?;
+        }
+
+        let Some(mut stream) = streams.get(&addr) else {
+            return Err(io::ErrorKind::NotConnected.into());
+        };
+        if let Err(e) = stream.write_all(&frame) {
+            let _ = stream.shutdown(Shutdown::Both);
+            streams.remove(&addr);
+            return Err(e);
+        }
+        Ok(buf.len())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut inbox = self.shared.inbox();
+        loop {
+            if let Some((data, from)) = inbox.pop_front() {
+                // Same truncation semantics as a UDP socket.
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                return Ok((len, from));
+            }
+            if self.nonblocking {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            inbox = self
+                .shared
+                .arrived
+                .wait(inbox)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.shared.local_addr)
+    }
+}
+
+/// A TCP socket for `addr` that can share its port with the listener.
+fn stream_socket(addr: SocketAddr) -> io::Result<Socket> {
+    let socket = Socket::new(
+        Domain::for_address(addr),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    Ok(socket)
+}
+
+/// Accept connections until the transport is dropped.
+fn accept(listener: &TcpListener, shared: &Weak<Shared>) {
+    loop {
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                let peer = canonical(peer);
+                let mut streams = shared.streams();
+                if streams.len() >= MAX_CONNECTIONS {
+                    warn!("Refusing a TCP connection from {peer}: too many");
+                    continue;
+                }
+                if let Err(e) = stream.set_nonblocking(false).and_then(|()| {
+                    register(&shared, &mut streams, stream, peer)
+                }) {
+                    warn!("Dropping a TCP connection from {peer}: {e}");
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                drop(shared);
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+            Err(e) => {
+                warn!("Could not accept a TCP connection: {e}");
+                drop(shared);
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Keep `stream` as the connection with `peer` and start reading it.
+fn register(
+    shared: &Arc<Shared>,
+    streams: &mut HashMap<SocketAddr, TcpStream>,
+    stream: TcpStream,
+    peer: SocketAddr,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let reader = stream.try_clone()?;
+    let weak = Arc::downgrade(shared);
+    thread::Builder::new()
+        .name(format!("tcp-{peer}"))
+        .spawn(move || read(reader, peer, &weak))?;
+    streams.insert(peer, stream);
+    Ok(())
+}
+
+/// Deliver the frames read from `stream` until it closes.
+fn read(mut stream: TcpStream, peer: SocketAddr, shared: &Weak<Shared>) {
+    loop {
+        match protocol::read_frame(&mut stream, protocol::MAX_MESSAGE_SIZE) {
+            Ok(Some(data)) => match shared.upgrade() {
+                Some(shared) => shared.deliver(data, peer),
+                None => return,
+            },
+            Ok(None) => break,
+            Err(e @ FrameError::TooLarge { .. }) => {
+                // The stream can not be resynchronised past it.
+                warn!("Closing the TCP connection with {peer}: {e}");
+                break;
+            }
+            Err(e) => {
+                debug!("TCP connection with {peer} closed: {e}");
+                break;
+            }
+        }
+    }
+
+    let _ = stream.shutdown(Shutdown::Both);
+    if let Some(shared) = shared.upgrade() {
+        shared.streams().remove(&peer);
+    }
+}
+
+/// Destinations [`FallbackTransport`] sends to over TCP.
+#[derive(Debug, Default)]
+struct Routes {
+    over_tcp: HashSet<SocketAddr>,
+    /// Destinations only reachable over WebSocket, see
+    /// [`FallbackTransport::route_over_websocket`].
+    over_websocket: HashSet<SocketAddr>,
+    /// QUIC address of the destinations advertising one, see
+    /// [`FallbackTransport::with_quic`].
+    over_quic: HashMap<SocketAddr, SocketAddr>,
+    /// Destination behind each QUIC address of `over_quic`.
+    from_quic: HashMap<SocketAddr, SocketAddr>,
+    /// Destinations moved off QUIC by timeouts, not routed over it again.
+    quic_failed: HashSet<SocketAddr>,
+    /// Destinations advertising WebRTC, see
+    /// [`FallbackTransport::with_webrtc`].
+    webrtc_peers: HashSet<SocketAddr>,
+    /// Consecutive timeouts per destination since it was last heard.
+    strikes: HashMap<SocketAddr, u32>,
+}
+
+/// UDP transport that falls back to TCP for the destinations it can not
+/// reach over UDP.
+///
+/// A destination moves to TCP after [`FallbackTransport::fallback_after`]
+/// consecutive timeouts reported with [`Transport::timed_out`], and moves
+/// back to UDP when the TCP connection can not be made or breaks. Peers
+/// that connected to us over TCP are answered over TCP, and likewise for
+/// WebSocket. Without a [`TcpTransport`] this is plain UDP.
+///
+/// With a [`QuicTransport`], destinations advertising a QUIC port with
+/// [`quic::capability`] are reached over QUIC, until
+/// [`FallbackTransport::fallback_after`] consecutive timeouts move them
+/// back to UDP.
+///
+/// With a [`WebRtcTransport`], channels are negotiated with destinations
+/// advertising [`webrtc::CAPABILITY`], see [`Transport::negotiate`], and
+/// used while open.
+///
+/// `recv_from` reads every transport, which must be nonblocking.
+#[derive(Debug)]
+pub struct FallbackTransport {
+    udp: UdpTransport,
+    tcp: Option<TcpTransport>,
+    websocket: Option<WebSocketTransport>,
+    quic: Option<QuicTransport>,
+    webrtc: Option<WebRtcTransport>,
+    fallback_after: u32,
+    routes: Mutex<Routes>,
+}
+
+impl FallbackTransport {
+    pub fn new(udp: UdpTransport, tcp: Option<TcpTransport>) -> Self {
+        FallbackTransport {
+            udp,
+            tcp,
+            websocket: None,
+            quic: None,
+            webrtc: None,
+            fallback_after: DEFAULT_FALLBACK_AFTER,
+            routes: Mutex::new(Routes::default()),
+        }
+    }
+
+    /// Switch a destination to TCP after `timeouts` consecutive timeouts.
+    pub fn with_fallback_after(mut self, timeouts: u32) -> Self {
+        self.fallback_after = timeouts.max(1);
+        self
+    }
+
+    /// Answer the peers connected over `websocket` through it, and reach
+    /// the destinations given to [`Self::route_over_websocket`].
+    pub fn with_websocket(mut self, websocket: WebSocketTransport) -> Self {
+        self.websocket = Some(websocket);
+        self
+    }
+
+    /// Reach the destinations advertising a QUIC port over `quic`, and
+    /// advertise its port, see [`Transport::capabilities`].
+    pub fn with_quic(mut self, quic: QuicTransport) -> Self {
+        self.quic = Some(quic);
+        self
+    }
+
+    /// Negotiate channels over `webrtc` with the destinations advertising
+    /// WebRTC, and advertise it, see [`Transport::capabilities`].
+    pub fn with_webrtc(mut self, webrtc: WebRtcTransport) -> Self {
+        self.webrtc = Some(webrtc);
+        self
+    }
+
+    /// Send to `addr` over TCP from the start, e.g. a server only reachable
+    /// through a proxy. Needs a [`TcpTransport`]; `addr` moves back to UDP
+    /// when the connection can not be made.
+    pub fn route_over_tcp(&self, addr: SocketAddr) {
+        if self.tcp.is_some() {
+            self.routes().over_tcp.insert(addr);
+        }
+    }
+
+    /// Send to `addr` over WebSocket only, e.g. a server behind a proxy
+    /// letting nothing else through. Needs [`Self::with_websocket`].
+    pub fn route_over_websocket(&self, addr: SocketAddr) {
+        self.routes().over_websocket.insert(addr);
+    }
+
+    pub fn fallback_after(&self) -> u32 {
+        self.fallback_after
+    }
+
+    pub fn udp(&self) -> &UdpTransport {
+        &self.udp
+    }
+
+    pub fn tcp(&self) -> Option<&TcpTransport> {
+        self.tcp.as_ref()
+    }
+
+    pub fn websocket(&self) -> Option<&WebSocketTransport> {
+        self.websocket.as_ref()
+    }
+
+    pub fn quic(&self) -> Option<&QuicTransport> {
+        self.quic.as_ref()
+    }
+
+    pub fn webrtc(&self) -> Option<&WebRtcTransport> {
+        self.webrtc.as_ref()
+    }
+
+    /// Destinations currently sent to over QUIC, with their QUIC address.
+    pub fn over_quic(&self) -> Vec<(SocketAddr, SocketAddr)> {
+        let routes = self.routes();
+        routes.over_quic.iter().map(|(&addr, &to)| (addr, to)).collect()
+    }
+
+    /// Destinations currently sent to over TCP because of timeouts.
+    pub fn over_tcp(&self) -> Vec<SocketAddr> {
+        self.routes().over_tcp.iter().copied().collect()
+    }
+
+    fn routes(&self) -> MutexGuard<'_, Routes> {
+        self.routes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Address to send datagrams for `addr` to over QUIC, if any: its
+    /// advertised QUIC address, or `addr` itself when it connected to us
+    /// over QUIC.
+    fn quic_route(&self, addr: SocketAddr) -> Option<SocketAddr> {
+        let quic = self.quic.as_ref()?;
+        if let Some(&to) = self.routes().over_quic.get(&addr) {
+            return Some(to);
+        }
+        quic.is_connected(addr).then_some(addr)
+    }
+
+    /// Whether a WebRTC channel with `addr` is open.
+    fn over_webrtc(&self, addr: SocketAddr) -> bool {
+        self.webrtc.as_ref().is_some_and(|webrtc| webrtc.is_connected(addr))
+    }
+
+    /// Whether datagrams to `addr` go over WebSocket, WebRTC, QUIC or TCP
+    /// rather than UDP.
+    fn over_stream(&self, addr: SocketAddr) -> bool {
+        if self.tcp.is_none()
+            && self.websocket.is_none()
+            && self.quic.is_none()
+            && self.webrtc.is_none()
+        {
+            return false;
+        }
+        if self.over_webrtc(addr) || self.quic_route(addr).is_some() {
+            return true;
+        }
+        let routes = self.routes();
+        self.websocket.as_ref().is_some_and(|websocket| {
+            websocket.is_connected(addr)
+                || routes.over_websocket.contains(&addr)
+        }) || self.tcp.as_ref().is_some_and(|tcp| {
+            tcp.is_connected(addr) || routes.over_tcp.contains(&addr)
+        })
+    }
+}
+
+impl Transport for FallbackTransport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let websocket = self.websocket.as_ref().filter(|websocket| {
+            websocket.is_connected(addr)
+                || self.routes().over_websocket.contains(&addr)
+        });
+        if let Some(websocket) = websocket {
+            return websocket.send_to(buf, addr);
+        }
+        if let Some(webrtc) = &self.webrtc
+            && webrtc.is_connected(addr)
+        {
+            return webrtc.send_to(buf, addr);
+        }
+        if let (Some(quic), Some(to)) = (&self.quic, self.quic_route(addr)) {
+            return quic.send_to(buf, to);
+        }
+        let tcp = self.tcp.as_ref().filter(|tcp| {
+            tcp.is_connected(addr) || self.routes().over_tcp.contains(&addr)
+        });
+        if let Some(tcp) = tcp {
+            match tcp.send_to(buf, addr) {
+                Ok(len) => return Ok(len),
+                Err(e) => {
+                    info!("TCP to {addr} failed, back to UDP: {e}");
+                    let mut routes = self.routes();
+                    routes.over_tcp.remove(&addr);
+                    routes.strikes.remove(&addr);
+                }
+            }
+        }
+        self.udp.send_to(buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self.udp.recv_from(buf) {
+            Ok((len, from)) => {
+                self.routes().strikes.remove(&from);
+                Ok((len, from))
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                let streams = [
+                    self.tcp.as_ref().map(|tcp| tcp as &dyn Transport),
+                    self.websocket.as_ref().map(|ws| ws as &dyn Transport),
+                    self.webrtc.as_ref().map(|rtc| rtc as &dyn Transport),
+                ];
+                for transport in streams.into_iter().flatten() {
+                    match transport.recv_from(buf) {
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                        result => return result,
+                    }
+                }
+                if let Some(quic) = &self.quic {
+                    match quic.recv_from(buf) {
+                        Ok((len, from)) => {
+                            let mut routes = self.routes();
+                            let from = routes
+                                .from_quic
+                                .get(&from)
+                                .copied()
+                                .unwrap_or(from);
+                            routes.strikes.remove(&from);
+                            return Ok((len, from));
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                Err(e)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.udp.local_addr()
+    }
+
+    /// Sends the leading datagrams going over UDP at once, and the others
+    /// one at a time.
+    fn send_batch(
+        &self,
+        datagrams: &[(&[u8], SocketAddr)],
+    ) -> io::Result<usize> {
+        let over_udp = datagrams
+            .iter()
+            .take_while(|(_, addr)| !self.over_stream(*addr))
+            .count();
+        match over_udp {
+            0 => send_each(self, &datagrams[..datagrams.len().min(1)]),
+            n => self.udp.send_batch(&datagrams[..n]),
+        }
+    }
+
+    /// Moves `addr` off QUIC first, then to TCP.
+    fn timed_out(&self, addr: SocketAddr) {
+        if self.tcp.is_none() && self.quic.is_none() {
+            return;
+        }
+        let mut routes = self.routes();
+        let strikes = routes.strikes.entry(addr).or_default();
+        *strikes += 1;
+        if *strikes < self.fallback_after {
+            return;
+        }
+        if let Some(to) = routes.over_quic.remove(&addr) {
+            info!("Requests to {addr} over QUIC keep timing out, back to UDP");
+            routes.from_quic.remove(&to);
+            routes.quic_failed.insert(addr);
+            routes.strikes.remove(&addr);
+            return;
+        }
+        if self.tcp.is_some() && routes.over_tcp.insert(addr) {
+            info!("Requests to {addr} keep timing out, switching to TCP");
+        }
+    }
+
+    /// The QUIC port and WebRTC, if any.
+    fn capabilities(&self) -> Vec<String> {
+        let port = self.quic.as_ref().and_then(|quic| quic.local_addr().ok());
+        let quic = port.map(|addr| quic::capability(addr.port()));
+        let webrtc = self.webrtc.as_ref().map(|webrtc| webrtc.capabilities());
+        quic.into_iter().chain(webrtc.into_iter().flatten()).collect()
+    }
+
+    /// Routes `addr` over QUIC when it advertises a QUIC port, and notes
+    /// whether it takes WebRTC offers.
+    fn learn_capabilities(&self, addr: SocketAddr, capabilities: &[String]) {
+        if self.webrtc.is_some()
+            && capabilities.iter().any(|tag| tag == webrtc::CAPABILITY)
+        {
+            self.routes().webrtc_peers.insert(addr);
+        }
+        if self.quic.is_none() {
+            return;
+        }
+        let Some(port) = quic::advertised_port(capabilities) else {
+            return;
+        };
+        let to = SocketAddr::new(addr.ip(), port);
+        let mut routes = self.routes();
+        if routes.quic_failed.contains(&addr) {
+            return;
+        }
+        if routes.over_quic.insert(addr, to) != Some(to) {
+            debug!("Reaching {addr} over QUIC at {to}");
+        }
+        routes.from_quic.insert(to, addr);
+    }
+
+    /// Offers a WebRTC channel to destinations advertising WebRTC.
+    fn negotiate(&self, peer_id: &str, addr: SocketAddr) -> bool {
+        match &self.webrtc {
+            Some(webrtc) if self.routes().webrtc_peers.contains(&addr) => {
+                webrtc.negotiate(peer_id, addr)
+            }
+            _ => false,
+        }
+    }
+
+    fn take_signals(&self) -> Vec<(String, Vec<u8>)> {
+        let signals = self.webrtc.as_ref().map(|webrtc| webrtc.take_signals());
+        signals.unwrap_or_default()
+    }
+
+    fn on_signal(&self, peer_id: &str, payload: &[u8]) -> bool {
+        self.webrtc
+            .as_ref()
+            .is_some_and(|webrtc| webrtc.on_signal(peer_id, payload))
+    }
+}
@@ -0,0 +1,599 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Datagram transports.
+//!
+//! [`Transport`] abstracts the `send_to`/`recv_from` pair and the bound
+//! address so protocol logic can run over a real [`UdpTransport`] or over
+//! an in-process [`MockTransport`] that delivers datagrams
+//! deterministically.
+//! [`MeteredTransport`] wraps either one to count the traffic.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use log::{debug, warn};
+
+use crate::batch::{self, RecvBatch};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+
+/// A datagram transport.
+///
+/// `recv_from` returns [`io::ErrorKind::WouldBlock`] when no datagram is
+/// available and the transport is nonblocking.
+pub trait Transport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    /// Address this transport is bound to, as seen by the local host.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Receive the datagrams available, up to the capacity of `batch`, into
+    /// `batch` and return how many. Waits for the first one like
+    /// `recv_from`. By default receives a single datagram.
+    fn recv_batch(&self, batch: &mut RecvBatch) -> io::Result<usize> {
+        batch.clear();
+        batch.receive_with(|buf| self.recv_from(buf))?;
+        Ok(batch.len())
+    }
+
+    /// Send `datagrams` in order and return how many were sent before one
+    /// could not be. Fails only when the first one can not be sent. By
+    /// default sends them one by one.
+    fn send_batch(
+        &self,
+        datagrams: &[(&[u8], SocketAddr)],
+    ) -> io::Result<usize> {
+        batch::send_each(self, datagrams)
+    }
+
+    /// Note that a request sent to `addr` got no reply in time. Transports
+    /// able to reach `addr` another way may switch to it, see
+    /// [`crate::tcp::FallbackTransport`].
+    fn timed_out(&self, _addr: SocketAddr) {}
+
+    /// Capability tags telling peers about other ways to reach this
+    /// transport, advertised when registering. None by default.
+    fn capabilities(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Note that the peer at `addr` advertises `capabilities`, so the
+    /// transport may reach it another way, see [`Transport::capabilities`].
+    fn learn_capabilities(&self, _addr: SocketAddr, _capabilities: &[String]) {
+    }
+
+    /// Start setting up another path to `peer_id`, known at `addr`, by
+    /// exchanging signals with it through the rendezvous server, see
+    /// [`Transport::take_signals`]. Returns whether one is being set up,
+    /// never by default.
+    fn negotiate(&self, _peer_id: &str, _addr: SocketAddr) -> bool {
+        false
+    }
+
+    /// Signals to relay to other peers, with the peer id each is for.
+    fn take_signals(&self) -> Vec<(String, Vec<u8>)> {
+        Vec::new()
+    }
+
+    /// Handle `payload`, relayed from `peer_id`. Returns whether it was a
+    /// signal of this transport, never by default.
+    fn on_signal(&self, _peer_id: &str, _payload: &[u8]) -> bool {
+        false
+    }
+}
+
+/// Options applied to a UDP socket before it is bound.
+#[derive(Debug, Clone, Copy)]
+pub struct SocketOptions {
+    /// Set `SO_REUSEADDR`, so a restarted process can bind the same port
+    /// while the previous socket is still being torn down.
+    pub reuse_address: bool,
+    /// Set `SO_REUSEPORT`, so several processes can bind the same port and
+    /// share its datagrams. Only on Unix.
+    pub reuse_port: bool,
+    /// Kernel receive buffer size (`SO_RCVBUF`) in bytes. `None` keeps the
+    /// OS default.
+    ///
+    /// Datagrams arriving while the buffer is full are dropped by the
+    /// kernel without any error reaching the application, so a busy
+    /// server with a small buffer silently loses requests.
+    ///
+    /// A few MiB (e.g. `4194304`) absorb bursts of several thousand
+    /// requests. The OS may clamp the size: Linux caps it at
+    /// `net.core.rmem_max` (often 208 KiB) and reports twice the value
+    /// set, to account for its bookkeeping, see
+    /// [`UdpTransport::buffer_sizes`].
+    pub recv_buffer_size: Option<usize>,
+    /// Kernel send buffer size (`SO_SNDBUF`) in bytes. `None` keeps the OS
+    /// default.
+    ///
+    /// Only matters when replies are sent in bursts, e.g. relayed traffic;
+    /// 1 MiB (`1048576`) is usually plenty. Linux caps it at
+    /// `net.core.wmem_max`.
+    pub send_buffer_size: Option<usize>,
+    /// When bound to an IPv6 address, carry IPv4 too by clearing
+    /// `IPV6_V6ONLY`, so binding `[::]` serves both families. Some systems,
+    /// like OpenBSD, never carry IPv4 on an IPv6 socket.
+    pub dual_stack: bool,
+    /// Time to live of outbound datagrams (`IP_TTL`, `IPV6_UNICAST_HOPS`).
+    /// `None` keeps the OS default, usually 64.
+    ///
+    /// A low value lets a hole punching probe open the local NAT mapping
+    /// without reaching, and upsetting, the NAT of the other peer.
+    pub ttl: Option<u32>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        SocketOptions {
+            reuse_address: true,
+            reuse_port: false,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            dual_stack: true,
+            ttl: None,
+        }
+    }
+}
+
+/// Transport backed by an OS UDP socket.
+#[derive(Debug)]
+pub struct UdpTransport {
+    socket: UdpSocket,
+    /// Whether `socket` is an IPv6 one, which reaches IPv4 addresses
+    /// mapped.
+    ipv6: bool,
+}
+
+impl UdpTransport {
+    /// Bind a UDP socket to `addr`, dual-stack if it is an IPv6 one, see
+    /// [`SocketOptions::dual_stack`].
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let options =
+            SocketOptions { reuse_address: false, ..Default::default() };
+        Self::bind_with(addr, &options)
+    }
+
+    /// Bind a UDP socket to `addr` after applying `options`.
+    pub fn bind_with<A: ToSocketAddrs>(
+        addr: A,
+        options: &SocketOptions,
+    ) -> io::Result<Self> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no address to bind")
+        })?;
+
+        let socket = Socket::new(
+            Domain::for_address(addr),
+            Type::DGRAM,
+            Some(Protocol::UDP),
+        )?;
+        socket.set_reuse_address(options.reuse_address)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(options.reuse_port)?;
+        if let Some(size) = options.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = options.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if addr.is_ipv6()
+            && options.dual_stack
+            && let Err(e) = socket.set_only_v6(false)
+        {
+            warn!("{addr} does not carry IPv4: {e}");
+        }
+        if let Some(ttl) = options.ttl {
+            if addr.is_ipv6() {
+                socket.set_unicast_hops_v6(ttl)?;
+                // IPv4 datagrams of a dual-stack socket have their own.
+                if options.dual_stack
+                    && let Err(e) = socket.set_ttl_v4(ttl)
+                {
+                    debug!("{addr} keeps the IPv4 TTL: {e}");
+                }
+            } else {
+                socket.set_ttl_v4(ttl)?;
+            }
+        }
+        socket.bind(&addr.into())?;
+
+        Ok(UdpTransport::from_socket(socket.into()))
+    }
+
+    pub fn from_socket(socket: UdpSocket) -> Self {
+        let ipv6 = socket.local_addr().is_ok_and(|addr| addr.is_ipv6());
+        UdpTransport { socket, ipv6 }
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Receive and send buffer sizes actually granted by the OS, which may
+    /// differ from the requested ones.
+    pub fn buffer_sizes(&self) -> io::Result<(usize, usize)> {
+        let socket = SockRef::from(&self.socket);
+        Ok((socket.recv_buffer_size()?, socket.send_buffer_size()?))
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if self.ipv6 {
+            self.socket.send_to(buf, to_ipv6(addr))
+        } else {
+            self.socket.send_to(buf, addr)
+        }
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (len, from) = self.socket.recv_from(buf)?;
+        Ok((len, canonical(from)))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn recv_batch(&self, batch: &mut RecvBatch) -> io::Result<usize> {
+        let received = batch::recv_mmsg(&self.socket, batch)?;
+        batch.map_senders(canonical);
+        Ok(received)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn send_batch(
+        &self,
+        datagrams: &[(&[u8], SocketAddr)],
+    ) -> io::Result<usize> {
+        if !self.ipv6 {
+            return batch::send_mmsg(&self.socket, datagrams);
+        }
+        let mapped: Vec<(&[u8], SocketAddr)> = datagrams
+            .iter()
+            .map(|(datagram, to)| (*datagram, to_ipv6(*to)))
+            .collect();
+        batch::send_mmsg(&self.socket, &mapped)
+    }
+}
+
+/// `addr` with an IPv4-mapped IPv6 address turned back into IPv4, as
+/// dual-stack sockets report IPv4 peers, so every peer has one address.
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+/// `addr` as an IPv6 socket reaches it, IPv4 addresses mapped.
+pub fn to_ipv6(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            SocketAddr::new(ip.to_ipv6_mapped().into(), addr.port())
+        }
+        IpAddr::V6(_) => addr,
+    }
+}
+
+/// Local address the OS sends from to reach `dest`, `None` when there is
+/// no route to it. Found by connecting a UDP socket, which sends nothing.
+pub fn source_address(dest: IpAddr) -> Option<IpAddr> {
+    let unspecified = match dest {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind((unspecified, 0)).ok()?;
+    socket.connect((dest, 9)).ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+/// Traffic counters shared by one or more [`MeteredTransport`]s.
+#[derive(Debug, Default)]
+pub struct TrafficCounters {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    /// Part of `bytes_out` forwarded on behalf of other peers.
+    bytes_relayed: AtomicU64,
+    /// Failed sends and receives, not counting `WouldBlock`.
+    errors: AtomicU64,
+}
+
+impl TrafficCounters {
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_relayed(&self) -> u64 {
+        self.bytes_relayed.load(Ordering::Relaxed)
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Count `len` sent bytes as relayed.
+    pub fn add_relayed(&self, len: usize) {
+        self.bytes_relayed.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// Zero every counter.
+    pub fn reset(&self) {
+        self.bytes_in.store(0, Ordering::Relaxed);
+        self.bytes_out.store(0, Ordering::Relaxed);
+        self.bytes_relayed.store(0, Ordering::Relaxed);
+        self.errors.store(0, Ordering::Relaxed);
+    }
+
+    fn count<T>(&self, result: &io::Result<T>, bytes: &AtomicU64, len: usize) {
+        match result {
+            Ok(_) => {
+                bytes.fetch_add(len as u64, Ordering::Relaxed);
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(_) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Transport counting the bytes and errors of another transport.
+#[derive(Debug)]
+pub struct MeteredTransport<T: Transport> {
+    inner: T,
+    counters: Arc<TrafficCounters>,
+}
+
+impl<T: Transport> MeteredTransport<T> {
+    pub fn new(inner: T, counters: Arc<TrafficCounters>) -> Self {
+        MeteredTransport { inner, counters }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn counters(&self) -> &TrafficCounters {
+        &self.counters
+    }
+}
+
+impl<T: Transport> Transport for MeteredTransport<T> {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let result = self.inner.send_to(buf, addr);
+        let len = *result.as_ref().unwrap_or(&0);
+        self.counters.count(&result, &self.counters.bytes_out, len);
+        result
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let result = self.inner.recv_from(buf);
+        let len = result.as_ref().map_or(0, |(len, _)| *len);
+        self.counters.count(&result, &self.counters.bytes_in, len);
+        result
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn recv_batch(&self, batch: &mut RecvBatch) -> io::Result<usize> {
+        let result = self.inner.recv_batch(batch);
+        let len = batch.iter().map(|(datagram, _)| datagram.len()).sum();
+        self.counters.count(&result, &self.counters.bytes_in, len);
+        result
+    }
+
+    fn send_batch(
+        &self,
+        datagrams: &[(&[u8], SocketAddr)],
+    ) -> io::Result<usize> {
+        let result = self.inner.send_batch(datagrams);
+        let sent = *result.as_ref().unwrap_or(&0);
+        let len = datagrams[..sent].iter().map(|(d, _)| d.len()).sum();
+        self.counters.count(&result, &self.counters.bytes_out, len);
+        result
+    }
+
+    fn timed_out(&self, addr: SocketAddr) {
+        self.inner.timed_out(addr);
+    }
+
+    fn capabilities(&self) -> Vec<String> {
+        self.inner.capabilities()
+    }
+
+    fn learn_capabilities(&self, addr: SocketAddr, capabilities: &[String]) {
+        self.inner.learn_capabilities(addr, capabilities);
+    }
+
+    fn negotiate(&self, peer_id: &str, addr: SocketAddr) -> bool {
+        self.inner.negotiate(peer_id, addr)
+    }
+
+    fn take_signals(&self) -> Vec<(String, Vec<u8>)> {
+        self.inner.take_signals()
+    }
+
+    fn on_signal(&self, peer_id: &str, payload: &[u8]) -> bool {
+        self.inner.on_signal(peer_id, payload)
+    }
+}
+
+type Mailboxes = HashMap<SocketAddr, VecDeque<(Vec<u8>, SocketAddr)>>;
+
+/// In-process network connecting [`MockTransport`]s.
+///
+/// Datagrams are queued per destination address in send order and never
+/// lost, reordered or duplicated. Datagrams sent to an address nobody is
+/// bound to are dropped, like UDP would.
+#[derive(Debug, Clone, Default)]
+pub struct MockNetwork {
+    mailboxes: Arc<Mutex<Mailboxes>>,
+}
+
+impl MockNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attach a new transport to the network at `addr`.
+    pub fn bind(&self, addr: SocketAddr) -> io::Result<MockTransport> {
+        let mut mailboxes = self.lock();
+        if mailboxes.contains_key(&addr) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{addr} is already bound"),
+            ));
+        }
+        mailboxes.insert(addr, VecDeque::new());
+
+        Ok(MockTransport { addr, network: self.clone() })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Mailboxes> {
+        // A panic while holding the lock leaves the queues intact.
+        self.mailboxes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Transport attached to a [`MockNetwork`]. Always nonblocking.
+#[derive(Debug)]
+pub struct MockTransport {
+    addr: SocketAddr,
+    network: MockNetwork,
+}
+
+impl Transport for MockTransport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if let Some(queue) = self.network.lock().get_mut(&addr) {
+            queue.push_back((buf.to_vec(), self.addr));
+        }
+        Ok(buf.len())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut mailboxes = self.network.lock();
+        let queue = mailboxes.get_mut(&self.addr).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotConnected, "transport closed")
+        })?;
+
+        match queue.pop_front() {
+            Some((data, from)) => {
+                // Same truncation semantics as a UDP socket.
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                Ok((len, from))
+            }
+            None => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+}
+
+impl Drop for MockTransport {
+    fn drop(&mut self) {
+        self.network.lock().remove(&self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn recv(transport: &MockTransport) -> io::Result<(Vec<u8>, SocketAddr)> {
+        let mut buf = [0u8; 16];
+        let (len, from) = transport.recv_from(&mut buf)?;
+        Ok((buf[..len].to_vec(), from))
+    }
+
+    #[test]
+    fn receive_buffer_is_applied_before_bind() {
+        let default = UdpTransport::bind("127.0.0.1:0").unwrap();
+        let options = SocketOptions {
+            recv_buffer_size: Some(4 * 1024),
+            ..Default::default()
+        };
+        let small = UdpTransport::bind_with("127.0.0.1:0", &options).unwrap();
+        // The kernel rounds the size up, but not to its default.
+        let (recv_buffer, _) = small.buffer_sizes().unwrap();
+        assert!(recv_buffer < default.buffer_sizes().unwrap().0);
+    }
+
+    #[test]
+    fn mock_delivers_in_send_order() {
+        let network = MockNetwork::new();
+        let a = network.bind(addr("10.0.0.1:1")).unwrap();
+        let b = network.bind(addr("10.0.0.2:1")).unwrap();
+        a.send_to(b"one", b.local_addr().unwrap()).unwrap();
+        a.send_to(b"two", b.local_addr().unwrap()).unwrap();
+
+        assert_eq!(recv(&b).unwrap(), (b"one".to_vec(), addr("10.0.0.1:1")));
+        assert_eq!(recv(&b).unwrap(), (b"two".to_vec(), addr("10.0.0.1:1")));
+        let empty = recv(&b).unwrap_err();
+        assert_eq!(empty.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn mock_truncates_like_udp() {
+        let network = MockNetwork::new();
+        let a = network.bind(addr("10.0.0.1:1")).unwrap();
+        a.send_to(&[7; 32], a.local_addr().unwrap()).unwrap();
+        assert_eq!(recv(&a).unwrap().0, [7; 16]);
+    }
+
+    #[test]
+    fn mock_drops_datagrams_to_unbound_addresses() {
+        let network = MockNetwork::new();
+        let a = network.bind(addr("10.0.0.1:1")).unwrap();
+        assert_eq!(a.send_to(b"lost", addr("10.0.0.9:1")).unwrap(), 4);
+
+        // Binding afterwards does not deliver what was sent before.
+        let late = network.bind(addr("10.0.0.9:1")).unwrap();
+        let empty = recv(&late).unwrap_err();
+        assert_eq!(empty.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn mock_address_is_freed_on_drop() {
+        let network = MockNetwork::new();
+        let a = network.bind(addr("10.0.0.1:1")).unwrap();
+        let taken = network.bind(addr("10.0.0.1:1")).unwrap_err();
+        assert_eq!(taken.kind(), io::ErrorKind::AddrInUse);
+
+        drop(a);
+        network.bind(addr("10.0.0.1:1")).unwrap();
+    }
+}
@@ -0,0 +1,79 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Stall detection for long running loops.
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{error, info};
+
+/// Watchdog
+///
+/// The watched loop calls [`Watchdog::beat`] on every iteration; a
+/// separate thread logs an error when no beat arrived within the
+/// threshold, and again once the loop recovers. The thread stops when the
+/// watchdog is dropped.
+pub struct Watchdog {
+    start: Instant,
+    /// Milliseconds since `start` of the last beat.
+    last_beat: Arc<AtomicU64>,
+}
+
+impl Watchdog {
+    pub fn spawn(name: &str, threshold: Duration) -> Self {
+        let start = Instant::now();
+        let last_beat = Arc::new(AtomicU64::new(0));
+        let beats = Arc::downgrade(&last_beat);
+        let name = name.to_string();
+        let check = (threshold / 4).max(Duration::from_millis(10));
+
+        thread::spawn(move || {
+            let mut stalled = false;
+
+            while let Some(beats) = beats.upgrade() {
+                let last =
+                    Duration::from_millis(beats.load(Ordering::Relaxed));
+                let idle = start.elapsed().saturating_sub(last);
+
+                if idle > threshold && !stalled {
+                    error!("{name} stalled: no iteration for {idle:?}");
+                    stalled = true;
+                } else if idle <= threshold && stalled {
+                    info!("{name} recovered");
+                    stalled = false;
+                }
+
+                drop(beats);
+                thread::sleep(check);
+            }
+        });
+
+        Watchdog { start, last_beat }
+    }
+
+    /// Record an iteration of the watched loop.
+    pub fn beat(&self) {
+        let now = self.start.elapsed().as_millis() as u64;
+        self.last_beat.store(now, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,642 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! WebRTC transport.
+//!
+//! Some NATs, the symmetric ones, map every destination to a port of its
+//! own, so the address the rendezvous server sees is not the one a peer
+//! must punch towards and probes never get through. [`WebRtcTransport`]
+//! reaches such peers over WebRTC data channels instead: ICE tries every
+//! pair of candidate addresses, through STUN and TURN servers when given
+//! some, and keeps the first one to answer.
+//!
+//! The session descriptions are exchanged through the rendezvous server,
+//! relayed like any payload, see [`RendezvousMessage::Relay`]. Each one
+//! carries every candidate of its side, so a single offer and answer set
+//! up a channel. The transport only produces and consumes them, see
+//! [`Transport::negotiate`]; the client relays them, through a server
+//! started with `--relay`.
+//!
+//! Peers advertise support with the [`CAPABILITY`] tag, and
+//! [`crate::tcp::FallbackTransport`] negotiates a channel with those the
+//! hole punch did not reach.
+//!
+//! [`RendezvousMessage::Relay`]: crate::protocol::RendezvousMessage::Relay
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Condvar, Mutex, MutexGuard, Weak},
+    thread,
+    time::Duration,
+};
+
+use ::webrtc::{
+    data_channel::{DataChannel, DataChannelEvent, RTCDataChannelInit},
+    peer_connection::{
+        PeerConnection, PeerConnectionBuilder, PeerConnectionEventHandler,
+        RTCConfigurationBuilder, RTCIceGatheringState, RTCIceServer,
+        RTCSdpType, RTCSessionDescription,
+    },
+};
+use bytes::BytesMut;
+use log::{debug, info};
+use tokio::{
+    runtime::Handle,
+    sync::{mpsc, oneshot},
+    time::timeout,
+};
+
+use crate::{
+    protocol,
+    transport::{Transport, canonical},
+};
+
+/// Capability tag of the peers accepting WebRTC offers.
+pub const CAPABILITY: &str = "webrtc";
+
+/// Start of every signal, telling them apart from other relayed payloads.
+const SIGNAL_PREFIX: &[u8] = b"tesseras-webrtc\n";
+
+/// Label of the data channel carrying the datagrams.
+const LABEL: &str = "tesseras";
+
+/// How long to gather the local candidates of a session description.
+const GATHER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a negotiation may take, from the offer to the open channel.
+const NEGOTIATE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Maximum number of connections, open or being negotiated. Further
+/// offers are ignored.
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// Maximum number of received messages waiting for `recv_from`. Messages
+/// beyond this are dropped, like datagrams on a full socket buffer.
+const MAX_QUEUED_MESSAGES: usize = 1024;
+
+type Failure = Box<dyn std::error::Error + Send + Sync>;
+
+/// State shared by the handles of a [`WebRtcTransport`] and its tasks.
+#[derive(Debug)]
+struct Shared {
+    /// Address the channels are bound to, port zero.
+    local_addr: SocketAddr,
+    /// STUN and TURN server URLs, see [`WebRtcTransport::with_ice_servers`].
+    ice_servers: Mutex<Vec<String>>,
+    /// Runtime driving the connections, see [`WebRtcTransport::bind`].
+    runtime: Handle,
+    /// Peers with a connection, open or being negotiated, by peer id.
+    peers: Mutex<HashSet<String>>,
+    /// Offers waiting for their answer, by peer id.
+    answers: Mutex<HashMap<String, oneshot::Sender<RTCSessionDescription>>>,
+    /// Queue of the task writing to each open channel, by peer address.
+    channels: Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<Vec<u8>>>>,
+    /// Signals waiting to be relayed, with the peer id they are for.
+    signals: Mutex<Vec<(String, Vec<u8>)>>,
+    /// Received messages, oldest first.
+    inbox: Mutex<VecDeque<(Vec<u8>, SocketAddr)>>,
+    arrived: Condvar,
+    /// Stops the runtime thread once dropped.
+    _stop: oneshot::Sender<()>,
+}
+
+/// Lock `mutex`. A panic while holding it leaves the data intact.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Shared {
+    fn deliver(&self, data: Vec<u8>, from: SocketAddr) {
+        let mut inbox = lock(&self.inbox);
+        if inbox.len() >= MAX_QUEUED_MESSAGES {
+            debug!("Dropping a WebRTC message from {from}: queue full");
+            return;
+        }
+        inbox.push_back((data, from));
+        self.arrived.notify_one();
+    }
+
+    fn signal(&self, peer_id: &str, description: &RTCSessionDescription) {
+        let mut payload = SIGNAL_PREFIX.to_vec();
+        match serde_json::to_writer(&mut payload, description) {
+            Ok(()) => lock(&self.signals).push((peer_id.to_string(), payload)),
+            Err(e) => debug!("Could not encode a signal to {peer_id}: {e}"),
+        }
+    }
+}
+
+/// Transport carrying datagrams over WebRTC data channels.
+///
+/// A channel is set up by [`Transport::negotiate`] on one side and
+/// [`Transport::on_signal`] on the other, each passing the signals of
+/// [`Transport::take_signals`] to the other through the rendezvous
+/// server. Datagrams can be sent to a peer once its channel is open, see
+/// [`WebRtcTransport::is_connected`]. The side that made the offer knows
+/// the peer by the address given to `negotiate`, the other by the address
+/// ICE reached it at.
+///
+/// Channels are unordered and never retransmit, so they behave like UDP.
+/// Handles made with [`Clone`] share the channels, each has its own
+/// blocking mode. New transports are nonblocking.
+#[derive(Debug, Clone)]
+pub struct WebRtcTransport {
+    shared: Arc<Shared>,
+    nonblocking: bool,
+}
+
+impl WebRtcTransport {
+    /// Gather candidates on `ip`, every interface when unspecified.
+    pub fn bind(ip: IpAddr) -> io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let (stop, stopped) = oneshot::channel::<()>();
+        let shared = Arc::new(Shared {
+            local_addr: SocketAddr::new(ip, 0),
+            ice_servers: Mutex::new(Vec::new()),
+            runtime: runtime.handle().clone(),
+            peers: Mutex::new(HashSet::new()),
+            answers: Mutex::new(HashMap::new()),
+            channels: Mutex::new(HashMap::new()),
+            signals: Mutex::new(Vec::new()),
+            inbox: Mutex::new(VecDeque::new()),
+            arrived: Condvar::new(),
+            _stop: stop,
+        });
+        thread::Builder::new().name("webrtc".to_string()).spawn(
+            move || {
+                let _ = runtime.block_on(stopped);
+            },
+        )?;
+        Ok(WebRtcTransport { shared, nonblocking: true })
+    }
+
+    /// Gather candidates through the STUN and TURN servers at `urls`, e.g.
+    /// `stun:stun.example.org:3478`. Without any only the addresses of the
+    /// local interfaces are tried.
+    pub fn with_ice_servers(self, urls: Vec<String>) -> Self {
+        *lock(&self.shared.ice_servers) = urls;
+        self
+    }
+
+    /// Make `recv_from` wait for a message instead of failing with
+    /// [`io::ErrorKind::WouldBlock`].
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    /// Whether a channel with `addr` is open.
+    pub fn is_connected(&self, addr: SocketAddr) -> bool {
+        lock(&self.shared.channels).contains_key(&addr)
+    }
+
+    /// Number of connections, open or being negotiated.
+    pub fn connections(&self) -> usize {
+        lock(&self.shared.peers).len()
+    }
+
+    /// Claim a connection slot for `peer_id`, unless it has one already
+    /// or none is left.
+    fn claim(&self, peer_id: &str) -> bool {
+        let mut peers = lock(&self.shared.peers);
+        if peers.len() >= MAX_CONNECTIONS || peers.contains(peer_id) {
+            return false;
+        }
+        peers.insert(peer_id.to_string())
+    }
+}
+
+impl Transport for WebRtcTransport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if buf.len() > protocol::MAX_MESSAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} bytes is too large to send", buf.len()),
+            ));
+        }
+        let channels = lock(&self.shared.channels);
+        let Some(queue) = channels.get(&addr) else {
+            return Err(io::ErrorKind::NotConnected.into());
+        };
+        queue
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::NotConnected))?;
+        Ok(buf.len())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut inbox = lock(&self.shared.inbox);
+        loop {
+            if let Some((data, from)) = inbox.pop_front() {
+                // Same truncation semantics as a UDP socket.
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                return Ok((len, from));
+            }
+            if self.nonblocking {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            inbox = self
+                .shared
+                .arrived
+                .wait(inbox)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// The address candidates are gathered on, with port zero: each
+    /// connection has sockets of its own.
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.shared.local_addr)
+    }
+
+    fn capabilities(&self) -> Vec<String> {
+        vec![CAPABILITY.to_string()]
+    }
+
+    /// Sends an offer to `peer_id`, unless a connection with it is open or
+    /// being negotiated already.
+    fn negotiate(&self, peer_id: &str, addr: SocketAddr) -> bool {
+        if !self.claim(peer_id) {
+            return lock(&self.shared.peers).contains(peer_id);
+        }
+        let (answer, answered) = oneshot::channel();
+        lock(&self.shared.answers).insert(peer_id.to_string(), answer);
+        let shared = Arc::downgrade(&self.shared);
+        let peer_id = peer_id.to_string();
+        self.shared.runtime.spawn(async move {
+            let result = timeout(
+                NEGOTIATE_TIMEOUT,
+                offer(&shared, &peer_id, addr, answered),
+            )
+            .await
+            .unwrap_or_else(|_| Err("timed out".into()));
+            if let Err(e) = result {
+                info!("No WebRTC channel to {peer_id} at {addr}: {e}");
+            }
+            if let Some(shared) = shared.upgrade() {
+                lock(&shared.answers).remove(&peer_id);
+                lock(&shared.peers).remove(&peer_id);
+            }
+        });
+        true
+    }
+
+    fn take_signals(&self) -> Vec<(String, Vec<u8>)> {
+        std::mem::take(&mut *lock(&self.shared.signals))
+    }
+
+    /// Answers offers and hands answers to the offer they answer. Offers
+    /// from a peer a connection is open or negotiated with are ignored.
+    fn on_signal(&self, peer_id: &str, payload: &[u8]) -> bool {
+        let Some(json) = payload.strip_prefix(SIGNAL_PREFIX) else {
+            return false;
+        };
+        let description: RTCSessionDescription =
+            match serde_json::from_slice(json) {
+                Ok(description) => description,
+                Err(e) => {
+                    debug!("Ignoring a bad signal from {peer_id}: {e}");
+                    return true;
+                }
+            };
+
+        match description.sdp_type {
+            RTCSdpType::Offer if self.claim(peer_id) => {
+                let shared = Arc::downgrade(&self.shared);
+                let peer_id = peer_id.to_string();
+                self.shared.runtime.spawn(async move {
+                    let result = timeout(
+                        NEGOTIATE_TIMEOUT,
+                        answer(&shared, &peer_id, description),
+                    )
+                    .await
+                    .unwrap_or_else(|_| Err("timed out".into()));
+                    if let Err(e) = result {
+                        info!("No WebRTC channel from {peer_id}: {e}");
+                    }
+                    if let Some(shared) = shared.upgrade() {
+                        lock(&shared.peers).remove(&peer_id);
+                    }
+                });
+            }
+            RTCSdpType::Answer => {
+                if let Some(answer) =
+                    lock(&self.shared.answers).remove(peer_id)
+                {
+                    let _ = answer.send(description);
+                }
+            }
+            _ => debug!("Ignoring a signal from {peer_id}"),
+        }
+        true
+    }
+}
+
+/// Events of a connection the negotiation waits for.
+struct Events {
+    gathered: mpsc::Sender<()>,
+    channels: mpsc::Sender<Arc<dyn DataChannel>>,
+}
+
+#[async_trait::async_trait]
+impl PeerConnectionEventHandler for Events {
+    async fn on_ice_gathering_state_change(
+        &self,
+        state: RTCIceGatheringState,
+    ) {
+        if state == RTCIceGatheringState::Complete {
+            let _ = self.gathered.try_send(());
+        }
+    }
+
+    async fn on_data_channel(&self, channel: Arc<dyn DataChannel>) {
+        let _ = self.channels.try_send(channel);
+    }
+}
+
+/// A connection under negotiation and the receivers of its events.
+struct Negotiation {
+    connection: Arc<dyn PeerConnection>,
+    gathered: mpsc::Receiver<()>,
+    channels: mpsc::Receiver<Arc<dyn DataChannel>>,
+}
+
+impl Negotiation {
+    async fn new(shared: &Weak<Shared>) -> Result<Self, Failure> {
+        let (ip, urls) = match shared.upgrade() {
+            Some(shared) => {
+                (shared.local_addr.ip(), lock(&shared.ice_servers).clone())
+            }
+            None => return Err("transport dropped".into()),
+        };
+        let ice_servers = match urls.is_empty() {
+            true => Vec::new(),
+            false => vec![RTCIceServer { urls, ..Default::default() }],
+        };
+
+        let (gathered_tx, gathered) = mpsc::channel(1);
+        let (channels_tx, channels) = mpsc::channel(1);
+        let connection = PeerConnectionBuilder::new()
+            .with_configuration(
+                RTCConfigurationBuilder::new()
+                    .with_ice_servers(ice_servers)
+                    .build(),
+            )
+            .with_handler(Arc::new(Events {
+                gathered: gathered_tx,
+                channels: channels_tx,
+            }))
+            .with_udp_addrs(vec![SocketAddr::new(ip, 0)])
+            .build()
+            .await?;
+        Ok(Negotiation {
+            connection: Arc::new(connection),
+            gathered,
+            channels,
+        })
+    }
+
+    /// Set `description` as the local one, then signal it to `peer_id`
+    /// with every candidate gathered.
+    async fn describe(
+        &mut self,
+        shared: &Weak<Shared>,
+        peer_id: &str,
+        description: RTCSessionDescription,
+    ) -> Result<(), Failure> {
+        self.connection.set_local_description(description).await?;
+        // Candidates gathered so far still go out on a timeout.
+        let _ = timeout(GATHER_TIMEOUT, self.gathered.recv()).await;
+        let description = self
+            .connection
+            .local_description()
+            .await
+            .ok_or("no local description")?;
+        match shared.upgrade() {
+            Some(shared) => shared.signal(peer_id, &description),
+            None => return Err("transport dropped".into()),
+        }
+        Ok(())
+    }
+}
+
+/// Offer a channel to `peer_id`, known at `addr`, and carry datagrams
+/// over it once `answered`.
+async fn offer(
+    shared: &Weak<Shared>,
+    peer_id: &str,
+    addr: SocketAddr,
+    answered: oneshot::Receiver<RTCSessionDescription>,
+) -> Result<(), Failure> {
+    let mut negotiation = Negotiation::new(shared).await?;
+    let init = RTCDataChannelInit {
+        ordered: false,
+        max_retransmits: Some(0),
+        ..Default::default()
+    };
+    let channel =
+        negotiation.connection.create_data_channel(LABEL, Some(init)).await?;
+    let offer = negotiation.connection.create_offer(None).await?;
+    negotiation.describe(shared, peer_id, offer).await?;
+
+    let answer = answered.await.map_err(|_| "no answer")?;
+    negotiation.connection.set_remote_description(answer).await?;
+    opened(&channel).await?;
+
+    debug!("Opened a WebRTC channel to {peer_id} at {addr}");
+    carry(shared, &negotiation.connection, channel, canonical(addr)).await;
+    Ok(())
+}
+
+/// Answer the `offer` of `peer_id` and carry datagrams over the channel it
+/// opens, knowing the peer by the address ICE reached it at.
+async fn answer(
+    shared: &Weak<Shared>,
+    peer_id: &str,
+    offer: RTCSessionDescription,
+) -> Result<(), Failure> {
+    let mut negotiation = Negotiation::new(shared).await?;
+    negotiation.connection.set_remote_description(offer).await?;
+    let answer = negotiation.connection.create_answer(None).await?;
+    negotiation.describe(shared, peer_id, answer).await?;
+
+    let channel = negotiation.channels.recv().await.ok_or("no channel")?;
+    opened(&channel).await?;
+    let addr = remote_addr(&negotiation.connection).await?;
+
+    debug!("Opened a WebRTC channel from {peer_id} at {addr}");
+    carry(shared, &negotiation.connection, channel, addr).await;
+    Ok(())
+}
+
+/// Wait for `channel` to open.
+async fn opened(channel: &Arc<dyn DataChannel>) -> Result<(), Failure> {
+    loop {
+        match channel.poll().await {
+            Some(DataChannelEvent::OnOpen) => return Ok(()),
+            Some(DataChannelEvent::OnClose) | None => {
+                return Err("channel closed".into());
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+/// Address of the remote candidate ICE selected.
+async fn remote_addr(
+    connection: &Arc<dyn PeerConnection>,
+) -> Result<SocketAddr, Failure> {
+    let sctp = connection.sctp().await.ok_or("no SCTP transport")?;
+    let pair = sctp
+        .transport()
+        .ice_transport()
+        .get_selected_candidate_pair()
+        .await?
+        .ok_or("no selected candidate pair")?;
+    let remote = pair.remote();
+    let ip: IpAddr = remote.address.parse()?;
+    Ok(canonical(SocketAddr::new(ip, remote.port)))
+}
+
+/// Carry datagrams between `channel` and `addr` until it closes or the
+/// transport is dropped, then close `connection`.
+async fn carry(
+    shared: &Weak<Shared>,
+    connection: &Arc<dyn PeerConnection>,
+    channel: Arc<dyn DataChannel>,
+    addr: SocketAddr,
+) {
+    let (queue, mut datagrams) = mpsc::unbounded_channel::<Vec<u8>>();
+    match shared.upgrade() {
+        Some(shared) => lock(&shared.channels).insert(addr, queue),
+        None => return,
+    };
+
+    loop {
+        tokio::select! {
+            event = channel.poll() => match event {
+                Some(DataChannelEvent::OnMessage(message)) => {
+                    match shared.upgrade() {
+                        Some(shared) => shared.deliver(message.data.to_vec(), addr),
+                        None => break,
+                    }
+                }
+                Some(DataChannelEvent::OnClose) | None => break,
+                Some(_) => {}
+            },
+            datagram = datagrams.recv() => {
+                let Some(datagram) = datagram else { break };
+                let data = BytesMut::from(&datagram[..]);
+                if let Err(e) = channel.send(data).await {
+                    debug!("Could not send to {addr} over WebRTC: {e}");
+                    break;
+                }
+            }
+        }
+    }
+
+    debug!("WebRTC channel with {addr} closed");
+    if let Some(shared) = shared.upgrade() {
+        lock(&shared.channels).remove(&addr);
+    }
+    let _ = connection.close().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    /// Relay the signals of `a`, peer "a", and `b`, peer "b", to each
+    /// other until `done`, for up to ten seconds.
+    fn exchange(
+        a: &WebRtcTransport,
+        b: &WebRtcTransport,
+        done: impl Fn() -> bool,
+    ) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !done() {
+            assert!(Instant::now() < deadline, "no channel opened");
+            for (to, payload) in a.take_signals() {
+                assert_eq!(to, "b");
+                assert!(b.on_signal("a", &payload));
+            }
+            for (to, payload) in b.take_signals() {
+                assert_eq!(to, "a");
+                assert!(a.on_signal("b", &payload));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Receive from `transport`, polling for up to five seconds.
+    fn recv(transport: &WebRtcTransport) -> (Vec<u8>, SocketAddr) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut buf = [0u8; 64];
+        loop {
+            match transport.recv_from(&mut buf) {
+                Ok((len, from)) => return (buf[..len].to_vec(), from),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    assert!(Instant::now() < deadline, "nothing received");
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => panic!("recv failed: {e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn other_payloads_are_not_signals() {
+        let transport = WebRtcTransport::bind([127, 0, 0, 1].into()).unwrap();
+        assert!(!transport.on_signal("bob", b"hello"));
+        assert!(transport.on_signal("bob", b"tesseras-webrtc\nnot json"));
+        assert_eq!(transport.connections(), 0);
+    }
+
+    #[test]
+    fn offer_and_answer_open_a_channel() {
+        let a = WebRtcTransport::bind([127, 0, 0, 1].into()).unwrap();
+        let b = WebRtcTransport::bind([127, 0, 0, 1].into()).unwrap();
+        let b_addr: SocketAddr = "192.0.2.1:4000".parse().unwrap();
+
+        assert!(a.negotiate("b", b_addr));
+        // Negotiating again reuses the connection.
+        assert!(a.negotiate("b", b_addr));
+        assert_eq!(a.connections(), 1);
+        exchange(&a, &b, || a.is_connected(b_addr));
+
+        a.send_to(b"ping", b_addr).unwrap();
+        let (data, from) = recv(&b);
+        assert_eq!(data, b"ping");
+        assert!(from.ip().is_loopback());
+        assert!(b.is_connected(from));
+
+        b.send_to(b"pong", from).unwrap();
+        assert_eq!(recv(&a), (b"pong".to_vec(), b_addr));
+    }
+
+    #[test]
+    fn unknown_peers_are_not_sent_to() {
+        let transport = WebRtcTransport::bind([127, 0, 0, 1].into()).unwrap();
+        let err = transport
+            .send_to(b"ping", "192.0.2.1:4000".parse().unwrap())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+    }
+}
@@ -0,0 +1,687 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+//! WebSocket transport.
+//!
+//! Browsers can not send UDP, and some proxies only let HTTP through.
+//! [`WebSocketTransport`] carries the same datagrams as binary WebSocket
+//! messages (RFC 6455), one datagram per message, so such clients can
+//! reach a rendezvous server listening for WebSocket connections, see
+//! [`crate::server::ServerConfig::websocket`].
+//!
+//! Only what the protocol needs is implemented: binary messages, possibly
+//! fragmented, pings and closes. A text message closes the connection, and
+//! neither extensions nor subprotocols are negotiated. TLS is left to a
+//! reverse proxy in front of the listener.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Condvar, Mutex, MutexGuard, Weak},
+    thread,
+    time::Duration,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use log::{debug, warn};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    protocol::{self, FrameError},
+    routing::random_id,
+    socks::Socks5Proxy,
+    tcp::MAX_CONNECTIONS,
+    transport::{Transport, canonical},
+};
+
+/// Appended to the key of a handshake before hashing it, see RFC 6455.
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// How long the opening handshake may take.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum size of the head of a handshake request or response.
+const MAX_HANDSHAKE_SIZE: u64 = 8192;
+
+/// How long to wait for an outgoing connection to be accepted.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a write may block before the connection is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often the accept thread checks whether the transport is gone.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Maximum number of received messages waiting for `recv_from`. Messages
+/// beyond this are dropped, like datagrams on a full socket buffer.
+const MAX_QUEUED_MESSAGES: usize = 1024;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+/// Masking keys of the frames a client sends, derived from a random seed
+/// and a counter so no frame costs a read of the entropy source.
+#[derive(Debug)]
+struct Masks {
+    seed: [u8; 20],
+    sent: u64,
+}
+
+impl Masks {
+    fn new() -> io::Result<Self> {
+        Ok(Masks { seed: random_id()?, sent: 0 })
+    }
+
+    fn next(&mut self) -> [u8; 4] {
+        self.sent += 1;
+        let digest = Sha1::new()
+            .chain_update(self.seed)
+            .chain_update(self.sent.to_le_bytes())
+            .finalize();
+        [digest[0], digest[1], digest[2], digest[3]]
+    }
+}
+
+/// An open connection, used for writing.
+#[derive(Debug)]
+struct Connection {
+    stream: TcpStream,
+    /// Masking keys when we are the client end, which must mask.
+    masks: Option<Masks>,
+}
+
+impl Connection {
+    fn write(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mask = self.masks.as_mut().map(Masks::next);
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        encode_frame(&mut frame, opcode, payload, mask);
+        self.stream.write_all(&frame)
+    }
+}
+
+/// State shared by the handles of a [`WebSocketTransport`] and its threads.
+#[derive(Debug)]
+struct Shared {
+    local_addr: Option<SocketAddr>,
+    connections: Mutex<HashMap<SocketAddr, Connection>>,
+    /// Received messages, oldest first.
+    inbox: Mutex<VecDeque<(Vec<u8>, SocketAddr)>>,
+    arrived: Condvar,
+}
+
+impl Shared {
+    fn connections(&self) -> MutexGuard<'_, HashMap<SocketAddr, Connection>> {
+        // A panic while holding the lock leaves the map intact.
+        self.connections.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn inbox(&self) -> MutexGuard<'_, VecDeque<(Vec<u8>, SocketAddr)>> {
+        self.inbox.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn deliver(&self, data: Vec<u8>, from: SocketAddr) {
+        let mut inbox = self.inbox();
+        if inbox.len() >= MAX_QUEUED_MESSAGES {
+            debug!("Dropping a message from {from}: receive queue full");
+            return;
+        }
+        inbox.push_back((data, from));
+        self.arrived.notify_one();
+    }
+
+    /// Write a control frame to `peer`, dropping the connection if that
+    /// fails.
+    fn control(&self, peer: SocketAddr, opcode: u8, payload: &[u8]) {
+        let mut connections = self.connections();
+        if let Some(connection) = connections.get_mut(&peer)
+            && connection.write(opcode, payload).is_err()
+        {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+            connections.remove(&peer);
+        }
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        // Wakes up the reader threads, which hold clones of the streams.
+        for connection in self.connections().values() {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Transport carrying datagrams as binary WebSocket messages.
+///
+/// Like [`crate::tcp::TcpTransport`], a datagram to a peer goes over the
+/// connection it has with us, whoever opened it, and a datagram to any
+/// other address opens a connection to it, as the client end. Each
+/// connection is read by its own thread. Handles made with [`Clone`] share
+/// the connections, each has its own blocking mode. New transports are
+/// nonblocking.
+#[derive(Debug, Clone)]
+pub struct WebSocketTransport {
+    shared: Arc<Shared>,
+    nonblocking: bool,
+    /// Proxy outgoing connections go through.
+    proxy: Option<Arc<Socks5Proxy>>,
+}
+
+impl WebSocketTransport {
+    /// A transport opening connections but accepting none.
+    pub fn new() -> Self {
+        WebSocketTransport {
+            shared: Arc::new(Shared {
+                local_addr: None,
+                connections: Mutex::new(HashMap::new()),
+                inbox: Mutex::new(VecDeque::new()),
+                arrived: Condvar::new(),
+            }),
+            nonblocking: true,
+            proxy: None,
+        }
+    }
+
+    /// Listen on `addr` and start accepting connections.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+
+        let shared = Arc::new(Shared {
+            local_addr: Some(listener.local_addr()?),
+            connections: Mutex::new(HashMap::new()),
+            inbox: Mutex::new(VecDeque::new()),
+            arrived: Condvar::new(),
+        });
+        let weak = Arc::downgrade(&shared);
+        thread::Builder::new()
+            .name("websocket-accept".to_string())
+            .spawn(move || accept(&listener, &weak))?;
+
+        Ok(WebSocketTransport { shared, nonblocking: true, proxy: None })
+    }
+
+    /// Open outgoing connections through `proxy`.
+    pub fn with_proxy(mut self, proxy: Socks5Proxy) -> Self {
+        self.proxy = Some(Arc::new(proxy));
+        self
+    }
+
+    /// Make `recv_from` wait for a message instead of returning
+    /// [`io::ErrorKind::WouldBlock`].
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    /// Whether a connection with `addr` is open.
+    pub fn is_connected(&self, addr: SocketAddr) -> bool {
+        self.shared.connections().contains_key(&addr)
+    }
+
+    /// Number of open connections.
+    pub fn connections(&self) -> usize {
+        self.shared.connections().len()
+    }
+}
+
+impl Default for WebSocketTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for WebSocketTransport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if buf.len() > protocol::MAX_MESSAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                FrameError::TooLarge {
+                    len: buf.len(),
+                    max: protocol::MAX_MESSAGE_SIZE,
+                },
+            ));
+        }
+        let mut connections = self.shared.connections();
+
+        if !connections.contains_key(&addr) {
+            if connections.len() >= MAX_CONNECTIONS {
+                return Err(io::Error::other(
+                    "too many WebSocket connections",
+                ));
+            }
+            let (stream, reader) = connect(addr, self.proxy.as_deref())?;
+            start_reading(&self.shared, reader, addr, false)?;
+            connections.insert(
+                addr,
+                Connection { stream, masks: Some(Masks::new()?) },
+            );
+        }
+
+        let Some(connection) = connections.get_mut(&addr) else {
+            return Err(io::ErrorKind::NotConnected.into());
+        };
+        if let Err(e) = connection.write(OPCODE_BINARY, buf) {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+            connections.remove(&addr);
+            return Err(e);
+        }
+        Ok(buf.len())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut inbox = self.shared.inbox();
+        loop {
+            if let Some((data, from)) = inbox.pop_front() {
+                // Same truncation semantics as a UDP socket.
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                return Ok((len, from));
+            }
+            if self.nonblocking {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            inbox = self
+                .shared
+                .arrived
+                .wait(inbox)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.shared.local_addr.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotConnected, "not listening")
+        })
+    }
+}
+
+/// `Sec-WebSocket-Accept` answering the handshake key `key`.
+pub fn accept_key(key: &str) -> String {
+    let digest = Sha1::new()
+        .chain_update(key.as_bytes())
+        .chain_update(HANDSHAKE_GUID.as_bytes())
+        .finalize();
+    BASE64.encode(digest)
+}
+
+/// Accept connections until the transport is dropped.
+fn accept(listener: &TcpListener, shared: &Weak<Shared>) {
+    loop {
+        let Some(strong) = shared.upgrade() else {
+            return;
+        };
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                let peer = canonical(peer);
+                if strong.connections().len() >= MAX_CONNECTIONS {
+                    warn!(
+                        "Refusing a WebSocket connection from {peer}: too many"
+                    );
+                    continue;
+                }
+                let weak = shared.clone();
+                // The handshake is read by the connection's own thread, so
+                // a slow client does not hold up the others.
+                let spawned = thread::Builder::new()
+                    .name(format!("websocket-{peer}"))
+                    .spawn(move || serve(stream, peer, &weak));
+                if let Err(e) = spawned {
+                    warn!("Dropping a WebSocket connection from {peer}: {e}");
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                drop(strong);
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+            Err(e) => {
+                warn!("Could not accept a WebSocket connection: {e}");
+                drop(strong);
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Answer the handshake of an accepted connection, then read it.
+fn serve(stream: TcpStream, peer: SocketAddr, shared: &Weak<Shared>) {
+    let opened = stream
+        .set_nonblocking(false)
+        .and_then(|()| stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)))
+        .and_then(|()| stream.try_clone())
+        .and_then(|reader| {
+            let mut reader = BufReader::new(reader);
+            answer_handshake(&mut reader, &stream)?;
+            stream.set_read_timeout(None)?;
+            Ok(reader)
+        });
+    let reader = match opened {
+        Ok(reader) => reader,
+        Err(e) => {
+            debug!("WebSocket handshake with {peer} failed: {e}");
+            let _ = stream.shutdown(Shutdown::Both);
+            return;
+        }
+    };
+
+    let Some(strong) = shared.upgrade() else {
+        return;
+    };
+    if let Err(e) = prepare(&stream) {
+        debug!("Dropping the WebSocket connection with {peer}: {e}");
+        return;
+    }
+    strong.connections().insert(peer, Connection { stream, masks: None });
+    drop(strong);
+    debug!("WebSocket connection from {peer}");
+    read(reader, peer, shared, true);
+}
+
+/// Open a connection to `addr`, through `proxy` if any, and make the
+/// opening handshake, as the client end. Returns the stream for writing
+/// and a reader for the rest.
+fn connect(
+    addr: SocketAddr,
+    proxy: Option<&Socks5Proxy>,
+) -> io::Result<(TcpStream, BufReader<TcpStream>)> {
+    let stream = match proxy {
+        Some(proxy) => proxy.connect(addr, CONNECT_TIMEOUT)?,
+        None => TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?,
+    };
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let key = BASE64.encode(&random_id()?[..16]);
+    let request = format!(
+        "GET / HTTP/1.1\r\nHost: {addr}\r\nUpgrade: websocket\r\n\
+         Connection: Upgrade\r\nSec-WebSocket-Key: {key}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n"
+    );
+    (&stream).write_all(request.as_bytes())?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let head = read_head(&mut reader)?;
+    let status = head.first().map(String::as_str).unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("101") {
+        return Err(handshake_error(format!("answered '{status}'")));
+    }
+    if header(&head, "sec-websocket-accept") != Some(&*accept_key(&key)) {
+        return Err(handshake_error("wrong Sec-WebSocket-Accept"));
+    }
+    stream.set_read_timeout(None)?;
+    prepare(&stream)?;
+    debug!("Connected to {addr} over WebSocket");
+    Ok((stream, reader))
+}
+
+fn prepare(stream: &TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))
+}
+
+/// Read a handshake request from `reader` and answer it on `stream`.
+fn answer_handshake<R: BufRead>(
+    reader: &mut R,
+    mut stream: &TcpStream,
+) -> io::Result<()> {
+    let head = read_head(reader)?;
+    let request = head.first().map(String::as_str).unwrap_or_default();
+    let has_token = |name, token: &str| {
+        header(&head, name).is_some_and(|value| {
+            value.split(',').any(|v| v.trim().eq_ignore_ascii_case(token))
+        })
+    };
+    let key = header(&head, "sec-websocket-key");
+
+    let valid = request.starts_with("GET ")
+        && has_token("upgrade", "websocket")
+        && has_token("connection", "upgrade")
+        && header(&head, "sec-websocket-version") == Some("13");
+    let (Some(key), true) = (key, valid) else {
+        stream.write_all(
+            b"HTTP/1.1 400 Bad Request\r\nSec-WebSocket-Version: 13\r\n\
+              Content-Length: 0\r\n\r\n",
+        )?;
+        return Err(handshake_error(format!("not a handshake: '{request}'")));
+    };
+
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+         Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    );
+    stream.write_all(response.as_bytes())
+}
+
+fn handshake_error(reason: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.into())
+}
+
+/// Lines of an HTTP head, up to the blank line ending it.
+fn read_head<R: BufRead>(reader: &mut R) -> io::Result<Vec<String>> {
+    let mut head = Vec::new();
+    let mut limited = reader.take(MAX_HANDSHAKE_SIZE);
+    loop {
+        let mut line = String::new();
+        if limited.read_line(&mut line)? == 0 {
+            return Err(handshake_error("handshake cut short"));
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            return Ok(head);
+        }
+        head.push(line.to_string());
+    }
+}
+
+/// Value of the header `name`, matched without regard to case.
+fn header<'a>(head: &'a [String], name: &str) -> Option<&'a str> {
+    head.iter().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Start the thread reading the connection with `peer` through `reader`.
+fn start_reading(
+    shared: &Arc<Shared>,
+    reader: BufReader<TcpStream>,
+    peer: SocketAddr,
+    server: bool,
+) -> io::Result<()> {
+    let weak = Arc::downgrade(shared);
+    thread::Builder::new()
+        .name(format!("websocket-{peer}"))
+        .spawn(move || read(reader, peer, &weak, server))?;
+    Ok(())
+}
+
+/// Deliver the messages read from `reader` until the connection closes.
+/// `server` tells whether we are the server end, receiving masked frames.
+fn read(
+    mut reader: BufReader<TcpStream>,
+    peer: SocketAddr,
+    shared: &Weak<Shared>,
+    server: bool,
+) {
+    loop {
+        let message =
+            read_message(&mut reader, server, &mut |opcode, data| {
+                if let Some(shared) = shared.upgrade() {
+                    shared.control(peer, opcode, data);
+                }
+            });
+        match message {
+            Ok(Some(data)) => match shared.upgrade() {
+                Some(shared) => shared.deliver(data, peer),
+                None => return,
+            },
+            Ok(None) => break,
+            Err(e @ FrameError::TooLarge { .. }) => {
+                warn!("Closing the WebSocket connection with {peer}: {e}");
+                break;
+            }
+            Err(e) => {
+                debug!("WebSocket connection with {peer} closed: {e}");
+                break;
+            }
+        }
+    }
+
+    let _ = reader.get_ref().shutdown(Shutdown::Both);
+    if let Some(shared) = shared.upgrade() {
+        shared.connections().remove(&peer);
+    }
+}
+
+/// Append a frame to `out`, masked with `mask` if any.
+fn encode_frame(
+    out: &mut Vec<u8>,
+    opcode: u8,
+    payload: &[u8],
+    mask: Option<[u8; 4]>,
+) {
+    out.push(0x80 | opcode);
+    let masked = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        len @ 0..=125 => out.push(masked | len as u8),
+        len @ 126..=0xffff => {
+            out.push(masked | 126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            out.push(masked | 127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(mask) => {
+            out.extend_from_slice(&mask);
+            out.extend(
+                payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]),
+            );
+        }
+        None => out.extend_from_slice(payload),
+    }
+}
+
+/// Read the next binary message, reassembling its fragments. Control
+/// frames met on the way are handed to `control` as an opcode and payload
+/// to send back, if any. Returns `None` once the peer closed.
+///
+/// `masked` tells whether frames must be masked, as those from a client
+/// are, and those from a server are not.
+fn read_message<R: Read>(
+    reader: &mut R,
+    masked: bool,
+    control: &mut dyn FnMut(u8, &[u8]),
+) -> Result<Option<Vec<u8>>, FrameError> {
+    let violation = |reason: &str| {
+        FrameError::Io(io::Error::new(io::ErrorKind::InvalidData, reason))
+    };
+    let mut message: Option<Vec<u8>> = None;
+    loop {
+        let mut head = [0u8; 2];
+        match reader.read_exact(&mut head) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return match message {
+                    Some(_) => Err(FrameError::Truncated),
+                    None => Ok(None),
+                };
+            }
+            Err(e) => return Err(FrameError::Io(e)),
+        }
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0f;
+        if head[0] & 0x70 != 0 {
+            return Err(violation("reserved bits set"));
+        }
+        if (head[1] & 0x80 != 0) != masked {
+            return Err(violation("wrong masking"));
+        }
+
+        let len = match head[1] & 0x7f {
+            126 => {
+                let mut len = [0u8; 2];
+                read_exact(reader, &mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0u8; 8];
+                read_exact(reader, &mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+        let so_far = message.as_ref().map_or(0, Vec::len) as u64;
+        let max = protocol::MAX_MESSAGE_SIZE;
+        if so_far + len > max as u64 {
+            return Err(FrameError::TooLarge {
+                len: usize::try_from(so_far + len).unwrap_or(usize::MAX),
+                max,
+            });
+        }
+        let mut mask = [0u8; 4];
+        if masked {
+            read_exact(reader, &mut mask)?;
+        }
+        let mut payload = vec![0u8; len as usize];
+        read_exact(reader, &mut payload)?;
+        if masked {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+
+        match opcode {
+            OPCODE_CLOSE => {
+                control(OPCODE_CLOSE, &[]);
+                return Ok(None);
+            }
+            OPCODE_PING if fin && len <= 125 => control(OPCODE_PONG, &payload),
+            OPCODE_PONG if fin && len <= 125 => {}
+            OPCODE_BINARY if message.is_none() => {
+                if fin {
+                    return Ok(Some(payload));
+                }
+                message = Some(payload);
+            }
+            OPCODE_CONTINUATION => {
+                let Some(data) = message.as_mut() else {
+                    return Err(violation("continuation without a message"));
+                };
+                data.extend_from_slice(&payload);
+                if fin {
+                    return Ok(message);
+                }
+            }
+            OPCODE_TEXT => return Err(violation("text message")),
+            _ => return Err(violation("unexpected frame")),
+        }
+    }
+}
+
+fn read_exact<R: Read>(
+    reader: &mut R,
+    buf: &mut [u8],
+) -> Result<(), FrameError> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => FrameError::Truncated,
+        _ => FrameError::Io(e),
+    })
+}
@@ -0,0 +1,655 @@
+//
+// Copyright (c) 2025 murilo ijanc' <murilo@ijanc.org>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Golden encodings of every [`RendezvousMessage`] variant and of the
+//! envelope, the same as in `docs/wire-format.md`.
+//!
+//! A failure here is a wire-format change. When it is intended, update the
+//! fixtures and the document together.
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use tesseras::{
+    dht,
+    identity::Identity,
+    protocol::{
+        self, Contact, KeyRange, KeyVersion, NetworkId, PeerInfo,
+        RendezvousMessage, RendezvousStats,
+    },
+    store::Value,
+};
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn addr(s: &str) -> SocketAddr {
+    s.parse().unwrap()
+}
+
+/// The `peer` of the common inputs.
+fn peer() -> PeerInfo {
+    PeerInfo {
+        peer_id: "alice".to_string(),
+        public_addr: addr("192.0.2.1:4000"),
+        private_addr: Some(addr("10.0.0.2:4000")),
+        last_seen: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        capabilities: vec!["relay".to_string()],
+        candidates: vec![addr("[2001:db8::1]:4000")],
+    }
+}
+
+fn range() -> KeyRange {
+    let mut prefix = [0; 20];
+    prefix[0] = 0xa0;
+    KeyRange { prefix, depth: 3 }
+}
+
+/// Check that `msg` encodes to `varint`, or to `fixed` under the
+/// `fixed-int` feature, behind the envelope, and decodes back.
+fn check(msg: RendezvousMessage, varint: &str, fixed: &str) {
+    let expected = if cfg!(feature = "fixed-int") { fixed } else { varint };
+
+    let datagram = protocol::encode(NetworkId::MAIN, &msg).unwrap();
+    let body = &datagram[protocol::ENVELOPE_SIZE..];
+    assert_eq!(hex(body), expected, "{msg:?}");
+    assert_eq!(usize::from(datagram[3]), usize::from(body[0]));
+
+    let (network, decoded) = protocol::decode(&datagram).unwrap();
+    assert_eq!(network, NetworkId::MAIN);
+    let again = protocol::encode(network, &decoded).unwrap();
+    assert_eq!(hex(&again), hex(&datagram), "{decoded:?}");
+}
+
+/// Fails to compile when a variant is added without a fixture below.
+#[allow(dead_code)]
+fn covered(msg: &RendezvousMessage) {
+    match msg {
+        RendezvousMessage::Register { .. }
+        | RendezvousMessage::Query { .. }
+        | RendezvousMessage::PeerInfo { .. }
+        | RendezvousMessage::InitiateConnection { .. }
+        | RendezvousMessage::ListPeers { .. }
+        | RendezvousMessage::PeerList { .. }
+        | RendezvousMessage::Relay { .. }
+        | RendezvousMessage::Relayed { .. }
+        | RendezvousMessage::Probe { .. }
+        | RendezvousMessage::Heartbeat { .. }
+        | RendezvousMessage::HeartbeatAck { .. }
+        | RendezvousMessage::GetStats
+        | RendezvousMessage::Stats { .. }
+        | RendezvousMessage::WhatIsMyAddr
+        | RendezvousMessage::YourAddr { .. }
+        | RendezvousMessage::Redirect { .. }
+        | RendezvousMessage::FindNode { .. }
+        | RendezvousMessage::Nodes { .. }
+        | RendezvousMessage::Store { .. }
+        | RendezvousMessage::Stored { .. }
+        | RendezvousMessage::FindValue { .. }
+        | RendezvousMessage::Found { .. }
+        | RendezvousMessage::Ping { .. }
+        | RendezvousMessage::Pong { .. }
+        | RendezvousMessage::Cache { .. }
+        | RendezvousMessage::WrongNetwork { .. }
+        | RendezvousMessage::Leave { .. }
+        | RendezvousMessage::SyncDigest { .. }
+        | RendezvousMessage::Digests { .. }
+        | RendezvousMessage::SyncKeys { .. }
+        | RendezvousMessage::Keys { .. }
+        | RendezvousMessage::UnsupportedVersion { .. }
+        | RendezvousMessage::Hello { .. }
+        | RendezvousMessage::HelloAck { .. }
+        | RendezvousMessage::Fragment { .. } => {}
+    }
+}
+
+#[test]
+fn envelope() {
+    let msg = RendezvousMessage::Ping { nonce: 1 };
+    let datagram = protocol::encode(NetworkId::MAIN, &msg).unwrap();
+    assert_eq!(hex(&datagram[..protocol::ENVELOPE_SIZE]), "7473021674657373");
+
+    let network: NetworkId = "test".parse().unwrap();
+    let datagram = protocol::encode(network, &msg).unwrap();
+    assert_eq!(hex(&datagram[..protocol::ENVELOPE_SIZE]), "74730216a94a8fe5");
+}
+
+/// The breakdown of the `Register` vector in `docs/wire-format.md`: every
+/// integer at its full width, little-endian.
+#[cfg(feature = "fixed-int")]
+#[test]
+fn fixed_int_layout() {
+    let msg = RendezvousMessage::Register {
+        nonce: 1,
+        peer_id: "alice".to_string(),
+        private_addr: addr("10.0.0.2:4000"),
+        capabilities: vec!["relay".to_string()],
+        candidates: vec![addr("[2001:db8::1]:4000")],
+    };
+    let mut expected = Vec::new();
+    expected.extend_from_slice(&0u32.to_le_bytes()); // variant 0, Register
+    expected.extend_from_slice(&1u64.to_le_bytes()); // nonce
+    expected.extend_from_slice(&5u64.to_le_bytes()); // peer_id
+    expected.extend_from_slice(b"alice");
+    expected.extend_from_slice(&0u32.to_le_bytes()); // private_addr, V4
+    expected.extend_from_slice(&[10, 0, 0, 2]);
+    expected.extend_from_slice(&4000u16.to_le_bytes());
+    expected.extend_from_slice(&1u64.to_le_bytes()); // capabilities
+    expected.extend_from_slice(&5u64.to_le_bytes());
+    expected.extend_from_slice(b"relay");
+    expected.extend_from_slice(&1u64.to_le_bytes()); // candidates
+    expected.extend_from_slice(&1u32.to_le_bytes()); // V6
+    expected.extend_from_slice(
+        &"2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap().octets(),
+    );
+    expected.extend_from_slice(&4000u16.to_le_bytes());
+
+    let datagram = protocol::encode(NetworkId::MAIN, &msg).unwrap();
+    assert_eq!(hex(&datagram[protocol::ENVELOPE_SIZE..]), hex(&expected));
+}
+
+#[test]
+fn register() {
+    check(
+        RendezvousMessage::Register {
+            nonce: 1,
+            peer_id: "alice".to_string(),
+            private_addr: addr("10.0.0.2:4000"),
+            capabilities: vec!["relay".to_string()],
+            candidates: vec![addr("[2001:db8::1]:4000")],
+        },
+        concat!(
+            "000105616c696365000a000002fba00f010572656c6179010120010d",
+            "b8000000000000000000000001fba00f",
+        ),
+        concat!(
+            "0000000001000000000000000500000000000000616c696365000000",
+            "000a000002a00f0100000000000000050000000000000072656c6179",
+            "01000000000000000100000020010db8000000000000000000000001",
+            "a00f",
+        ),
+    );
+}
+
+#[test]
+fn query() {
+    check(
+        RendezvousMessage::Query { target_peer_id: "bob".to_string() },
+        "0103626f62",
+        "010000000300000000000000626f62",
+    );
+}
+
+#[test]
+fn peer_info() {
+    check(
+        RendezvousMessage::PeerInfo { peer: peer() },
+        concat!(
+            "0205616c69636500c0000201fba00f01000a000002fba00ffc00f153",
+            "6500010572656c6179010120010db8000000000000000000000001fb",
+            "a00f",
+        ),
+        concat!(
+            "020000000500000000000000616c69636500000000c0000201a00f01",
+            "000000000a000002a00f00f153650000000000000000010000000000",
+            "0000050000000000000072656c617901000000000000000100000020",
+            "010db8000000000000000000000001a00f",
+        ),
+    );
+}
+
+#[test]
+fn initiate_connection() {
+    check(
+        RendezvousMessage::InitiateConnection {
+            nonce: 2,
+            from_peer_id: "alice".to_string(),
+            to_peer_id: "bob".to_string(),
+        },
+        "030205616c69636503626f62",
+        concat!(
+            "0300000002000000000000000500000000000000616c696365030000",
+            "0000000000626f62",
+        ),
+    );
+}
+
+#[test]
+fn list_peers() {
+    check(
+        RendezvousMessage::ListPeers {
+            capabilities: vec!["relay".to_string()],
+        },
+        "04010572656c6179",
+        "040000000100000000000000050000000000000072656c6179",
+    );
+}
+
+#[test]
+fn peer_list() {
+    check(
+        RendezvousMessage::PeerList { peers: vec![peer()] },
+        concat!(
+            "050105616c69636500c0000201fba00f01000a000002fba00ffc00f1",
+            "536500010572656c6179010120010db8000000000000000000000001",
+            "fba00f",
+        ),
+        concat!(
+            "0500000001000000000000000500000000000000616c696365000000",
+            "00c0000201a00f01000000000a000002a00f00f15365000000000000",
+            "00000100000000000000050000000000000072656c61790100000000",
+            "0000000100000020010db8000000000000000000000001a00f",
+        ),
+    );
+}
+
+#[test]
+fn relay() {
+    check(
+        RendezvousMessage::Relay {
+            to_peer_id: "bob".to_string(),
+            payload: vec![0xde, 0xad, 0xbe, 0xef],
+            hops: 8,
+        },
+        "0603626f6204deadbeef08",
+        "060000000300000000000000626f620400000000000000deadbeef08",
+    );
+}
+
+#[test]
+fn relayed() {
+    check(
+        RendezvousMessage::Relayed {
+            from_peer_id: "alice".to_string(),
+            payload: vec![0xde, 0xad, 0xbe, 0xef],
+            hops: 7,
+        },
+        "0705616c69636504deadbeef07",
+        concat!(
+            "070000000500000000000000616c6963650400000000000000deadbe",
+            "ef07",
+        ),
+    );
+}
+
+#[test]
+fn probe() {
+    check(
+        RendezvousMessage::Probe {
+            from_peer_id: "alice".to_string(),
+            ack: false,
+        },
+        "0805616c69636500",
+        "080000000500000000000000616c69636500",
+    );
+}
+
+#[test]
+fn heartbeat() {
+    check(
+        RendezvousMessage::Heartbeat { peer_id: "alice".to_string() },
+        "0905616c696365",
+        "090000000500000000000000616c696365",
+    );
+}
+
+#[test]
+fn heartbeat_ack() {
+    check(
+        RendezvousMessage::HeartbeatAck { epoch: 1_700_000_000_000 },
+        "0afd0068e5cf8b010000",
+        "0a0000000068e5cf8b010000",
+    );
+}
+
+#[test]
+fn get_stats() {
+    check(RendezvousMessage::GetStats, "0b", "0b000000");
+}
+
+#[test]
+fn stats() {
+    check(
+        RendezvousMessage::Stats {
+            stats: RendezvousStats {
+                peers: 2,
+                bytes_in: 1000,
+                bytes_out: 300,
+                bytes_in_per_sec: 10,
+                bytes_out_per_sec: 3,
+                negative_cache_hits: 5,
+                negative_cache_lookups: 8,
+            },
+        },
+        "0c02fbe803fb2c010a030508",
+        concat!(
+            "0c0000000200000000000000e8030000000000002c01000000000000",
+            "0a000000000000000300000000000000050000000000000008000000",
+            "00000000",
+        ),
+    );
+}
+
+#[test]
+fn what_is_my_addr() {
+    check(RendezvousMessage::WhatIsMyAddr, "0d", "0d000000");
+}
+
+#[test]
+fn your_addr() {
+    check(
+        RendezvousMessage::YourAddr { addr: addr("192.0.2.1:4000") },
+        "0e00c0000201fba00f",
+        "0e00000000000000c0000201a00f",
+    );
+}
+
+#[test]
+fn redirect() {
+    check(
+        RendezvousMessage::Redirect {
+            addresses: vec![addr("192.0.2.1:4000"), addr("10.0.0.2:4000")],
+        },
+        "0f0200c0000201fba00f000a000002fba00f",
+        concat!(
+            "0f000000020000000000000000000000c0000201a00f000000000a00",
+            "0002a00f",
+        ),
+    );
+}
+
+#[test]
+fn find_node() {
+    check(
+        RendezvousMessage::FindNode { nonce: 1, target: [0xab; 20] },
+        "1001abababababababababababababababababababab",
+        concat!(
+            "100000000100000000000000abababababababababababababababab",
+            "abababab",
+        ),
+    );
+}
+
+#[test]
+fn nodes() {
+    check(
+        RendezvousMessage::Nodes {
+            nonce: 1,
+            contacts: vec![Contact {
+                node_id: [0xab; 20],
+                addr: addr("192.0.2.1:4000"),
+            }],
+        },
+        concat!(
+            "110101abababababababababababababababababababab00c0000201",
+            "fba00f",
+        ),
+        concat!(
+            "1100000001000000000000000100000000000000abababababababab",
+            "abababababababababababab00000000c0000201a00f",
+        ),
+    );
+}
+
+#[test]
+fn store() {
+    check(
+        RendezvousMessage::Store {
+            nonce: 1,
+            key: "alice".to_string(),
+            value: Value::Utf8("relay".to_string()),
+            seq: 2,
+            ttl: 86400,
+        },
+        "120105616c696365000572656c617902fc80510100",
+        concat!(
+            "1200000001000000000000000500000000000000616c696365000000",
+            "00050000000000000072656c61790200000000000000805101000000",
+            "0000",
+        ),
+    );
+}
+
+#[test]
+fn stored() {
+    check(
+        RendezvousMessage::Stored { nonce: 1 },
+        "1301",
+        "130000000100000000000000",
+    );
+}
+
+#[test]
+fn find_value() {
+    check(
+        RendezvousMessage::FindValue { nonce: 1, key: "alice".to_string() },
+        "140105616c696365",
+        "1400000001000000000000000500000000000000616c696365",
+    );
+}
+
+#[test]
+fn found() {
+    check(
+        RendezvousMessage::Found {
+            nonce: 1,
+            value: Value::Bytes(vec![1, 2, 3]),
+            seq: 2,
+        },
+        "1501010301020302",
+        concat!(
+            "15000000010000000000000001000000030000000000000001020302",
+            "00000000000000",
+        ),
+    );
+}
+
+#[test]
+fn ping() {
+    check(
+        RendezvousMessage::Ping { nonce: 1 },
+        "1601",
+        "160000000100000000000000",
+    );
+}
+
+#[test]
+fn pong() {
+    let identity = Identity::from_seed([7; 32]);
+    check(
+        RendezvousMessage::Pong {
+            nonce: 1,
+            public_key: identity.public_key(),
+            signature: identity.sign(&dht::pong_challenge(1)).to_vec(),
+        },
+        concat!(
+            "1701ea4a6c63e29c520abef5507b132ec5f9954776aebebe7b92421e",
+            "ea691446d22c40ae4e5171bb9f42f3905a213ad58c7857fd56180ecf",
+            "8c27ff1411c56613d978bdb0fba31214f2ed53736c71c54ee503d255",
+            "6cc51729ed6f38bc6de6ddc36c860b",
+        ),
+        concat!(
+            "170000000100000000000000ea4a6c63e29c520abef5507b132ec5f9",
+            "954776aebebe7b92421eea691446d22c4000000000000000ae4e5171",
+            "bb9f42f3905a213ad58c7857fd56180ecf8c27ff1411c56613d978bd",
+            "b0fba31214f2ed53736c71c54ee503d2556cc51729ed6f38bc6de6dd",
+            "c36c860b",
+        ),
+    );
+}
+
+#[test]
+fn cache() {
+    check(
+        RendezvousMessage::Cache {
+            nonce: 1,
+            key: "alice".to_string(),
+            value: Value::Utf8("relay".to_string()),
+            ttl: 60,
+        },
+        "180105616c696365000572656c61793c",
+        concat!(
+            "1800000001000000000000000500000000000000616c696365000000",
+            "00050000000000000072656c61793c00000000000000",
+        ),
+    );
+}
+
+#[test]
+fn wrong_network() {
+    check(
+        RendezvousMessage::WrongNetwork { network: NetworkId::MAIN },
+        "1974657373",
+        "1900000074657373",
+    );
+}
+
+#[test]
+fn leave() {
+    let identity = Identity::from_seed([7; 32]);
+    check(
+        RendezvousMessage::Leave {
+            nonce: 1,
+            public_key: identity.public_key(),
+            signature: identity.sign(&dht::leave_challenge(1)).to_vec(),
+        },
+        concat!(
+            "1a01ea4a6c63e29c520abef5507b132ec5f9954776aebebe7b92421e",
+            "ea691446d22c4026c9130f89942a8eb23634e99264b006decd739fa7",
+            "95696e7f0ed6b5d321d4bd2c2e7318f6adc6074ae39cb8b105877ec0",
+            "6c4abf876b2454b97a02a3e7c4b502",
+        ),
+        concat!(
+            "1a0000000100000000000000ea4a6c63e29c520abef5507b132ec5f9",
+            "954776aebebe7b92421eea691446d22c400000000000000026c9130f",
+            "89942a8eb23634e99264b006decd739fa795696e7f0ed6b5d321d4bd",
+            "2c2e7318f6adc6074ae39cb8b105877ec06c4abf876b2454b97a02a3",
+            "e7c4b502",
+        ),
+    );
+}
+
+#[test]
+fn sync_digest() {
+    check(
+        RendezvousMessage::SyncDigest {
+            nonce: 1,
+            range: range(),
+            level: 1,
+            indexes: vec![2, 5],
+        },
+        "1b01a0000000000000000000000000000000000000000301020205",
+        concat!(
+            "1b0000000100000000000000a0000000000000000000000000000000",
+            "000000000301020000000000000002000500",
+        ),
+    );
+}
+
+#[test]
+fn digests() {
+    check(
+        RendezvousMessage::Digests { nonce: 1, hashes: vec![[0xab; 20]] },
+        "1c0101abababababababababababababababababababab",
+        concat!(
+            "1c00000001000000000000000100000000000000abababababababab",
+            "abababababababababababab",
+        ),
+    );
+}
+
+#[test]
+fn sync_keys() {
+    check(
+        RendezvousMessage::SyncKeys {
+            nonce: 1,
+            range: range(),
+            leaves: vec![37],
+        },
+        "1d01a000000000000000000000000000000000000000030125",
+        concat!(
+            "1d0000000100000000000000a0000000000000000000000000000000",
+            "000000000301000000000000002500",
+        ),
+    );
+}
+
+#[test]
+fn keys() {
+    check(
+        RendezvousMessage::Keys {
+            nonce: 1,
+            versions: vec![KeyVersion {
+                key: "alice".to_string(),
+                seq: 2,
+                ttl: 86400,
+            }],
+        },
+        "1e010105616c69636502fc80510100",
+        concat!(
+            "1e000000010000000000000001000000000000000500000000000000",
+            "616c69636502000000000000008051010000000000",
+        ),
+    );
+}
+
+#[test]
+fn unsupported_version() {
+    check(
+        RendezvousMessage::UnsupportedVersion {
+            version: 3,
+            min_version: 2,
+            max_version: 2,
+        },
+        "1f030202",
+        "1f000000030202",
+    );
+}
+
+#[test]
+fn hello() {
+    check(
+        RendezvousMessage::Hello { nonce: 1, min_version: 2, max_version: 2 },
+        "20010202",
+        "2000000001000000000000000202",
+    );
+}
+
+#[test]
+fn hello_ack() {
+    check(
+        RendezvousMessage::HelloAck { nonce: 1, version: 2 },
+        "210102",
+        "21000000010000000000000002",
+    );
+}
+
+#[test]
+fn fragment() {
+    check(
+        RendezvousMessage::Fragment {
+            id: 1,
+            index: 0,
+            count: 2,
+            data: vec![0x74, 0x73],
+        },
+        "22010002027473",
+        "2200000001000000000000000000020002000000000000007473",
+    );
+}